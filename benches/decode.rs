@@ -0,0 +1,59 @@
+//! Hot path of every commit: frame header, commit body, CAR blocks and the post record.
+//!
+//! Run with `cargo bench`; inputs are the captured frames in `fixtures/`.
+
+use std::hint::black_box;
+
+use atrium_api::app::bsky::feed::post;
+use bsky_firehose_listener::frame::{self, Frame};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+fn decode(c: &mut Criterion) {
+    let data = std::fs::read("fixtures/commit.bin").expect("fixtures/commit.bin is readable");
+    let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+        panic!("fixtures/commit.bin is not a commit frame");
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let blocks = runtime.block_on(frame::blocks(&commit)).unwrap();
+    let cid = commit.ops[0]
+        .cid
+        .as_ref()
+        .expect("the fixture creates a record");
+    let block = blocks
+        .get(&cid.0)
+        .expect("the record is in the CAR")
+        .clone();
+
+    let mut group = c.benchmark_group("decode");
+
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("split_frame", |b| {
+        b.iter(|| frame::split_frame(black_box(&data)).unwrap())
+    });
+    group.bench_function("frame", |b| {
+        b.iter(|| frame::decode(black_box(&data)).unwrap())
+    });
+
+    group.throughput(Throughput::Bytes(commit.blocks.len() as u64));
+    group.bench_function("car", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { frame::blocks(black_box(&commit)).await.unwrap() })
+    });
+
+    group.bench_function("block_lookup", |b| {
+        b.iter(|| blocks.get(black_box(&cid.0)).cloned().unwrap())
+    });
+
+    group.throughput(Throughput::Bytes(block.len() as u64));
+    group.bench_function("post_record", |b| {
+        b.iter(|| serde_ipld_dagcbor::from_slice::<post::Record>(black_box(&block)).unwrap())
+    });
+    group.bench_function("record_json", |b| {
+        b.iter(|| frame::record_json(black_box(&block)).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+
+use bsky_firehose_listener::frame;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = frame::split_frame(data);
+    let _ = frame::decode(data);
+    let _ = frame::record_json(data);
+});
@@ -0,0 +1,85 @@
+//! Running alt-text statistics for image posts across the whole firehose.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tracing::info;
+
+use crate::{embed::Embed, task};
+
+#[derive(Debug, Default)]
+pub struct AltTextStats {
+    image_posts: AtomicU64,
+    images: AtomicU64,
+    images_with_alt: AtomicU64,
+    alt_chars: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AltTextSnapshot {
+    pub image_posts: u64,
+    pub images: u64,
+    pub images_with_alt: u64,
+    /// Fraction of images with alt text
+    pub alt_ratio: f64,
+    /// Average alt text length in characters, over images that have one
+    pub average_alt_len: f64,
+}
+
+impl AltTextStats {
+    pub fn record(&self, embed: &Embed) {
+        let mut images = 0;
+        for image in embed.images() {
+            images += 1;
+            if let Some(alt) = &image.alt {
+                self.images_with_alt.fetch_add(1, Ordering::Relaxed);
+                self.alt_chars
+                    .fetch_add(alt.chars().count() as u64, Ordering::Relaxed);
+            }
+        }
+        if images > 0 {
+            self.image_posts.fetch_add(1, Ordering::Relaxed);
+            self.images.fetch_add(images, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> AltTextSnapshot {
+        let images = self.images.load(Ordering::Relaxed);
+        let images_with_alt = self.images_with_alt.load(Ordering::Relaxed);
+        let ratio = |n: u64, d: u64| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+
+        AltTextSnapshot {
+            image_posts: self.image_posts.load(Ordering::Relaxed),
+            images,
+            images_with_alt,
+            alt_ratio: ratio(images_with_alt, images),
+            average_alt_len: ratio(self.alt_chars.load(Ordering::Relaxed), images_with_alt),
+        }
+    }
+
+    /// Logs a snapshot every `interval` in the background.
+    pub fn log_every(self: Arc<Self>, interval: Duration) {
+        task::spawn("alt-text-stats", async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let snapshot = self.snapshot();
+                info!(
+                    "Alt text: {} image posts, {}/{} images ({:.1}%) with alt text, {:.1} chars on average",
+                    snapshot.image_posts,
+                    snapshot.images_with_alt,
+                    snapshot.images,
+                    snapshot.alt_ratio * 100.0,
+                    snapshot.average_alt_len
+                );
+            }
+        });
+    }
+}
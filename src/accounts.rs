@@ -0,0 +1,122 @@
+//! Hosting status of accounts, kept up to date from `#account` frames so events from
+//! deactivated or taken-down repos can be told apart downstream.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::Write as _,
+    path::Path,
+    sync::Mutex,
+};
+
+use atrium_api::com::atproto::sync::subscribe_repos::Account;
+use serde::Serialize;
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    Active,
+    Deactivated,
+    Takendown,
+    Suspended,
+    Deleted,
+    /// Any status this version doesn't know about, e.g. `desynchronized`
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl AccountStatus {
+    /// The status of an `#account` frame. Inactive accounts without a status are deactivated.
+    pub fn from_frame(active: bool, status: Option<&str>) -> Self {
+        match (active, status) {
+            (true, _) => Self::Active,
+            (false, None | Some("deactivated")) => Self::Deactivated,
+            (false, Some("takendown")) => Self::Takendown,
+            (false, Some("suspended")) => Self::Suspended,
+            (false, Some("deleted")) => Self::Deleted,
+            (false, Some(other)) => Self::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Active => "active",
+            Self::Deactivated => "deactivated",
+            Self::Takendown => "takendown",
+            Self::Suspended => "suspended",
+            Self::Deleted => "deleted",
+            Self::Other(status) => status,
+        }
+    }
+}
+
+/// Status of every account seen going inactive, persisted as an append-only file of
+/// `<did> <status>` lines.
+///
+/// Only inactive accounts are kept: an account becoming active again is forgotten, and dropped
+/// from the file when it is next opened.
+#[derive(Debug)]
+pub struct AccountStatuses {
+    inactive: Mutex<HashMap<String, AccountStatus>>,
+    file: Mutex<File>,
+}
+
+impl AccountStatuses {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let mut inactive = HashMap::new();
+        if path.exists() {
+            for line in std::fs::read_to_string(path)?.lines() {
+                let Some((did, status)) = line.split_once(' ') else {
+                    continue;
+                };
+                match AccountStatus::from_frame(status == "active", Some(status)) {
+                    AccountStatus::Active => inactive.remove(did),
+                    status => inactive.insert(did.to_string(), status),
+                };
+            }
+        }
+
+        // Compact the file down to the accounts still inactive, replacing it only once the new
+        // one is complete so a crash can't lose them all
+        let mut compacted = String::new();
+        for (did, status) in &inactive {
+            let _ = writeln!(compacted, "{did} {}", status.as_str());
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::write(&partial, compacted)?;
+        std::fs::rename(&partial, path)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        info!("Loaded status of {} inactive accounts", inactive.len());
+
+        Ok(Self {
+            inactive: Mutex::new(inactive),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records the status carried by an `#account` frame.
+    pub fn record(&self, account: &Account) -> std::io::Result<()> {
+        let did = account.did.as_str();
+        let status = AccountStatus::from_frame(account.active, account.status.as_deref());
+
+        let mut inactive = self.inactive.lock().unwrap();
+        let changed = match &status {
+            AccountStatus::Active => inactive.remove(did).is_some(),
+            status => inactive.insert(did.to_string(), status.clone()).as_ref() != Some(status),
+        };
+        drop(inactive);
+
+        if changed {
+            writeln!(self.file.lock().unwrap(), "{did} {}", status.as_str())?;
+        }
+        Ok(())
+    }
+
+    /// The status of `did`, or `None` if it's active or was never seen going inactive.
+    pub fn status(&self, did: &str) -> Option<AccountStatus> {
+        self.inactive.lock().unwrap().get(did).cloned()
+    }
+}
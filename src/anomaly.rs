@@ -0,0 +1,134 @@
+//! Flags repos creating follows or posts faster than configured thresholds, a common sign of
+//! spam and follow-farming accounts.
+//!
+//! Rates are measured in commit time, decoded from each commit's rev, so replaying a backlog
+//! after a restart or from `--start-from` doesn't make every active repo look like a burst.
+
+use std::{collections::VecDeque, num::NonZeroUsize, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::{client::Event, config::Config, follows, revisions};
+
+/// Repos whose rates are tracked at once; the least recently active are forgotten first
+const TRACKED_REPOS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    Follows,
+    Posts,
+}
+
+impl Activity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Follows => "follows",
+            Self::Posts => "posts",
+        }
+    }
+}
+
+/// A repo went over its threshold. Raised at most once per window for each repo and activity.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyDetected {
+    pub did: String,
+    pub activity: Activity,
+    /// Records created within the window; one past the threshold
+    pub count: usize,
+    pub window_secs: u64,
+    pub detected_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    window: Duration,
+    /// `window` in commit time
+    span: TimeDelta,
+    max_follows: Option<usize>,
+    max_posts: Option<usize>,
+    rates: Mutex<LruCache<(String, Activity), Rate>>,
+}
+
+/// Recent creations by one repo.
+#[derive(Debug, Default)]
+struct Rate {
+    /// Commit times, oldest first, never more than one past the threshold
+    times: VecDeque<DateTime<Utc>>,
+    /// Quiet until then, in commit time, after raising an anomaly
+    flagged_until: Option<DateTime<Utc>>,
+}
+
+impl AnomalyDetector {
+    /// Returns `None` when no threshold is configured.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.anomaly_max_follows.is_none() && config.anomaly_max_posts.is_none() {
+            return None;
+        }
+        Some(Self {
+            window: config.anomaly_window,
+            span: TimeDelta::from_std(config.anomaly_window).unwrap_or(TimeDelta::MAX),
+            max_follows: config.anomaly_max_follows,
+            max_posts: config.anomaly_max_posts,
+            rates: Mutex::new(LruCache::new(
+                NonZeroUsize::new(TRACKED_REPOS).expect("not zero"),
+            )),
+        })
+    }
+
+    /// Collections that need to be passed to [`Self::record`].
+    pub fn collections(&self) -> Vec<&'static str> {
+        let mut collections = Vec::new();
+        if self.max_follows.is_some() {
+            collections.push(follows::COLLECTION);
+        }
+        if self.max_posts.is_some() {
+            collections.push("app.bsky.feed.post");
+        }
+        collections
+    }
+
+    /// Counts `evt` if it creates a follow or post, returning an anomaly if that takes its repo
+    /// over the threshold. Commits whose rev isn't a TID count as made now.
+    pub fn record(&self, evt: &Event) -> Option<AnomalyDetected> {
+        if evt.action != "create" {
+            return None;
+        }
+        let (activity, max) = match evt.collection.as_str() {
+            follows::COLLECTION => (Activity::Follows, self.max_follows?),
+            "app.bsky.feed.post" => (Activity::Posts, self.max_posts?),
+            _ => return None,
+        };
+
+        let now = revisions::rev_time(&evt.rev).unwrap_or_else(Utc::now);
+        let mut rates = self.rates.lock().unwrap();
+        let rate =
+            rates.get_or_insert_mut((evt.repo.as_str().to_string(), activity), Rate::default);
+        while rate
+            .times
+            .front()
+            .is_some_and(|time| now - *time >= self.span)
+        {
+            rate.times.pop_front();
+        }
+        rate.times.push_back(now);
+        if rate.times.len() > max + 1 {
+            rate.times.pop_front();
+        }
+
+        if rate.times.len() <= max || rate.flagged_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        rate.flagged_until =
+            Some(now.checked_add_signed(self.span).unwrap_or(DateTime::<Utc>::MAX_UTC));
+        Some(AnomalyDetected {
+            did: evt.repo.as_str().to_string(),
+            activity,
+            count: rate.times.len(),
+            window_secs: self.window.as_secs(),
+            detected_at: Utc::now(),
+        })
+    }
+}
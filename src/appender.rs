@@ -0,0 +1,102 @@
+//! Appends to output files from a dedicated thread, so disk latency never stalls the tasks
+//! producing the lines.
+
+use std::{
+    path::Path,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use tracing::{error, info_span};
+
+use crate::{
+    health::Health,
+    rotate::{RotatingFile, RotationPolicy},
+    telemetry::Metrics,
+};
+
+/// Lines waiting to be written before new ones are rejected
+const QUEUE_SIZE: usize = 10_000;
+/// Waiting lines are written together, up to this many bytes at a time
+const BATCH_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppendError {
+    #[error("unable to serialize: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("the writer is too far behind")]
+    Full,
+}
+
+/// Handle to a writer thread. Dropping it waits for every queued line to be written.
+#[derive(Debug)]
+pub struct Appender {
+    queue: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Appender {
+    /// Opens `path` right away, so that errors surface at startup, and starts its writer.
+    /// Write failures are logged and reported to `health`.
+    pub fn open(
+        path: &Path,
+        rotation: RotationPolicy,
+        header: Vec<u8>,
+        health: Arc<Health>,
+    ) -> std::io::Result<Self> {
+        let mut file = RotatingFile::open(path, rotation, header)?;
+        let name = path.display().to_string();
+        let (queue, lines) = mpsc::sync_channel::<Vec<u8>>(QUEUE_SIZE);
+
+        let thread = std::thread::Builder::new()
+            .name(format!("append {name}"))
+            .spawn(move || {
+                while let Ok(mut batch) = lines.recv() {
+                    // Whatever piled up while the last batch was written goes out in one go;
+                    // an idle writer still writes every line as soon as it arrives
+                    while batch.len() < BATCH_BYTES {
+                        match lines.try_recv() {
+                            Ok(line) => batch.extend(line),
+                            Err(_) => break,
+                        }
+                    }
+
+                    let _span = info_span!("sink_write").entered();
+                    let result = file.write_all(&batch);
+                    Metrics::get().record_sink_write(result.is_ok());
+                    health.record_sink(&result);
+                    if let Err(e) = result {
+                        error!("Unable to write to {name}: {e}");
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            queue: Some(queue),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues `line`, which must include its line terminator.
+    pub fn append(&self, line: Vec<u8>) -> Result<(), AppendError> {
+        let queue = self.queue.as_ref().expect("only taken on drop");
+        match queue.try_send(line) {
+            Ok(()) => Ok(()),
+            // The writer thread never exits while its queue is open
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => Err(AppendError::Full),
+        }
+    }
+}
+
+impl Drop for Appender {
+    fn drop(&mut self) {
+        // Closing the queue lets the writer finish what is left and exit
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Archives the firehose to S3-compatible object storage, one object per hour.
+//!
+//! Objects are named `<prefix>YYYY/MM/DD/HHMMSS.<ndjson|frames>[.gz|.zst]` after the time they were
+//! started, and uploaded in parts as they fill up so no hour is ever held in memory. The object
+//! being written is completed once the archiver is dropped, and its upload aborted when a part
+//! fails, so no parts are left behind in the bucket.
+
+use std::{io::Write, str::FromStr, sync::Arc};
+
+use bytes::Bytes;
+use chrono::{DateTime, Timelike, Utc};
+use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore, WriteMultipart};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    compress::{Compression, Encoder},
+    config::Config,
+    task,
+};
+
+/// Records waiting to be archived before new ones are dropped
+const QUEUE_SIZE: usize = 100_000;
+/// S3 requires every part but the last to be at least 5 MiB
+const PART_SIZE: usize = 8 * 1024 * 1024;
+/// Parts uploading concurrently per object
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("object store error: {0}")]
+    Store(#[from] object_store::Error),
+    #[error("unable to compress: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// What each archived object contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// One JSON object per repo operation, as served by `/subscribe`
+    Ndjson,
+    /// Frames exactly as received: concatenated header and body DAG-CBOR values
+    Frames,
+}
+
+impl ArchiveFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Ndjson => "ndjson",
+            Self::Frames => "frames",
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(Self::Ndjson),
+            "frames" => Ok(Self::Frames),
+            other => Err(format!("expected \"ndjson\" or \"frames\", got {other:?}")),
+        }
+    }
+}
+
+/// Archives records in the background. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Archiver {
+    format: ArchiveFormat,
+    queue: mpsc::Sender<Bytes>,
+}
+
+impl Archiver {
+    /// Starts archiving as described by `config`, or returns `None` if archiving is disabled.
+    /// Credentials and region come from the standard `AWS_*` environment variables.
+    ///
+    /// As with [`Archiver::spawn`], the returned task ends once every clone of the archiver is
+    /// dropped and the last object is complete; await it before exiting.
+    pub fn from_config(config: &Config) -> Result<Option<(Self, JoinHandle<()>)>, ArchiveError> {
+        let Some(store) = store(config)? else {
+            return Ok(None);
+        };
+        Ok(Some(Self::spawn(
+            store,
+            config.archive_prefix.clone(),
+            config.archive_format,
+            config.archive_compression,
+        )))
+    }
+
+    /// Archives to `store` in the background. The returned task ends once every clone of the
+    /// archiver is dropped and the last object is complete.
+    pub fn spawn(
+        store: Arc<dyn ObjectStore>,
+        prefix: String,
+        format: ArchiveFormat,
+        compression: Compression,
+    ) -> (Self, JoinHandle<()>) {
+        let (queue, records) = mpsc::channel(QUEUE_SIZE);
+        let task = task::spawn("archive", run(store, prefix, format, compression, records));
+        (Self { format, queue }, task)
+    }
+
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Queues one record: a JSON line without its newline, or a raw frame. Records are
+    /// dropped when uploads fall too far behind.
+    pub fn push(&self, record: Bytes) {
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(record) {
+            warn!("Archive uploads are behind, dropping a record");
+        }
+    }
+}
+
+/// The bucket configured by `config`, or `None` if archiving is disabled.
+pub fn store(config: &Config) -> Result<Option<Arc<dyn ObjectStore>>, ArchiveError> {
+    let Some(bucket) = &config.archive_bucket else {
+        return Ok(None);
+    };
+    let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+    if let Some(endpoint) = &config.archive_endpoint {
+        builder = builder
+            .with_allow_http(endpoint.starts_with("http://"))
+            .with_endpoint(endpoint);
+    }
+    Ok(Some(Arc::new(builder.build()?)))
+}
+
+/// The object currently being written.
+struct Object {
+    path: Path,
+    hour: DateTime<Utc>,
+    upload: WriteMultipart,
+    encoder: Encoder<Vec<u8>>,
+}
+
+impl Object {
+    async fn start(
+        store: &dyn ObjectStore,
+        prefix: &str,
+        format: ArchiveFormat,
+        compression: Compression,
+        now: DateTime<Utc>,
+    ) -> Result<Self, ArchiveError> {
+        let path = Path::from(format!(
+            "{prefix}{}.{}{}",
+            now.format("%Y/%m/%d/%H%M%S"),
+            format.extension(),
+            compression.extension()
+        ));
+        let upload = store.put_multipart(&path).await?;
+        Ok(Self {
+            path,
+            hour: hour_of(now),
+            upload: WriteMultipart::new_with_chunk_size(upload, PART_SIZE),
+            encoder: compression.encoder(Vec::new())?,
+        })
+    }
+
+    async fn write(&mut self, data: &[u8], terminator: &[u8]) -> Result<(), ArchiveError> {
+        self.upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+        self.encoder.write_all(data)?;
+        self.encoder.write_all(terminator)?;
+        // Hand over whatever the encoder has produced so far
+        let compressed = std::mem::take(self.encoder.get_mut());
+        self.upload.write(&compressed);
+        Ok(())
+    }
+
+    /// Uploads what's left and completes the object, aborting the upload if a part failed.
+    async fn finish(self) -> Result<Path, ArchiveError> {
+        let Self {
+            path,
+            mut upload,
+            encoder,
+            ..
+        } = self;
+        let written = match encoder.finish() {
+            Ok(rest) => {
+                upload.write(&rest);
+                // Surfaces failed parts while the upload can still be aborted
+                upload
+                    .wait_for_capacity(0)
+                    .await
+                    .map_err(ArchiveError::from)
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = written {
+            abort(upload, &path).await;
+            return Err(e);
+        }
+        // Aborts the upload itself when completing it fails
+        upload.finish().await?;
+        Ok(path)
+    }
+
+    async fn abort(self) {
+        abort(self.upload, &self.path).await;
+    }
+}
+
+async fn abort(upload: WriteMultipart, path: &Path) {
+    if let Err(e) = upload.abort().await {
+        error!("Unable to abort the upload of {path}: {e}");
+    }
+}
+
+async fn complete(object: Object) {
+    match object.finish().await {
+        Ok(path) => info!("Archived {path}"),
+        Err(e) => error!("Unable to complete archive object: {e}"),
+    }
+}
+
+fn hour_of(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.with_minute(0)
+        .and_then(|time| time.with_second(0))
+        .and_then(|time| time.with_nanosecond(0))
+        .expect("the start of an hour always exists in UTC")
+}
+
+async fn run(
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    format: ArchiveFormat,
+    compression: Compression,
+    mut records: mpsc::Receiver<Bytes>,
+) {
+    let mut object: Option<Object> = None;
+    while let Some(record) = records.recv().await {
+        let now = Utc::now();
+        if object
+            .as_ref()
+            .is_some_and(|object| object.hour != hour_of(now))
+        {
+            complete(object.take().expect("checked above")).await;
+        }
+
+        let current = match &mut object {
+            Some(current) => current,
+            None => match Object::start(&*store, &prefix, format, compression, now).await {
+                Ok(started) => object.insert(started),
+                Err(e) => {
+                    error!("Unable to start archive object: {e}");
+                    continue;
+                }
+            },
+        };
+        let terminator: &[u8] = match format {
+            ArchiveFormat::Ndjson => b"\n",
+            ArchiveFormat::Frames => b"",
+        };
+        if let Err(e) = current.write(&record, terminator).await {
+            error!("Unable to archive to {}: {e}", current.path);
+            // The upload is unusable after a failed part; start over with a new object
+            object.take().expect("written to above").abort().await;
+        }
+    }
+    // Every archiver is gone, so nothing more is coming for the current object
+    if let Some(last) = object {
+        complete(last).await;
+    }
+}
@@ -0,0 +1,180 @@
+//! Atom feed of the most recent findings at `/feed.atom`, for following the listener in a feed
+//! reader: every detected haiku, and posts given one of the configured classifier labels.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use atrium_api::app::bsky::feed::post;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    classify::Label,
+    client::{glob_match, Event},
+    gallery::escape,
+    haiku::HaikuRecord,
+};
+
+const FEED_ID: &str = "tag:bsky-firehose-listener,2024:findings";
+
+#[derive(Debug, Clone)]
+pub struct FeedEntry {
+    /// The post's `at://` URI
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub author: String,
+    pub author_url: String,
+    /// Shown with line breaks kept
+    pub lines: Vec<String>,
+    /// When the post was found
+    pub updated: DateTime<Utc>,
+}
+
+impl FeedEntry {
+    pub fn haiku(haiku: &HaikuRecord) -> Self {
+        let author = match haiku.display_handle() {
+            Some(handle) => format!("@{handle}"),
+            None => haiku.did.clone(),
+        };
+        Self {
+            id: haiku.uri.clone(),
+            title: format!("A {} by {author}", haiku.form),
+            url: haiku.url.clone(),
+            author_url: format!("https://bsky.app/profile/{}", haiku.did),
+            author,
+            lines: haiku.lines.clone(),
+            updated: Utc::now(),
+        }
+    }
+
+    /// A post given `labels`, of which `matched` are the ones the feed includes.
+    pub fn labeled(evt: &Event, record: &post::Record, matched: &[&Label]) -> Self {
+        let did = evt.repo.as_str();
+        let names = matched
+            .iter()
+            .map(|label| label.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Self {
+            id: format!("at://{did}/{}/{}", evt.collection, evt.rkey),
+            title: format!("Labelled {names} by {did}"),
+            url: format!("https://bsky.app/profile/{did}/post/{}", evt.rkey),
+            author: did.to_string(),
+            author_url: format!("https://bsky.app/profile/{did}"),
+            lines: record.text.lines().map(str::to_string).collect(),
+            updated: Utc::now(),
+        }
+    }
+}
+
+/// The latest entries, newest first. Kept in memory only, so the feed starts empty.
+#[derive(Debug)]
+pub struct AtomFeed {
+    capacity: usize,
+    /// Label globs whose posts are included besides haikus
+    labels: Vec<String>,
+    entries: Mutex<VecDeque<FeedEntry>>,
+}
+
+impl AtomFeed {
+    pub fn new(capacity: usize, labels: Vec<String>) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            labels,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether posts need classifying for the feed.
+    pub fn wants_labels(&self) -> bool {
+        !self.labels.is_empty()
+    }
+
+    pub fn push(&self, entry: FeedEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(self.capacity);
+    }
+
+    /// Adds `haiku`, unless moderation held it back.
+    pub fn push_haiku(&self, haiku: &HaikuRecord) {
+        if !haiku.held_back {
+            self.push(FeedEntry::haiku(haiku));
+        }
+    }
+
+    /// Adds the post if any of `labels` is included in the feed.
+    pub fn push_labeled(&self, evt: &Event, record: &post::Record, labels: &[Label]) {
+        let matched = labels
+            .iter()
+            .filter(|label| {
+                self.labels
+                    .iter()
+                    .any(|pattern| glob_match(pattern, &label.name))
+            })
+            .collect::<Vec<_>>();
+        if !matched.is_empty() {
+            self.push(FeedEntry::labeled(evt, record, &matched));
+        }
+    }
+
+    pub fn routes(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/feed.atom", get(feed))
+            .with_state(self)
+    }
+
+    pub fn render(&self) -> String {
+        let entries = self.entries.lock().unwrap();
+        let updated = entries
+            .front()
+            .map_or_else(Utc::now, |entry| entry.updated)
+            .to_rfc3339();
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        let _ = writeln!(xml, "  <id>{FEED_ID}</id>");
+        xml.push_str("  <title>Firehose findings</title>\n");
+        let _ = writeln!(xml, "  <updated>{updated}</updated>");
+        for entry in entries.iter() {
+            let content = entry
+                .lines
+                .iter()
+                .map(|line| escape(line))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            xml.push_str("  <entry>\n");
+            let _ = writeln!(xml, "    <id>{}</id>", escape(&entry.id));
+            let _ = writeln!(xml, "    <title>{}</title>", escape(&entry.title));
+            let _ = writeln!(xml, "    <link href=\"{}\"/>", escape(&entry.url));
+            let _ = writeln!(
+                xml,
+                "    <author><name>{}</name><uri>{}</uri></author>",
+                escape(&entry.author),
+                escape(&entry.author_url)
+            );
+            let _ = writeln!(xml, "    <updated>{}</updated>", entry.updated.to_rfc3339());
+            // Escaped again, as HTML inside XML
+            let _ = writeln!(
+                xml,
+                "    <content type=\"html\">{}</content>",
+                escape(&content)
+            );
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+async fn feed(State(feed): State<Arc<AtomFeed>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        feed.render(),
+    )
+}
@@ -0,0 +1,136 @@
+//! Downloads image blobs referenced by post embeds from the author's PDS.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tokio::{io::AsyncWriteExt, sync::Semaphore};
+use tracing::warn;
+
+use crate::{
+    embed::{Embed, Media},
+    identity::{self, IdentityError},
+};
+
+/// Resolved PDS endpoints are forgotten once this many are cached
+const PDS_CACHE_SIZE: usize = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BlobError {
+    #[error("unable to resolve PDS: {0}")]
+    Identity(#[from] IdentityError),
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("unable to save blob: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("blob is larger than {0} bytes")]
+    TooLarge(u64),
+}
+
+/// Saves blobs to a directory as `<cid>.<extension>`, with a cap on size and concurrent
+/// downloads.
+pub struct BlobFetcher {
+    http: reqwest::Client,
+    dir: PathBuf,
+    max_size: u64,
+    permits: Semaphore,
+    pds_cache: Mutex<HashMap<String, String>>,
+}
+
+impl BlobFetcher {
+    pub fn new(
+        http: reqwest::Client,
+        dir: PathBuf,
+        max_size: u64,
+        concurrency: usize,
+    ) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            http,
+            dir,
+            max_size,
+            permits: Semaphore::new(concurrency.max(1)),
+            pds_cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Downloads every image in `embed`, returning the local paths of those that succeeded.
+    pub async fn fetch_images(&self, did: &str, embed: &Embed) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for image in embed.images() {
+            match self.fetch(did, image).await {
+                Ok(path) => paths.push(path),
+                Err(e) => warn!("Unable to fetch blob {} of {did}: {e}", image.cid),
+            }
+        }
+        paths
+    }
+
+    pub async fn fetch(&self, did: &str, media: &Media) -> Result<PathBuf, BlobError> {
+        let extension = media.mime_type.rsplit('/').next().unwrap_or("bin");
+        let path = self.dir.join(format!("{}.{extension}", media.cid));
+        // Blobs are content-addressed, so an existing file is already the right one
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(path);
+        }
+        if media.size.is_some_and(|size| size as u64 > self.max_size) {
+            return Err(BlobError::TooLarge(self.max_size));
+        }
+
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("Semaphore is never closed");
+        let pds = self.pds(did).await?;
+        let mut response = self
+            .http
+            .get(format!("{pds}/xrpc/com.atproto.sync.getBlob"))
+            .query(&[("did", did), ("cid", &media.cid)])
+            .send()
+            .await?
+            .error_for_status()?;
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_size)
+        {
+            return Err(BlobError::TooLarge(self.max_size));
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            data.extend_from_slice(&chunk);
+            if data.len() as u64 > self.max_size {
+                return Err(BlobError::TooLarge(self.max_size));
+            }
+        }
+        write_atomically(&path, &data).await?;
+
+        Ok(path)
+    }
+
+    async fn pds(&self, did: &str) -> Result<String, BlobError> {
+        if let Some(pds) = self.pds_cache.lock().unwrap().get(did) {
+            return Ok(pds.clone());
+        }
+
+        let pds = identity::resolve_pds(&self.http, did).await?;
+        let mut cache = self.pds_cache.lock().unwrap();
+        if cache.len() >= PDS_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(did.to_string(), pds.clone());
+        Ok(pds)
+    }
+}
+
+/// Writes to a temporary file first so a crash never leaves a truncated blob behind.
+async fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("part");
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await
+}
@@ -0,0 +1,120 @@
+//! Content-addressed block store: every block seen in a commit's CAR file, kept on disk by
+//! CID so records, `tooBig` commits and whole repos can be read back later without going
+//! to the PDS again.
+//!
+//! Blocks are files named by their CID, spread over subdirectories named by the CID's last
+//! two characters so no directory grows too large. Blocks never change once written.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, warn};
+
+use crate::{
+    frame::{self, Frame},
+    task,
+};
+
+/// Frames waiting for their blocks to be stored before new ones are dropped
+const QUEUE_SIZE: usize = 10_000;
+
+/// Blocks stored in a directory, keyed by CID string.
+#[derive(Debug)]
+pub struct BlockStore {
+    dir: PathBuf,
+}
+
+impl BlockStore {
+    pub fn open(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, cid: &str) -> PathBuf {
+        let shard = cid.get(cid.len().saturating_sub(2)..).unwrap_or(cid);
+        self.dir.join(shard).join(cid)
+    }
+
+    pub fn get(&self, cid: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(cid)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn contains(&self, cid: &str) -> bool {
+        self.path(cid).exists()
+    }
+
+    /// Stores `data` under `cid`, returning whether it was new. Blocks are written to a
+    /// temporary file first, so a crash never leaves a partial block behind.
+    pub fn put(&self, cid: &str, data: &[u8]) -> std::io::Result<bool> {
+        let path = self.path(cid);
+        if path.exists() {
+            return Ok(false);
+        }
+        let dir = path.parent().expect("block paths are in a shard directory");
+        std::fs::create_dir_all(dir)?;
+        let partial = dir.join(format!(".{cid}.partial"));
+        std::fs::write(&partial, data)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(true)
+    }
+}
+
+/// Stores the blocks of every commit frame pushed to it, in the background. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct BlockWriter {
+    queue: mpsc::Sender<Bytes>,
+}
+
+impl BlockWriter {
+    pub fn spawn(store: Arc<BlockStore>) -> Self {
+        let (queue, frames) = mpsc::channel(QUEUE_SIZE);
+        task::spawn("block-store", run(store, frames));
+        Self { queue }
+    }
+
+    /// Queues a raw frame; frames other than commits are ignored. Frames are dropped when
+    /// writes fall too far behind.
+    pub fn push(&self, frame: Bytes) {
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(frame) {
+            warn!("Block store is behind, dropping a frame");
+        }
+    }
+}
+
+async fn run(store: Arc<BlockStore>, mut frames: mpsc::Receiver<Bytes>) {
+    while let Some(data) = frames.recv().await {
+        let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+            continue;
+        };
+        let blocks = match frame::blocks(&commit).await {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!("Not storing blocks of commit {}: {e}", commit.seq);
+                continue;
+            }
+        };
+        let store = store.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            for (cid, block) in blocks.iter() {
+                store.put(&cid.to_string(), block)?;
+            }
+            Ok::<_, std::io::Error>(())
+        })
+        .await
+        .expect("block writes don't panic");
+        if let Err(e) = written {
+            error!("Unable to store blocks of commit {}: {e}", commit.seq);
+        }
+    }
+}
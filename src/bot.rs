@@ -0,0 +1,163 @@
+//! Bot mode: likes, reposts or quote-posts detected haikus from a configured account.
+
+use std::{str::FromStr, sync::Arc};
+
+use atrium_api::types::string::Datetime;
+use serde_json::json;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::Config,
+    haiku::HaikuRecord,
+    ratelimit::{RateLimit, RateLimiter},
+    session::Session,
+    task,
+    xrpc::{AuthClient, StrongRef},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotAction {
+    Like,
+    Repost,
+    Quote,
+}
+
+impl FromStr for BotAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "like" => Ok(Self::Like),
+            "repost" => Ok(Self::Repost),
+            "quote" => Ok(Self::Quote),
+            other => Err(format!(
+                "expected \"like\", \"repost\" or \"quote\", got {other:?}"
+            )),
+        }
+    }
+}
+
+/// Queued actions before new ones are dropped
+const QUEUE_SIZE: usize = 100;
+
+/// A haiku the bot acts on.
+struct Target {
+    subject: StrongRef,
+    lines: Vec<String>,
+}
+
+/// Acts on haikus in the background, within its rate limit.
+pub struct Bot {
+    /// The bot account, whose own posts come back through the firehose
+    did: Option<String>,
+    queue: mpsc::Sender<Target>,
+}
+
+impl Bot {
+    /// Builds the bot described by `config`, or `None` if bot mode is disabled. `session` is
+    /// only used outside dry-run mode.
+    pub fn from_config(config: &Config, session: Option<Arc<Session>>) -> Option<Self> {
+        let action = config.bot_action?;
+
+        let client = if config.bot_dry_run {
+            info!("Bot running in dry-run mode, nothing will be posted");
+            None
+        } else {
+            let client = AuthClient::new(session.expect("bot session is logged in"));
+            info!("Bot logged in as {}", client.handle());
+            Some(client)
+        };
+        let limiter = RateLimiter::new(
+            RateLimit::per_hour(config.bot_max_per_hour),
+            config.bot_rate_policy,
+        );
+        let did = session.map(|session| session.did());
+        Some(Self::spawn(action, client, limiter, did))
+    }
+
+    /// Acts on haikus as the account `did` through `client`, or only logs what it would do
+    /// when `client` is `None`.
+    pub fn spawn(
+        action: BotAction,
+        client: Option<AuthClient>,
+        limiter: RateLimiter,
+        did: Option<String>,
+    ) -> Self {
+        let (queue, targets) = mpsc::channel(QUEUE_SIZE);
+        task::spawn("bot", run(action, client, limiter, targets));
+        Self { did, queue }
+    }
+
+    /// Queues an action on `haiku`, returning whether it was queued. Haikus the bot posted
+    /// itself, e.g. its own quotes, are skipped so it never acts on them in a loop, as are
+    /// haikus without a CID and those arriving while the bot is too far behind.
+    pub fn act(&self, haiku: &HaikuRecord) -> bool {
+        if self.did.as_deref() == Some(haiku.did.as_str()) {
+            return false;
+        }
+        let Some(cid) = &haiku.cid else {
+            warn!("Haiku {} has no CID, skipping", haiku.uri);
+            return false;
+        };
+        let target = Target {
+            subject: StrongRef {
+                uri: haiku.uri.clone(),
+                cid: cid.clone(),
+            },
+            lines: haiku.lines.clone(),
+        };
+        match self.queue.try_send(target) {
+            Ok(()) => true,
+            Err(TrySendError::Full(target)) => {
+                warn!("Bot is behind, skipping {}", target.subject.uri);
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// `client` is `None` in dry-run mode, where nothing is ever posted.
+async fn run(
+    action: BotAction,
+    client: Option<AuthClient>,
+    limiter: RateLimiter,
+    mut targets: mpsc::Receiver<Target>,
+) {
+    while let Some(Target { subject, lines }) = targets.recv().await {
+        if !limiter.admit().await {
+            info!("Bot rate limit reached, skipping {}", subject.uri);
+            continue;
+        }
+
+        let uri = subject.uri.clone();
+        let (collection, record) = match action {
+            BotAction::Like => (
+                "app.bsky.feed.like",
+                json!({ "subject": subject, "createdAt": Datetime::now() }),
+            ),
+            BotAction::Repost => (
+                "app.bsky.feed.repost",
+                json!({ "subject": subject, "createdAt": Datetime::now() }),
+            ),
+            BotAction::Quote => (
+                "app.bsky.feed.post",
+                json!({
+                    "text": lines.join("\n"),
+                    "embed": { "$type": "app.bsky.embed.record", "record": subject },
+                    "createdAt": Datetime::now(),
+                }),
+            ),
+        };
+
+        let Some(client) = &client else {
+            info!("[dry run] Would create {collection} for {uri}");
+            continue;
+        };
+        match client.create_record(collection, record).await {
+            Ok(created) => info!("Bot created {}", created.uri),
+            Err(e) => error!("Bot was unable to create {collection}: {e}"),
+        }
+    }
+}
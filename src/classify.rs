@@ -0,0 +1,472 @@
+//! Pluggable text classification: classifiers label post text, and posts with a given label
+//! can be routed to their own output file.
+
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+
+use atrium_api::app::bsky::feed::post;
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    client::{glob_match, Event},
+    config::Config,
+    emoji,
+    haiku::{self, SyllablePattern},
+    jsonl::JsonlWriter,
+    language::LanguageFilter,
+    rhyme,
+    syllables::SyllableCounter,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Label {
+    /// e.g. `haiku` or `lang:eng`
+    pub name: String,
+    /// The text split into lines, for classifiers recognizing a form
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<String>,
+}
+
+impl Label {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            lines: Vec::new(),
+        }
+    }
+}
+
+pub trait TextClassifier: Send + Sync {
+    /// Labels `text`, or returns `None` when it isn't of this classifier's kind.
+    fn classify(&self, text: &str) -> Option<Label>;
+}
+
+/// Labels text in one of a set of syllable-counted forms with the form's name.
+pub struct FormClassifier {
+    forms: Vec<SyllablePattern>,
+    syllables: Arc<SyllableCounter>,
+}
+
+impl FormClassifier {
+    pub fn new(forms: Vec<SyllablePattern>, syllables: Arc<SyllableCounter>) -> Self {
+        Self { forms, syllables }
+    }
+}
+
+impl TextClassifier for FormClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let haiku = haiku::detect(text, &self.forms, &self.syllables)?;
+        Some(Label {
+            name: haiku.form,
+            lines: haiku.lines,
+        })
+    }
+}
+
+/// Labels limericks `limerick`, see [`haiku::detect_limerick`].
+pub struct LimerickClassifier {
+    syllables: Arc<SyllableCounter>,
+}
+
+impl LimerickClassifier {
+    pub fn new(syllables: Arc<SyllableCounter>) -> Self {
+        Self { syllables }
+    }
+}
+
+impl TextClassifier for LimerickClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let limerick = haiku::detect_limerick(text, &self.syllables)?;
+        Some(Label {
+            name: limerick.form,
+            lines: limerick.lines,
+        })
+    }
+}
+
+/// Labels sonnets `sonnet`, see [`haiku::detect_sonnet`].
+pub struct SonnetClassifier {
+    syllables: Arc<SyllableCounter>,
+    check_rhymes: bool,
+}
+
+impl SonnetClassifier {
+    pub fn new(syllables: Arc<SyllableCounter>, check_rhymes: bool) -> Self {
+        Self {
+            syllables,
+            check_rhymes,
+        }
+    }
+}
+
+impl TextClassifier for SonnetClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let sonnet = haiku::detect_sonnet(text, &self.syllables, self.check_rhymes)?;
+        Some(Label {
+            name: sonnet.form,
+            lines: sonnet.lines,
+        })
+    }
+}
+
+/// Labels text whose lines pair up into rhyming couplets as `couplets`: an even number of lines,
+/// the first rhyming with the second, the third with the fourth, and so on.
+pub struct CoupletClassifier {
+    syllables: Arc<SyllableCounter>,
+}
+
+impl CoupletClassifier {
+    pub fn new(syllables: Arc<SyllableCounter>) -> Self {
+        Self { syllables }
+    }
+}
+
+impl TextClassifier for CoupletClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        if lines.is_empty() || lines.len() % 2 != 0 {
+            return None;
+        }
+        let last_words = lines
+            .iter()
+            .map(|line| {
+                line.split_whitespace()
+                    .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+                    .rfind(|word| !word.is_empty())
+            })
+            .collect::<Option<Vec<_>>>()?;
+        last_words
+            .chunks_exact(2)
+            .all(|pair| rhyme::rhymes(&self.syllables, pair[0], pair[1]))
+            .then(|| Label {
+                name: "couplets".to_string(),
+                lines: lines.into_iter().map(str::to_string).collect(),
+            })
+    }
+}
+
+/// Lines, and so letters of the word spelled, an acrostic needs at least
+pub const MIN_ACROSTIC_LINES: usize = 3;
+
+/// Labels text whose lines start with the letters of a listed word, in order, as
+/// `acrostic:<word>`.
+pub struct AcrosticClassifier {
+    words: Vec<String>,
+}
+
+impl AcrosticClassifier {
+    /// Words shorter than [`MIN_ACROSTIC_LINES`] are ignored, as too many posts would spell
+    /// them by chance.
+    pub fn new(words: &[String]) -> Self {
+        Self {
+            words: words
+                .iter()
+                .map(|word| word.trim().to_lowercase())
+                .filter(|word| word.chars().count() >= MIN_ACROSTIC_LINES)
+                .collect(),
+        }
+    }
+}
+
+impl TextClassifier for AcrosticClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        if lines.len() < MIN_ACROSTIC_LINES {
+            return None;
+        }
+        // The first letter of each line, past any leading emoji or punctuation
+        let initials = lines
+            .iter()
+            .map(|line| line.chars().find(|c| c.is_alphabetic()))
+            .collect::<Option<String>>()?
+            .to_lowercase();
+        let word = self.words.iter().find(|word| **word == initials)?;
+        Some(Label {
+            name: format!("acrostic:{word}"),
+            lines: lines.into_iter().map(str::to_string).collect(),
+        })
+    }
+}
+
+/// Labels text reading the same backwards, ignoring case, spaces and punctuation, as
+/// `palindrome`.
+pub struct PalindromeClassifier {
+    min_chars: usize,
+}
+
+impl PalindromeClassifier {
+    /// Text with fewer than `min_chars` letters and digits is never a palindrome, so single
+    /// words like "wow" aren't labelled.
+    pub fn new(min_chars: usize) -> Self {
+        Self { min_chars }
+    }
+}
+
+impl TextClassifier for PalindromeClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let normalized = text
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<Vec<_>>();
+        let palindrome = normalized.len() >= self.min_chars.max(1)
+            && normalized.iter().eq(normalized.iter().rev());
+        palindrome.then(|| Label::new("palindrome"))
+    }
+}
+
+/// What a [`CountForm`] counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountUnit {
+    /// Whitespace-separated words with at least one letter or digit, so dashes and emoji
+    /// between words don't count
+    Words,
+    /// Characters (code points) of the text, leading and trailing whitespace aside
+    Chars,
+}
+
+/// A form defined by its exact length, like six-word stories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountForm {
+    pub name: String,
+    pub count: usize,
+    pub unit: CountUnit,
+}
+
+impl CountForm {
+    fn length(&self, text: &str) -> usize {
+        match self.unit {
+            CountUnit::Words => text
+                .split_whitespace()
+                .filter(|word| word.chars().any(char::is_alphanumeric))
+                .count(),
+            CountUnit::Chars => text.trim().chars().count(),
+        }
+    }
+}
+
+impl FromStr for CountForm {
+    type Err = String;
+
+    /// Parses `name=<count>w` for words or `name=<count>c` for characters, e.g.
+    /// `six-word-story=6w`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, count)) = s.split_once('=') else {
+            return Err(format!("{s:?} is not name=<count>w or name=<count>c"));
+        };
+        let count = count.trim();
+        let (count, unit) = if let Some(count) = count.strip_suffix('w') {
+            (count, CountUnit::Words)
+        } else if let Some(count) = count.strip_suffix('c') {
+            (count, CountUnit::Chars)
+        } else {
+            return Err(format!(
+                "invalid count form {s:?}: count must end in w or c"
+            ));
+        };
+        let count = count
+            .parse::<usize>()
+            .map_err(|e| format!("invalid count form {s:?}: {e}"))?;
+        if name.trim().is_empty() || count == 0 {
+            return Err(format!(
+                "invalid count form {s:?}: needs a name and a count above 0"
+            ));
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            count,
+            unit,
+        })
+    }
+}
+
+/// Labels text of exactly a [`CountForm`]'s length with the form's name, trying forms in order.
+pub struct CountClassifier {
+    forms: Vec<CountForm>,
+}
+
+impl CountClassifier {
+    pub fn new(forms: Vec<CountForm>) -> Self {
+        Self { forms }
+    }
+}
+
+impl TextClassifier for CountClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let form = self
+            .forms
+            .iter()
+            .find(|form| form.length(text) == form.count)?;
+        Some(Label {
+            name: form.name.clone(),
+            lines: text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+/// Labels text made up of nothing but emoji as `emoji-only`, see [`emoji::is_emoji_only`].
+pub struct EmojiOnlyClassifier;
+
+impl TextClassifier for EmojiOnlyClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        emoji::is_emoji_only(text).then(|| Label::new("emoji-only"))
+    }
+}
+
+/// Labels text with its detected language as `lang:<ISO 639-3 code>`, when confident enough.
+pub struct LanguageClassifier {
+    min_confidence: f64,
+}
+
+impl LanguageClassifier {
+    pub fn new(min_confidence: f64) -> Self {
+        Self { min_confidence }
+    }
+}
+
+impl TextClassifier for LanguageClassifier {
+    fn classify(&self, text: &str) -> Option<Label> {
+        let detection = LanguageFilter::detect(text)?;
+        (detection.confidence >= self.min_confidence)
+            .then(|| Label::new(format!("lang:{}", detection.language)))
+    }
+}
+
+/// Sends posts with a label matching `pattern` (a glob, see [`glob_match`]) to `path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelRoute {
+    pub pattern: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for LabelRoute {
+    type Err = String;
+
+    /// Parses `pattern=path`, e.g. `lang:*=languages.jsonl`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((pattern, path)) if !pattern.trim().is_empty() && !path.trim().is_empty() => {
+                Ok(Self {
+                    pattern: pattern.trim().to_string(),
+                    path: PathBuf::from(path.trim()),
+                })
+            }
+            _ => Err(format!("{s:?} is not label=path")),
+        }
+    }
+}
+
+/// A labelled post, as written to route outputs.
+#[derive(Debug, Serialize)]
+struct LabeledPost<'a> {
+    uri: String,
+    url: String,
+    did: &'a str,
+    created_at: &'a str,
+    text: &'a str,
+    labels: &'a [Label],
+}
+
+/// Classifiers run over every post, and where their labels are routed.
+#[derive(Default)]
+pub struct ClassifierRegistry {
+    classifiers: Vec<Box<dyn TextClassifier>>,
+    routes: Vec<(String, JsonlWriter)>,
+}
+
+impl ClassifierRegistry {
+    /// The built-in classifiers enabled in `config`, without any routes.
+    pub fn builtin(config: &Config, syllables: Arc<SyllableCounter>) -> Self {
+        let mut classifiers = Self::default();
+        classifiers
+            .register(FormClassifier::new(config.forms.clone(), syllables.clone()))
+            .register(LanguageClassifier::new(config.min_language_confidence));
+        if config.limericks {
+            classifiers.register(LimerickClassifier::new(syllables.clone()));
+        }
+        if config.couplets {
+            classifiers.register(CoupletClassifier::new(syllables.clone()));
+        }
+        if config.sonnets {
+            classifiers.register(SonnetClassifier::new(syllables, config.sonnet_rhymes));
+        }
+        if !config.acrostic_words.is_empty() {
+            classifiers.register(AcrosticClassifier::new(&config.acrostic_words));
+        }
+        if let Some(min_chars) = config.palindrome_min_chars {
+            classifiers.register(PalindromeClassifier::new(min_chars));
+        }
+        if !config.count_forms.is_empty() {
+            classifiers.register(CountClassifier::new(config.count_forms.clone()));
+        }
+        if config.emoji_only {
+            classifiers.register(EmojiOnlyClassifier);
+        }
+        classifiers
+    }
+
+    pub fn register(&mut self, classifier: impl TextClassifier + 'static) -> &mut Self {
+        self.classifiers.push(Box::new(classifier));
+        self
+    }
+
+    /// Writes posts with a label matching `pattern` to `output`.
+    pub fn route(&mut self, pattern: &str, output: JsonlWriter) -> &mut Self {
+        self.routes.push((pattern.to_string(), output));
+        self
+    }
+
+    /// Whether any labels go anywhere; classifying is pointless otherwise.
+    pub fn is_routed(&self) -> bool {
+        !self.routes.is_empty()
+    }
+
+    /// Every label `text` is given, in registration order.
+    pub fn classify(&self, text: &str) -> Vec<Label> {
+        self.classifiers
+            .iter()
+            .filter_map(|classifier| classifier.classify(text))
+            .collect()
+    }
+
+    /// Classifies `record` and writes it, with all its labels, to each output routed any of
+    /// them.
+    pub fn dispatch(&self, evt: &Event, record: &post::Record) -> Vec<Label> {
+        let labels = self.classify(&record.text);
+        if labels.is_empty() {
+            return labels;
+        }
+
+        let did = evt.repo.as_str();
+        let post = LabeledPost {
+            uri: format!("at://{did}/{}/{}", evt.collection, evt.rkey),
+            url: format!("https://bsky.app/profile/{did}/post/{}", evt.rkey),
+            did,
+            created_at: record.created_at.as_str(),
+            text: &record.text,
+            labels: &labels,
+        };
+        for (pattern, output) in &self.routes {
+            if !labels.iter().any(|label| glob_match(pattern, &label.name)) {
+                continue;
+            }
+            if let Err(e) = output.append(&post) {
+                error!("Unable to write post labelled {pattern}: {e}");
+            }
+        }
+        labels
+    }
+}
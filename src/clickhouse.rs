@@ -0,0 +1,202 @@
+//! Streams repo operations into ClickHouse through its HTTP interface, in batched
+//! `INSERT ... FORMAT JSONEachRow` requests retried in the background.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, info_span, warn, Instrument};
+
+use crate::{client::Event, frame, task, telemetry::Metrics};
+
+/// Operations waiting to be batched before new ones are dropped
+const QUEUE_SIZE: usize = 100_000;
+/// Complete batches waiting to be inserted before new ones are dropped
+const PENDING_BATCHES: usize = 8;
+const MAX_ATTEMPTS: u32 = 8;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClickHouseError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("ClickHouse returned {status}: {message}")]
+    Server {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ClickHouseConfig {
+    /// HTTP interface, e.g. `http://localhost:8123`
+    pub url: String,
+    pub table: String,
+    pub user: String,
+    pub password: String,
+    /// Rows per insert
+    pub batch_rows: usize,
+    /// Longest a row waits for its batch to fill up
+    pub flush_interval: Duration,
+}
+
+/// One row of the events table.
+#[derive(Debug, Serialize)]
+struct Row {
+    seq: i64,
+    repo: String,
+    rev: String,
+    action: String,
+    collection: String,
+    rkey: String,
+    cid: Option<String>,
+    /// The record as atproto JSON, in a `String` column
+    record: Option<String>,
+    /// Unix milliseconds, for a `DateTime64(3)` column
+    indexed_at: i64,
+}
+
+/// Batches rows and inserts them in the background. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct ClickHouse {
+    queue: mpsc::Sender<Row>,
+}
+
+impl ClickHouse {
+    pub fn spawn(http: reqwest::Client, config: ClickHouseConfig) -> Self {
+        let (queue, rows) = mpsc::channel(QUEUE_SIZE);
+        let (batches, pending) = mpsc::channel(PENDING_BATCHES);
+        task::spawn(
+            "clickhouse/batch",
+            collect(rows, batches, config.batch_rows, config.flush_interval),
+        );
+        task::spawn("clickhouse/insert", insert_all(http, config, pending));
+        Self { queue }
+    }
+
+    /// Queues `evt` for insertion. Operations are dropped when ClickHouse falls too far behind.
+    pub fn push(&self, evt: &Event) {
+        let record = match evt.block.as_deref().map(frame::record_json).transpose() {
+            Ok(record) => record.map(|record| record.to_string()),
+            Err(e) => {
+                warn!("Unable to read {}/{}: {e}", evt.collection, evt.rkey);
+                None
+            }
+        };
+        let row = Row {
+            seq: evt.seq,
+            repo: evt.repo.as_str().to_string(),
+            rev: evt.rev.clone(),
+            action: evt.action.clone(),
+            collection: evt.collection.clone(),
+            rkey: evt.rkey.clone(),
+            cid: evt.cid.as_ref().map(|cid| cid.0.to_string()),
+            record,
+            indexed_at: Utc::now().timestamp_millis(),
+        };
+        if let Err(TrySendError::Full(row)) = self.queue.try_send(row) {
+            warn!(
+                "ClickHouse is behind, dropping {}/{}",
+                row.collection, row.rkey
+            );
+        }
+    }
+}
+
+/// Groups rows into `JSONEachRow` bodies of up to `batch_rows` rows, sent at least every
+/// `flush_interval`.
+async fn collect(
+    mut rows: mpsc::Receiver<Row>,
+    batches: mpsc::Sender<Vec<u8>>,
+    batch_rows: usize,
+    flush_interval: Duration,
+) {
+    let mut body = Vec::new();
+    let mut count = 0;
+    let mut flush = tokio::time::interval(flush_interval);
+    loop {
+        let closed = tokio::select! {
+            row = rows.recv() => match row {
+                Some(row) => {
+                    serde_json::to_writer(&mut body, &row).expect("rows are always serializable");
+                    body.push(b'\n');
+                    count += 1;
+                    if count < batch_rows.max(1) {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = flush.tick() => false,
+        };
+
+        if count > 0 {
+            if let Err(TrySendError::Full(_)) = batches.try_send(std::mem::take(&mut body)) {
+                warn!("ClickHouse inserts are behind, dropping {count} rows");
+            }
+            count = 0;
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+async fn insert_all(
+    http: reqwest::Client,
+    config: ClickHouseConfig,
+    mut pending: mpsc::Receiver<Vec<u8>>,
+) {
+    let query = format!("INSERT INTO {} FORMAT JSONEachRow", config.table);
+    while let Some(body) = pending.recv().await {
+        // Makes a retried insert that did reach ClickHouse a no-op on deduplicating tables
+        let token = data_encoding::HEXLOWER.encode(&Sha256::digest(&body));
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let result = insert(&http, &config, &query, &token, body.clone())
+                .instrument(info_span!("sink_write"))
+                .await;
+            Metrics::get().record_sink_write(result.is_ok());
+            match result {
+                Ok(()) => break,
+                Err(e) if attempt == MAX_ATTEMPTS => {
+                    error!("Giving up on a ClickHouse insert after {attempt} attempts: {e}");
+                }
+                Err(e) => {
+                    warn!("ClickHouse insert failed, retrying in {backoff:?}: {e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+async fn insert(
+    http: &reqwest::Client,
+    config: &ClickHouseConfig,
+    query: &str,
+    token: &str,
+    body: Vec<u8>,
+) -> Result<(), ClickHouseError> {
+    let response = http
+        .post(&config.url)
+        .query(&[("query", query), ("insert_deduplication_token", token)])
+        .header("X-ClickHouse-User", &config.user)
+        .header("X-ClickHouse-Key", &config.password)
+        .body(body)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(ClickHouseError::Server {
+            status,
+            message: response.text().await?.trim().to_string(),
+        });
+    }
+    Ok(())
+}
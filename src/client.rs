@@ -0,0 +1,922 @@
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use atrium_api::{
+    com::atproto::sync::subscribe_repos::{Commit, Identity},
+    types::{string::Did, CidLink},
+};
+use bytes::Bytes;
+use data_encoding::BASE64;
+use futures_util::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{runtime::Handle, task::AbortHandle, time::Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+use crate::{
+    accounts::{AccountStatus, AccountStatuses},
+    config::Config,
+    cursor::InFlight,
+    deadletter::{DeadLetter, DeadLetters, Payload},
+    firehose,
+    frame::{self, ErrorFrame, ErrorKind, Frame, FrameError, RepoSync},
+    health::Health,
+    mst,
+    quarantine::Quarantine,
+    queue::{self, PriorityQueue, QueueLimits},
+    relay::RelayPool,
+    revisions::{RevTracker, RevViolation},
+    seen::SeenOps,
+    shedding::LoadShedder,
+    stats::Stats,
+    task,
+    telemetry::Metrics,
+    watchlist::Watchlist,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Rough memory taken by a queued commit besides its blocks
+const COMMIT_OVERHEAD: usize = 512;
+
+type Handler = Box<dyn Fn(Event) -> BoxFuture<'static, ()> + Send + Sync>;
+type ErrorHandler = Box<dyn Fn(FrameError) -> BoxFuture<'static, ()> + Send + Sync>;
+type ErrorFrameHandler = Box<dyn Fn(ErrorFrame) -> BoxFuture<'static, ()> + Send + Sync>;
+type FrameHandler = Box<dyn Fn(&Bytes) + Send + Sync>;
+type IdentityHandler = Box<dyn Fn(&Identity) + Send + Sync>;
+type SyncHandler = Box<dyn Fn(&RepoSync) + Send + Sync>;
+type RevViolationHandler = Box<dyn Fn(&RevViolation) + Send + Sync>;
+
+/// A single repo operation, delivered to every handler whose pattern matches its collection.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub seq: i64,
+    pub repo: Did,
+    /// Repo revision (a TID) of the commit this operation belongs to
+    pub rev: String,
+    /// Revision of the repo's previous commit, if the relay knows it
+    pub since: Option<String>,
+    /// `create`, `update` or `delete`
+    pub action: String,
+    pub collection: String,
+    pub rkey: String,
+    pub cid: Option<CidLink>,
+    /// Raw DAG-CBOR record; `None` for deletes
+    pub block: Option<Bytes>,
+    /// Status of the repo's account if it was seen going inactive, see [`Client::accounts`]
+    pub account_status: Option<AccountStatus>,
+}
+
+impl Event {
+    /// Span carrying this operation's identifiers, entered while handlers run.
+    pub fn span(&self) -> Span {
+        info_span!(
+            "event",
+            seq = self.seq,
+            repo = self.repo.as_str(),
+            collection = self.collection,
+            rkey = self.rkey
+        )
+    }
+
+    /// Decodes the record carried by this operation, if any.
+    pub fn record<T: DeserializeOwned>(&self) -> Result<Option<T>, FrameError> {
+        self.block
+            .as_deref()
+            .map(serde_ipld_dagcbor::from_slice)
+            .transpose()
+            .map_err(|e| {
+                Metrics::get().record_decode_error("record");
+                FrameError::Record(e.to_string())
+            })
+    }
+
+    /// Serializes this operation as a JSON object with its record in atproto JSON form.
+    pub fn to_json(&self) -> Result<String, FrameError> {
+        #[derive(Serialize)]
+        struct EventJson<'a> {
+            seq: i64,
+            repo: &'a str,
+            rev: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            since: Option<&'a str>,
+            action: &'a str,
+            collection: &'a str,
+            rkey: &'a str,
+            cid: Option<String>,
+            record: Option<serde_json::Value>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            account_status: Option<&'a AccountStatus>,
+        }
+
+        let record = self.block.as_deref().map(frame::record_json).transpose()?;
+        Ok(serde_json::to_string(&EventJson {
+            seq: self.seq,
+            repo: self.repo.as_str(),
+            rev: &self.rev,
+            since: self.since.as_deref(),
+            action: &self.action,
+            collection: &self.collection,
+            rkey: &self.rkey,
+            cid: self.cid.as_ref().map(|cid| cid.0.to_string()),
+            record,
+            account_status: self.account_status.as_ref(),
+        })
+        .expect("event JSON is always serializable"))
+    }
+}
+
+/// Firehose client dispatching repo operations to handlers registered per collection.
+///
+/// ```no_run
+/// # async fn example() {
+/// use bsky_firehose_listener::{client::Client, config::Config};
+///
+/// let mut client = Client::new(Config::from_env());
+/// client.on("app.bsky.graph.*", |evt| async move {
+///     println!("{} {}/{}", evt.action, evt.collection, evt.rkey);
+/// });
+/// client.run().await;
+/// # }
+/// ```
+pub struct Client {
+    config: Config,
+    cursor: Option<i64>,
+    dispatcher: Dispatcher,
+}
+
+/// State shared by every per-frame task.
+#[derive(Default)]
+struct Dispatcher {
+    handlers: Vec<(String, Handler)>,
+    on_error: Option<ErrorHandler>,
+    on_error_frame: Option<ErrorFrameHandler>,
+    on_frame: Vec<FrameHandler>,
+    on_identity: Option<IdentityHandler>,
+    on_sync: Option<SyncHandler>,
+    watchlist: Option<Arc<Watchlist>>,
+    accounts: Option<Arc<AccountStatuses>>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    quarantine: Option<Arc<Quarantine>>,
+    revisions: Option<Arc<RevTracker>>,
+    /// Drops operations already delivered, see [`crate::seen`]
+    seen: Option<SeenOps>,
+    /// Check ops against the commit's MST, see [`crate::mst`]
+    verify_proofs: bool,
+    /// Ops of one commit handled at once
+    commit_parallelism: usize,
+    /// Parse CAR files on the blocking pool
+    decode_blocking: bool,
+    on_rev_violation: Option<RevViolationHandler>,
+    stats: Arc<Stats>,
+    health: Arc<Health>,
+    shedder: Arc<LoadShedder>,
+    /// Commits read but not handled yet, holding the saved cursor back. Not tracked when
+    /// following PDS hosts, whose sequence numbers don't share one order
+    in_flight: Option<InFlight>,
+}
+
+impl Client {
+    pub fn new(config: Config) -> Self {
+        let shedder = LoadShedder::new(config.shed_policy, config.shed_catch_up_lag);
+        let stats = Stats::new(Some(config.lag_warning));
+        let verify_proofs = config.verify_proofs;
+        let commit_parallelism = config.commit_parallelism.max(1);
+        let decode_blocking = config.decode_blocking;
+        let seen = NonZeroUsize::new(config.duplicate_window).map(SeenOps::new);
+        let in_flight = config.pds_hosts.is_empty().then(InFlight::default);
+        Self {
+            config,
+            cursor: None,
+            dispatcher: Dispatcher {
+                shedder: Arc::new(shedder),
+                stats: Arc::new(stats),
+                verify_proofs,
+                commit_parallelism,
+                decode_blocking,
+                seen,
+                in_flight,
+                ..Dispatcher::default()
+            },
+        }
+    }
+
+    /// Registers `handler` for every collection NSID matching `pattern`.
+    ///
+    /// Patterns are either an exact NSID or a glob where `*` matches any run of characters,
+    /// e.g. `app.bsky.graph.*`.
+    pub fn on<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.dispatcher.handlers.push((
+            pattern.to_string(),
+            Box::new(move |evt| handler(evt).boxed()),
+        ));
+        self
+    }
+
+    /// Registers `handler` for frames and CAR files that fail to decode. Errors are logged
+    /// either way.
+    pub fn on_error<F, Fut>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(FrameError) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.dispatcher.on_error = Some(Box::new(move |e| handler(e).boxed()));
+        self
+    }
+
+    /// Registers `handler` for error frames sent by the relay, such as `FutureCursor` or
+    /// `ConsumerTooSlow`. The client already reacts to the ones it knows about; this is for
+    /// applications that want to react too.
+    pub fn on_error_frame<F, Fut>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(ErrorFrame) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.dispatcher.on_error_frame = Some(Box::new(move |e| handler(e).boxed()));
+        self
+    }
+
+    /// Registers `handler` for every binary frame as received, before it is decoded. Handlers
+    /// run in the order they were registered.
+    ///
+    /// It runs inline in the websocket read loop, so it must hand the frame off rather than
+    /// do any slow work itself. Cloning the frame to do so is cheap.
+    pub fn on_frame<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Bytes) + Send + Sync + 'static,
+    {
+        self.dispatcher.on_frame.push(Box::new(handler));
+        self
+    }
+
+    /// Registers `handler` for `#identity` frames, sent when a DID's handle or DID document
+    /// may have changed.
+    ///
+    /// Like [`Client::on_frame`], it runs inline in the websocket read loop, so it must hand the
+    /// change off rather than look anything up itself.
+    pub fn on_identity<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Identity) + Send + Sync + 'static,
+    {
+        self.dispatcher.on_identity = Some(Box::new(handler));
+        self
+    }
+
+    /// Registers `handler` for `#sync` frames, sent when a repo's state was reset and earlier
+    /// commits from it no longer describe it. Repos being mirrored should be fetched again.
+    ///
+    /// Like [`Client::on_identity`], it runs inline in the websocket read loop.
+    pub fn on_sync<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&RepoSync) + Send + Sync + 'static,
+    {
+        self.dispatcher.on_sync = Some(Box::new(handler));
+        self
+    }
+
+    /// Checks each repo's commits arrive in rev order against `revisions`, logging and
+    /// counting the ones that don't. Their operations are still dispatched.
+    pub fn validate_revs(&mut self, revisions: Arc<RevTracker>) -> &mut Self {
+        self.dispatcher.revisions = Some(revisions);
+        self
+    }
+
+    /// Registers `handler` for commits out of rev order, see [`Client::validate_revs`].
+    ///
+    /// It runs inline in the repo's worker, before the commit's operations are dispatched.
+    pub fn on_rev_violation<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&RevViolation) + Send + Sync + 'static,
+    {
+        self.dispatcher.on_rev_violation = Some(Box::new(handler));
+        self
+    }
+
+    /// Starts from sequence number `seq` instead of the live tip of the firehose.
+    pub fn cursor(&mut self, seq: i64) -> &mut Self {
+        self.cursor = Some(seq);
+        self
+    }
+
+    /// Drops commits from repos rejected by `watchlist` before anything is decoded.
+    pub fn watchlist(&mut self, watchlist: Arc<Watchlist>) -> &mut Self {
+        self.dispatcher.watchlist = Some(watchlist);
+        self
+    }
+
+    /// Tracks account status from `#account` frames, annotating events from inactive
+    /// accounts with their [`Event::account_status`].
+    pub fn accounts(&mut self, accounts: Arc<AccountStatuses>) -> &mut Self {
+        self.dispatcher.accounts = Some(accounts);
+        self
+    }
+
+    /// Keeps frames that fail to decode or dispatch in `dead_letters`, for
+    /// [`Client::redeliver`].
+    pub fn dead_letters(&mut self, dead_letters: Arc<DeadLetters>) -> &mut Self {
+        self.dispatcher.dead_letters = Some(dead_letters);
+        self
+    }
+
+    /// Writes frames that fail to decode or dispatch to `quarantine`, as received.
+    pub fn quarantine(&mut self, quarantine: Arc<Quarantine>) -> &mut Self {
+        self.dispatcher.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Throughput and lag counters, for handlers that want to record their own lag.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.dispatcher.stats.clone()
+    }
+
+    /// Connection state, for readiness probes and handlers reporting sink failures.
+    pub fn health(&self) -> Arc<Health> {
+        self.dispatcher.health.clone()
+    }
+
+    /// Decides whether handlers should skip expensive work after the relay found us too slow.
+    pub fn load_shedder(&self) -> Arc<LoadShedder> {
+        self.dispatcher.shedder.clone()
+    }
+
+    /// Connects to the firehose and dispatches events, reconnecting (and failing over between
+    /// the configured relays) whenever the connection drops or stalls.
+    ///
+    /// With PDS hosts configured, subscribes to each of them directly instead, each connection
+    /// resuming from its own cursor after a reconnect. Those cursors aren't comparable, so no
+    /// cursor is reported to [`Health`] for saving.
+    ///
+    /// Dropping the returned future stops the reader and workers, releasing the handlers.
+    pub async fn run(self) {
+        let Self {
+            config,
+            cursor,
+            dispatcher,
+        } = self;
+        if let Some(interval) = config.stats_interval {
+            dispatcher.stats.clone().log_every(interval);
+        }
+        let dispatcher = Arc::new(dispatcher);
+        // One queue and worker per shard, so each repo's commits are handled in order while
+        // different repos proceed in parallel
+        let workers = config.workers.max(1);
+        let mut tasks = AbortOnDrop(Vec::with_capacity(workers + 1));
+        let shards = (0..workers)
+            .map(|_| {
+                let limits = QueueLimits {
+                    max_len: Some((config.queue_max_depth / workers).max(1)),
+                    max_bytes: config.queue_max_bytes.map(|bytes| bytes / workers),
+                    overflow: config.queue_overflow,
+                };
+                let queue = Arc::new(PriorityQueue::new(
+                    (config.queue_shed_depth / workers).max(1),
+                    config.queue_shed_policy,
+                    limits,
+                ));
+                let worker = task::spawn("worker", work(queue.clone(), dispatcher.clone()));
+                tasks.0.push(worker.abort_handle());
+                queue
+            })
+            .collect::<Vec<_>>();
+
+        // The reader (with its stall watchdog and pings) runs as its own task, so it shows up
+        // apart from the workers in tokio-console
+        let reader = async move {
+            if config.pds_hosts.is_empty() {
+                let relays = match RelayPool::from_config(&config) {
+                    Ok(relays) => relays,
+                    Err(e) => {
+                        error!("Unable to follow the firehose: {e}");
+                        return;
+                    }
+                };
+                subscribe(relays, cursor, &config, &dispatcher, &shards).await;
+                return;
+            }
+
+            // Sequence numbers are per PDS, so a single cursor can't apply to all of them
+            if cursor.is_some() {
+                warn!("Ignoring the starting cursor, as it can't apply to several PDSes");
+            }
+            let hosts = config.pds_hosts.iter().map(|host| {
+                let relays = RelayPool::new(
+                    vec![firehose::subscribe_url(host)],
+                    config.failover_after,
+                    config.preferred_retry,
+                );
+                subscribe(relays, None, &config, &dispatcher, &shards)
+            });
+            futures_util::future::join_all(hosts).await;
+        };
+        let reader = task::spawn("reader", reader);
+        tasks.0.push(reader.abort_handle());
+        if let Err(e) = reader.await {
+            std::panic::resume_unwind(e.into_panic());
+        }
+    }
+
+    /// Runs dead `letters` through the registered handlers one at a time, instead of
+    /// connecting to the firehose. Frames failing again go back to the
+    /// [dead-letter store](Client::dead_letters), if set.
+    ///
+    /// Returns once every handler has returned; work they handed off may still be running.
+    pub async fn redeliver(self, letters: Vec<DeadLetter>) {
+        let dispatcher = self.dispatcher;
+        for letter in letters {
+            match letter.payload {
+                Payload::Frame(frame) => {
+                    let data = match BASE64.decode(frame.as_bytes()) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            warn!("Skipping dead letter with an invalid frame: {e}");
+                            continue;
+                        }
+                    };
+                    let result = match frame::decode(&data) {
+                        Ok(Frame::Commit(commit)) => dispatcher
+                            .dispatch(&commit)
+                            .await
+                            .map_err(|e| ("dispatch", Some(commit.seq), e)),
+                        // Only commits are dispatched; anything else only needed decoding
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(("decode", None, e)),
+                    };
+                    if let Err((stage, seq, e)) = result {
+                        error!("Unable to redeliver frame ({stage}): {e}");
+                        if let Some(dead_letters) = &dispatcher.dead_letters {
+                            dead_letters.frame(stage, seq, &data, &e);
+                        }
+                    }
+                }
+                Payload::Event(stored) => {
+                    let mut event = match stored.into_event(None) {
+                        Ok(event) => event,
+                        Err(e) => {
+                            warn!("Skipping dead letter with an invalid event: {e}");
+                            continue;
+                        }
+                    };
+                    event.account_status = dispatcher
+                        .accounts
+                        .as_ref()
+                        .and_then(|accounts| accounts.status(event.repo.as_str()));
+                    dispatcher.deliver(event).await;
+                }
+            }
+        }
+    }
+}
+
+/// Aborts the tasks of a [`Client::run`] once it returns or is dropped.
+struct AbortOnDrop(Vec<AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// Follows the firehose of `relays`, forever.
+async fn subscribe(
+    mut relays: RelayPool,
+    cursor: Option<i64>,
+    config: &Config,
+    dispatcher: &Dispatcher,
+    shards: &[Arc<PriorityQueue<Box<Commit>>>],
+) {
+    // Sequence number of the latest commit we've seen, used to resume after a reconnect.
+    // Relays aren't guaranteed to share sequence numbers, but the major ones mirror
+    // bsky.network closely enough for the cursor to be a useful starting point.
+    let cursor = AtomicI64::new(cursor.unwrap_or(0));
+
+    loop {
+        let resume_from = match cursor.load(Ordering::Relaxed) {
+            0 => None,
+            seq => Some(seq),
+        };
+        let stream = match firehose::connect(relays.current(), resume_from, config).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!(
+                    "Unable to connect to {}: {e}. Retrying in {RECONNECT_DELAY:?}",
+                    relays.current()
+                );
+                relays.record_failure();
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!(
+            "Connected to {} (cursor: {resume_from:?}).",
+            relays.current()
+        );
+        let connected_to = relays.current().to_string();
+        dispatcher.health.connected(&connected_to);
+
+        let (mut sink, mut stream) = stream.split();
+        let mut ping = tokio::time::interval(config.ping_interval);
+        let mut last_data = Instant::now();
+        // Our latest ping still waiting for its pong, by payload
+        let mut pending_ping: Option<(u64, Instant)> = None;
+        let mut pings_sent: u64 = 0;
+
+        loop {
+            tokio::select! {
+                msg = stream.next() => {
+                    let Some(msg) = msg else {
+                        info!("Disconnected from Firehose.");
+                        relays.record_failure();
+                        break;
+                    };
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            info!("Error connecting to Firehose: {:?}", e);
+                            continue;
+                        }
+                    };
+
+                    match msg {
+                        Message::Binary(data) => {
+                            // Taken over without a copy, and shared from here on
+                            let data = Bytes::from(data);
+                            last_data = Instant::now();
+                            relays.record_success();
+                            dispatcher.health.record_message();
+                            // Decoded in order so commits reach their shard in order;
+                            // the expensive part (CAR parsing, handlers) happens on the
+                            // shard's worker
+                            handle_frame(&data, &cursor, dispatcher, shards, &config.priorities).await;
+                        }
+                        Message::Ping(_) => {
+                            // tungstenite queues the pong itself; flush it rather than waiting
+                            // for our next ping to carry it out
+                            if let Err(e) = sink.flush().await {
+                                warn!("Unable to answer Firehose ping: {e}");
+                            }
+                        }
+                        Message::Pong(payload) => {
+                            if let Some((id, sent_at)) = pending_ping {
+                                if payload == id.to_be_bytes() {
+                                    let rtt = sent_at.elapsed();
+                                    pending_ping = None;
+                                    debug!("Firehose ping round trip: {rtt:?}");
+                                    dispatcher.health.record_ping_rtt(rtt);
+                                    Metrics::get().record_ping_rtt(rtt);
+                                }
+                            }
+                        }
+                        Message::Close(_) => {
+                            info!("Firehose disconnected us.");
+                        }
+                        _ => {}
+                    }
+                }
+                _ = ping.tick() => {
+                    // Watchdog: the relay may stop sending frames without ever closing the socket
+                    if last_data.elapsed() >= config.stall_timeout {
+                        warn!(
+                            "No data from Firehose for {:?}, reconnecting.",
+                            last_data.elapsed()
+                        );
+                        relays.record_failure();
+                        break;
+                    }
+                    // A connection that stopped answering pings is likely dead even while
+                    // frames are still buffered
+                    if let Some((_, sent_at)) = pending_ping {
+                        if sent_at.elapsed() >= config.pong_timeout {
+                            warn!(
+                                "No pong from Firehose for {:?}, reconnecting.",
+                                sent_at.elapsed()
+                            );
+                            relays.record_failure();
+                            break;
+                        }
+                    }
+                    if relays.should_retry_preferred() {
+                        info!("Retrying preferred relay.");
+                        relays.retry_preferred();
+                        break;
+                    }
+                    if pending_ping.is_none() {
+                        pings_sent += 1;
+                        match sink.send(Message::Ping(pings_sent.to_be_bytes().to_vec())).await {
+                            Ok(()) => pending_ping = Some((pings_sent, Instant::now())),
+                            Err(e) => warn!("Unable to ping Firehose: {e}"),
+                        }
+                    }
+                }
+            }
+        }
+        dispatcher.health.disconnected(&connected_to);
+    }
+}
+
+async fn handle_frame(
+    data: &Bytes,
+    cursor: &AtomicI64,
+    dispatcher: &Dispatcher,
+    shards: &[Arc<PriorityQueue<Box<Commit>>>],
+    priorities: &[(String, i32)],
+) {
+    let metrics = Metrics::get();
+    for on_frame in &dispatcher.on_frame {
+        on_frame(data);
+    }
+    dispatcher.stats.record_frame();
+    metrics.record_frame();
+    let commit = match info_span!("decode_frame").in_scope(|| frame::decode(data)) {
+        Ok(Frame::Commit(commit)) => commit,
+        Ok(Frame::Error(e)) => {
+            match e.error {
+                // Resuming from a cursor the relay doesn't have yet would fail again, so
+                // reconnect to the live tip instead
+                ErrorKind::FutureCursor => {
+                    warn!("Relay rejected our cursor ({e}), resetting it.");
+                    cursor.store(0, Ordering::Relaxed);
+                }
+                ErrorKind::ConsumerTooSlow => {
+                    warn!("Relay says we are too slow ({e}).");
+                    dispatcher.shedder.enter();
+                }
+                ErrorKind::Other(_) => error!("Relay sent an error: {e}"),
+            }
+            if let Some(on_error_frame) = &dispatcher.on_error_frame {
+                on_error_frame(e).await;
+            }
+            return;
+        }
+        Ok(Frame::Account(account)) => {
+            cursor.fetch_max(account.seq, Ordering::Relaxed);
+            dispatcher.finish(account.seq);
+            if let Some(accounts) = &dispatcher.accounts {
+                if let Err(e) = accounts.record(&account) {
+                    error!("Unable to save account status: {e}");
+                }
+            }
+            return;
+        }
+        Ok(Frame::Identity(identity)) => {
+            cursor.fetch_max(identity.seq, Ordering::Relaxed);
+            dispatcher.finish(identity.seq);
+            if let Some(on_identity) = &dispatcher.on_identity {
+                on_identity(&identity);
+            }
+            return;
+        }
+        Ok(Frame::Sync(sync)) => {
+            cursor.fetch_max(sync.seq, Ordering::Relaxed);
+            dispatcher.finish(sync.seq);
+            if let Some(revisions) = &dispatcher.revisions {
+                revisions.reset(sync.did.as_str(), sync.seq, &sync.rev);
+            }
+            if let Some(on_sync) = &dispatcher.on_sync {
+                on_sync(&sync);
+            }
+            return;
+        }
+        // Only going to parse #commit, #sync, #account and #identity
+        Ok(Frame::Other(_)) => return,
+        Err(e) => {
+            error!("Unable to decode frame: {e}");
+            dispatcher.stats.record_decode_error();
+            metrics.record_decode_error(e.kind());
+            if let Some(dead_letters) = &dispatcher.dead_letters {
+                dead_letters.frame("decode", None, data, &e);
+            }
+            if let Some(quarantine) = &dispatcher.quarantine {
+                quarantine.keep(data, None, &e);
+            }
+            dispatcher.report_error(e).await;
+            return;
+        }
+    };
+    cursor.fetch_max(commit.seq, Ordering::Relaxed);
+    if let Some(in_flight) = &dispatcher.in_flight {
+        in_flight.start(commit.seq);
+    }
+    dispatcher.stats.record_commit(&commit);
+    dispatcher.shedder.record_commit(&commit);
+
+    // A commit is as important as its most important operation
+    let priority = commit
+        .ops
+        .iter()
+        .filter_map(|operation| operation.path.split_once('/'))
+        .map(|(collection, _)| queue::priority(priorities, collection))
+        .max()
+        .unwrap_or(0);
+    let repo = commit.repo.as_str().to_string();
+    let shard = &shards[queue::shard(&repo, shards.len())];
+    let bytes = COMMIT_OVERHEAD + commit.blocks.len();
+    let dropped = shard.push(&repo, priority, commit, bytes);
+    if !dropped.is_empty() {
+        dispatcher.stats.record_shed(dropped.len() as u64);
+    }
+    for commit in dropped {
+        dispatcher.finish(commit.seq);
+    }
+}
+
+/// Worker taking commits off `queue`, highest priority first but each repo's in order.
+async fn work(queue: Arc<PriorityQueue<Box<Commit>>>, dispatcher: Arc<Dispatcher>) {
+    loop {
+        let commit = queue.pop().await;
+        if let Err(e) = dispatcher.dispatch(&commit).await {
+            error!("Unable to dispatch commit: {e}");
+            dispatcher.stats.record_decode_error();
+            Metrics::get().record_decode_error(e.kind());
+            if dispatcher.dead_letters.is_some() || dispatcher.quarantine.is_some() {
+                let data = frame::encode_commit(&commit);
+                if let Some(dead_letters) = &dispatcher.dead_letters {
+                    dead_letters.frame("dispatch", Some(commit.seq), &data, &e);
+                }
+                if let Some(quarantine) = &dispatcher.quarantine {
+                    quarantine.keep(&data, Some(commit.seq), &e);
+                }
+            }
+            dispatcher.report_error(e).await;
+        }
+        dispatcher.finish(commit.seq);
+    }
+}
+
+impl Dispatcher {
+    /// Notes that `seq` was handled or dropped, moving the saved cursor past it once nothing
+    /// before it is left in flight.
+    fn finish(&self, seq: i64) {
+        let Some(in_flight) = &self.in_flight else {
+            return;
+        };
+        if let Some(checkpoint) = in_flight.finish(seq) {
+            self.health.record_cursor(checkpoint);
+        }
+    }
+
+    async fn report_error(&self, e: FrameError) {
+        if let Some(on_error) = &self.on_error {
+            on_error(e).await;
+        }
+    }
+
+    /// Runs the handlers matching `event`'s collection.
+    async fn deliver(&self, event: Event) {
+        let span = event.span();
+        for (pattern, handler) in &self.handlers {
+            if glob_match(pattern, &event.collection) {
+                handler(event.clone()).instrument(span.clone()).await;
+            }
+        }
+    }
+
+    async fn dispatch(&self, commit: &Commit) -> Result<(), FrameError> {
+        if let Some(watchlist) = &self.watchlist {
+            if !watchlist.allows(commit.repo.as_str()) {
+                return Ok(());
+            }
+        }
+        if let Some(revisions) = &self.revisions {
+            let violation = revisions.check(
+                commit.repo.as_str(),
+                commit.seq,
+                &commit.rev,
+                commit.since.as_deref(),
+            );
+            if let Some(violation) = violation {
+                warn!(
+                    "Commit {} from {} is out of order ({}): rev {} after {}, since {:?}",
+                    violation.seq,
+                    violation.repo,
+                    violation.kind.name(),
+                    violation.rev,
+                    violation.last_rev,
+                    violation.since
+                );
+                Metrics::get().record_rev_violation(violation.kind.name());
+                if let Some(on_rev_violation) = &self.on_rev_violation {
+                    on_rev_violation(&violation);
+                }
+            }
+        }
+
+        // Skip parsing the CAR file altogether when nobody is listening
+        let matched = commit
+            .ops
+            .iter()
+            .filter_map(|operation| {
+                let (collection, rkey) = operation.path.split_once('/')?;
+                let handlers = self
+                    .handlers
+                    .iter()
+                    .filter(|(pattern, _)| glob_match(pattern, collection))
+                    .map(|(_, handler)| handler)
+                    .collect::<Vec<_>>();
+                if handlers.is_empty() {
+                    return None;
+                }
+                // Relays resend what follows the cursor we resume from
+                if self.seen.as_ref().is_some_and(|seen| {
+                    seen.check(commit.seq, commit.repo.as_str(), &operation.path)
+                }) {
+                    debug!(
+                        "Dropping {} from commit {}, already delivered",
+                        operation.path, commit.seq
+                    );
+                    Metrics::get().record_duplicate();
+                    return None;
+                }
+                Some((operation, collection, rkey, handlers))
+            })
+            .collect::<Vec<_>>();
+        if matched.is_empty() {
+            return Ok(());
+        }
+
+        let account_status = self
+            .accounts
+            .as_ref()
+            .and_then(|accounts| accounts.status(commit.repo.as_str()));
+        let span = info_span!("parse_car", seq = commit.seq);
+        let blocks = if self.decode_blocking {
+            let car = commit.blocks.clone();
+            tokio::task::spawn_blocking(move || {
+                span.in_scope(|| Handle::current().block_on(frame::parse_car(&car)))
+            })
+            .await
+            .expect("CAR parsing doesn't panic")?
+        } else {
+            frame::parse_car(&commit.blocks).instrument(span).await?
+        };
+        if self.verify_proofs {
+            mst::verify(commit, &blocks)?;
+        }
+        // Ops are built in order; with a parallelism of 1 they are also handled in order
+        let ops = matched
+            .into_iter()
+            .map(|(operation, collection, rkey, handlers)| {
+                let block = operation
+                    .cid
+                    .as_ref()
+                    .and_then(|cid| blocks.get(&cid.0).cloned());
+                let event = Event {
+                    seq: commit.seq,
+                    repo: commit.repo.clone(),
+                    rev: commit.rev.clone(),
+                    since: commit.since.clone(),
+                    action: operation.action.clone(),
+                    collection: collection.to_string(),
+                    rkey: rkey.to_string(),
+                    cid: operation.cid.clone(),
+                    block,
+                    account_status: account_status.clone(),
+                };
+                async move {
+                    Metrics::get().record_op(&event.collection);
+                    let span = event.span();
+                    for handler in handlers {
+                        handler(event.clone()).instrument(span.clone()).await;
+                    }
+                }
+            });
+        futures_util::stream::iter(ops)
+            .for_each_concurrent(self.commit_parallelism, |op| op)
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any (possibly empty) run of characters.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut text) = text.strip_prefix(prefix) else {
+        return false;
+    };
+
+    let mut parts = rest.split('*').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return text.ends_with(part);
+        }
+        match text.find(part) {
+            Some(idx) => text = &text[idx + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,692 @@
+//! Runtime configuration, read from `FIREHOSE_*` environment variables.
+
+use std::{
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    time::Duration,
+};
+
+use chrono::NaiveTime;
+
+use crate::{
+    archive::ArchiveFormat,
+    bot::BotAction,
+    classify::{CountForm, LabelRoute},
+    compress::Compression,
+    csv::CsvColumn,
+    embed::EmbedKind,
+    firehose::{self, ExtraHeader, USER_AGENT},
+    haiku::SyllablePattern,
+    notify::NotifyOn,
+    plugin::PluginSpec,
+    proxy::Proxy,
+    queue::OverflowPolicy,
+    ratelimit::{LimitPolicy, RateLimit},
+    rotate::{RotateEvery, RotationPolicy},
+    shedding::ShedPolicy,
+    watchlist::WatchlistMode,
+};
+
+pub const DEFAULT_RELAY: &str = "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Relay endpoints in order of preference
+    pub relays: Vec<String>,
+    /// Fail over to the next relay after this many consecutive failures
+    pub failover_after: u32,
+    /// How long to stay on a fallback relay before retrying the preferred one
+    pub preferred_retry: Duration,
+    /// PDS hosts subscribed to directly instead of the relays, each with its own cursor that
+    /// isn't saved across restarts
+    pub pds_hosts: Vec<String>,
+    /// File the latest cursor is saved to and resumed from on the next start, see
+    /// [`crate::cursor`]; disabled when unset
+    pub cursor_file: Option<PathBuf>,
+    /// Proxy the firehose connection and every HTTP call go through
+    pub proxy: Option<Proxy>,
+    /// `User-Agent` of the firehose connection and HTTP calls
+    pub user_agent: String,
+    /// Extra headers sent when connecting to relays or PDS hosts
+    pub headers: Vec<ExtraHeader>,
+    /// Reconnect when no frame has been received for this long
+    pub stall_timeout: Duration,
+    /// How often a websocket ping is sent to the relay
+    pub ping_interval: Duration,
+    /// Reconnect when a ping goes unanswered for this long
+    pub pong_timeout: Duration,
+    /// Hosts (relays or PDSes) whose repos the `crawl` subcommand backfills
+    pub crawl_hosts: Vec<String>,
+    /// Maximum concurrent repo backfills while crawling
+    pub crawl_concurrency: usize,
+    /// Address the HTTP server (health checks, event re-broadcast) listens on; disabled when unset
+    pub http_addr: Option<SocketAddr>,
+    /// Detect the language of every post for `/stats` and metrics
+    pub language_stats: bool,
+    /// Events buffered per websocket or SSE consumer before slow ones start skipping
+    pub rebroadcast_capacity: usize,
+    /// Address the gRPC server listens on; disabled when unset
+    pub grpc_addr: Option<SocketAddr>,
+    /// Serve the haiku gallery from the HTTP server
+    pub gallery: bool,
+    /// Haikus per gallery page
+    pub gallery_page_size: usize,
+    /// Serve an Atom feed of recent findings from the HTTP server
+    pub feed: bool,
+    /// Entries kept in the feed
+    pub feed_size: usize,
+    /// Classifier label globs whose posts go in the feed besides haikus
+    pub feed_labels: Vec<String>,
+    /// Public hostname the feed generator is reached at; the generator is disabled when unset
+    pub feedgen_hostname: Option<String>,
+    /// DID of the account publishing the feed generator record
+    pub feedgen_publisher: Option<String>,
+    /// Record key of the feed generator record
+    pub feedgen_rkey: String,
+    /// Haikus the feed serves
+    pub feedgen_size: usize,
+    /// Recent replies indexed by parent and thread root; the index is disabled when unset
+    pub thread_index_size: Option<usize>,
+    /// Events buffered per in-process fan-out consumer before slow ones start skipping
+    pub fanout_capacity: usize,
+    /// S3 bucket the firehose is archived to; archiving is disabled when unset
+    pub archive_bucket: Option<String>,
+    /// Prepended to every archive object name
+    pub archive_prefix: String,
+    pub archive_format: ArchiveFormat,
+    pub archive_compression: Compression,
+    /// S3-compatible endpoint (e.g. MinIO) used instead of AWS
+    pub archive_endpoint: Option<String>,
+    /// Directory every block seen in a commit is kept in by CID; disabled when unset
+    pub block_store: Option<PathBuf>,
+    /// Directory each repo's operations are logged to for the repo state mirror; disabled when
+    /// unset
+    pub mirror_dir: Option<PathBuf>,
+    /// SQLite database posts are captured to for full-text search; disabled when unset
+    pub sqlite_path: Option<PathBuf>,
+    /// Serve a GraphQL API over the SQLite capture from the HTTP server
+    pub graphql: bool,
+    /// Directory Parquet files are written to; disabled when unset
+    pub parquet_dir: Option<PathBuf>,
+    /// Collection globs written to Parquet
+    pub parquet_collections: Vec<String>,
+    /// Rows per Parquet file before a new one is started
+    pub parquet_max_rows: usize,
+    /// Age of a Parquet file after which a new one is started
+    pub parquet_max_age: Duration,
+    /// ClickHouse HTTP interface events are inserted through; disabled when unset
+    pub clickhouse_url: Option<String>,
+    pub clickhouse_table: String,
+    pub clickhouse_user: String,
+    pub clickhouse_password: String,
+    /// Collection globs inserted into ClickHouse
+    pub clickhouse_collections: Vec<String>,
+    /// Rows per ClickHouse insert
+    pub clickhouse_batch_rows: usize,
+    /// Longest a row waits for its ClickHouse batch to fill up
+    pub clickhouse_flush_interval: Duration,
+    /// How often throughput and lag statistics are logged; disabled when unset
+    pub stats_interval: Option<Duration>,
+    /// Warn when commits arrive this long after the relay saw them
+    pub lag_warning: Duration,
+    /// Tasks running handlers; commits are sharded between them by repo
+    pub workers: usize,
+    /// Operations of one commit handled at once; above 1, a commit's operations may reach
+    /// handlers out of order
+    pub commit_parallelism: usize,
+    /// Parse commit CAR files on the blocking thread pool instead of the worker task
+    pub decode_blocking: bool,
+    /// Threads running async tasks; one per CPU when unset
+    pub runtime_threads: Option<usize>,
+    /// Most threads running blocking work (file writes, SQLite, decoding) at once
+    pub blocking_threads: usize,
+    /// `(collection glob, priority)` rules; higher priorities are handled first
+    pub priorities: Vec<(String, i32)>,
+    /// Queue depth, across all workers, past which commits of priority 0 or below are shed
+    pub queue_shed_depth: usize,
+    pub queue_shed_policy: ShedPolicy,
+    /// Queued commits of any priority, across all workers, past which queued ones are dropped
+    pub queue_max_depth: usize,
+    /// Approximate memory taken by queued commits, across all workers, past which queued ones
+    /// are dropped
+    pub queue_max_bytes: Option<usize>,
+    /// Which queued commits are dropped past the queue bounds
+    pub queue_overflow: OverflowPolicy,
+    /// What to do with expensive handlers after the relay found us too slow
+    pub shed_policy: ShedPolicy,
+    /// Leave load shedding once the firehose lag is back under this
+    pub shed_catch_up_lag: Duration,
+    /// Only keep posts mentioning one of these keywords (case-insensitive)
+    pub keywords: Vec<String>,
+    /// File with one regex per line; posts matching any of them are kept
+    pub regex_file: Option<PathBuf>,
+    /// Only keep posts tagged with all of these hashtags
+    pub require_tags: Vec<String>,
+    /// Drop posts containing links
+    pub exclude_links: bool,
+    /// Only keep posts with one of these embed kinds; empty keeps every post
+    pub embed_kinds: Vec<EmbedKind>,
+    /// Only keep posts carrying one of these content label globs, see [`crate::labels`]
+    pub content_labels: Vec<String>,
+    /// Drop posts carrying any of these content label globs
+    pub exclude_content_labels: Vec<String>,
+    /// Labelers whose labels count as content labels
+    pub labelers: Vec<String>,
+    /// Subjects labels are kept for, forgetting those labelled the longest ago first
+    pub labeler_capacity: NonZeroUsize,
+    /// Rhai script filtering and transforming posts, see [`crate::script`]
+    pub script: Option<PathBuf>,
+    /// File listing repo DIDs or handles to allow or block
+    pub watchlist: Option<PathBuf>,
+    pub watchlist_mode: WatchlistMode,
+    /// File the status of inactive accounts is kept in; account tracking is disabled when unset
+    pub account_status: Option<PathBuf>,
+    /// Check the records each commit's ops point to are in its MST, rejecting it otherwise
+    pub verify_proofs: bool,
+    /// Check each repo's commits arrive in rev order
+    pub validate_revs: bool,
+    /// Repos whose latest rev is remembered for `validate_revs`
+    pub rev_capacity: NonZeroUsize,
+    /// JSONL file commits out of rev order are appended to
+    pub rev_violations: Option<PathBuf>,
+    /// Recently delivered repo operations remembered to drop ones sent again after a
+    /// reconnect; disabled when 0
+    pub duplicate_window: usize,
+    /// JSONL file detected haikus are appended to
+    pub haiku_output: PathBuf,
+    /// CSV file posts passing the filters are appended to; disabled when unset
+    pub csv_output: Option<PathBuf>,
+    pub csv_columns: Vec<CsvColumn>,
+    /// How the haiku and CSV outputs roll over
+    pub output_rotation: RotationPolicy,
+    /// Syllable patterns to detect, tried in order
+    pub forms: Vec<SyllablePattern>,
+    /// CMU pronouncing dictionary used for syllable counting instead of estimation
+    pub cmudict: Option<PathBuf>,
+    /// Detect limericks alongside the syllable forms
+    pub limericks: bool,
+    /// Label posts made of rhyming couplets `couplets`
+    pub couplets: bool,
+    /// Label posts of fourteen lines of about ten syllables `sonnet`
+    pub sonnets: bool,
+    /// Only label sonnets rhyming like a Shakespearean or Petrarchan one
+    pub sonnet_rhymes: bool,
+    /// Words acrostics are detected for when classifying
+    pub acrostic_words: Vec<String>,
+    /// Letters and digits a post needs to be classified as a palindrome; palindromes aren't
+    /// detected when unset
+    pub palindrome_min_chars: Option<usize>,
+    /// Forms of an exact word or character count posts are labelled with
+    pub count_forms: Vec<CountForm>,
+    /// Label posts made up of nothing but emoji
+    pub emoji_only: bool,
+    /// Where posts given each classifier label are written; classification is disabled when
+    /// empty
+    pub label_routes: Vec<LabelRoute>,
+    /// Score the sentiment of posts passing the filters
+    pub sentiment: bool,
+    /// VADER-format lexicon replacing the built-in one
+    pub sentiment_lexicon: Option<PathBuf>,
+    /// Posts scoring below this are skipped
+    pub min_sentiment: Option<f64>,
+    /// Posts scoring above this are skipped
+    pub max_sentiment: Option<f64>,
+    /// Terms posts are held back from notifications, bot actions and the digest for
+    pub moderation_terms: Vec<String>,
+    /// File of more such terms, one per line
+    pub moderation_lexicon: Option<PathBuf>,
+    /// OpenAI-compatible moderations endpoint posts are also checked with; disabled when unset
+    pub moderation_url: Option<String>,
+    pub moderation_model: Option<String>,
+    pub moderation_api_key: Option<String>,
+    /// File held back posts are appended to with what they matched
+    pub moderation_audit: Option<PathBuf>,
+    /// OpenAI-compatible embeddings endpoint haikus are embedded with; disabled when unset
+    pub embedding_url: Option<String>,
+    pub embedding_model: String,
+    pub embedding_api_key: Option<String>,
+    /// SQLite database the haiku embeddings are kept in
+    pub embedding_index: PathBuf,
+    /// ISO 639-3 codes of languages forms are detected in; empty accepts every language
+    pub languages: Vec<String>,
+    /// Minimum language detection confidence, between 0 and 1
+    pub min_language_confidence: f64,
+    /// Where hashes of detected posts are kept to skip duplicates; dedup is disabled when unset
+    pub dedup_file: Option<PathBuf>,
+    /// How long a detected post's text is remembered
+    pub dedup_retention: Duration,
+    /// Similarity from which posts count as near-duplicates; detection is disabled when unset
+    pub near_duplicate_threshold: Option<f64>,
+    /// Recent posts compared against for near-duplicates
+    pub near_duplicate_capacity: NonZeroUsize,
+    pub skip_near_duplicates: bool,
+    /// Directory image blobs of detected posts are saved to; blob fetching is disabled when
+    /// unset
+    pub blob_dir: Option<PathBuf>,
+    pub blob_max_bytes: u64,
+    /// Maximum concurrent blob downloads
+    pub blob_concurrency: usize,
+    /// How often alt text statistics are logged; disabled when unset
+    pub alt_text_stats_interval: Option<Duration>,
+    /// How often trending hashtags are reported; disabled when unset
+    pub trending_interval: Option<Duration>,
+    /// Hashtags listed per window
+    pub trending_top: usize,
+    /// File trending reports are appended to, one JSON line per window
+    pub trending_output: Option<PathBuf>,
+    /// How often the most used emoji are reported; disabled when unset
+    pub emoji_interval: Option<Duration>,
+    /// Emoji listed per window
+    pub emoji_top: usize,
+    /// File emoji reports are appended to, one JSON line per window
+    pub emoji_output: Option<PathBuf>,
+    /// How often the most liked posts are reported; disabled when unset
+    pub engagement_interval: Option<Duration>,
+    /// Likes and reposts older than this no longer count
+    pub engagement_window: Duration,
+    /// Subject posts tallied at once; the least recently engaged with are forgotten first
+    pub engagement_capacity: NonZeroUsize,
+    /// Posts listed per report
+    pub engagement_top: usize,
+    /// File engagement reports are appended to as JSON lines
+    pub engagement_output: Option<PathBuf>,
+    /// File follows and unfollows are logged to; disabled when unset
+    pub follow_log: Option<PathBuf>,
+    /// File identity changes are logged to; disabled when unset
+    pub identity_log: Option<PathBuf>,
+    /// Check that authors' handles resolve back to their DID before showing them
+    pub verify_handles: bool,
+    /// Look up each changed DID's handle, PDS and keys in its PLC audit log or DID document
+    pub identity_lookups: bool,
+    /// Identity lookups in flight at once
+    pub identity_concurrency: usize,
+    /// Window follow and post rates are measured over
+    pub anomaly_window: Duration,
+    /// Follows a repo may create within the window before it is flagged
+    pub anomaly_max_follows: Option<usize>,
+    /// Posts a repo may create within the window before it is flagged
+    pub anomaly_max_posts: Option<usize>,
+    /// File anomalies are appended to as JSON lines
+    pub anomaly_output: Option<PathBuf>,
+    /// Send anomalies to the notification sinks too
+    pub anomaly_notify: bool,
+    /// WebAssembly plugins and the collections they handle
+    pub plugins: Vec<PluginSpec>,
+    /// Fuel each plugin gets per event
+    pub plugin_fuel: u64,
+    /// Memory each plugin instance may grow to, in bytes
+    pub plugin_max_memory: usize,
+    /// Largest action a plugin may return, in bytes
+    pub plugin_max_action: usize,
+    /// Where records emitted by plugins are written; emits are dropped when unset
+    pub plugin_output: Option<PathBuf>,
+    /// TOML file of pipelines routing events to their own sinks, see [`crate::pipeline`]
+    pub pipelines: Option<PathBuf>,
+    /// File frames and events that fail to decode, dispatch or reach a pipeline sink are kept
+    /// in, for the `redeliver` subcommand; disabled when unset
+    pub dead_letters: Option<PathBuf>,
+    /// Directory frames that fail to decode or dispatch are written to as received, see
+    /// [`crate::quarantine`]; disabled when unset
+    pub quarantine_dir: Option<PathBuf>,
+    /// Frames quarantined per run before the rest are only counted
+    pub quarantine_limit: usize,
+    /// What the bot does with detected haikus; bot mode is disabled when unset
+    pub bot_action: Option<BotAction>,
+    pub bot_pds: String,
+    pub bot_identifier: String,
+    /// App password of the bot account
+    pub bot_password: String,
+    /// File the bot account's session is kept in across restarts; kept in memory only when unset
+    pub bot_session: Option<PathBuf>,
+    pub bot_max_per_hour: NonZeroU32,
+    /// What happens to bot actions over `bot_max_per_hour`
+    pub bot_rate_policy: LimitPolicy,
+    /// Log what the bot would do without logging in or posting anything
+    pub bot_dry_run: bool,
+    /// Time of day (UTC) the bot posts a digest of the day's best haikus; disabled when unset
+    pub digest_time: Option<NaiveTime>,
+    /// Haikus in each digest
+    pub digest_size: usize,
+    /// Text of the digest's first post, see [`crate::digest`]
+    pub digest_template: String,
+    /// Text of each reply quoting a haiku, see [`crate::digest`]
+    pub digest_entry_template: String,
+    /// Log the digest instead of posting it
+    pub digest_dry_run: bool,
+    /// Which posts are sent to notification sinks
+    pub notify_on: NotifyOn,
+    /// Discord webhook notifications are posted to; disabled when unset
+    pub discord_webhook: Option<String>,
+    /// Limit on Discord notifications on top of Discord's own; unlimited when unset
+    pub discord_rate_limit: Option<RateLimit>,
+    pub discord_rate_policy: LimitPolicy,
+    /// Token of the Telegram bot notifications are sent from; disabled when unset
+    pub telegram_token: Option<String>,
+    /// Chat ID or `@channel` username notifications are sent to
+    pub telegram_chat_id: String,
+    /// Telegram message template, see [`crate::notify::Notification::render`]
+    pub telegram_template: String,
+    /// Limit on Telegram messages on top of Telegram's own; unlimited when unset
+    pub telegram_rate_limit: Option<RateLimit>,
+    pub telegram_rate_policy: LimitPolicy,
+    /// Show notifications on the desktop
+    pub desktop_notify: bool,
+    /// Only show desktop notifications mentioning one of these keywords (case-insensitive)
+    pub desktop_keywords: Vec<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            relays: env_list("FIREHOSE_RELAYS", &[DEFAULT_RELAY]),
+            failover_after: env_parse("FIREHOSE_FAILOVER_AFTER", 3),
+            preferred_retry: env_secs("FIREHOSE_PREFERRED_RETRY_SECS", 600),
+            pds_hosts: env_list("FIREHOSE_PDS_HOSTS", &[]),
+            cursor_file: env_opt("FIREHOSE_CURSOR_FILE"),
+            proxy: env_opt("FIREHOSE_PROXY"),
+            user_agent: env_parse("FIREHOSE_USER_AGENT", USER_AGENT.to_string()),
+            headers: firehose::parse_headers(
+                &std::env::var("FIREHOSE_HEADERS").unwrap_or_default(),
+            )
+            .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_HEADERS: {e}")),
+            stall_timeout: env_secs("FIREHOSE_STALL_TIMEOUT_SECS", 30),
+            ping_interval: env_secs("FIREHOSE_PING_INTERVAL_SECS", 10),
+            pong_timeout: env_secs("FIREHOSE_PONG_TIMEOUT_SECS", 20),
+            crawl_hosts: env_list("FIREHOSE_CRAWL_HOSTS", &["https://bsky.network"]),
+            crawl_concurrency: env_parse("FIREHOSE_CRAWL_CONCURRENCY", 8),
+            http_addr: env_opt("FIREHOSE_HTTP_ADDR"),
+            language_stats: env_parse("FIREHOSE_LANGUAGE_STATS", false),
+            rebroadcast_capacity: env_parse("FIREHOSE_REBROADCAST_CAPACITY", 1024),
+            grpc_addr: env_opt("FIREHOSE_GRPC_ADDR"),
+            gallery: env_parse("FIREHOSE_GALLERY", false),
+            gallery_page_size: env_parse("FIREHOSE_GALLERY_PAGE_SIZE", 20),
+            feed: env_parse("FIREHOSE_FEED", false),
+            feed_size: env_parse("FIREHOSE_FEED_SIZE", 50),
+            feed_labels: env_list("FIREHOSE_FEED_LABELS", &[]),
+            feedgen_hostname: env_opt("FIREHOSE_FEEDGEN_HOSTNAME"),
+            feedgen_publisher: env_opt("FIREHOSE_FEEDGEN_PUBLISHER"),
+            feedgen_rkey: env_parse("FIREHOSE_FEEDGEN_RKEY", "haiku".to_string()),
+            feedgen_size: env_parse("FIREHOSE_FEEDGEN_SIZE", 10_000),
+            thread_index_size: env_opt("FIREHOSE_THREAD_INDEX_SIZE"),
+            fanout_capacity: env_parse("FIREHOSE_FANOUT_CAPACITY", 4096),
+            archive_bucket: env_opt("FIREHOSE_ARCHIVE_BUCKET"),
+            archive_prefix: env_parse("FIREHOSE_ARCHIVE_PREFIX", String::new()),
+            archive_format: env_parse("FIREHOSE_ARCHIVE_FORMAT", ArchiveFormat::Ndjson),
+            archive_compression: env_parse("FIREHOSE_ARCHIVE_COMPRESSION", Compression::Gzip),
+            archive_endpoint: env_opt("FIREHOSE_ARCHIVE_ENDPOINT"),
+            block_store: env_opt("FIREHOSE_BLOCK_STORE"),
+            mirror_dir: env_opt("FIREHOSE_MIRROR_DIR"),
+            sqlite_path: env_opt("FIREHOSE_SQLITE_PATH"),
+            graphql: env_parse("FIREHOSE_GRAPHQL", false),
+            parquet_dir: env_opt("FIREHOSE_PARQUET_DIR"),
+            parquet_collections: env_list("FIREHOSE_PARQUET_COLLECTIONS", &["*"]),
+            parquet_max_rows: env_parse("FIREHOSE_PARQUET_MAX_ROWS", 1_000_000),
+            parquet_max_age: env_secs("FIREHOSE_PARQUET_ROTATE_SECS", 60 * 60),
+            clickhouse_url: env_opt("FIREHOSE_CLICKHOUSE_URL"),
+            clickhouse_table: env_parse("FIREHOSE_CLICKHOUSE_TABLE", "firehose_events".to_string()),
+            clickhouse_user: env_parse("FIREHOSE_CLICKHOUSE_USER", "default".to_string()),
+            clickhouse_password: env_parse("FIREHOSE_CLICKHOUSE_PASSWORD", String::new()),
+            clickhouse_collections: env_list("FIREHOSE_CLICKHOUSE_COLLECTIONS", &["*"]),
+            clickhouse_batch_rows: env_parse("FIREHOSE_CLICKHOUSE_BATCH_ROWS", 10_000),
+            clickhouse_flush_interval: env_secs("FIREHOSE_CLICKHOUSE_FLUSH_SECS", 1),
+            stats_interval: env_opt("FIREHOSE_STATS_SECS").map(Duration::from_secs),
+            lag_warning: env_secs("FIREHOSE_LAG_WARNING_SECS", 60),
+            workers: env_parse(
+                "FIREHOSE_WORKERS",
+                std::thread::available_parallelism().map_or(4, |n| n.get()),
+            ),
+            commit_parallelism: env_parse("FIREHOSE_COMMIT_PARALLELISM", 1),
+            decode_blocking: env_parse("FIREHOSE_DECODE_BLOCKING", false),
+            runtime_threads: env_opt("FIREHOSE_RUNTIME_THREADS"),
+            blocking_threads: env_parse("FIREHOSE_BLOCKING_THREADS", 512),
+            priorities: env_list("FIREHOSE_PRIORITIES", &["app.bsky.feed.post=1"])
+                .iter()
+                .map(|rule| {
+                    rule.rsplit_once('=')
+                        .and_then(|(pattern, priority)| {
+                            Some((pattern.trim().to_string(), priority.trim().parse().ok()?))
+                        })
+                        .unwrap_or_else(|| {
+                            panic!("Invalid value for FIREHOSE_PRIORITIES: {rule:?} is not pattern=priority")
+                        })
+                })
+                .collect(),
+            queue_shed_depth: env_parse("FIREHOSE_QUEUE_SHED_DEPTH", 10_000),
+            queue_shed_policy: env_parse("FIREHOSE_QUEUE_SHED_POLICY", ShedPolicy::Skip),
+            queue_max_depth: env_parse("FIREHOSE_QUEUE_MAX_DEPTH", 100_000),
+            queue_max_bytes: env_opt::<usize>("FIREHOSE_QUEUE_MAX_MB").map(|mb| mb << 20),
+            queue_overflow: env_parse("FIREHOSE_QUEUE_OVERFLOW", OverflowPolicy::Oldest),
+            shed_policy: env_parse("FIREHOSE_SHED_POLICY", ShedPolicy::Off),
+            shed_catch_up_lag: env_secs("FIREHOSE_SHED_CATCH_UP_SECS", 10),
+            keywords: env_list("FIREHOSE_KEYWORDS", &[]),
+            regex_file: env_opt("FIREHOSE_REGEX_FILE"),
+            require_tags: env_list("FIREHOSE_REQUIRE_TAGS", &[]),
+            exclude_links: env_parse("FIREHOSE_EXCLUDE_LINKS", false),
+            embed_kinds: env_list("FIREHOSE_EMBED_TYPES", &[])
+                .iter()
+                .map(|kind| {
+                    kind.parse()
+                        .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_EMBED_TYPES: {e}"))
+                })
+                .collect(),
+            content_labels: env_list("FIREHOSE_CONTENT_LABELS", &[]),
+            exclude_content_labels: env_list("FIREHOSE_EXCLUDE_CONTENT_LABELS", &[]),
+            labelers: env_list("FIREHOSE_LABELERS", &[]),
+            labeler_capacity: env_parse(
+                "FIREHOSE_LABELER_CAPACITY",
+                NonZeroUsize::new(1_000_000).unwrap(),
+            ),
+            script: env_opt("FIREHOSE_SCRIPT"),
+            watchlist: env_opt("FIREHOSE_WATCHLIST"),
+            watchlist_mode: env_parse("FIREHOSE_WATCHLIST_MODE", WatchlistMode::Allow),
+            account_status: env_opt("FIREHOSE_ACCOUNT_STATUS"),
+            verify_proofs: env_parse("FIREHOSE_VERIFY_PROOFS", false),
+            validate_revs: env_parse("FIREHOSE_VALIDATE_REVS", false),
+            rev_capacity: env_parse(
+                "FIREHOSE_REV_CAPACITY",
+                NonZeroUsize::new(1_000_000).unwrap(),
+            ),
+            rev_violations: env_opt("FIREHOSE_REV_VIOLATIONS"),
+            duplicate_window: env_parse("FIREHOSE_DUPLICATE_WINDOW", 100_000),
+            haiku_output: env_parse("FIREHOSE_HAIKU_OUTPUT", PathBuf::from("haikus.jsonl")),
+            csv_output: env_opt("FIREHOSE_CSV_OUTPUT"),
+            csv_columns: env_list(
+                "FIREHOSE_CSV_COLUMNS",
+                &["seq", "time", "did", "collection", "rkey", "text"],
+            )
+            .iter()
+            .map(|column| {
+                column
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_CSV_COLUMNS: {e}"))
+            })
+            .collect(),
+            output_rotation: RotationPolicy {
+                every: env_parse("FIREHOSE_OUTPUT_ROTATE", RotateEvery::Never),
+                max_bytes: env_opt("FIREHOSE_OUTPUT_MAX_BYTES"),
+                compression: env_parse("FIREHOSE_OUTPUT_COMPRESSION", Compression::None),
+                retention: env_opt("FIREHOSE_OUTPUT_RETENTION_DAYS")
+                    .map(|days: u64| Duration::from_secs(days * 24 * 60 * 60)),
+            },
+            forms: env_list("FIREHOSE_FORMS", &["haiku=5-7-5"])
+                .iter()
+                .map(|form| {
+                    form.parse()
+                        .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_FORMS: {e}"))
+                })
+                .collect(),
+            cmudict: env_opt("FIREHOSE_CMUDICT"),
+            limericks: env_parse("FIREHOSE_LIMERICKS", false),
+            couplets: env_parse("FIREHOSE_COUPLETS", false),
+            sonnets: env_parse("FIREHOSE_SONNETS", false),
+            sonnet_rhymes: env_parse("FIREHOSE_SONNET_RHYMES", false),
+            acrostic_words: env_list("FIREHOSE_ACROSTIC_WORDS", &[]),
+            palindrome_min_chars: env_opt("FIREHOSE_PALINDROME_MIN_CHARS"),
+            count_forms: env_list("FIREHOSE_COUNT_FORMS", &[])
+                .iter()
+                .map(|form| {
+                    form.parse()
+                        .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_COUNT_FORMS: {e}"))
+                })
+                .collect(),
+            emoji_only: env_parse("FIREHOSE_EMOJI_ONLY", false),
+            label_routes: env_list("FIREHOSE_LABEL_ROUTES", &[])
+                .iter()
+                .map(|route| {
+                    route
+                        .parse()
+                        .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_LABEL_ROUTES: {e}"))
+                })
+                .collect(),
+            sentiment: env_parse("FIREHOSE_SENTIMENT", false),
+            sentiment_lexicon: env_opt("FIREHOSE_SENTIMENT_LEXICON"),
+            min_sentiment: env_opt("FIREHOSE_MIN_SENTIMENT"),
+            max_sentiment: env_opt("FIREHOSE_MAX_SENTIMENT"),
+            moderation_terms: env_list("FIREHOSE_MODERATION_TERMS", &[]),
+            moderation_lexicon: env_opt("FIREHOSE_MODERATION_LEXICON"),
+            moderation_url: env_opt("FIREHOSE_MODERATION_URL"),
+            moderation_model: env_opt("FIREHOSE_MODERATION_MODEL"),
+            moderation_api_key: env_opt("FIREHOSE_MODERATION_API_KEY"),
+            moderation_audit: env_opt("FIREHOSE_MODERATION_AUDIT"),
+            embedding_url: env_opt("FIREHOSE_EMBEDDING_URL"),
+            embedding_model: env_parse("FIREHOSE_EMBEDDING_MODEL", "nomic-embed-text".to_string()),
+            embedding_api_key: env_opt("FIREHOSE_EMBEDDING_API_KEY"),
+            embedding_index: env_parse(
+                "FIREHOSE_EMBEDDING_INDEX",
+                PathBuf::from("embeddings.sqlite"),
+            ),
+            languages: env_list("FIREHOSE_LANGUAGES", &["eng"]),
+            min_language_confidence: env_parse("FIREHOSE_MIN_LANGUAGE_CONFIDENCE", 0.5),
+            dedup_file: env_opt("FIREHOSE_DEDUP_FILE"),
+            dedup_retention: Duration::from_secs(
+                env_parse("FIREHOSE_DEDUP_RETENTION_DAYS", 30) * 24 * 60 * 60,
+            ),
+            near_duplicate_threshold: env_opt("FIREHOSE_NEAR_DUPLICATE_THRESHOLD"),
+            near_duplicate_capacity: env_parse(
+                "FIREHOSE_NEAR_DUPLICATE_CAPACITY",
+                NonZeroUsize::new(50_000).unwrap(),
+            ),
+            skip_near_duplicates: env_parse("FIREHOSE_SKIP_NEAR_DUPLICATES", false),
+            blob_dir: env_opt("FIREHOSE_BLOB_DIR"),
+            blob_max_bytes: env_parse("FIREHOSE_BLOB_MAX_BYTES", 5 * 1024 * 1024),
+            blob_concurrency: env_parse("FIREHOSE_BLOB_CONCURRENCY", 4),
+            alt_text_stats_interval: env_opt("FIREHOSE_ALT_TEXT_STATS_SECS")
+                .map(Duration::from_secs),
+            trending_interval: env_opt("FIREHOSE_TRENDING_SECS").map(Duration::from_secs),
+            trending_top: env_parse("FIREHOSE_TRENDING_TOP", 10),
+            trending_output: env_opt("FIREHOSE_TRENDING_OUTPUT"),
+            emoji_interval: env_opt("FIREHOSE_EMOJI_SECS").map(Duration::from_secs),
+            emoji_top: env_parse("FIREHOSE_EMOJI_TOP", 10),
+            emoji_output: env_opt("FIREHOSE_EMOJI_OUTPUT"),
+            engagement_interval: env_opt("FIREHOSE_ENGAGEMENT_SECS").map(Duration::from_secs),
+            engagement_window: env_secs("FIREHOSE_ENGAGEMENT_WINDOW_SECS", 3600),
+            engagement_capacity: env_parse(
+                "FIREHOSE_ENGAGEMENT_CAPACITY",
+                NonZeroUsize::new(100_000).unwrap(),
+            ),
+            engagement_top: env_parse("FIREHOSE_ENGAGEMENT_TOP", 10),
+            engagement_output: env_opt("FIREHOSE_ENGAGEMENT_OUTPUT"),
+            follow_log: env_opt("FIREHOSE_FOLLOW_LOG"),
+            identity_log: env_opt("FIREHOSE_IDENTITY_LOG"),
+            verify_handles: env_parse("FIREHOSE_VERIFY_HANDLES", true),
+            identity_lookups: env_parse("FIREHOSE_IDENTITY_LOOKUPS", false),
+            identity_concurrency: env_parse("FIREHOSE_IDENTITY_CONCURRENCY", 4),
+            anomaly_window: env_secs("FIREHOSE_ANOMALY_WINDOW_SECS", 60),
+            anomaly_max_follows: env_opt("FIREHOSE_ANOMALY_MAX_FOLLOWS"),
+            anomaly_max_posts: env_opt("FIREHOSE_ANOMALY_MAX_POSTS"),
+            anomaly_output: env_opt("FIREHOSE_ANOMALY_OUTPUT"),
+            anomaly_notify: env_parse("FIREHOSE_ANOMALY_NOTIFY", false),
+            plugins: env_list("FIREHOSE_PLUGINS", &[])
+                .iter()
+                .map(|plugin| {
+                    plugin
+                        .parse()
+                        .unwrap_or_else(|e| panic!("Invalid value for FIREHOSE_PLUGINS: {e}"))
+                })
+                .collect(),
+            plugin_fuel: env_parse("FIREHOSE_PLUGIN_FUEL", 10_000_000),
+            plugin_max_memory: env_parse::<usize>("FIREHOSE_PLUGIN_MAX_MEMORY_MB", 64) << 20,
+            plugin_max_action: env_parse::<usize>("FIREHOSE_PLUGIN_MAX_ACTION_KB", 64) << 10,
+            plugin_output: env_opt("FIREHOSE_PLUGIN_OUTPUT"),
+            pipelines: env_opt("FIREHOSE_PIPELINES"),
+            dead_letters: env_opt("FIREHOSE_DEAD_LETTERS"),
+            quarantine_dir: env_opt("FIREHOSE_QUARANTINE_DIR"),
+            quarantine_limit: env_parse("FIREHOSE_QUARANTINE_LIMIT", 10_000),
+            bot_action: env_opt("FIREHOSE_BOT_ACTION"),
+            bot_pds: env_parse("FIREHOSE_BOT_PDS", "https://bsky.social".to_string()),
+            bot_identifier: env_parse("FIREHOSE_BOT_IDENTIFIER", String::new()),
+            bot_password: env_parse("FIREHOSE_BOT_PASSWORD", String::new()),
+            bot_session: env_opt("FIREHOSE_BOT_SESSION"),
+            bot_max_per_hour: env_parse("FIREHOSE_BOT_MAX_PER_HOUR", NonZeroU32::new(10).unwrap()),
+            bot_rate_policy: env_parse("FIREHOSE_BOT_RATE_POLICY", LimitPolicy::Drop),
+            bot_dry_run: env_parse("FIREHOSE_BOT_DRY_RUN", false),
+            digest_time: env_opt("FIREHOSE_DIGEST_TIME"),
+            digest_size: env_parse("FIREHOSE_DIGEST_SIZE", 5),
+            digest_template: env_parse(
+                "FIREHOSE_DIGEST_TEMPLATE",
+                "Today's best haikus, {shown} of {count} found on {date} 🧵".to_string(),
+            )
+            .replace(r"\n", "\n"),
+            digest_entry_template: env_parse(
+                "FIREHOSE_DIGEST_ENTRY_TEMPLATE",
+                r"#{rank}: {text}\n— {author}".to_string(),
+            )
+            .replace(r"\n", "\n"),
+            digest_dry_run: env_parse("FIREHOSE_DIGEST_DRY_RUN", false),
+            notify_on: env_parse("FIREHOSE_NOTIFY_ON", NotifyOn::Haikus),
+            discord_webhook: env_opt("FIREHOSE_DISCORD_WEBHOOK"),
+            discord_rate_limit: env_opt("FIREHOSE_DISCORD_RATE_LIMIT"),
+            discord_rate_policy: env_parse("FIREHOSE_DISCORD_RATE_POLICY", LimitPolicy::Queue),
+            telegram_token: env_opt("FIREHOSE_TELEGRAM_TOKEN"),
+            telegram_chat_id: env_parse("FIREHOSE_TELEGRAM_CHAT_ID", String::new()),
+            telegram_template: env_parse(
+                "FIREHOSE_TELEGRAM_TEMPLATE",
+                r"{title} by {author}\n{text}\n{url}".to_string(),
+            )
+            .replace(r"\n", "\n"),
+            telegram_rate_limit: env_opt("FIREHOSE_TELEGRAM_RATE_LIMIT"),
+            telegram_rate_policy: env_parse("FIREHOSE_TELEGRAM_RATE_POLICY", LimitPolicy::Queue),
+            desktop_notify: env_parse("FIREHOSE_DESKTOP_NOTIFY", false),
+            desktop_keywords: env_list("FIREHOSE_DESKTOP_KEYWORDS", &[]),
+        }
+    }
+}
+
+/// Reads a comma-separated list, ignoring empty entries.
+fn env_list(name: &str, default: &[&str]) -> Vec<String> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => default.iter().map(|item| item.to_string()).collect(),
+    }
+}
+
+fn env_secs(name: &str, default: u64) -> Duration {
+    Duration::from_secs(env_parse(name, default))
+}
+
+fn env_parse<T>(name: &str, default: T) -> T
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    env_opt(name).unwrap_or(default)
+}
+
+fn env_opt<T>(name: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = std::env::var(name).ok()?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid value for {name}: {e}")),
+    )
+}
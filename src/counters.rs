@@ -0,0 +1,117 @@
+//! Running totals since startup of repo operations by collection and of posts by detected
+//! language, served as JSON on `/stats`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::{language::LanguageFilter, telemetry::Metrics};
+
+/// Counted for posts whose language couldn't be detected (ISO 639-3 for "undetermined")
+const UNDETERMINED: &str = "und";
+
+#[derive(Debug)]
+pub struct Counters {
+    started_at: Instant,
+    collections: Mutex<HashMap<String, u64>>,
+    languages: Mutex<HashMap<String, u64>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CountersReport {
+    pub uptime_secs: f64,
+    /// Repo operations per collection
+    pub collections: BTreeMap<String, u64>,
+    /// Posts whose language was detected
+    pub posts: u64,
+    /// Posts per detected ISO 639-3 code, `und` when undetectable
+    pub languages: BTreeMap<String, LanguageCount>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LanguageCount {
+    pub posts: u64,
+    /// Share of all posts whose language was detected
+    pub fraction: f64,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            collections: Mutex::default(),
+            languages: Mutex::default(),
+        }
+    }
+}
+
+impl Counters {
+    pub fn record_op(&self, collection: &str) {
+        increment(&self.collections, collection);
+    }
+
+    /// Detects the language of a post's `text` and counts it.
+    pub fn record_post(&self, text: &str) {
+        let detection = LanguageFilter::detect(text);
+        let language = detection
+            .as_ref()
+            .map_or(UNDETERMINED, |detection| detection.language.as_str());
+        increment(&self.languages, language);
+        Metrics::get().record_post_language(language);
+    }
+
+    pub fn report(&self) -> CountersReport {
+        let languages = self.languages.lock().unwrap().clone();
+        let posts = languages.values().sum::<u64>();
+        CountersReport {
+            uptime_secs: self.started_at.elapsed().as_secs_f64(),
+            collections: self
+                .collections
+                .lock()
+                .unwrap()
+                .clone()
+                .into_iter()
+                .collect(),
+            posts,
+            languages: languages
+                .into_iter()
+                .map(|(language, count)| {
+                    let fraction = count as f64 / posts as f64;
+                    (
+                        language,
+                        LanguageCount {
+                            posts: count,
+                            fraction,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+fn increment(counts: &Mutex<HashMap<String, u64>>, key: &str) {
+    let mut counts = counts.lock().unwrap();
+    match counts.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(key.to_string(), 1);
+        }
+    }
+}
+
+/// `/stats` returns the current [`CountersReport`].
+pub fn routes(counters: Arc<Counters>) -> Router {
+    Router::new()
+        .route("/stats", get(stats))
+        .with_state(counters)
+}
+
+async fn stats(State(counters): State<Arc<Counters>>) -> impl IntoResponse {
+    Json(counters.report())
+}
@@ -0,0 +1,88 @@
+//! Network-wide crawl: enumerates repos on each host with `com.atproto.sync.listRepos` and
+//! backfills them with bounded concurrency.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+};
+
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::{error, info};
+
+use crate::repo;
+
+/// Backfills every active repo listed by `hosts`, each at most once even when several hosts
+/// list it, running up to `concurrency` backfills at a time.
+///
+/// `backfill` returns the revision it backfilled the repo at, and the returned map holds those
+/// revisions by DID, so live commits already covered by the crawl can be told apart.
+pub async fn crawl<F, Fut>(
+    http: &reqwest::Client,
+    hosts: &[String],
+    concurrency: usize,
+    backfill: F,
+) -> HashMap<String, String>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Option<String>> + Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    let mut seen = HashSet::new();
+    let mut revs = HashMap::new();
+
+    for host in hosts {
+        let host = host.trim_end_matches('/');
+        info!("Listing repos on {host}");
+        let mut cursor = None;
+        loop {
+            let page = match repo::list_repos(http, host, cursor.as_deref()).await {
+                Ok(page) => page,
+                Err(e) => {
+                    error!("Unable to list repos on {host}: {e}");
+                    break;
+                }
+            };
+            let last = page.repos.is_empty();
+            for listed in page.repos {
+                if listed.active == Some(false) || !seen.insert(listed.did.clone()) {
+                    continue;
+                }
+                let permit = permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("Semaphore is never closed");
+                let backfill = backfill(listed.did.clone());
+                tasks.spawn(async move {
+                    let rev = backfill.await;
+                    drop(permit);
+                    (listed.did, rev)
+                });
+                // Collect finished backfills as we go so they don't pile up
+                while let Some(Ok((did, rev))) = tasks.try_join_next() {
+                    revs.extend(rev.map(|rev| (did, rev)));
+                }
+            }
+
+            match page.cursor {
+                Some(next) if !last && cursor.as_ref() != Some(&next) => cursor = Some(next),
+                _ => break,
+            }
+        }
+        info!("Queued {} repos so far", seen.len());
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((did, Some(rev))) = joined {
+            revs.insert(did, rev);
+        }
+    }
+    info!(
+        "Crawl finished: {} of {} repos backfilled",
+        revs.len(),
+        seen.len()
+    );
+    revs
+}
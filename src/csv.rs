@@ -0,0 +1,112 @@
+//! CSV export of posts passing the filters, for quick analysis in a spreadsheet.
+
+use std::{path::Path, str::FromStr, sync::Arc};
+
+use atrium_api::app::bsky::feed::post;
+
+use crate::{
+    appender::{AppendError, Appender},
+    client::Event,
+    health::Health,
+    rotate::RotationPolicy,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    Seq,
+    /// The post's `createdAt`
+    Time,
+    Did,
+    Collection,
+    Rkey,
+    Text,
+}
+
+impl CsvColumn {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Seq => "seq",
+            Self::Time => "time",
+            Self::Did => "did",
+            Self::Collection => "collection",
+            Self::Rkey => "rkey",
+            Self::Text => "text",
+        }
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seq" => Ok(Self::Seq),
+            "time" => Ok(Self::Time),
+            "did" => Ok(Self::Did),
+            "collection" => Ok(Self::Collection),
+            "rkey" => Ok(Self::Rkey),
+            "text" => Ok(Self::Text),
+            other => Err(format!(
+                "expected one of seq, time, did, collection, rkey or text, got {other:?}"
+            )),
+        }
+    }
+}
+
+/// Appends rows to a CSV file, writing the header at the start of every new file.
+#[derive(Debug)]
+pub struct CsvWriter {
+    columns: Vec<CsvColumn>,
+    appender: Appender,
+}
+
+impl CsvWriter {
+    pub fn open(
+        path: &Path,
+        columns: Vec<CsvColumn>,
+        rotation: RotationPolicy,
+        health: Arc<Health>,
+    ) -> std::io::Result<Self> {
+        let header = line(columns.iter().map(|column| column.name()));
+        Ok(Self {
+            appender: Appender::open(path, rotation, header.into_bytes(), health)?,
+            columns,
+        })
+    }
+
+    /// Queues a row for `record` to be written in the background.
+    pub fn append(&self, evt: &Event, record: &post::Record) -> Result<(), AppendError> {
+        let seq = evt.seq.to_string();
+        let row = line(self.columns.iter().map(|column| match column {
+            CsvColumn::Seq => seq.as_str(),
+            CsvColumn::Time => record.created_at.as_str(),
+            CsvColumn::Did => evt.repo.as_str(),
+            CsvColumn::Collection => &evt.collection,
+            CsvColumn::Rkey => &evt.rkey,
+            CsvColumn::Text => &record.text,
+        }));
+        self.appender.append(row.into_bytes())
+    }
+}
+
+/// Formats one CSV line as per RFC 4180.
+fn line<'a>(fields: impl Iterator<Item = &'a str>) -> String {
+    let mut line = fields.map(field).collect::<Vec<_>>().join(",");
+    line.push_str("\r\n");
+    line
+}
+
+fn field(value: &str) -> String {
+    // Spreadsheets evaluate cells starting with these as formulas, which post text must never
+    // be
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
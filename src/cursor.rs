@@ -0,0 +1,155 @@
+//! Where `listen` starts reading the firehose: a cursor given on the command line, the sequence
+//! number a relay was at at a given time, or the cursor saved by a previous run.
+//!
+//! The saved cursor is a file holding a sequence number as text, so it can be exported to or
+//! imported from another instance by copying it, or written by hand. It only moves past a
+//! commit once the commit was handled, so commits still queued or being handled when the
+//! process stops are replayed on the next start.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use tracing::{error, info};
+
+use crate::{config::Config, firehose, health::Health, task};
+
+/// How often the cursor file is rewritten
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A starting point chosen on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartAt {
+    /// `--cursor <seq>`
+    Cursor(i64),
+    /// `--start-from <RFC 3339 time>`
+    Time(DateTime<Utc>),
+}
+
+/// Resolves the cursor to start from: `start` if given, else the one saved in
+/// `config.cursor_file`, else `None` for the live tip.
+pub async fn resolve(start: Option<StartAt>, config: &Config) -> Option<i64> {
+    match start {
+        Some(StartAt::Cursor(seq)) => Some(seq),
+        Some(StartAt::Time(time)) => match firehose::seq_at(config, time).await {
+            Ok((relay, seq)) => {
+                info!("{relay} was at cursor {seq} at {time}");
+                Some(seq)
+            }
+            Err(e) => {
+                error!("Unable to find the cursor for {time}, starting live: {e}");
+                None
+            }
+        },
+        None => {
+            let path = config.cursor_file.as_ref()?;
+            match load(path) {
+                Ok(seq) => seq,
+                Err(e) => {
+                    error!("Unable to read saved cursor from {}: {e}", path.display());
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Reads the cursor saved in `path`, or `None` if nothing was saved yet.
+pub fn load(path: &Path) -> std::io::Result<Option<i64>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    text.trim()
+        .parse()
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Saves `seq` to `path`, through a temporary file so a crash never leaves it half written.
+pub fn save(path: &Path, seq: i64) -> std::io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, format!("{seq}\n"))?;
+    std::fs::rename(&partial, path)
+}
+
+/// Sequence numbers read from the firehose but not handled yet, to find the cursor a restart
+/// can resume from without skipping any of them.
+#[derive(Debug, Default)]
+pub struct InFlight {
+    state: Mutex<InFlightState>,
+}
+
+#[derive(Debug, Default)]
+struct InFlightState {
+    /// How many of each are in flight, as a replay after reconnecting may read one again
+    /// before the first was handled
+    pending: BTreeMap<i64, usize>,
+    /// Latest handled
+    latest: Option<i64>,
+}
+
+impl InFlight {
+    /// Notes that `seq` was read and is waiting to be handled.
+    pub fn start(&self, seq: i64) {
+        *self.state.lock().unwrap().pending.entry(seq).or_default() += 1;
+    }
+
+    /// Notes that `seq` was handled, or dropped, returning the cursor to resume from.
+    pub fn finish(&self, seq: i64) -> Option<i64> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.pending.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                state.pending.remove(&seq);
+            }
+        }
+        state.latest = state.latest.max(Some(seq));
+        state.checkpoint()
+    }
+
+    /// The cursor to resume from: just before the oldest sequence number still in flight, or
+    /// the latest handled when none is.
+    pub fn checkpoint(&self) -> Option<i64> {
+        self.state.lock().unwrap().checkpoint()
+    }
+}
+
+impl InFlightState {
+    fn checkpoint(&self) -> Option<i64> {
+        match self.pending.keys().next() {
+            // Relays replay from the sequence number after the cursor
+            Some(oldest) => Some(oldest - 1),
+            None => self.latest,
+        }
+    }
+}
+
+/// Saves the cursor `health` has handled everything up to, to `path` every few seconds, in the
+/// background.
+pub fn keep_saved(path: PathBuf, health: Arc<Health>) {
+    task::spawn("cursor", async move {
+        let mut saved = None;
+        let mut interval = tokio::time::interval(SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cursor = health.cursor();
+            if cursor == saved {
+                continue;
+            }
+            let Some(seq) = cursor else {
+                continue;
+            };
+            match save(&path, seq) {
+                Ok(()) => saved = cursor,
+                Err(e) => error!("Unable to save cursor to {}: {e}", path.display()),
+            }
+        }
+    });
+}
@@ -0,0 +1,311 @@
+//! Terminal dashboard shown by `--tui`: connection status, event rates per collection, lag,
+//! error counts, the latest haikus and the latest log lines, refreshed every second.
+//!
+//! Log lines are kept for the dashboard instead of being printed, which would draw over it.
+
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, List, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    health::{Health, Report},
+    stats::{Stats, Window},
+};
+
+/// How often the dashboard is redrawn, and the window rates are averaged over
+const REFRESH: Duration = Duration::from_secs(1);
+/// Log lines kept for the log panel
+const LOG_LINES: usize = 200;
+/// Haikus kept for the matches panel
+const MATCHES: usize = 50;
+/// Collections listed in the rates panel
+const TOP_COLLECTIONS: usize = 12;
+
+/// The latest log lines, written by the `tracing` fmt layer. Cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct LogLines(Arc<Mutex<VecDeque<String>>>);
+
+impl LogLines {
+    fn push(&self, line: &str) {
+        let mut lines = self.0.lock().unwrap();
+        if lines.len() == LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogLines {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogWriter {
+            lines: self.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Collects one log event, added to its [`LogLines`] when dropped.
+pub struct LogWriter {
+    lines: LogLines,
+    buf: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        for line in String::from_utf8_lossy(&self.buf).lines() {
+            self.lines.push(line);
+        }
+    }
+}
+
+/// A haiku shown in the matches panel.
+#[derive(Debug, Clone)]
+struct Match {
+    form: String,
+    author: String,
+    text: String,
+}
+
+/// What the dashboard shows besides [`Stats`] and [`Health`].
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    logs: LogLines,
+    matches: Mutex<VecDeque<Match>>,
+}
+
+/// Counters since the dashboard started, as [`Stats`] windows only cover one refresh.
+#[derive(Debug, Default)]
+struct Totals {
+    frames: u64,
+    decode_errors: u64,
+    shed: u64,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where log lines should go while the dashboard is shown, see [`crate::logging::init`].
+    pub fn logs(&self) -> LogLines {
+        self.logs.clone()
+    }
+
+    /// Adds a haiku of `form` by `author` (an `@handle`, or a DID) to the matches panel.
+    pub fn push_match(&self, form: &str, author: &str, text: &str) {
+        let mut matches = self.matches.lock().unwrap();
+        if matches.len() == MATCHES {
+            matches.pop_back();
+        }
+        matches.push_front(Match {
+            form: form.to_string(),
+            author: author.to_string(),
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        });
+    }
+
+    /// Takes over the terminal until `q`, `Esc` or `Ctrl-C` is pressed, then restores it.
+    ///
+    /// Blocks, so run it on a thread of its own. `stats` windows are taken every refresh, so
+    /// they shouldn't also be logged periodically.
+    pub fn run(
+        &self,
+        stats: &Stats,
+        health: &Health,
+        stall_timeout: Duration,
+    ) -> std::io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.run_in(&mut terminal, stats, health, stall_timeout);
+        ratatui::restore();
+        result
+    }
+
+    fn run_in(
+        &self,
+        terminal: &mut DefaultTerminal,
+        stats: &Stats,
+        health: &Health,
+        stall_timeout: Duration,
+    ) -> std::io::Result<()> {
+        let mut totals = Totals::default();
+        let mut window = stats.take();
+        loop {
+            let report = health.report(stall_timeout);
+            terminal.draw(|frame| self.draw(frame, &window, &totals, &report))?;
+
+            // Keys are handled as they come; the dashboard only moves on once a refresh is due
+            let due = std::time::Instant::now() + REFRESH;
+            while let Some(left) = due.checked_duration_since(std::time::Instant::now()) {
+                if !event::poll(left)? {
+                    break;
+                }
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c;
+                    if key.kind == KeyEventKind::Press && quit {
+                        return Ok(());
+                    }
+                }
+            }
+
+            window = stats.take();
+            totals.frames += window.frames;
+            totals.decode_errors += window.decode_errors;
+            totals.shed += window.shed;
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, window: &Window, totals: &Totals, report: &Report) {
+        let [status, middle, logs] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(8),
+            Constraint::Length(10),
+        ])
+        .areas(frame.area());
+        let [left, matches] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(middle);
+        let [rates, errors] =
+            Layout::vertical([Constraint::Min(5), Constraint::Length(7)]).areas(left);
+
+        frame.render_widget(status_line(report), status);
+        frame.render_widget(rate_table(window), rates);
+        frame.render_widget(error_panel(window, totals, report), errors);
+        self.draw_matches(frame, matches);
+        self.draw_logs(frame, logs);
+    }
+
+    fn draw_matches(&self, frame: &mut Frame, area: Rect) {
+        let matches = self.matches.lock().unwrap();
+        let items = matches.iter().map(|found| {
+            Line::from(vec![
+                Span::styled(format!("{} ", found.form), Style::new().fg(Color::Magenta)),
+                Span::styled(format!("{} ", found.author), Style::new().fg(Color::Cyan)),
+                Span::raw(found.text.clone()),
+            ])
+        });
+        let list = List::new(items).block(Block::bordered().title(" Recent haikus "));
+        frame.render_widget(list, area);
+    }
+
+    fn draw_logs(&self, frame: &mut Frame, area: Rect) {
+        let lines = self.logs.0.lock().unwrap();
+        // Only the lines fitting inside the borders, newest last
+        let shown = usize::from(area.height.saturating_sub(2));
+        let items = lines
+            .iter()
+            .skip(lines.len().saturating_sub(shown))
+            .map(|line| Line::raw(line.clone()));
+        let list = List::new(items).block(Block::bordered().title(" Log "));
+        frame.render_widget(list, area);
+    }
+}
+
+fn status_line(report: &Report) -> Paragraph<'static> {
+    let (state, color) = if report.ready {
+        ("connected", Color::Green)
+    } else if report.connections > 0 {
+        ("stalled", Color::Yellow)
+    } else {
+        ("disconnected", Color::Red)
+    };
+    let mut spans = vec![Span::styled(format!("● {state}"), Style::new().fg(color))];
+    if let Some(relay) = &report.relay {
+        spans.push(Span::raw(format!("  {relay}")));
+    }
+    if report.connections > 1 {
+        spans.push(Span::raw(format!(" (+{} more)", report.connections - 1)));
+    }
+    if let Some(cursor) = report.cursor {
+        spans.push(Span::raw(format!("  cursor {cursor}")));
+    }
+    if let Some(rtt) = report.ping_rtt_ms {
+        spans.push(Span::raw(format!("  ping {rtt:.0}ms")));
+    }
+    if let Some(age) = report.last_message_age_secs {
+        spans.push(Span::raw(format!("  last frame {age:.1}s ago")));
+    }
+    Paragraph::new(Line::from(spans))
+        .block(Block::bordered().title(" bsky-firehose-listener — q to quit "))
+}
+
+fn rate_table(window: &Window) -> Table<'static> {
+    let elapsed = window.started_at.elapsed().as_secs_f64().max(f64::EPSILON);
+    let mut collections = window.collections.iter().collect::<Vec<_>>();
+    collections.sort_unstable_by(|a, b| b.1.cmp(a.1));
+
+    let frames = Row::new(vec![
+        "frames".to_string(),
+        format!("{:.1}", window.frames as f64 / elapsed),
+    ])
+    .style(Style::new().fg(Color::Yellow));
+    let rows = std::iter::once(frames).chain(collections.into_iter().take(TOP_COLLECTIONS).map(
+        |(collection, count)| {
+            Row::new(vec![
+                collection.clone(),
+                format!("{:.1}", *count as f64 / elapsed),
+            ])
+        },
+    ));
+    Table::new(rows, [Constraint::Fill(1), Constraint::Length(10)])
+        .header(Row::new(vec!["collection", "per second"]).style(Style::new().fg(Color::DarkGray)))
+        .block(Block::bordered().title(" Events "))
+}
+
+fn error_panel(window: &Window, totals: &Totals, report: &Report) -> Paragraph<'static> {
+    let error_rate = if totals.frames == 0 {
+        0.0
+    } else {
+        totals.decode_errors as f64 / totals.frames as f64 * 100.0
+    };
+    let mut lines = vec![
+        Line::raw(format!(
+            "commit lag     {:.1}s avg, {:.1}s max",
+            window.commit_lag.average_secs(),
+            window.commit_lag.max_secs
+        )),
+        Line::raw(format!(
+            "createdAt lag  {:.1}s avg",
+            window.created_at_lag.average_secs()
+        )),
+        Line::raw(format!(
+            "decode errors  {} ({error_rate:.2}%)",
+            totals.decode_errors
+        )),
+        Line::raw(format!("shed commits   {}", totals.shed)),
+    ];
+    if let Some(error) = &report.sink_error {
+        lines.push(Line::styled(
+            format!("output failing: {error}"),
+            Style::new().fg(Color::Red),
+        ));
+    }
+    Paragraph::new(lines).block(Block::bordered().title(" Lag and errors "))
+}
@@ -0,0 +1,156 @@
+//! Dead-letter store: frames that failed to decode or dispatch and events a sink failed to
+//! deliver, kept with their error so the `redeliver` subcommand can run them through the
+//! handlers again once the cause is fixed.
+//!
+//! Each line is a JSON object with the `stage` that failed (`decode`, `dispatch`, or the
+//! sink's name), the `error`, and either the raw `frame` (base64) or the `event`.
+
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+use atrium_api::types::{string::Did, CidLink};
+use bytes::Bytes;
+use chrono::Utc;
+use data_encoding::BASE64;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{accounts::AccountStatus, client::Event};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub at: String,
+    /// Where it failed: `decode`, `dispatch`, or the name of the sink
+    pub stage: String,
+    pub error: String,
+    pub seq: Option<i64>,
+    #[serde(flatten)]
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    /// A whole websocket message, base64-encoded
+    Frame(String),
+    Event(Box<StoredEvent>),
+}
+
+/// An [`Event`] as stored, with its record block base64-encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredEvent {
+    seq: i64,
+    repo: Did,
+    rev: String,
+    #[serde(default)]
+    since: Option<String>,
+    action: String,
+    collection: String,
+    rkey: String,
+    cid: Option<CidLink>,
+    block: Option<String>,
+}
+
+impl StoredEvent {
+    /// The stored event, with the account status it has now.
+    pub fn into_event(
+        self,
+        account_status: Option<AccountStatus>,
+    ) -> Result<Event, data_encoding::DecodeError> {
+        Ok(Event {
+            seq: self.seq,
+            repo: self.repo,
+            rev: self.rev,
+            since: self.since,
+            action: self.action,
+            collection: self.collection,
+            rkey: self.rkey,
+            cid: self.cid,
+            block: self
+                .block
+                .map(|block| BASE64.decode(block.as_bytes()).map(Bytes::from))
+                .transpose()?,
+            account_status,
+        })
+    }
+}
+
+impl From<&Event> for StoredEvent {
+    fn from(evt: &Event) -> Self {
+        Self {
+            seq: evt.seq,
+            repo: evt.repo.clone(),
+            rev: evt.rev.clone(),
+            since: evt.since.clone(),
+            action: evt.action.clone(),
+            collection: evt.collection.clone(),
+            rkey: evt.rkey.clone(),
+            cid: evt.cid.clone(),
+            block: evt.block.as_deref().map(|block| BASE64.encode(block)),
+        }
+    }
+}
+
+/// Appends dead letters to a file.
+#[derive(Debug)]
+pub struct DeadLetters {
+    file: Mutex<File>,
+}
+
+impl DeadLetters {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Keeps a websocket message that failed at `stage`.
+    pub fn frame(&self, stage: &str, seq: Option<i64>, frame: &[u8], error: &dyn Display) {
+        self.push(DeadLetter {
+            at: Utc::now().to_rfc3339(),
+            stage: stage.to_string(),
+            error: error.to_string(),
+            seq,
+            payload: Payload::Frame(BASE64.encode(frame)),
+        });
+    }
+
+    /// Keeps an event the sink `stage` failed to deliver.
+    pub fn event(&self, stage: &str, evt: &Event, error: &dyn Display) {
+        self.push(DeadLetter {
+            at: Utc::now().to_rfc3339(),
+            stage: stage.to_string(),
+            error: error.to_string(),
+            seq: Some(evt.seq),
+            payload: Payload::Event(Box::new(evt.into())),
+        });
+    }
+
+    fn push(&self, letter: DeadLetter) {
+        let line = serde_json::to_string(&letter).expect("dead letters are always serializable");
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{line}") {
+            error!("Unable to keep dead letter from {}: {e}", letter.stage);
+        }
+    }
+}
+
+/// Reads every dead letter in `path`, skipping malformed lines.
+pub fn read(path: &Path) -> std::io::Result<Vec<DeadLetter>> {
+    let mut letters = Vec::new();
+    for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(letter) => letters.push(letter),
+            Err(e) => warn!("Skipping malformed dead letter on line {}: {e}", i + 1),
+        }
+    }
+    Ok(letters)
+}
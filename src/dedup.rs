@@ -0,0 +1,118 @@
+//! Remembers the text of detected posts so copypasta isn't saved over and over.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    appender::{AppendError, Appender},
+    compress::Compression,
+    health::Health,
+    rotate::{RotateEvery, RotationPolicy},
+};
+
+/// The store is compacted when opened rather than rotated
+const NO_ROTATION: RotationPolicy = RotationPolicy {
+    every: RotateEvery::Never,
+    max_bytes: None,
+    compression: Compression::None,
+    retention: None,
+};
+
+/// Seen text hashes, persisted as an append-only file of `<hash> <unix seconds>` lines.
+///
+/// Entries older than the retention window are forgotten, and dropped from the file when it
+/// is next opened.
+#[derive(Debug)]
+pub struct DedupStore {
+    retention: Duration,
+    seen: Mutex<HashMap<u64, u64>>,
+    appender: Appender,
+}
+
+impl DedupStore {
+    /// Loads the entries of `path` still within `retention`. Write failures later on are
+    /// reported to `health`.
+    pub fn open(path: &Path, retention: Duration, health: Arc<Health>) -> std::io::Result<Self> {
+        let now = unix_now();
+        let mut seen = HashMap::new();
+        if path.exists() {
+            for line in std::fs::read_to_string(path)?.lines() {
+                let Some((hash, seen_at)) = line.split_once(' ') else {
+                    continue;
+                };
+                let (Ok(hash), Ok(seen_at)) = (u64::from_str_radix(hash, 16), seen_at.parse())
+                else {
+                    continue;
+                };
+                if now.saturating_sub(seen_at) < retention.as_secs() {
+                    seen.insert(hash, seen_at);
+                }
+            }
+        }
+
+        // Compact the file down to the entries still within the retention window, replacing it
+        // only once the new one is complete so a crash can't lose them all
+        let mut compacted = String::new();
+        for (hash, seen_at) in &seen {
+            let _ = writeln!(compacted, "{hash:016x} {seen_at}");
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::write(&partial, compacted)?;
+        std::fs::rename(&partial, path)?;
+
+        Ok(Self {
+            retention,
+            seen: Mutex::new(seen),
+            appender: Appender::open(path, NO_ROTATION, Vec::new(), health)?,
+        })
+    }
+
+    /// Records `text`, returning whether it had not been seen within the retention window.
+    /// The entry is written in the background; an error means it is remembered only until the
+    /// next restart.
+    pub fn insert(&self, text: &str) -> Result<bool, AppendError> {
+        let hash = hash(text);
+        let now = unix_now();
+
+        let mut seen = self.seen.lock().unwrap();
+        if let Some(&seen_at) = seen.get(&hash) {
+            if now.saturating_sub(seen_at) < self.retention.as_secs() {
+                return Ok(false);
+            }
+        }
+        seen.insert(hash, now);
+        drop(seen);
+
+        self.appender
+            .append(format!("{hash:016x} {now}\n").into_bytes())?;
+        Ok(true)
+    }
+}
+
+/// Lowercases `text` and collapses all whitespace into single spaces.
+pub fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn hash(text: &str) -> u64 {
+    let digest = Sha256::digest(normalize(text).as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,102 @@
+//! Pops up native desktop notifications through notify-rust. On Linux and the BSDs, clicking
+//! one opens the post in the browser; elsewhere the link is part of the notification text.
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
+
+use crate::{
+    filter::{FilterError, PostFilter},
+    identity::HandleResolver,
+    notify::Notification,
+    task,
+};
+
+/// Notifications waiting to be shown before new ones are dropped
+const QUEUE_SIZE: usize = 20;
+/// How long a notification stays on screen
+const TIMEOUT_MS: u32 = 10_000;
+const APP_NAME: &str = "bsky-firehose-listener";
+
+/// Shows notifications on the desktop in the background, optionally only those mentioning a
+/// watched keyword.
+pub struct Desktop {
+    watch: PostFilter,
+    queue: mpsc::Sender<Notification>,
+}
+
+impl Desktop {
+    /// Shows notifications whose text mentions one of `keywords` (case-insensitive), or all of
+    /// them when `keywords` is empty.
+    pub fn spawn(handles: HandleResolver, keywords: Vec<String>) -> Result<Self, FilterError> {
+        let watch = PostFilter::new(keywords, &[])?;
+        let (queue, notifications) = mpsc::channel(QUEUE_SIZE);
+        task::spawn("desktop", run(handles, notifications));
+        Ok(Self { watch, queue })
+    }
+
+    /// Queues `notification` if it mentions a watched keyword, dropping it if the desktop is
+    /// too far behind.
+    pub fn notify(&self, notification: Notification) {
+        if self.watch.matches(&notification.text).is_none() {
+            return;
+        }
+        if let Err(TrySendError::Full(notification)) = self.queue.try_send(notification) {
+            warn!(
+                "Desktop notifications are behind, dropping notification for {}",
+                notification.url
+            );
+        }
+    }
+}
+
+async fn run(handles: HandleResolver, mut notifications: mpsc::Receiver<Notification>) {
+    while let Some(mut notification) = notifications.recv().await {
+        notification.resolve_handle(&handles).await;
+        // Showing talks to the notification daemon, and waiting for a click blocks until the
+        // notification closes
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = show(&notification) {
+                warn!("Unable to show desktop notification: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show(notification: &Notification) -> notify_rust::Result<()> {
+    let url = notification.url.clone();
+    notify_rust::Notification::new()
+        .appname(APP_NAME)
+        .summary(&format!(
+            "{} by {}",
+            notification.title,
+            notification.author()
+        ))
+        .body(&notification.text)
+        .action("default", "Open post")
+        .timeout(notify_rust::Timeout::Milliseconds(TIMEOUT_MS))
+        .show()?
+        .wait_for_action(|action| {
+            if action == "default" {
+                if let Err(e) = std::process::Command::new("xdg-open").arg(&url).spawn() {
+                    warn!("Unable to open {url}: {e}");
+                }
+            }
+        });
+    Ok(())
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn show(notification: &Notification) -> notify_rust::Result<()> {
+    notify_rust::Notification::new()
+        .appname(APP_NAME)
+        .summary(&format!(
+            "{} by {}",
+            notification.title,
+            notification.author()
+        ))
+        .body(&format!("{}\n{}", notification.text, notification.url))
+        .timeout(notify_rust::Timeout::Milliseconds(TIMEOUT_MS))
+        .show()?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! Daily digest: once a day, posts a thread of the day's most liked and reposted haikus from
+//! the bot account, each reply quoting one of them.
+
+use std::{collections::HashMap, sync::Arc, sync::Mutex};
+
+use atrium_api::{
+    app::bsky::feed::{like, repost},
+    types::string::Datetime,
+};
+use chrono::{NaiveTime, Utc};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    client::Event,
+    config::Config,
+    haiku::HaikuRecord,
+    notify::Notification,
+    session::Session,
+    task,
+    xrpc::{AuthClient, StrongRef, XrpcError},
+};
+
+/// Longest post Bluesky accepts, in graphemes; counted here in characters
+const MAX_POST_CHARS: usize = 300;
+
+/// One of the day's haikus, with the engagement it got since it was found.
+#[derive(Debug, Clone)]
+struct Candidate {
+    haiku: HaikuRecord,
+    likes: u64,
+    reposts: u64,
+}
+
+#[derive(Debug)]
+pub struct Digest {
+    /// `None` in dry-run mode, where the thread is only logged
+    client: Option<AuthClient>,
+    /// The account posting the digest, whose replies quote haikus and would be found again
+    did: Option<String>,
+    /// When the digest is posted each day, in UTC
+    at: NaiveTime,
+    size: usize,
+    template: String,
+    entry_template: String,
+    /// Haikus found since the last digest, by URI
+    candidates: Mutex<HashMap<String, Candidate>>,
+}
+
+impl Digest {
+    /// Returns `None` when no digest time is configured. `session` is only used outside
+    /// dry-run mode.
+    pub fn from_config(config: &Config, session: Option<Arc<Session>>) -> Option<Self> {
+        let at = config.digest_time?;
+        Some(Self {
+            did: session.as_ref().map(|session| session.did()),
+            client: session
+                .filter(|_| !config.digest_dry_run)
+                .map(AuthClient::new),
+            at,
+            size: config.digest_size,
+            template: config.digest_template.clone(),
+            entry_template: config.digest_entry_template.clone(),
+            candidates: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Adds a haiku to the next digest. Haikus without a CID can't be quoted, so are skipped,
+    /// as are the digest account's own posts.
+    pub fn add(&self, haiku: &HaikuRecord) {
+        if haiku.cid.is_none() || self.did.as_deref() == Some(haiku.did.as_str()) {
+            return;
+        }
+        self.candidates.lock().unwrap().insert(
+            haiku.uri.clone(),
+            Candidate {
+                haiku: haiku.clone(),
+                likes: 0,
+                reposts: 0,
+            },
+        );
+    }
+
+    /// Counts `evt` if it likes or reposts one of the candidates.
+    pub fn record(&self, evt: &Event) {
+        if evt.action != "create" {
+            return;
+        }
+        let (uri, is_like) = match evt.collection.as_str() {
+            "app.bsky.feed.like" => match evt.record::<like::Record>() {
+                Ok(Some(record)) => (record.data.subject.data.uri, true),
+                _ => return,
+            },
+            "app.bsky.feed.repost" => match evt.record::<repost::Record>() {
+                Ok(Some(record)) => (record.data.subject.data.uri, false),
+                _ => return,
+            },
+            _ => return,
+        };
+        if let Some(candidate) = self.candidates.lock().unwrap().get_mut(&uri) {
+            if is_like {
+                candidate.likes += 1;
+            } else {
+                candidate.reposts += 1;
+            }
+        }
+    }
+
+    /// Spawns a task posting the digest every day at the configured time.
+    pub fn schedule(self: Arc<Self>) {
+        task::spawn("digest", async move {
+            loop {
+                let now = Utc::now();
+                let mut next = now.date_naive().and_time(self.at).and_utc();
+                if next <= now {
+                    next += chrono::Duration::days(1);
+                }
+                info!("Next digest at {next}");
+                let wait = (next - now).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+
+                let candidates = std::mem::take(&mut *self.candidates.lock().unwrap());
+                if candidates.is_empty() {
+                    info!("No haikus found today, skipping the digest");
+                    continue;
+                }
+                if let Err(e) = self.post(candidates.into_values().collect()).await {
+                    error!("Unable to post the digest: {e}");
+                }
+            }
+        });
+    }
+
+    /// The thread's root post, then a reply for each of the best candidates with the haiku it
+    /// quotes.
+    fn compose(&self, mut candidates: Vec<Candidate>) -> (String, Vec<(String, StrongRef)>) {
+        let count = candidates.len();
+        candidates.sort_by(|a, b| {
+            (b.likes + b.reposts)
+                .cmp(&(a.likes + a.reposts))
+                .then_with(|| b.haiku.created_at.cmp(&a.haiku.created_at))
+        });
+        candidates.truncate(self.size);
+
+        let root = self
+            .template
+            .replace("{count}", &count.to_string())
+            .replace("{shown}", &candidates.len().to_string())
+            .replace("{date}", &Utc::now().format("%Y-%m-%d").to_string());
+        let entries = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| {
+                let template = self
+                    .entry_template
+                    .replace("{rank}", &(i + 1).to_string())
+                    .replace("{likes}", &candidate.likes.to_string())
+                    .replace("{reposts}", &candidate.reposts.to_string());
+                let text = Notification::haiku(&candidate.haiku).render(&template);
+                let quote = StrongRef {
+                    uri: candidate.haiku.uri.clone(),
+                    cid: candidate.haiku.cid.clone().unwrap_or_default(),
+                };
+                (truncate(&text), quote)
+            })
+            .collect();
+        (truncate(&root), entries)
+    }
+
+    async fn post(&self, candidates: Vec<Candidate>) -> Result<(), XrpcError> {
+        let (root_text, entries) = self.compose(candidates);
+        let Some(client) = &self.client else {
+            info!("[dry run] Would post digest: {root_text}");
+            for (text, quote) in &entries {
+                info!("[dry run] Would reply quoting {}: {text}", quote.uri);
+            }
+            return Ok(());
+        };
+
+        let root = client
+            .create_record(
+                "app.bsky.feed.post",
+                json!({ "text": root_text, "createdAt": Datetime::now() }),
+            )
+            .await?;
+        let mut parent = root.clone();
+        for (text, quote) in entries {
+            let reply = json!({
+                "text": text,
+                "embed": { "$type": "app.bsky.embed.record", "record": quote },
+                "reply": { "root": root, "parent": parent },
+                "createdAt": Datetime::now(),
+            });
+            match client.create_record("app.bsky.feed.post", reply).await {
+                Ok(created) => parent = created,
+                Err(e) => warn!("Unable to post digest entry quoting {}: {e}", quote.uri),
+            }
+        }
+        info!("Posted digest {}", root.uri);
+        Ok(())
+    }
+}
+
+/// Cuts `text` down to the longest post Bluesky accepts.
+fn truncate(text: &str) -> String {
+    if text.chars().count() <= MAX_POST_CHARS {
+        return text.to_string();
+    }
+    let mut truncated = text.chars().take(MAX_POST_CHARS - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
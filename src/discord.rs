@@ -0,0 +1,157 @@
+//! Posts notifications to a Discord channel through a webhook.
+
+use std::time::Duration;
+
+use chrono::DateTime;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
+use tracing::warn;
+
+use crate::{identity::HandleResolver, notify::Notification, ratelimit::RateLimiter, task};
+
+/// Notifications waiting to be sent before new ones are dropped
+const QUEUE_SIZE: usize = 100;
+/// Discord allows about 30 messages per minute in a channel
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+/// Attempts at a notification that keeps being rate limited
+const MAX_ATTEMPTS: u32 = 5;
+/// Bluesky blue
+const EMBED_COLOR: u32 = 0x0085ff;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiscordError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("still rate limited after {MAX_ATTEMPTS} attempts")]
+    RateLimited,
+}
+
+/// Body of a 429 response.
+#[derive(Debug, Deserialize)]
+struct RateLimit {
+    retry_after: f64,
+}
+
+/// Sends notifications in the background, one at a time and within Discord's rate limits.
+pub struct Discord {
+    queue: mpsc::Sender<Notification>,
+}
+
+impl Discord {
+    /// Sends to `webhook`, further limited by `limiter` if given.
+    pub fn spawn(
+        http: reqwest::Client,
+        handles: HandleResolver,
+        webhook: String,
+        limiter: Option<RateLimiter>,
+    ) -> Self {
+        let (queue, notifications) = mpsc::channel(QUEUE_SIZE);
+        task::spawn(
+            "discord",
+            run(http, handles, webhook, limiter, notifications),
+        );
+        Self { queue }
+    }
+
+    /// Queues `notification`, dropping it if Discord is too far behind.
+    pub fn notify(&self, notification: Notification) {
+        if let Err(TrySendError::Full(notification)) = self.queue.try_send(notification) {
+            warn!(
+                "Discord is behind, dropping notification for {}",
+                notification.url
+            );
+        }
+    }
+}
+
+async fn run(
+    http: reqwest::Client,
+    handles: HandleResolver,
+    webhook: String,
+    limiter: Option<RateLimiter>,
+    mut notifications: mpsc::Receiver<Notification>,
+) {
+    let mut next_send = Instant::now();
+    while let Some(mut notification) = notifications.recv().await {
+        if let Some(limiter) = &limiter {
+            if !limiter.admit().await {
+                warn!(
+                    "Discord rate limit reached, dropping notification for {}",
+                    notification.url
+                );
+                continue;
+            }
+        }
+        notification.resolve_handle(&handles).await;
+        tokio::time::sleep_until(next_send).await;
+        if let Err(e) = send(&http, &webhook, &notification).await {
+            warn!("Unable to notify Discord of {}: {e}", notification.url);
+        }
+        next_send = Instant::now() + MIN_INTERVAL;
+    }
+}
+
+async fn send(
+    http: &reqwest::Client,
+    webhook: &str,
+    notification: &Notification,
+) -> Result<(), DiscordError> {
+    let body = message(notification);
+    for _ in 0..MAX_ATTEMPTS {
+        let response = http.post(webhook).json(&body).send().await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .json::<RateLimit>()
+                .await
+                .ok()
+                .and_then(|limit| Duration::try_from_secs_f64(limit.retry_after).ok())
+                .unwrap_or(MIN_INTERVAL);
+            tokio::time::sleep(retry_after).await;
+            continue;
+        }
+
+        let response = response.error_for_status()?;
+        // Wait out an exhausted bucket rather than run into a 429 on the next message
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok()?.parse::<f64>().ok())
+        };
+        if header("x-ratelimit-remaining") == Some(0.0) {
+            if let Some(reset_after) = header("x-ratelimit-reset-after")
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            {
+                tokio::time::sleep(reset_after).await;
+            }
+        }
+        return Ok(());
+    }
+    Err(DiscordError::RateLimited)
+}
+
+/// Formats `notification` as a webhook message with a single embed.
+fn message(notification: &Notification) -> Value {
+    // Discord rejects the whole message over a malformed timestamp, and `createdAt` is
+    // whatever the author's client wrote
+    let mut embed = json!({
+        "author": { "name": notification.author(), "url": notification.profile_url() },
+        "title": notification.title,
+        "url": notification.url,
+        "description": notification.text,
+        "color": EMBED_COLOR,
+    });
+    if let Ok(time) = DateTime::parse_from_rfc3339(&notification.created_at) {
+        embed["timestamp"] = time.to_rfc3339().into();
+    }
+    json!({
+        "embeds": [embed],
+        // Post text is untrusted, so never ping anyone
+        "allowed_mentions": { "parse": [] },
+    })
+}
@@ -0,0 +1,149 @@
+//! Classification of post embeds (images, video, link cards, quote posts).
+
+use std::str::FromStr;
+
+use atrium_api::{
+    app::bsky::{
+        embed::{images, record_with_media::MainMediaRefs, video},
+        feed::post::{self, RecordEmbedRefs},
+    },
+    types::{BlobRef, TypedBlobRef, Union},
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmbedKind {
+    /// No embed at all
+    Text,
+    Images,
+    Video,
+    /// External link card
+    External,
+    Quote,
+    QuoteWithMedia,
+    /// An embed type this version doesn't know about
+    Unknown,
+}
+
+impl FromStr for EmbedKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "images" => Ok(Self::Images),
+            "video" => Ok(Self::Video),
+            "external" => Ok(Self::External),
+            "quote" => Ok(Self::Quote),
+            "quote-with-media" => Ok(Self::QuoteWithMedia),
+            "unknown" => Ok(Self::Unknown),
+            other => Err(format!("unknown embed type {other:?}")),
+        }
+    }
+}
+
+/// An image or video blob attached to a post.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Media {
+    pub cid: String,
+    pub mime_type: String,
+    pub size: Option<usize>,
+    /// Empty alt text is reported as `None`
+    pub alt: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Embed {
+    pub kind: EmbedKind,
+    /// `at://` URI of the quoted record
+    pub quoted: Option<String>,
+    /// URI of the external link card
+    pub external: Option<String>,
+    pub media: Vec<Media>,
+}
+
+impl Embed {
+    pub fn from_record(record: &post::Record) -> Self {
+        let mut embed = Self {
+            kind: EmbedKind::Text,
+            quoted: None,
+            external: None,
+            media: Vec::new(),
+        };
+
+        match &record.embed {
+            None => {}
+            Some(Union::Unknown(_)) => embed.kind = EmbedKind::Unknown,
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedImagesMain(main))) => {
+                embed.kind = EmbedKind::Images;
+                embed.push_images(main);
+            }
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedVideoMain(main))) => {
+                embed.kind = EmbedKind::Video;
+                embed.push_video(main);
+            }
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedExternalMain(main))) => {
+                embed.kind = EmbedKind::External;
+                embed.external = Some(main.external.uri.clone());
+            }
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordMain(main))) => {
+                embed.kind = EmbedKind::Quote;
+                embed.quoted = Some(main.record.uri.clone());
+            }
+            Some(Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(main))) => {
+                embed.kind = EmbedKind::QuoteWithMedia;
+                embed.quoted = Some(main.record.record.uri.clone());
+                match &main.media {
+                    Union::Refs(MainMediaRefs::AppBskyEmbedImagesMain(images)) => {
+                        embed.push_images(images)
+                    }
+                    Union::Refs(MainMediaRefs::AppBskyEmbedVideoMain(video)) => {
+                        embed.push_video(video)
+                    }
+                    Union::Refs(MainMediaRefs::AppBskyEmbedExternalMain(external)) => {
+                        embed.external = Some(external.external.uri.clone())
+                    }
+                    Union::Unknown(_) => {}
+                }
+            }
+        }
+
+        embed
+    }
+
+    /// Images attached to the post, including those next to a quoted record.
+    pub fn images(&self) -> impl Iterator<Item = &Media> {
+        self.media
+            .iter()
+            .filter(|media| media.mime_type.starts_with("image/"))
+    }
+
+    fn push_images(&mut self, main: &images::Main) {
+        for image in &main.images {
+            self.media.push(media(&image.image, Some(&image.alt)));
+        }
+    }
+
+    fn push_video(&mut self, main: &video::Main) {
+        self.media.push(media(&main.video, main.alt.as_ref()));
+    }
+}
+
+fn media(blob: &BlobRef, alt: Option<&String>) -> Media {
+    let (cid, mime_type, size) = match blob {
+        BlobRef::Typed(TypedBlobRef::Blob(blob)) => (
+            blob.r#ref.0.to_string(),
+            blob.mime_type.clone(),
+            Some(blob.size),
+        ),
+        BlobRef::Untyped(blob) => (blob.cid.clone(), blob.mime_type.clone(), None),
+    };
+
+    Media {
+        cid,
+        mime_type,
+        size,
+        alt: alt.filter(|alt| !alt.trim().is_empty()).cloned(),
+    }
+}
@@ -0,0 +1,302 @@
+//! Text embeddings for semantic search: haikus are embedded through an OpenAI-compatible
+//! `/v1/embeddings` API (OpenAI itself, or a local model served by e.g. Ollama or llama.cpp)
+//! and kept in a SQLite vector index, searched with `similar`.
+//!
+//! Vectors are normalized when stored, so cosine similarity is a dot product. Search is an
+//! exact scan over every vector, which stays fast at haiku volumes.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, warn};
+
+use crate::task;
+
+/// Texts waiting to be embedded before new ones are dropped
+const QUEUE_SIZE: usize = 1000;
+/// Texts per embedding request
+const BATCH_SIZE: usize = 32;
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS embeddings (
+    uri TEXT PRIMARY KEY,
+    did TEXT NOT NULL,
+    text TEXT NOT NULL,
+    model TEXT NOT NULL,
+    vector BLOB NOT NULL
+);
+";
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmbeddingError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("embedding API returned {returned} embeddings for {sent} texts")]
+    Count { sent: usize, returned: usize },
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddingConfig {
+    /// Embeddings endpoint, e.g. `http://localhost:11434/v1/embeddings`
+    pub url: String,
+    pub model: String,
+    /// Sent as a bearer token when set
+    pub api_key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Calls the embedding API.
+#[derive(Debug, Clone)]
+pub struct Embedder {
+    http: reqwest::Client,
+    config: EmbeddingConfig,
+}
+
+impl Embedder {
+    pub fn new(http: reqwest::Client, config: EmbeddingConfig) -> Self {
+        Self { http, config }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    /// One embedding per text, in order.
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        let mut request = self.http.post(&self.config.url).json(&EmbeddingRequest {
+            model: &self.config.model,
+            input: texts,
+        });
+        if let Some(key) = &self.config.api_key {
+            request = request.bearer_auth(key);
+        }
+        let mut response: EmbeddingResponse =
+            request.send().await?.error_for_status()?.json().await?;
+        if response.data.len() != texts.len() {
+            return Err(EmbeddingError::Count {
+                sent: texts.len(),
+                returned: response.data.len(),
+            });
+        }
+        response.data.sort_by_key(|data| data.index);
+        Ok(response
+            .data
+            .into_iter()
+            .map(|data| data.embedding)
+            .collect())
+    }
+}
+
+/// A stored text close to the one searched for, most similar first.
+#[derive(Debug, Clone, Serialize)]
+pub struct Neighbor {
+    pub uri: String,
+    pub did: String,
+    pub text: String,
+    /// Cosine similarity, 1 for the same direction
+    pub similarity: f32,
+}
+
+/// Embeddings by post URI, in SQLite.
+#[derive(Debug)]
+pub struct VectorIndex {
+    db: Mutex<Connection>,
+}
+
+impl VectorIndex {
+    pub fn open(path: &Path) -> Result<Self, EmbeddingError> {
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+
+    /// Stores the embedding of a post, replacing any earlier one.
+    pub fn insert(
+        &self,
+        uri: &str,
+        did: &str,
+        text: &str,
+        model: &str,
+        vector: &[f32],
+    ) -> Result<(), EmbeddingError> {
+        self.db.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO embeddings (uri, did, text, model, vector)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![uri, did, text, model, to_blob(&normalize(vector))],
+        )?;
+        Ok(())
+    }
+
+    /// The stored embedding of `uri`, if any.
+    pub fn get(&self, uri: &str) -> Result<Option<Vec<f32>>, EmbeddingError> {
+        let blob: Option<Vec<u8>> = self
+            .db
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT vector FROM embeddings WHERE uri = ?1",
+                params![uri],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(blob.map(|blob| from_blob(&blob)))
+    }
+
+    /// The `k` stored texts most similar to `vector`. Only embeddings from `model` are
+    /// compared, as vectors from different models don't share a space.
+    pub fn nearest(
+        &self,
+        vector: &[f32],
+        model: &str,
+        k: usize,
+    ) -> Result<Vec<Neighbor>, EmbeddingError> {
+        let query = normalize(vector);
+        let db = self.db.lock().unwrap();
+        let mut statement =
+            db.prepare_cached("SELECT uri, did, text, vector FROM embeddings WHERE model = ?1")?;
+        let mut neighbors = statement
+            .query_map(params![model], |row| {
+                let vector: Vec<u8> = row.get(3)?;
+                Ok(Neighbor {
+                    uri: row.get(0)?,
+                    did: row.get(1)?,
+                    text: row.get(2)?,
+                    similarity: dot(&query, &from_blob(&vector)),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        neighbors.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        neighbors.truncate(k);
+        Ok(neighbors)
+    }
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+/// Zero when the lengths differ, e.g. after switching to a model of another size.
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().expect("chunks are 4 bytes")))
+        .collect()
+}
+
+struct Pending {
+    uri: String,
+    did: String,
+    text: String,
+}
+
+/// Embeds texts and stores them in the background, batching whatever is waiting into one
+/// request. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct EmbeddingWriter {
+    queue: mpsc::Sender<Pending>,
+}
+
+impl EmbeddingWriter {
+    pub fn spawn(embedder: Embedder, index: Arc<VectorIndex>) -> Self {
+        let (queue, pending) = mpsc::channel(QUEUE_SIZE);
+        task::spawn("embedding", run(embedder, index, pending));
+        Self { queue }
+    }
+
+    /// Queues the text of a post. Texts are dropped when the embedding API falls too far
+    /// behind.
+    pub fn push(&self, uri: &str, did: &str, text: &str) {
+        let pending = Pending {
+            uri: uri.to_string(),
+            did: did.to_string(),
+            text: text.to_string(),
+        };
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(pending) {
+            warn!("Embedding API is behind, dropping {uri}");
+        }
+    }
+}
+
+async fn run(embedder: Embedder, index: Arc<VectorIndex>, mut queue: mpsc::Receiver<Pending>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while queue.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+        let texts: Vec<String> = batch.iter().map(|pending| pending.text.clone()).collect();
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        let vectors = loop {
+            match embedder.embed(&texts).await {
+                Ok(vectors) => break Some(vectors),
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    warn!("Unable to embed {} texts, retrying: {e}", texts.len());
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!("Unable to embed {} texts: {e}", texts.len());
+                    break None;
+                }
+            }
+        };
+
+        if let Some(vectors) = vectors {
+            let index = index.clone();
+            let model = embedder.model().to_string();
+            let pending = std::mem::take(&mut batch);
+            let stored = tokio::task::spawn_blocking(move || {
+                pending
+                    .iter()
+                    .zip(&vectors)
+                    .try_for_each(|(pending, vector)| {
+                        index.insert(&pending.uri, &pending.did, &pending.text, &model, vector)
+                    })
+            })
+            .await
+            .expect("vector index writes don't panic");
+            if let Err(e) = stored {
+                error!("Unable to store embeddings: {e}");
+            }
+        }
+        batch.clear();
+    }
+}
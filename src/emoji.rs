@@ -0,0 +1,286 @@
+//! Emoji in post text: finding them, recognizing emoji-only posts, and per-minute counts of the
+//! emoji posts use, summed over the same sliding windows as [trending hashtags](crate::trending).
+//!
+//! Detection works on code point ranges rather than the full Unicode emoji data, so a handful of
+//! rarely used symbols are miscounted either way. Sequences (skin tones, ZWJ families, flags and
+//! keycaps) count as one emoji.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    jsonl::JsonlWriter,
+    task,
+    telemetry::Metrics,
+    trending::{MinuteWindow, WINDOWS},
+};
+
+const ZWJ: char = '\u{200D}';
+/// Variation selector asking for the emoji rather than the text presentation
+const VS16: char = '\u{FE0F}';
+const KEYCAP: char = '\u{20E3}';
+
+/// Symbols shown as emoji on their own.
+fn is_pictographic(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF
+            | 0x2600..=0x27BF
+            | 0x231A..=0x231B
+            | 0x23E9..=0x23F3
+            | 0x23F8..=0x23FA
+            | 0x2B1B..=0x2B1C
+            | 0x2B50
+            | 0x2B55
+    ) && !is_regional_indicator(c)
+}
+
+/// Symbols shown as text unless followed by [`VS16`], e.g. © and ↔.
+fn is_text_default(c: char) -> bool {
+    matches!(
+        c as u32,
+        0xA9 | 0xAE
+            | 0x203C
+            | 0x2049
+            | 0x2122
+            | 0x2139
+            | 0x2194..=0x2199
+            | 0x21A9..=0x21AA
+            | 0x2328
+            | 0x23CF
+            | 0x24C2
+            | 0x25AA..=0x25AB
+            | 0x25B6
+            | 0x25C0
+            | 0x25FB..=0x25FE
+            | 0x2934..=0x2935
+            | 0x2B05..=0x2B07
+            | 0x3030
+            | 0x303D
+            | 0x3297
+            | 0x3299
+    )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+/// Characters attaching to the emoji before them: presentation selectors, skin tones, keycaps
+/// and the tags of subdivision flags.
+fn is_modifier(c: char) -> bool {
+    matches!(c as u32, 0xFE0E..=0xFE0F | 0x1F3FB..=0x1F3FF | 0x20E3 | 0xE0020..=0xE007F)
+}
+
+/// Whether an emoji starts at `c`, followed by `rest`.
+fn starts_emoji(c: char, rest: &str) -> bool {
+    let mut next = rest.chars();
+    if is_regional_indicator(c) {
+        next.next().is_some_and(is_regional_indicator)
+    } else if c.is_ascii_digit() || c == '#' || c == '*' {
+        rest.starts_with(KEYCAP) || rest.starts_with("\u{FE0F}\u{20E3}")
+    } else {
+        is_pictographic(c) || (is_text_default(c) && rest.starts_with(VS16))
+    }
+}
+
+/// Byte ranges of the emoji in `text`, in order.
+fn spans(text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        if !starts_emoji(c, &text[end..]) {
+            continue;
+        }
+        if is_regional_indicator(c) {
+            let (i, flag) = chars.next().expect("checked by starts_emoji");
+            end = i + flag.len_utf8();
+        }
+        while let Some(&(i, c)) = chars.peek() {
+            if is_modifier(c) {
+                chars.next();
+                end = i + c.len_utf8();
+                continue;
+            }
+            let joined = text[i + c.len_utf8()..].chars().next();
+            if c != ZWJ || !joined.is_some_and(|c| is_pictographic(c) || is_text_default(c)) {
+                break;
+            }
+            chars.next();
+            let (i, c) = chars.next().expect("peeked above");
+            end = i + c.len_utf8();
+        }
+        spans.push(start..end);
+    }
+    spans
+}
+
+/// The emoji in `text`, in order and with repeats.
+pub fn emojis(text: &str) -> Vec<&str> {
+    spans(text).into_iter().map(|span| &text[span]).collect()
+}
+
+/// Whether `text` has emoji and nothing else but whitespace.
+pub fn is_emoji_only(text: &str) -> bool {
+    let spans = spans(text);
+    let mut last = 0;
+    for span in &spans {
+        if !text[last..span.start].chars().all(is_filler) {
+            return false;
+        }
+        last = span.end;
+    }
+    !spans.is_empty() && text[last..].chars().all(is_filler)
+}
+
+/// Characters an emoji-only post may have between its emoji.
+fn is_filler(c: char) -> bool {
+    c.is_whitespace() || c == VS16 || c == ZWJ
+}
+
+#[derive(Debug, Default)]
+pub struct EmojiUsage {
+    minutes: Mutex<MinuteWindow<Minute>>,
+}
+
+#[derive(Debug, Default)]
+struct Minute {
+    emoji_only: u64,
+    counts: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmojiCount {
+    pub emoji: String,
+    /// Posts using the emoji within the window
+    pub posts: u64,
+}
+
+/// Emoji use within one window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmojiWindow {
+    /// Posts made up of nothing but emoji
+    pub emoji_only: u64,
+    pub top: Vec<EmojiCount>,
+}
+
+/// One line of the emoji output file.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    at: DateTime<Utc>,
+    window: &'a str,
+    #[serde(flatten)]
+    usage: EmojiWindow,
+}
+
+impl EmojiUsage {
+    /// Counts each emoji in `text` once, and the post as emoji-only if it is.
+    pub fn record(&self, text: &str) {
+        let emojis = emojis(text).into_iter().collect::<HashSet<_>>();
+        if emojis.is_empty() {
+            return;
+        }
+        let emoji_only = is_emoji_only(text);
+        if emoji_only {
+            Metrics::get().record_emoji_only_post();
+        }
+
+        let mut minutes = self.minutes.lock().unwrap();
+        let minute = minutes.current();
+        minute.emoji_only += u64::from(emoji_only);
+        for emoji in emojis {
+            *minute.counts.entry(emoji.to_string()).or_default() += 1;
+        }
+    }
+
+    /// The `n` most used emoji within `window` of now, most used first, and the emoji-only
+    /// posts seen.
+    pub fn top(&self, window: Duration, n: usize) -> EmojiWindow {
+        let mut emoji_only = 0;
+        let mut totals = HashMap::<&str, u64>::new();
+        let minutes = self.minutes.lock().unwrap();
+        for minute in minutes.recent(window) {
+            emoji_only += minute.emoji_only;
+            for (emoji, count) in &minute.counts {
+                *totals.entry(emoji).or_default() += count;
+            }
+        }
+
+        let mut top = totals
+            .into_iter()
+            .map(|(emoji, posts)| EmojiCount {
+                emoji: emoji.to_string(),
+                posts,
+            })
+            .collect::<Vec<_>>();
+        // Ties broken by code points, so reports are stable
+        top.sort_unstable_by(|a, b| b.posts.cmp(&a.posts).then_with(|| a.emoji.cmp(&b.emoji)));
+        top.truncate(n);
+        EmojiWindow { emoji_only, top }
+    }
+
+    /// Logs the top `n` emoji of every window each `interval` in the background, also
+    /// appending them to `output` when set. Only those are exported as metrics; emoji falling
+    /// out of the top are set back to zero.
+    pub fn report_every(
+        self: Arc<Self>,
+        interval: Duration,
+        n: usize,
+        output: Option<JsonlWriter>,
+    ) {
+        task::spawn("emoji-report", async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            let metrics = Metrics::get();
+            // Emoji exported by the previous report, by window
+            let mut exported = HashSet::new();
+            loop {
+                ticker.tick().await;
+                let at = Utc::now();
+                let mut shown = HashSet::new();
+                for (name, window) in WINDOWS {
+                    let usage = self.top(window, n);
+                    for count in &usage.top {
+                        metrics.record_top_emoji(name, &count.emoji, count.posts);
+                        shown.insert((name, count.emoji.clone()));
+                    }
+                    let list = usage
+                        .top
+                        .iter()
+                        .map(|count| format!("{} ({})", count.emoji, count.posts))
+                        .collect::<Vec<_>>();
+                    info!(
+                        "Emoji over {name}: {}; {} emoji-only posts",
+                        list.join(", "),
+                        usage.emoji_only
+                    );
+
+                    if let Some(output) = &output {
+                        let report = Report {
+                            at,
+                            window: name,
+                            usage,
+                        };
+                        if let Err(e) = output.append(&report) {
+                            error!("Unable to write emoji usage: {e}");
+                        }
+                    }
+                }
+                for (name, emoji) in exported.difference(&shown) {
+                    metrics.record_top_emoji(name, emoji, 0);
+                }
+                exported = shown;
+            }
+        });
+    }
+}
@@ -0,0 +1,163 @@
+//! Likes and reposts per subject post, tallied from the firehose alone and reported as the
+//! most engaged-with posts of a recent window.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use atrium_api::app::bsky::feed::{like, repost};
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{client::Event, jsonl::JsonlWriter, task, trending::MinuteWindow};
+
+/// Tallies for the most recently liked or reposted subjects. Subjects that go unmentioned the
+/// longest are forgotten first once `capacity` are tracked.
+///
+/// Only new likes and reposts are counted; deleting one doesn't take it back, since the
+/// deletion doesn't say what its subject was.
+#[derive(Debug)]
+pub struct Engagement {
+    window: Duration,
+    subjects: Mutex<LruCache<String, MinuteWindow<Minute>>>,
+}
+
+#[derive(Debug, Default)]
+struct Minute {
+    likes: u64,
+    reposts: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubjectCount {
+    /// `at://` URI of the subject post
+    pub uri: String,
+    pub likes: u64,
+    pub reposts: u64,
+}
+
+/// One line of the engagement output file.
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    at: DateTime<Utc>,
+    window_secs: u64,
+    top: Vec<SubjectCount>,
+}
+
+impl Engagement {
+    pub fn new(capacity: NonZeroUsize, window: Duration) -> Self {
+        Self {
+            window,
+            subjects: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Counts `evt` if it creates a like or repost.
+    pub fn record(&self, evt: &Event) {
+        if evt.action != "create" {
+            return;
+        }
+        let subject = match evt.collection.as_str() {
+            "app.bsky.feed.like" => evt
+                .record::<like::Record>()
+                .map(|record| record.map(|record| (record.subject.uri.clone(), true))),
+            "app.bsky.feed.repost" => evt
+                .record::<repost::Record>()
+                .map(|record| record.map(|record| (record.subject.uri.clone(), false))),
+            _ => return,
+        };
+        let (uri, like) = match subject {
+            Ok(Some(subject)) => subject,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Malformed {} record: {e}", evt.collection);
+                return;
+            }
+        };
+
+        let mut subjects = self.subjects.lock().unwrap();
+        let minute = subjects
+            .get_or_insert_mut(uri, || MinuteWindow::new(self.window))
+            .current();
+        if like {
+            minute.likes += 1;
+        } else {
+            minute.reposts += 1;
+        }
+    }
+
+    /// The `n` subjects with the most likes within the window, then the most reposts.
+    pub fn top(&self, n: usize) -> Vec<SubjectCount> {
+        let subjects = self.subjects.lock().unwrap();
+        let mut top = subjects
+            .iter()
+            .map(|(uri, minutes)| {
+                let recent = minutes.recent(self.window);
+                let (likes, reposts) = recent.fold((0, 0), |(likes, reposts), minute| {
+                    (likes + minute.likes, reposts + minute.reposts)
+                });
+                SubjectCount {
+                    uri: uri.clone(),
+                    likes,
+                    reposts,
+                }
+            })
+            .filter(|count| count.likes + count.reposts > 0)
+            .collect::<Vec<_>>();
+        drop(subjects);
+
+        top.sort_unstable_by(|a, b| {
+            (b.likes, b.reposts, &a.uri).cmp(&(a.likes, a.reposts, &b.uri))
+        });
+        top.truncate(n);
+        top
+    }
+
+    /// Logs the top `n` subjects each `interval` in the background, also appending them to
+    /// `output` when set.
+    pub fn report_every(
+        self: Arc<Self>,
+        interval: Duration,
+        n: usize,
+        output: Option<JsonlWriter>,
+    ) {
+        task::spawn("engagement-report", async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let top = self.top(n);
+                let list = top
+                    .iter()
+                    .map(|count| {
+                        format!(
+                            "{} ({} likes, {} reposts)",
+                            count.uri, count.likes, count.reposts
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                info!(
+                    "Most liked over {}s: {}",
+                    self.window.as_secs(),
+                    list.join(", ")
+                );
+
+                if let Some(output) = &output {
+                    let snapshot = Snapshot {
+                        at: Utc::now(),
+                        window_secs: self.window.as_secs(),
+                        top,
+                    };
+                    if let Err(e) = output.append(&snapshot) {
+                        error!("Unable to write engagement snapshot: {e}");
+                    }
+                }
+            }
+        });
+    }
+}
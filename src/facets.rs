@@ -0,0 +1,49 @@
+//! Structured view of a post's rich-text facets.
+
+use atrium_api::{
+    app::bsky::{feed::post, richtext::facet::MainFeaturesItem},
+    types::Union,
+};
+use serde::Serialize;
+
+/// Mentions, links and hashtags of a post.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Facets {
+    /// Mentioned DIDs
+    pub mentions: Vec<String>,
+    pub links: Vec<String>,
+    /// Hashtags without the leading `#`, including the record's outline `tags`
+    pub tags: Vec<String>,
+}
+
+impl Facets {
+    pub fn from_record(record: &post::Record) -> Self {
+        let mut facets = Self::default();
+        for feature in record
+            .facets
+            .iter()
+            .flatten()
+            .flat_map(|facet| &facet.features)
+        {
+            match feature {
+                Union::Refs(MainFeaturesItem::Mention(mention)) => {
+                    facets.mentions.push(mention.did.as_str().to_string())
+                }
+                Union::Refs(MainFeaturesItem::Link(link)) => facets.links.push(link.uri.clone()),
+                Union::Refs(MainFeaturesItem::Tag(tag)) => facets.tags.push(tag.tag.clone()),
+                Union::Unknown(_) => {}
+            }
+        }
+        facets.tags.extend(record.tags.iter().flatten().cloned());
+
+        facets
+    }
+
+    /// Whether the post is tagged with `tag`, ignoring case and a leading `#`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim_start_matches('#');
+        self.tags
+            .iter()
+            .any(|t| t.trim_start_matches('#').eq_ignore_ascii_case(tag))
+    }
+}
@@ -0,0 +1,156 @@
+//! In-process fan-out: every consumer gets every published event, decoded once and shared, and
+//! a slow consumer never holds up the others.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::{client::Event, task, telemetry::Metrics};
+
+/// What a consumer does when it falls more than the fan-out capacity behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Skip the events it missed and carry on from the oldest one still buffered
+    Skip,
+    /// Stop receiving, for consumers that can't tolerate gaps
+    Close,
+}
+
+#[derive(Debug)]
+pub struct Fanout {
+    tx: broadcast::Sender<Arc<Event>>,
+    consumers: Mutex<Vec<Arc<ConsumerStats>>>,
+}
+
+#[derive(Debug)]
+struct ConsumerStats {
+    name: String,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    /// Events buffered for the consumer as of its latest receive
+    pending: AtomicU64,
+}
+
+/// Per-consumer lag, as reported by [`Fanout::lag`].
+#[derive(Debug, Clone)]
+pub struct ConsumerLag {
+    pub name: String,
+    pub received: u64,
+    pub dropped: u64,
+    pub pending: u64,
+}
+
+impl Fanout {
+    /// Each consumer can fall `capacity` events behind before its [`DropPolicy`] applies.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+            consumers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn publish(&self, evt: Event) {
+        // Only fails when there are no consumers
+        let _ = self.tx.send(Arc::new(evt));
+    }
+
+    /// Adds a consumer receiving every event published from now on.
+    pub fn subscribe(&self, name: &str, policy: DropPolicy) -> Consumer {
+        let stats = Arc::new(ConsumerStats {
+            name: name.to_string(),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            pending: AtomicU64::new(0),
+        });
+        self.consumers.lock().unwrap().push(stats.clone());
+        Consumer {
+            rx: self.tx.subscribe(),
+            policy,
+            stats,
+        }
+    }
+
+    pub fn lag(&self) -> Vec<ConsumerLag> {
+        self.consumers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|stats| ConsumerLag {
+                name: stats.name.clone(),
+                received: stats.received.load(Ordering::Relaxed),
+                dropped: stats.dropped.load(Ordering::Relaxed),
+                pending: stats.pending.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Spawns a task logging each consumer's lag every `interval`.
+    pub fn log_every(self: Arc<Self>, interval: Duration) {
+        task::spawn("fanout-lag", async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                for lag in self.lag() {
+                    info!(
+                        "Consumer {}: {} received, {} dropped, {} pending",
+                        lag.name, lag.received, lag.dropped, lag.pending
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Receiving end of a [`Fanout`].
+#[derive(Debug)]
+pub struct Consumer {
+    rx: broadcast::Receiver<Arc<Event>>,
+    policy: DropPolicy,
+    stats: Arc<ConsumerStats>,
+}
+
+impl Consumer {
+    /// Waits for the next event, or returns `None` once the fan-out is gone or, with
+    /// [`DropPolicy::Close`], the consumer fell behind.
+    pub async fn recv(&mut self) -> Option<Arc<Event>> {
+        loop {
+            match self.rx.recv().await {
+                Ok(evt) => {
+                    self.stats.received.fetch_add(1, Ordering::Relaxed);
+                    self.stats
+                        .pending
+                        .store(self.rx.len() as u64, Ordering::Relaxed);
+                    return Some(evt);
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    self.stats.dropped.fetch_add(skipped, Ordering::Relaxed);
+                    Metrics::get().record_fanout_drop(&self.stats.name, skipped);
+                    match self.policy {
+                        DropPolicy::Skip => {
+                            warn!(
+                                "Consumer {} fell behind, skipped {skipped} events",
+                                self.stats.name
+                            );
+                        }
+                        DropPolicy::Close => {
+                            error!(
+                                "Consumer {} fell behind by {skipped} events, closing",
+                                self.stats.name
+                            );
+                            return None;
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
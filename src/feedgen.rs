@@ -0,0 +1,217 @@
+//! Bluesky feed generator serving detected haikus as a custom feed, newest first.
+//!
+//! The generator is reached as `did:web:<hostname>`: it serves its DID document at
+//! `/.well-known/did.json`, and `app.bsky.feed.describeFeedGenerator` and
+//! `app.bsky.feed.getFeedSkeleton` under `/xrpc`. The feed itself is published by creating an
+//! `app.bsky.feed.generator` record with the generator's DID in the publisher's repo.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::haiku::HaikuRecord;
+
+/// Most posts `getFeedSkeleton` returns at once, per the lexicon
+const MAX_LIMIT: usize = 100;
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Debug)]
+pub struct FeedGenerator {
+    /// `did:web:<hostname>`
+    did: String,
+    hostname: String,
+    /// `at://<publisher>/app.bsky.feed.generator/<rkey>`
+    feed_uri: String,
+    capacity: usize,
+    posts: Mutex<Posts>,
+}
+
+/// The latest haiku URIs, oldest first, numbered in the order they were found so a cursor
+/// stays valid as new ones arrive.
+#[derive(Debug, Default)]
+struct Posts {
+    next: u64,
+    uris: VecDeque<(u64, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SkeletonQuery {
+    feed: String,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Skeleton {
+    feed: Vec<SkeletonPost>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SkeletonPost {
+    post: String,
+}
+
+/// A line of the haiku output file, of which only the URI and whether it was held back are
+/// needed.
+#[derive(Debug, Deserialize)]
+struct StoredHaiku {
+    uri: String,
+    #[serde(default)]
+    held_back: bool,
+}
+
+impl FeedGenerator {
+    /// Serves the feed `rkey` published by `publisher` (a DID), keeping the latest `capacity`
+    /// haikus.
+    pub fn new(hostname: &str, publisher: &str, rkey: &str, capacity: usize) -> Self {
+        Self {
+            did: format!("did:web:{hostname}"),
+            hostname: hostname.to_string(),
+            feed_uri: format!("at://{publisher}/app.bsky.feed.generator/{rkey}"),
+            capacity: capacity.max(1),
+            posts: Mutex::new(Posts::default()),
+        }
+    }
+
+    /// Adds the haikus already in the haiku output file at `path`, skipping malformed lines
+    /// and held back haikus. A missing file is not an error.
+    pub fn load(&self, path: &Path) -> io::Result<usize> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut loaded = 0;
+        for line in BufReader::new(file).lines() {
+            if let Ok(haiku) = serde_json::from_str::<StoredHaiku>(&line?) {
+                if haiku.held_back {
+                    continue;
+                }
+                self.push(haiku.uri);
+                loaded += 1;
+            }
+        }
+        Ok(loaded.min(self.capacity))
+    }
+
+    /// Adds the post at `uri` to the top of the feed.
+    pub fn push(&self, uri: String) {
+        let mut posts = self.posts.lock().unwrap();
+        let id = posts.next;
+        posts.next += 1;
+        posts.uris.push_back((id, uri));
+        if posts.uris.len() > self.capacity {
+            posts.uris.pop_front();
+        }
+    }
+
+    /// Adds `haiku` to the top of the feed, unless moderation held it back.
+    pub fn push_haiku(&self, haiku: &HaikuRecord) {
+        if !haiku.held_back {
+            self.push(haiku.uri.clone());
+        }
+    }
+
+    /// The URIs in the feed, newest first.
+    pub fn uris(&self) -> Vec<String> {
+        let posts = self.posts.lock().unwrap();
+        posts
+            .uris
+            .iter()
+            .rev()
+            .map(|(_, uri)| uri.clone())
+            .collect()
+    }
+
+    pub fn routes(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/.well-known/did.json", get(did_document))
+            .route(
+                "/xrpc/app.bsky.feed.describeFeedGenerator",
+                get(describe_feed_generator),
+            )
+            .route("/xrpc/app.bsky.feed.getFeedSkeleton", get(feed_skeleton))
+            .with_state(self)
+    }
+
+    /// Up to `limit` posts older than `cursor`, newest first.
+    fn skeleton(&self, limit: usize, cursor: Option<u64>) -> Skeleton {
+        let posts = self.posts.lock().unwrap();
+        let page = posts
+            .uris
+            .iter()
+            .rev()
+            .skip_while(|(id, _)| cursor.is_some_and(|cursor| *id >= cursor))
+            .take(limit)
+            .collect::<Vec<_>>();
+        let cursor = match page.last() {
+            Some((id, _)) if page.len() == limit && *id > 0 => Some(id.to_string()),
+            _ => None,
+        };
+        Skeleton {
+            feed: page
+                .into_iter()
+                .map(|(_, uri)| SkeletonPost { post: uri.clone() })
+                .collect(),
+            cursor,
+        }
+    }
+}
+
+async fn did_document(State(feedgen): State<Arc<FeedGenerator>>) -> impl IntoResponse {
+    Json(json!({
+        "@context": ["https://www.w3.org/ns/did/v1"],
+        "id": feedgen.did,
+        "service": [{
+            "id": "#bsky_fg",
+            "type": "BskyFeedGenerator",
+            "serviceEndpoint": format!("https://{}", feedgen.hostname),
+        }],
+    }))
+}
+
+async fn describe_feed_generator(State(feedgen): State<Arc<FeedGenerator>>) -> impl IntoResponse {
+    Json(json!({
+        "did": feedgen.did,
+        "feeds": [{ "uri": feedgen.feed_uri }],
+    }))
+}
+
+async fn feed_skeleton(
+    State(feedgen): State<Arc<FeedGenerator>>,
+    Query(query): Query<SkeletonQuery>,
+) -> axum::response::Response {
+    if query.feed != feedgen.feed_uri {
+        return xrpc_error("UnknownFeed", &format!("Unknown feed {}", query.feed));
+    }
+    let cursor = match query.cursor.as_deref().map(str::parse) {
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(_)) => return xrpc_error("InvalidRequest", "Malformed cursor"),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    Json(feedgen.skeleton(limit, cursor)).into_response()
+}
+
+fn xrpc_error(error: &str, message: &str) -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": error, "message": message })),
+    )
+        .into_response()
+}
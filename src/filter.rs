@@ -0,0 +1,163 @@
+//! Cheap keyword/regex matching on post text, applied before anything else looks at a post.
+
+use std::{collections::BTreeSet, path::Path};
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::RegexSet;
+
+use crate::{
+    config::Config,
+    embed::{Embed, EmbedKind},
+    facets::Facets,
+    labels::LabelFilter,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error("unable to read regex file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid keyword list: {0}")]
+    Keywords(#[from] aho_corasick::BuildError),
+    #[error("invalid regex: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+/// Finds terms in text as whole words, ignoring ASCII case, so `rust` doesn't match "trust".
+/// Where terms overlap the longest is reported, e.g. `rustacean` rather than `rust`.
+#[derive(Debug)]
+pub struct WordMatcher {
+    terms: Vec<String>,
+    matcher: AhoCorasick,
+}
+
+impl WordMatcher {
+    /// Returns `None` when there are no `terms`, as nothing would ever match.
+    pub fn new(terms: Vec<String>) -> Result<Option<Self>, aho_corasick::BuildError> {
+        if terms.is_empty() {
+            return Ok(None);
+        }
+        let matcher = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(&terms)?;
+        Ok(Some(Self { terms, matcher }))
+    }
+
+    /// Terms found in `text` as whole words, each once, in order of appearance.
+    pub fn find(&self, text: &str) -> Vec<&str> {
+        let mut terms = Vec::new();
+        for found in self.matcher.find_iter(text) {
+            let before = text[..found.start()].chars().next_back();
+            let after = text[found.end()..].chars().next();
+            if before.is_some_and(char::is_alphanumeric) || after.is_some_and(char::is_alphanumeric)
+            {
+                continue;
+            }
+            let term = self.terms[found.pattern().as_usize()].as_str();
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+        terms
+    }
+}
+
+/// Matches post text against a keyword list (whole words, case-insensitive) and a set of
+/// regexes, facets against required hashtags, and content labels against included and excluded
+/// ones.
+///
+/// An empty filter lets every post through.
+#[derive(Debug, Default)]
+pub struct PostFilter {
+    keywords: Option<WordMatcher>,
+    patterns: Option<RegexSet>,
+    require_tags: Vec<String>,
+    exclude_links: bool,
+    /// Accepted embed kinds; empty accepts every kind
+    embed_kinds: Vec<EmbedKind>,
+    labels: LabelFilter,
+}
+
+impl PostFilter {
+    pub fn new(keywords: Vec<String>, patterns: &[String]) -> Result<Self, FilterError> {
+        let keywords = WordMatcher::new(keywords)?;
+        let patterns = (!patterns.is_empty())
+            .then(|| RegexSet::new(patterns))
+            .transpose()?;
+
+        Ok(Self {
+            keywords,
+            patterns,
+            ..Default::default()
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, FilterError> {
+        let patterns = match &config.regex_file {
+            Some(path) => read_patterns(path)?,
+            None => Vec::new(),
+        };
+        let mut filter = Self::new(config.keywords.clone(), &patterns)?;
+        filter.require_tags = config.require_tags.clone();
+        filter.exclude_links = config.exclude_links;
+        filter.embed_kinds = config.embed_kinds.clone();
+        filter.labels = LabelFilter::new(
+            config.content_labels.clone(),
+            config.exclude_content_labels.clone(),
+        );
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_none() && self.patterns.is_none()
+    }
+
+    /// Whether the post carries every required hashtag, and no links if those are excluded.
+    pub fn allows_facets(&self, facets: &Facets) -> bool {
+        (!self.exclude_links || facets.links.is_empty())
+            && self.require_tags.iter().all(|tag| facets.has_tag(tag))
+    }
+
+    pub fn allows_embed(&self, embed: &Embed) -> bool {
+        self.embed_kinds.is_empty() || self.embed_kinds.contains(&embed.kind)
+    }
+
+    /// Content labels posts must or mustn't carry, see [`crate::labels`].
+    pub fn labels(&self) -> &LabelFilter {
+        &self.labels
+    }
+
+    /// Returns the keywords and regexes matching `text`, or `None` if nothing matched.
+    ///
+    /// Always returns `Some` (possibly empty) for an empty filter.
+    pub fn matches(&self, text: &str) -> Option<Vec<&str>> {
+        if self.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let mut matched = BTreeSet::new();
+        if let Some(keywords) = &self.keywords {
+            matched.extend(keywords.find(text));
+        }
+        if let Some(patterns) = &self.patterns {
+            matched.extend(
+                patterns
+                    .matches(text)
+                    .iter()
+                    .map(|idx| patterns.patterns()[idx].as_str()),
+            );
+        }
+
+        (!matched.is_empty()).then(|| matched.into_iter().collect())
+    }
+}
+
+/// Reads one regex per line, skipping blank lines and `#` comments.
+fn read_patterns(path: &Path) -> Result<Vec<String>, FilterError> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
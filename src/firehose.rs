@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use futures_util::StreamExt;
+use native_tls::TlsConnector;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue},
+        Message,
+    },
+    Connector, MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    config::Config,
+    frame::{self, Frame},
+    relay::{RelayError, RelayPool},
+};
+
+/// Default `User-Agent` of the firehose connection and HTTP calls
+pub const USER_AGENT: &str =
+    "bsky-firehose-listener (https://github.com/angeloanan/bsky-firehose-listener)";
+
+/// An extra header sent when connecting to relays, e.g. an auth token for a private relay.
+#[derive(Debug, Clone)]
+pub struct ExtraHeader {
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl FromStr for ExtraHeader {
+    type Err = String;
+
+    /// Parses `Name: value`, e.g. `Authorization: Bearer abc`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("{s:?} is not Name: value"))?;
+        Ok(Self {
+            name: HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|e| format!("invalid header name {name:?}: {e}"))?,
+            value: HeaderValue::from_str(value.trim())
+                .map_err(|e| format!("invalid value for header {name:?}: {e}"))?,
+        })
+    }
+}
+
+/// Parses one `Name: value` header per line, skipping blank lines. Lines rather than commas
+/// separate them, since header values may contain commas.
+pub fn parse_headers(s: &str) -> Result<Vec<ExtraHeader>, String> {
+    s.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+pub type FirehoseStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// The `subscribeRepos` websocket URL of `host`, given as a hostname or an HTTP(S) URL.
+pub fn subscribe_url(host: &str) -> String {
+    xrpc_websocket_url(host, "com.atproto.sync.subscribeRepos")
+}
+
+/// The `subscribeLabels` websocket URL of the labeler `host`, given as a hostname or an HTTP(S)
+/// URL.
+pub fn subscribe_labels_url(host: &str) -> String {
+    xrpc_websocket_url(host, "com.atproto.label.subscribeLabels")
+}
+
+fn xrpc_websocket_url(host: &str, method: &str) -> String {
+    let host = host.trim_end_matches('/');
+    let base = if let Some(rest) = host.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = host.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else if host.starts_with("wss://") || host.starts_with("ws://") {
+        host.to_string()
+    } else {
+        format!("wss://{host}")
+    };
+    if base.ends_with(&format!("/xrpc/{method}")) {
+        base
+    } else {
+        format!("{base}/xrpc/{method}")
+    }
+}
+
+/// Opens a websocket to `relay`, resuming from `cursor` if one is given, with the configured
+/// user agent and extra headers and through the configured proxy if any.
+pub async fn connect(
+    relay: &str,
+    cursor: Option<i64>,
+    config: &Config,
+) -> Result<FirehoseStream, tungstenite::Error> {
+    let url = match cursor {
+        Some(cursor) => format!("{relay}?cursor={cursor}"),
+        None => relay.to_string(),
+    };
+
+    let mut firehose_request = url.into_client_request()?;
+    let headers = firehose_request.headers_mut();
+    headers.insert(
+        "User-Agent",
+        HeaderValue::from_str(&config.user_agent).expect("Invalid FIREHOSE_USER_AGENT"),
+    );
+    for header in &config.headers {
+        headers.insert(header.name.clone(), header.value.clone());
+    }
+    let connector = Some(Connector::NativeTls(
+        TlsConnector::new().expect("Unable to use Native TLS. Does your system have it installed?"),
+    ));
+
+    let Some(proxy) = &config.proxy else {
+        let (stream, _response) = tokio_tungstenite::connect_async_tls_with_config(
+            firehose_request,
+            None,
+            true,
+            connector,
+        )
+        .await?;
+        return Ok(stream);
+    };
+
+    let uri = firehose_request.uri();
+    let host = uri.host().unwrap_or_default().to_string();
+    let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("ws") {
+        80
+    } else {
+        443
+    });
+    let tunnel = proxy
+        .connect(&host, port)
+        .await
+        .map_err(|e| tungstenite::Error::Io(std::io::Error::other(e)))?;
+    let (stream, _response) =
+        tokio_tungstenite::client_async_tls_with_config(firehose_request, tunnel, None, connector)
+            .await?;
+    Ok(stream)
+}
+
+/// Returns the sequence number of the first commit a relay in `config.relays` sends, i.e.
+/// roughly its live tip, and which relay it was. Fails over between them as the firehose
+/// connection does.
+pub async fn current_seq(config: &Config) -> Result<(String, i64), RelayError> {
+    RelayPool::from_config(config)?
+        .first_ok(
+            |relay| async move { first_commit(&relay, None, config).await.map(|(seq, _)| seq) },
+        )
+        .await
+}
+
+/// Returns the sequence number and time of the first commit `relay` sends from `cursor`.
+async fn first_commit(
+    relay: &str,
+    cursor: Option<i64>,
+    config: &Config,
+) -> Result<(i64, DateTime<FixedOffset>), tungstenite::Error> {
+    let mut stream = connect(relay, cursor, config).await?;
+    while let Some(msg) = stream.next().await {
+        if let Message::Binary(data) = msg? {
+            let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+                continue;
+            };
+            // Commits with a broken time can't place the stream in time; wait for another
+            if let Ok(time) = DateTime::parse_from_rfc3339(commit.time.as_str()) {
+                return Ok((commit.seq, time));
+            }
+        }
+    }
+    Err(tungstenite::Error::ConnectionClosed)
+}
+
+/// Finds the cursor to replay a relay in `config.relays` from so the first commit is the first
+/// one from `time` onwards, and which relay it was. Fails over between them as the firehose
+/// connection does, searching each one afresh since relays number commits differently.
+pub async fn seq_at(config: &Config, time: DateTime<Utc>) -> Result<(String, i64), RelayError> {
+    RelayPool::from_config(config)?
+        .first_ok(|relay| async move { relay_seq_at(&relay, time, config).await })
+        .await
+}
+
+/// Finds the cursor to replay `relay` from so the first commit is the first one from `time`
+/// onwards, by binary search over the commits it replays from different cursors. Times
+/// before the relay's replay window give a cursor replaying its oldest commit, and times in
+/// the future its live tip.
+async fn relay_seq_at(
+    relay: &str,
+    time: DateTime<Utc>,
+    config: &Config,
+) -> Result<i64, tungstenite::Error> {
+    let (mut high, tip) = first_commit(relay, None, config).await?;
+    if tip <= time {
+        return Ok(high);
+    }
+
+    // Replaying from `high` starts at or after `time`, and from below `low` before it.
+    // Relays take 0 as no cursor, so 1 is the oldest there is.
+    let mut low = 1;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let (seq, at) = first_commit(relay, Some(mid), config).await?;
+        if at < time {
+            // Sequence numbers have gaps: every cursor up to the commit sent starts there
+            low = seq.max(mid + 1);
+        } else {
+            high = mid;
+        }
+    }
+    Ok(high)
+}
@@ -0,0 +1,72 @@
+//! Append-only log of follow graph changes, from which the graph can be rebuilt as of any
+//! point in time.
+//!
+//! Each line is tab-separated: the time observed (Unix milliseconds), the firehose sequence
+//! number, `+` for a follow or `-` for an unfollow, the follower's DID, the follow record's
+//! rkey and the followed DID. Deletions don't say who was unfollowed, so their last field is
+//! empty; they undo the earlier `+` line with the same follower and rkey.
+
+use std::{path::Path, sync::Arc};
+
+use atrium_api::app::bsky::graph::follow;
+use chrono::Utc;
+
+use crate::{
+    appender::{AppendError, Appender},
+    client::Event,
+    frame::FrameError,
+    health::Health,
+    rotate::RotationPolicy,
+};
+
+pub const COLLECTION: &str = "app.bsky.graph.follow";
+const HEADER: &str = "time_ms\tseq\top\tfollower\trkey\tsubject\n";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FollowLogError {
+    #[error("malformed follow record: {0}")]
+    Malformed(#[from] FrameError),
+    #[error(transparent)]
+    Append(#[from] AppendError),
+}
+
+#[derive(Debug)]
+pub struct FollowLog {
+    appender: Appender,
+}
+
+impl FollowLog {
+    pub fn open(
+        path: &Path,
+        rotation: RotationPolicy,
+        health: Arc<Health>,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            appender: Appender::open(path, rotation, HEADER.as_bytes().to_vec(), health)?,
+        })
+    }
+
+    /// Queues a line for `evt` if it creates or deletes a follow.
+    pub fn record(&self, evt: &Event) -> Result<(), FollowLogError> {
+        if evt.collection != COLLECTION {
+            return Ok(());
+        }
+        let (op, subject) = match evt.action.as_str() {
+            "create" => match evt.record::<follow::Record>()? {
+                Some(record) => ('+', record.subject.as_str().to_string()),
+                None => return Ok(()),
+            },
+            "delete" => ('-', String::new()),
+            _ => return Ok(()),
+        };
+
+        let line = format!(
+            "{}\t{}\t{op}\t{}\t{}\t{subject}\n",
+            Utc::now().timestamp_millis(),
+            evt.seq,
+            evt.repo.as_str(),
+            evt.rkey,
+        );
+        Ok(self.appender.append(line.into_bytes())?)
+    }
+}
@@ -0,0 +1,387 @@
+use std::{collections::HashMap, io::Cursor};
+
+use atrium_api::{
+    app::bsky::feed::post,
+    com::atproto::sync::subscribe_repos::{
+        Account, AccountData, Commit, Handle, Identity, IdentityData, Migrate, Tombstone,
+    },
+    types::{
+        string::{Datetime, Did},
+        CidLink,
+    },
+};
+use bytes::Bytes;
+use ipld_core::{cid::Cid, ipld::Ipld};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use tracing::error;
+
+use crate::mst::ProofError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FrameError {
+    #[error("frame only contains a single DAG-CBOR object")]
+    MissingBody,
+    #[error("malformed frame header: {0}")]
+    Header(String),
+    #[error("malformed frame body: {0}")]
+    Body(String),
+    #[error("malformed record: {0}")]
+    Record(String),
+    #[error("invalid CAR file: {0}")]
+    Car(String),
+    #[error("inconsistent MST proof: {0}")]
+    Proof(#[from] ProofError),
+}
+
+impl FrameError {
+    /// What failed to decode, as counted in metrics and named in quarantine paths: `header`,
+    /// `commit` (the frame body), `car`, `proof` or `record`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MissingBody | Self::Header(_) => "header",
+            Self::Body(_) => "commit",
+            Self::Car(_) => "car",
+            Self::Proof(_) => "proof",
+            Self::Record(_) => "record",
+        }
+    }
+}
+
+/// A decoded `com.atproto.sync.subscribeRepos` websocket frame.
+#[derive(Debug)]
+pub enum Frame {
+    /// `op = 1`, `t = "#commit"`
+    Commit(Box<Commit>),
+    /// `op = 1`, `t = "#account"`, or a legacy `#tombstone` as a deleted, inactive account
+    Account(Box<Account>),
+    /// `op = 1`, `t = "#identity"`, or a legacy `#handle` or `#migrate`
+    Identity(Box<Identity>),
+    /// `op = 1`, `t = "#sync"`
+    Sync(Box<RepoSync>),
+    /// `op = -1`
+    Error(ErrorFrame),
+    /// Any other message type, identified by its `t`
+    Other(String),
+}
+
+/// A `#sync` message (sync v1.1): the current state of a repo, sent instead of a commit when
+/// its history can't be followed from earlier commits, e.g. after an account migration or a
+/// relay recovering from missed commits. Whatever was known about the repo before is stale.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RepoSync {
+    pub seq: i64,
+    pub did: Did,
+    /// CAR file holding only the signed commit block, see [`read_car`]
+    pub blocks: Bytes,
+    /// Rev of the commit the repo is now at
+    pub rev: String,
+    pub time: Datetime,
+}
+
+/// Error sent by the relay right before it closes the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorFrame {
+    pub error: ErrorKind,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The requested cursor is ahead of the relay's latest sequence number
+    FutureCursor,
+    /// We didn't keep up with the relay's outbound buffer
+    ConsumerTooSlow,
+    Other(String),
+}
+
+impl std::fmt::Display for ErrorFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            ErrorKind::FutureCursor => write!(f, "FutureCursor")?,
+            ErrorKind::ConsumerTooSlow => write!(f, "ConsumerTooSlow")?,
+            ErrorKind::Other(name) => write!(f, "{name}")?,
+        }
+        match &self.message {
+            Some(message) => write!(f, ": {message}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A post record created by a commit.
+#[derive(Debug)]
+pub struct Post {
+    pub cid: Option<CidLink>,
+    pub record: post::Record,
+}
+
+/// The header half of a frame.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameHeader {
+    /// `1` for messages, `-1` for errors
+    pub op: i8,
+    /// Message type, e.g. `#commit`; absent on errors
+    // https://github.com/bluesky-social/atproto/blob/c307a75db11503eedf743c01e62f90413f07fe2a/lexicons/com/atproto/sync/subscribeRepos.json#L20-L27
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub t: Option<String>,
+}
+
+/// Splits a binary websocket message into its decoded header and the raw body that follows.
+///
+/// Never panics, whatever the input.
+pub fn split_frame(data: &[u8]) -> Result<(FrameHeader, &[u8]), FrameError> {
+    // A frame is two DAG-CBOR objects back to back, the header then the body, and we don't
+    // know the header's size ahead of time. Skipping over a single object from a cursor fails
+    // with trailing data when a body follows, leaving the cursor right after the header.
+    let mut cursor = Cursor::new(data);
+    if serde_ipld_dagcbor::from_reader::<IgnoredAny, _>(&mut cursor).is_ok() {
+        return Err(FrameError::MissingBody);
+    }
+    let split = (cursor.position() as usize).min(data.len());
+    let (header, body) = data.split_at(split);
+
+    let header = serde_ipld_dagcbor::from_slice::<FrameHeader>(header)
+        .map_err(|e| FrameError::Header(e.to_string()))?;
+    Ok((header, body))
+}
+
+/// Encodes `commit` back into a `#commit` frame, e.g. to keep one that failed to dispatch.
+pub fn encode_commit(commit: &Commit) -> Vec<u8> {
+    let header = FrameHeader {
+        op: 1,
+        t: Some("#commit".to_string()),
+    };
+    let mut frame = serde_ipld_dagcbor::to_vec(&header).expect("frame headers always encode");
+    frame.extend(serde_ipld_dagcbor::to_vec(commit).expect("commits always encode"));
+    frame
+}
+
+/// Decodes a single binary websocket message into a [`Frame`].
+pub fn decode(data: &[u8]) -> Result<Frame, FrameError> {
+    let (header, body) = split_frame(data)?;
+    if header.op == -1 {
+        return decode_error(body).map(Frame::Error);
+    }
+
+    let Some(message) = header.t else {
+        return Err(FrameError::Header("expected \"t\" to be a string".into()));
+    };
+    // Only going to parse #commit, #sync, #account and #identity, and the events they replaced
+    match message.as_str() {
+        "#commit" => {
+            let commit = serde_ipld_dagcbor::from_slice::<Commit>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            Ok(Frame::Commit(Box::new(commit)))
+        }
+        "#account" => {
+            let account = serde_ipld_dagcbor::from_slice::<Account>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            Ok(Frame::Account(Box::new(account)))
+        }
+        "#identity" => {
+            let identity = serde_ipld_dagcbor::from_slice::<Identity>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            Ok(Frame::Identity(Box::new(identity)))
+        }
+        "#sync" => {
+            let sync = serde_ipld_dagcbor::from_slice::<RepoSync>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            Ok(Frame::Sync(Box::new(sync)))
+        }
+        // Deprecated in favour of #identity and #account, but older relays and archived
+        // streams still carry them
+        "#handle" => {
+            let handle = serde_ipld_dagcbor::from_slice::<Handle>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            let identity = IdentityData {
+                did: handle.data.did,
+                handle: Some(handle.data.handle),
+                seq: handle.data.seq,
+                time: handle.data.time,
+            };
+            Ok(Frame::Identity(Box::new(identity.into())))
+        }
+        "#migrate" => {
+            // The account moved to another PDS: its DID document changed, which is what
+            // #identity signals
+            let migrate = serde_ipld_dagcbor::from_slice::<Migrate>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            let identity = IdentityData {
+                did: migrate.data.did,
+                handle: None,
+                seq: migrate.data.seq,
+                time: migrate.data.time,
+            };
+            Ok(Frame::Identity(Box::new(identity.into())))
+        }
+        "#tombstone" => {
+            let tombstone = serde_ipld_dagcbor::from_slice::<Tombstone>(body)
+                .map_err(|e| FrameError::Body(e.to_string()))?;
+            let account = AccountData {
+                active: false,
+                did: tombstone.data.did,
+                seq: tombstone.data.seq,
+                status: Some("deleted".to_string()),
+                time: tombstone.data.time,
+            };
+            Ok(Frame::Account(Box::new(account.into())))
+        }
+        _ => Ok(Frame::Other(message)),
+    }
+}
+
+/// Decodes the `{error, message}` body of an `op = -1` frame.
+fn decode_error(data: &[u8]) -> Result<ErrorFrame, FrameError> {
+    let Ipld::Map(mut body) = serde_ipld_dagcbor::from_slice::<Ipld>(data)
+        .map_err(|e| FrameError::Body(e.to_string()))?
+    else {
+        return Err(FrameError::Body("expected a map".into()));
+    };
+    let Some(Ipld::String(error)) = body.remove("error") else {
+        return Err(FrameError::Body("expected \"error\" to be a string".into()));
+    };
+    let message = match body.remove("message") {
+        Some(Ipld::String(message)) => Some(message),
+        _ => None,
+    };
+
+    Ok(ErrorFrame {
+        error: match error.as_str() {
+            "FutureCursor" => ErrorKind::FutureCursor,
+            "ConsumerTooSlow" => ErrorKind::ConsumerTooSlow,
+            _ => ErrorKind::Other(error),
+        },
+        message,
+    })
+}
+
+/// Longest binary CID looked up: version, codec, hash code and digest length varints, then a
+/// digest of up to 64 bytes
+const MAX_CID_LEN: usize = 4 * 9 + 64;
+
+/// The blocks of a CAR file, keyed by binary CID so lookups need no string encoding.
+///
+/// `rs-car` and `atrium-api` depend on different versions of the `cid` crate; both agree on a
+/// CID's bytes. Blocks are [`Bytes`], so handing one to every event interested in it is a
+/// reference count rather than a copy.
+#[derive(Debug, Clone, Default)]
+pub struct Blocks(HashMap<Box<[u8]>, Bytes>);
+
+impl Blocks {
+    pub fn get(&self, cid: &Cid) -> Option<&Bytes> {
+        let mut key = [0; MAX_CID_LEN];
+        let len = cid.write_bytes(&mut key[..]).ok()?;
+        self.0.get(&key[..len])
+    }
+
+    pub fn insert(&mut self, cid: &Cid, block: Bytes) -> Option<Bytes> {
+        self.0.insert(cid.to_bytes().into(), block)
+    }
+
+    pub fn remove(&mut self, cid: &Cid) -> Option<Bytes> {
+        self.0.remove(cid.to_bytes().as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Every block with its CID.
+    pub fn iter(&self) -> impl Iterator<Item = (Cid, &Bytes)> {
+        self.0.iter().filter_map(|(cid, block)| {
+            // Keys only ever come from CIDs, so they always parse back
+            Some((Cid::try_from(&cid[..]).ok()?, block))
+        })
+    }
+}
+
+/// Reads the CAR file attached to `commit`.
+pub async fn blocks(commit: &Commit) -> Result<Blocks, FrameError> {
+    parse_car(&commit.blocks).await
+}
+
+/// Like [`blocks`], from the raw CAR file.
+pub async fn parse_car(car: &[u8]) -> Result<Blocks, FrameError> {
+    read_car(car).await.map(|(_, blocks)| blocks)
+}
+
+/// Reads a CAR file's roots and blocks. Every block is checked against its CID.
+pub async fn read_car(mut car: &[u8]) -> Result<(Vec<Cid>, Blocks), FrameError> {
+    let (items, header) = rs_car::car_read_all(&mut car, true)
+        .await
+        .map_err(|e| FrameError::Car(format!("{e:?}")))?;
+
+    let roots = header
+        .roots
+        .iter()
+        .map(|root| Cid::try_from(root.to_bytes()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| FrameError::Car(e.to_string()))?;
+    let blocks = items
+        .into_iter()
+        .map(|(cid, data)| (cid.to_bytes().into(), Bytes::from(data)))
+        .collect();
+    Ok((roots, Blocks(blocks)))
+}
+
+/// Extracts every post created by `commit` from its CAR blocks.
+pub async fn posts(commit: &Commit) -> Result<Vec<Post>, FrameError> {
+    let blocks = blocks(commit).await?;
+
+    let mut posts = Vec::new();
+    for operation in &commit.ops {
+        // Only parse CREATE action
+        if operation.action != "create" {
+            continue;
+        }
+
+        // Only parse post
+        if !operation.path.starts_with("app.bsky.feed.post") {
+            continue;
+        }
+
+        let Some(data) = operation.cid.as_ref().and_then(|cid| blocks.get(&cid.0)) else {
+            error!("Could not find block for CID {:?}", operation.cid);
+            continue;
+        };
+
+        let record = serde_ipld_dagcbor::from_slice::<post::Record>(data)
+            .map_err(|e| FrameError::Record(e.to_string()))?;
+        posts.push(Post {
+            cid: operation.cid.clone(),
+            record,
+        });
+    }
+
+    Ok(posts)
+}
+
+/// Converts a DAG-CBOR record to its atproto JSON form, where links become `{"$link": cid}`
+/// and bytes become `{"$bytes": base64}`.
+pub fn record_json(block: &[u8]) -> Result<serde_json::Value, FrameError> {
+    let record = serde_ipld_dagcbor::from_slice::<Ipld>(block)
+        .map_err(|e| FrameError::Record(e.to_string()))?;
+    Ok(ipld_json(record))
+}
+
+fn ipld_json(ipld: Ipld) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    match ipld {
+        Ipld::Null => Value::Null,
+        Ipld::Bool(b) => Value::Bool(b),
+        Ipld::Integer(i) => i64::try_from(i)
+            .map(Value::from)
+            .unwrap_or_else(|_| Value::String(i.to_string())),
+        Ipld::Float(f) => json!(f),
+        Ipld::String(s) => Value::String(s),
+        Ipld::Bytes(bytes) => json!({ "$bytes": data_encoding::BASE64_NOPAD.encode(&bytes) }),
+        Ipld::List(list) => Value::Array(list.into_iter().map(ipld_json).collect()),
+        Ipld::Map(map) => Value::Object(map.into_iter().map(|(k, v)| (k, ipld_json(v))).collect()),
+        Ipld::Link(cid) => json!({ "$link": cid.to_string() }),
+    }
+}
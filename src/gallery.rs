@@ -0,0 +1,330 @@
+//! Browsable web gallery of detected haikus at `/haikus`, newest first, paged from the haiku
+//! output file, with new haikus streamed in as server-sent events from `/haikus/events`.
+
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        Html,
+    },
+    routing::get,
+    Router,
+};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+use crate::haiku::HaikuRecord;
+
+/// New haikus buffered per live page before the slowest ones start skipping
+const LIVE_CAPACITY: usize = 64;
+
+/// The parts of a [`HaikuRecord`] the gallery shows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GalleryHaiku {
+    pub url: String,
+    pub did: String,
+    pub handle: Option<String>,
+    /// Unverified handles aren't shown
+    #[serde(default)]
+    pub handle_verified: Option<bool>,
+    pub created_at: String,
+    pub form: String,
+    pub lines: Vec<String>,
+    /// Read from the haiku output file so held back haikus stay off the pages
+    #[serde(default, skip_serializing)]
+    pub held_back: bool,
+}
+
+impl From<&HaikuRecord> for GalleryHaiku {
+    fn from(haiku: &HaikuRecord) -> Self {
+        Self {
+            url: haiku.url.clone(),
+            did: haiku.did.clone(),
+            handle: haiku.handle.clone(),
+            handle_verified: haiku.handle_verified,
+            created_at: haiku.created_at.clone(),
+            form: haiku.form.clone(),
+            lines: haiku.lines.clone(),
+            held_back: haiku.held_back,
+        }
+    }
+}
+
+/// Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Gallery {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    page_size: usize,
+    index: Mutex<LineIndex>,
+    live: broadcast::Sender<Arc<str>>,
+}
+
+/// Where each complete line of the haiku file starts, so a page can be read without reading
+/// the whole file. Extended as the file grows, and rebuilt when it's rotated.
+#[derive(Debug, Default)]
+struct LineIndex {
+    starts: Vec<u64>,
+    /// Start of the line being written, and how far the file has been scanned
+    end: u64,
+    scanned: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    page: Option<usize>,
+}
+
+impl Gallery {
+    /// Serves haikus from `path`, the haiku output file, `page_size` to a page.
+    pub fn new(path: PathBuf, page_size: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                path,
+                page_size: page_size.max(1),
+                index: Mutex::new(LineIndex::default()),
+                live: broadcast::channel(LIVE_CAPACITY).0,
+            }),
+        }
+    }
+
+    /// Sends `haiku` to every open gallery page, unless moderation held it back.
+    pub fn publish(&self, haiku: &HaikuRecord) {
+        if haiku.held_back || self.inner.live.receiver_count() == 0 {
+            return;
+        }
+        let json = serde_json::to_string(&GalleryHaiku::from(haiku))
+            .expect("gallery haikus are always serializable");
+        // Only fails when every page closed in the meantime
+        let _ = self.inner.live.send(json.into());
+    }
+
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route("/haikus", get(page))
+            .route("/haikus/events", get(events))
+            .with_state(self.clone())
+    }
+
+    /// Haikus published from now on, as the JSON sent to open pages.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<str>> {
+        self.inner.live.subscribe()
+    }
+
+    /// The haikus on 1-based `page`, newest first, and the number of pages. Held back haikus
+    /// are left out, so a page may show fewer than `page_size`.
+    pub fn read_page(&self, page: usize) -> io::Result<(Vec<GalleryHaiku>, usize)> {
+        let mut file = File::open(&self.inner.path)?;
+        let mut index = self.inner.index.lock().unwrap();
+        index.update(&mut file)?;
+
+        let total = index.starts.len();
+        let pages = total.div_ceil(self.inner.page_size).max(1);
+        let newest = total.saturating_sub((page - 1) * self.inner.page_size);
+        let oldest = newest.saturating_sub(self.inner.page_size);
+
+        let mut haikus = Vec::with_capacity(newest - oldest);
+        for i in (oldest..newest).rev() {
+            let start = index.starts[i];
+            let end = index.starts.get(i + 1).copied().unwrap_or(index.end);
+            let mut line = vec![0; (end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut line)?;
+            match serde_json::from_slice(&line) {
+                Ok(GalleryHaiku {
+                    held_back: true, ..
+                }) => {}
+                Ok(haiku) => haikus.push(haiku),
+                Err(e) => warn!("Skipping malformed haiku at byte {start}: {e}"),
+            }
+        }
+        Ok((haikus, pages))
+    }
+}
+
+impl LineIndex {
+    fn update(&mut self, file: &mut File) -> io::Result<()> {
+        let len = file.metadata()?.len();
+        if len < self.scanned {
+            *self = Self::default();
+        }
+
+        file.seek(SeekFrom::Start(self.scanned))?;
+        let mut buf = Vec::new();
+        file.take(len - self.scanned).read_to_end(&mut buf)?;
+        for (i, byte) in buf.iter().enumerate() {
+            if *byte == b'\n' {
+                self.starts.push(self.end);
+                self.end = self.scanned + i as u64 + 1;
+            }
+        }
+        self.scanned += buf.len() as u64;
+        Ok(())
+    }
+}
+
+async fn page(
+    State(gallery): State<Gallery>,
+    Query(query): Query<PageQuery>,
+) -> Result<Html<String>, (StatusCode, &'static str)> {
+    let page = query.page.unwrap_or(1).max(1);
+    let read = {
+        let gallery = gallery.clone();
+        tokio::task::spawn_blocking(move || gallery.read_page(page))
+            .await
+            .expect("gallery page reader panicked")
+    };
+    match read {
+        Ok((haikus, pages)) => Ok(Html(render_page(&haikus, page, pages))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Html(render_page(&[], 1, 1))),
+        Err(e) => {
+            error!("Unable to read haikus for the gallery: {e}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Unable to read haikus"))
+        }
+    }
+}
+
+async fn events(
+    State(gallery): State<Gallery>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let events = stream::unfold(gallery.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(json) => {
+                    return Some((Ok(sse::Event::default().event("haiku").data(&*json)), rx));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Gallery page is too slow, skipped {skipped} haikus");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+fn render_page(haikus: &[GalleryHaiku], page: usize, pages: usize) -> String {
+    let mut html = String::from(HEADER);
+    html.push_str("<main id=\"haikus\">\n");
+    if haikus.is_empty() {
+        html.push_str("<p class=\"empty\">No haikus yet.</p>\n");
+    }
+    for haiku in haikus {
+        html.push_str(&render_haiku(haiku));
+    }
+    html.push_str("</main>\n<nav>");
+    if page > 1 {
+        html.push_str(&format!("<a href=\"?page={}\">&larr; Newer</a>", page - 1));
+    }
+    html.push_str(&format!(" <span>Page {page} of {pages}</span> "));
+    if page < pages {
+        html.push_str(&format!("<a href=\"?page={}\">Older &rarr;</a>", page + 1));
+    }
+    html.push_str("</nav>\n");
+    // Only the first page shows the newest haikus, so only it updates live
+    if page == 1 {
+        html.push_str(LIVE_SCRIPT);
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn render_haiku(haiku: &GalleryHaiku) -> String {
+    let lines = haiku
+        .lines
+        .iter()
+        .map(|line| escape(line))
+        .collect::<Vec<_>>()
+        .join("<br>");
+    let author = match &haiku.handle {
+        Some(handle) if haiku.handle_verified != Some(false) => format!("@{handle}"),
+        _ => haiku.did.clone(),
+    };
+    format!(
+        "<article><p>{lines}</p><footer>{} by <a href=\"https://bsky.app/profile/{}\">{}</a> \
+         &middot; <a href=\"{}\">{}</a></footer></article>\n",
+        escape(&haiku.form),
+        escape(&haiku.did),
+        escape(&author),
+        escape(&haiku.url),
+        escape(&haiku.created_at),
+    )
+}
+
+/// Escapes `text` for use in HTML or XML text and attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const HEADER: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Haikus from the firehose</title>
+<style>
+body { font-family: Georgia, serif; max-width: 40em; margin: 2em auto; padding: 0 1em; color: #222; }
+article { border-bottom: 1px solid #ddd; padding: 1em 0; }
+article p { font-size: 1.2em; line-height: 1.5; margin: 0 0 0.5em; }
+footer { font: 0.85em sans-serif; color: #666; }
+a { color: #0560ff; }
+nav { display: flex; justify-content: space-between; margin: 1.5em 0; font-family: sans-serif; }
+</style>
+</head>
+<body>
+<h1>Haikus from the firehose</h1>
+"#;
+
+/// Renders haikus streamed from `/haikus/events` like the server does, using text nodes so
+/// post text can't inject markup.
+const LIVE_SCRIPT: &str = r#"<script>
+const haikus = document.getElementById("haikus");
+new EventSource("/haikus/events").addEventListener("haiku", (event) => {
+  const haiku = JSON.parse(event.data);
+  const article = document.createElement("article");
+  const text = document.createElement("p");
+  haiku.lines.forEach((line, i) => {
+    if (i > 0) text.append(document.createElement("br"));
+    text.append(line);
+  });
+  const footer = document.createElement("footer");
+  const author = document.createElement("a");
+  author.href = "https://bsky.app/profile/" + haiku.did;
+  author.textContent =
+    haiku.handle && haiku.handle_verified !== false ? "@" + haiku.handle : haiku.did;
+  const post = document.createElement("a");
+  post.href = haiku.url;
+  post.textContent = haiku.created_at;
+  footer.append(haiku.form + " by ", author, " · ", post);
+  article.append(text, footer);
+  haikus.querySelector(".empty")?.remove();
+  haikus.prepend(article);
+});
+</script>
+"#;
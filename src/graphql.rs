@@ -0,0 +1,229 @@
+//! GraphQL API over the posts and haikus captured in SQLite, at `/graphql` (GraphiQL when
+//! opened in a browser), with newly found haikus pushed to subscriptions over `/graphql/ws`.
+//!
+//! ```graphql
+//! {
+//!   posts(author: "did:plc:z72i7hdynmk6r22z27h6tvur", limit: 10) { uri text createdAt }
+//!   haikus(from: "2024-11-01", to: "2024-11-02") { form lines handle }
+//! }
+//! ```
+
+use async_graphql::{
+    http::GraphiQLSource, EmptyMutation, Object, Result, Schema, SimpleObject, Subscription,
+};
+use async_graphql_axum::{GraphQL, GraphQLSubscription};
+use axum::{response::Html, routing::get, Router};
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::{
+    haiku::HaikuRecord,
+    sqlite::{PostSearch, SearchHit, StoredHaiku, Window},
+};
+
+/// New haikus buffered per subscription before the slowest ones start skipping
+const LIVE_CAPACITY: usize = 64;
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+pub type FirehoseSchema = Schema<Query, EmptyMutation, Subscriptions>;
+
+#[derive(Debug, Clone, SimpleObject)]
+struct Post {
+    uri: String,
+    did: String,
+    text: String,
+    created_at: Option<String>,
+    /// Unix milliseconds the post was captured at
+    indexed_at: i64,
+}
+
+impl From<SearchHit> for Post {
+    fn from(hit: SearchHit) -> Self {
+        Self {
+            uri: hit.uri,
+            did: hit.did,
+            text: hit.text,
+            created_at: hit.created_at,
+            indexed_at: hit.indexed_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+struct Haiku {
+    uri: String,
+    did: String,
+    /// Only set when verified, if handle verification is enabled
+    handle: Option<String>,
+    form: String,
+    lines: Vec<String>,
+    created_at: String,
+}
+
+impl From<StoredHaiku> for Haiku {
+    fn from(haiku: StoredHaiku) -> Self {
+        Self {
+            uri: haiku.uri,
+            did: haiku.did,
+            handle: haiku.handle,
+            form: haiku.form,
+            lines: haiku.lines,
+            created_at: haiku.created_at,
+        }
+    }
+}
+
+impl From<&HaikuRecord> for Haiku {
+    fn from(haiku: &HaikuRecord) -> Self {
+        Self {
+            uri: haiku.uri.clone(),
+            did: haiku.did.clone(),
+            handle: haiku.display_handle().map(str::to_string),
+            form: haiku.form.clone(),
+            lines: haiku.lines.clone(),
+            created_at: haiku.created_at.clone(),
+        }
+    }
+}
+
+/// The query root.
+pub struct Query {
+    posts: PostSearch,
+}
+
+#[Object]
+impl Query {
+    /// Posts by `author` (a DID), most recently captured first.
+    async fn posts(&self, author: String, limit: Option<i32>) -> Result<Vec<Post>> {
+        let posts = self.posts.clone();
+        let limit = clamp(limit);
+        let hits = tokio::task::spawn_blocking(move || posts.by_author(&author, limit))
+            .await
+            .expect("queries don't panic")?;
+        Ok(hits.into_iter().map(Post::from).collect())
+    }
+
+    /// Posts matching the FTS5 `query`, best match first, captured within `since` (e.g. `1d`)
+    /// if given.
+    async fn search(
+        &self,
+        query: String,
+        since: Option<String>,
+        limit: Option<i32>,
+    ) -> Result<Vec<Post>> {
+        let window = since.as_deref().map(str::parse::<Window>).transpose()?;
+        let posts = self.posts.clone();
+        let limit = clamp(limit);
+        let hits = tokio::task::spawn_blocking(move || posts.search(&query, window, limit))
+            .await
+            .expect("queries don't panic")?;
+        Ok(hits.into_iter().map(Post::from).collect())
+    }
+
+    /// Haikus created from `from` up to `to` (RFC 3339 times or dates), of `form` if given,
+    /// newest first.
+    async fn haikus(
+        &self,
+        from: Option<String>,
+        to: Option<String>,
+        form: Option<String>,
+        limit: Option<i32>,
+    ) -> Result<Vec<Haiku>> {
+        let posts = self.posts.clone();
+        let limit = clamp(limit);
+        let haikus = tokio::task::spawn_blocking(move || {
+            posts.haikus(from.as_deref(), to.as_deref(), form.as_deref(), limit)
+        })
+        .await
+        .expect("queries don't panic")?;
+        Ok(haikus.into_iter().map(Haiku::from).collect())
+    }
+}
+
+fn clamp(limit: Option<i32>) -> usize {
+    limit
+        .map_or(DEFAULT_LIMIT, |limit| limit.max(1) as usize)
+        .min(MAX_LIMIT)
+}
+
+/// The subscription root.
+pub struct Subscriptions {
+    live: broadcast::Sender<Haiku>,
+}
+
+#[Subscription]
+impl Subscriptions {
+    /// Haikus as they're found, only those of `form` if given.
+    async fn haikus(&self, form: Option<String>) -> impl Stream<Item = Haiku> {
+        stream::unfold(self.live.subscribe(), move |mut rx| {
+            let form = form.clone();
+            async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(haiku) if form.as_ref().is_none_or(|form| *form == haiku.form) => {
+                            return Some((haiku, rx));
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("GraphQL subscriber is too slow, skipped {skipped} haikus");
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Serves the API and feeds its subscriptions. Cheap to clone.
+#[derive(Clone)]
+pub struct GraphQl {
+    schema: FirehoseSchema,
+    live: broadcast::Sender<Haiku>,
+}
+
+impl GraphQl {
+    pub fn new(posts: PostSearch) -> Self {
+        let live = broadcast::channel(LIVE_CAPACITY).0;
+        let schema = Schema::build(
+            Query { posts },
+            EmptyMutation,
+            Subscriptions { live: live.clone() },
+        )
+        .finish();
+        Self { schema, live }
+    }
+
+    /// Sends `haiku` to every subscription. Does nothing when nobody is subscribed or
+    /// moderation held it back.
+    pub fn publish(&self, haiku: &HaikuRecord) {
+        if !haiku.held_back && self.live.receiver_count() > 0 {
+            // Only fails when every subscriber disconnected in the meantime
+            let _ = self.live.send(Haiku::from(haiku));
+        }
+    }
+
+    pub fn schema(&self) -> &FirehoseSchema {
+        &self.schema
+    }
+
+    pub fn routes(self) -> Router {
+        Router::new()
+            .route(
+                "/graphql",
+                get(graphiql).post_service(GraphQL::new(self.schema.clone())),
+            )
+            .route_service("/graphql/ws", GraphQLSubscription::new(self.schema))
+    }
+}
+
+async fn graphiql() -> Html<String> {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/graphql")
+            .subscription_endpoint("/graphql/ws")
+            .finish(),
+    )
+}
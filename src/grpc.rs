@@ -0,0 +1,145 @@
+//! Serves decoded repo operations over gRPC, for services in other languages that want typed
+//! events on a stable contract. The schema is `proto/firehose.proto`; each `Subscribe` call
+//! streams the operations passing its filters, like `/subscribe` does over websocket.
+
+use std::{collections::HashSet, net::SocketAddr, pin::Pin, sync::Arc};
+
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{error, info, warn};
+
+use crate::{
+    client::{glob_match, Event},
+    frame, task,
+};
+
+use self::proto::{
+    firehose_server::{Firehose, FirehoseServer},
+    FilterRequest,
+};
+
+/// Code generated from `proto/firehose.proto`.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("firehose.v1");
+}
+
+/// Converts and broadcasts operations to every `Subscribe` call. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct EventService {
+    tx: broadcast::Sender<Arc<proto::Event>>,
+}
+
+impl EventService {
+    /// `capacity` events are buffered per subscriber before the slowest ones start skipping.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Sends `evt` to every subscriber. Does nothing when nobody is subscribed.
+    pub fn publish(&self, evt: &Event) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+
+        let record_json = match evt.block.as_deref().map(frame::record_json).transpose() {
+            Ok(record) => record.map(|record| record.to_string()),
+            Err(e) => {
+                warn!(
+                    "Unable to convert {}/{} to JSON: {e}",
+                    evt.collection, evt.rkey
+                );
+                return;
+            }
+        };
+        // Only fails when every subscriber disconnected in the meantime
+        let _ = self.tx.send(Arc::new(proto::Event {
+            seq: evt.seq,
+            repo: evt.repo.as_str().to_string(),
+            rev: evt.rev.clone(),
+            since: evt.since.clone(),
+            action: evt.action.clone(),
+            collection: evt.collection.clone(),
+            rkey: evt.rkey.clone(),
+            cid: evt.cid.as_ref().map(|cid| cid.0.to_string()),
+            record_json,
+            record_cbor: evt.block.as_ref().map(|block| block.to_vec()),
+            account_status: evt
+                .account_status
+                .as_ref()
+                .map(|status| status.as_str().to_string()),
+        }));
+    }
+}
+
+/// A subscription's filters, each matching everything when empty.
+struct Filter {
+    collections: Vec<String>,
+    dids: HashSet<String>,
+    actions: Vec<String>,
+}
+
+impl Filter {
+    fn matches(&self, event: &proto::Event) -> bool {
+        (self.collections.is_empty()
+            || self
+                .collections
+                .iter()
+                .any(|pattern| glob_match(pattern, &event.collection)))
+            && (self.dids.is_empty() || self.dids.contains(&event.repo))
+            && (self.actions.is_empty() || self.actions.contains(&event.action))
+    }
+}
+
+#[tonic::async_trait]
+impl Firehose for EventService {
+    type SubscribeStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<FilterRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        info!("gRPC consumer subscribed ({request:?})");
+        let filter = Filter {
+            collections: request.collections,
+            dids: request.dids.into_iter().collect(),
+            actions: request.actions,
+        };
+        let events = stream::unfold(
+            (self.tx.subscribe(), filter),
+            |(mut rx, filter)| async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(event) if filter.matches(&event) => {
+                            return Some((Ok(proto::Event::clone(&event)), (rx, filter)));
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("gRPC consumer is too slow, skipped {skipped} events");
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        );
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Serves `service` on `addr` in the background.
+pub fn spawn(addr: SocketAddr, service: EventService) {
+    task::spawn("grpc", async move {
+        info!("Serving gRPC on {addr}");
+        if let Err(e) = Server::builder()
+            .add_service(FirehoseServer::new(service))
+            .serve(addr)
+            .await
+        {
+            error!("gRPC server on {addr} failed: {e}");
+        }
+    });
+}
@@ -0,0 +1,324 @@
+//! Haiku (and other syllable-counted form) detection: posts whose words split cleanly into
+//! lines of a given number of syllables.
+
+use std::{ops::RangeInclusive, path::PathBuf, str::FromStr};
+
+use atrium_api::app::bsky::feed::post;
+use serde::Serialize;
+
+use crate::{
+    accounts::AccountStatus, client::Event, embed::Embed, facets::Facets, identity::ResolvedHandle,
+    language::Detection, neardup::NearDuplicate, rhyme::Rhyme, syllables::SyllableCounter,
+    thread::Reply,
+};
+
+/// A poetic form defined by the number of syllables on each line, e.g. `tanka=5-7-5-7-7`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyllablePattern {
+    pub name: String,
+    pub lines: Vec<usize>,
+}
+
+impl SyllablePattern {
+    pub fn haiku() -> Self {
+        Self {
+            name: "haiku".to_string(),
+            lines: vec![5, 7, 5],
+        }
+    }
+
+    /// Splits `words` into lines following this pattern, breaking only between words.
+    fn split(&self, words: &[(&str, usize)]) -> Option<Haiku> {
+        let mut words = words.iter();
+        let mut haiku = Haiku {
+            form: self.name.clone(),
+            lines: Vec::with_capacity(self.lines.len()),
+            syllables: Vec::with_capacity(self.lines.len()),
+        };
+        for &target in &self.lines {
+            let mut line = Vec::new();
+            let mut count = 0;
+            while count < target {
+                let (word, n) = words.next()?;
+                line.push(*word);
+                count += n;
+            }
+            if count != target {
+                return None;
+            }
+            haiku.lines.push(line.join(" "));
+            haiku.syllables.push(count);
+        }
+
+        // Leftover words means the post is longer than the pattern
+        words.next().is_none().then_some(haiku)
+    }
+}
+
+impl FromStr for SyllablePattern {
+    type Err = String;
+
+    /// Parses `name=5-7-5`, or a bare `5-7-5` which is named after itself.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, lines) = s.split_once('=').unwrap_or((s, s));
+        let lines = lines
+            .split('-')
+            .map(|n| n.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid syllable pattern {s:?}: {e}"))?;
+        if lines.is_empty() || lines.contains(&0) {
+            return Err(format!(
+                "invalid syllable pattern {s:?}: lines can't be empty"
+            ));
+        }
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            lines,
+        })
+    }
+}
+
+/// A post matching one of the configured [`SyllablePattern`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Haiku {
+    /// Name of the matched pattern
+    pub form: String,
+    pub lines: Vec<String>,
+    pub syllables: Vec<usize>,
+}
+
+/// Returns the first of `patterns` that `text` can be split into.
+///
+/// Posts containing anything we can't count syllables for (digits, URLs, non-latin scripts)
+/// never match.
+pub fn detect(
+    text: &str,
+    patterns: &[SyllablePattern],
+    counter: &SyllableCounter,
+) -> Option<Haiku> {
+    let words = countable_words(text)?
+        .into_iter()
+        .map(|(word, letters)| (word, counter.count(letters)))
+        .collect::<Vec<_>>();
+
+    patterns.iter().find_map(|pattern| pattern.split(&words))
+}
+
+/// Syllables allowed on each line of a limerick
+const LIMERICK_LINES: [RangeInclusive<usize>; 5] = [7..=10, 7..=10, 4..=7, 4..=7, 7..=10];
+
+/// Returns `text` as a limerick: five lines rhyming AABBA, the A lines of 7 to 10 syllables
+/// and the B lines of 4 to 7.
+///
+/// Unlike [`detect`], the post's own line breaks are kept, since they are what makes the rhyme
+/// scheme.
+pub fn detect_limerick(text: &str, counter: &SyllableCounter) -> Option<Haiku> {
+    let verse = Verse::scan(text, LIMERICK_LINES.len(), counter)?;
+    if !verse
+        .syllables
+        .iter()
+        .zip(&LIMERICK_LINES)
+        .all(|(count, allowed)| allowed.contains(count))
+        || !verse.rhymes_as("AABBA")
+        || verse.rhymes[0].rhymes_with(&verse.rhymes[2])
+    {
+        return None;
+    }
+    Some(verse.into_form("limerick"))
+}
+
+/// Lines in a sonnet
+const SONNET_LINES: usize = 14;
+/// Syllables allowed on each line of a sonnet: iambic pentameter's ten, give or take a
+/// feminine ending or an estimate off by one
+const SONNET_SYLLABLES: RangeInclusive<usize> = 8..=12;
+/// Rhyme schemes accepted when checking rhymes: Shakespearean, and Petrarchan with its two
+/// usual sestets
+const SONNET_SCHEMES: [&str; 3] = ["ABABCDCDEFEFGG", "ABBAABBACDECDE", "ABBAABBACDCDCD"];
+
+/// Returns `text` as a sonnet: fourteen lines of 8 to 12 syllables, and if `check_rhymes`,
+/// rhyming like a Shakespearean or Petrarchan sonnet. Stress isn't checked, so this is only a
+/// heuristic for iambic pentameter.
+pub fn detect_sonnet(text: &str, counter: &SyllableCounter, check_rhymes: bool) -> Option<Haiku> {
+    let verse = Verse::scan(text, SONNET_LINES, counter)?;
+    if !verse
+        .syllables
+        .iter()
+        .all(|count| SONNET_SYLLABLES.contains(count))
+        || (check_rhymes && !SONNET_SCHEMES.iter().any(|scheme| verse.rhymes_as(scheme)))
+    {
+        return None;
+    }
+    Some(verse.into_form("sonnet"))
+}
+
+/// A post's own lines with their syllable counts and end rhymes, for forms defined by both.
+struct Verse<'a> {
+    lines: Vec<&'a str>,
+    syllables: Vec<usize>,
+    /// Rhyme of each line's last word
+    rhymes: Vec<Rhyme>,
+}
+
+impl<'a> Verse<'a> {
+    /// Returns `None` unless `text` has `expected` lines, each with something to count and
+    /// nothing that can't be counted.
+    fn scan(text: &'a str, expected: usize, counter: &SyllableCounter) -> Option<Self> {
+        let lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        if lines.len() != expected {
+            return None;
+        }
+        let mut syllables = Vec::with_capacity(lines.len());
+        let mut rhymes = Vec::with_capacity(lines.len());
+        for line in &lines {
+            let words = countable_words(line)?;
+            let (_, last) = words.last()?;
+            syllables.push(
+                words
+                    .iter()
+                    .map(|(_, letters)| counter.count(letters))
+                    .sum::<usize>(),
+            );
+            rhymes.push(counter.rhyme(last));
+        }
+        Some(Self {
+            lines,
+            syllables,
+            rhymes,
+        })
+    }
+
+    /// Whether lines given the same letter in `scheme` rhyme, e.g. `ABAB`.
+    fn rhymes_as(&self, scheme: &str) -> bool {
+        scheme.len() == self.rhymes.len()
+            && scheme.bytes().zip(&self.rhymes).all(|(letter, rhyme)| {
+                scheme
+                    .bytes()
+                    .zip(&self.rhymes)
+                    .all(|(other, other_rhyme)| other != letter || other_rhyme.rhymes_with(rhyme))
+            })
+    }
+
+    fn into_form(self, form: &str) -> Haiku {
+        Haiku {
+            form: form.to_string(),
+            lines: self.lines.into_iter().map(String::from).collect(),
+            syllables: self.syllables,
+        }
+    }
+}
+
+/// Splits `text` into words paired with their letters, without surrounding punctuation.
+///
+/// Returns `None` if any word contains something we can't count syllables for (digits, URLs,
+/// non-latin scripts).
+fn countable_words(text: &str) -> Option<Vec<(&str, &str)>> {
+    let mut words = Vec::new();
+    for word in text.split_whitespace() {
+        let letters = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if letters.is_empty() {
+            continue;
+        }
+        if !letters
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || c == '\'' || c == '’' || c == '-')
+        {
+            return None;
+        }
+        words.push((word, letters));
+    }
+    Some(words)
+}
+
+/// A detected haiku, as written to the haiku output file.
+#[derive(Debug, Clone, Serialize)]
+pub struct HaikuRecord {
+    pub uri: String,
+    pub url: String,
+    pub cid: Option<String>,
+    pub did: String,
+    pub handle: Option<String>,
+    /// Whether `handle` resolves back to `did`; unset when handle verification is disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle_verified: Option<bool>,
+    pub rkey: String,
+    pub created_at: String,
+    /// First language the post declares in `langs`
+    pub language: Option<String>,
+    /// Language detected from the post text
+    pub detected_language: Option<Detection>,
+    pub form: String,
+    pub text: String,
+    pub facets: Facets,
+    pub embed: Embed,
+    pub reply: Option<Reply>,
+    /// Local copies of the post's images, when blob fetching is enabled
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blob_paths: Vec<PathBuf>,
+    /// Compound sentiment between -1 and 1, when scoring is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sentiment: Option<f64>,
+    /// Set when near-duplicate detection found a recent post with similar text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub near_duplicate: Option<NearDuplicate>,
+    /// Set when the author's account was seen going inactive, e.g. `takendown`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_status: Option<AccountStatus>,
+    /// Set when moderation held the post back; public sinks leave it out
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub held_back: bool,
+    pub lines: Vec<String>,
+    pub syllables: Vec<usize>,
+}
+
+impl HaikuRecord {
+    pub fn new(
+        evt: &Event,
+        record: &post::Record,
+        haiku: Haiku,
+        detected_language: Option<Detection>,
+        handle: Option<ResolvedHandle>,
+    ) -> Self {
+        let did = evt.repo.as_str().to_string();
+        Self {
+            uri: format!("at://{did}/{}/{}", evt.collection, evt.rkey),
+            url: format!("https://bsky.app/profile/{did}/post/{}", evt.rkey),
+            cid: evt.cid.as_ref().map(|cid| cid.0.to_string()),
+            handle_verified: handle.as_ref().and_then(|handle| handle.verified),
+            handle: handle.map(|handle| handle.handle),
+            rkey: evt.rkey.clone(),
+            created_at: record.created_at.as_str().to_string(),
+            language: record
+                .langs
+                .as_ref()
+                .and_then(|langs| langs.first())
+                .map(|lang| lang.as_ref().to_string()),
+            detected_language,
+            form: haiku.form,
+            text: record.text.clone(),
+            facets: Facets::from_record(record),
+            embed: Embed::from_record(record),
+            reply: Reply::from_record(record),
+            blob_paths: Vec::new(),
+            sentiment: None,
+            near_duplicate: None,
+            account_status: evt.account_status.clone(),
+            held_back: false,
+            lines: haiku.lines,
+            syllables: haiku.syllables,
+            did,
+        }
+    }
+
+    /// The author's handle, unless it failed verification, see [`ResolvedHandle::display`].
+    pub fn display_handle(&self) -> Option<&str> {
+        (self.handle_verified != Some(false))
+            .then_some(self.handle.as_deref())
+            .flatten()
+    }
+}
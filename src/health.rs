@@ -0,0 +1,173 @@
+//! Liveness and readiness reporting for process supervisors: `/healthz` and `/readyz`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use serde::Serialize;
+use tokio::time::Instant;
+
+/// Connection and sink state, updated by the client and the post handler.
+#[derive(Debug, Default)]
+pub struct Health {
+    state: Mutex<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    /// Hosts currently connected to; more than one when subscribed to PDSes directly
+    connections: Vec<String>,
+    last_message: Option<Instant>,
+    ping_rtt: Option<Duration>,
+    cursor: Option<i64>,
+    sink_error: Option<String>,
+    pipeline_sinks: BTreeMap<String, SinkStatus>,
+}
+
+/// Delivery state of one pipeline sink.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SinkStatus {
+    /// Error of the latest event given up on, cleared by the next successful delivery
+    pub error: Option<String>,
+    pub delivered: u64,
+    /// Events given up on after every retry failed
+    pub failed: u64,
+    /// Events dropped because the sink's queue was full
+    pub dropped: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Connected, receiving frames and able to write output
+    pub ready: bool,
+    /// Relay currently connected to, or the first PDS when subscribed to PDSes directly
+    pub relay: Option<String>,
+    /// Firehose connections currently open
+    pub connections: usize,
+    pub last_message_age_secs: Option<f64>,
+    /// Round trip of the latest answered websocket ping
+    pub ping_rtt_ms: Option<f64>,
+    pub cursor: Option<i64>,
+    /// Error of the latest output write, if it failed
+    pub sink_error: Option<String>,
+    /// Pipeline sinks by name. These fail on their own and don't affect `ready`.
+    pub pipeline_sinks: BTreeMap<String, SinkStatus>,
+}
+
+impl Health {
+    pub fn connected(&self, relay: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .connections
+            .push(relay.to_string());
+    }
+
+    pub fn disconnected(&self, relay: &str) {
+        let connections = &mut self.state.lock().unwrap().connections;
+        if let Some(i) = connections.iter().position(|host| host == relay) {
+            connections.remove(i);
+        }
+    }
+
+    pub fn record_message(&self) {
+        self.state.lock().unwrap().last_message = Some(Instant::now());
+    }
+
+    pub fn record_ping_rtt(&self, rtt: Duration) {
+        self.state.lock().unwrap().ping_rtt = Some(rtt);
+    }
+
+    pub fn record_cursor(&self, seq: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.cursor = state.cursor.max(Some(seq));
+    }
+
+    /// Cursor up to which every commit has been handled, see [`crate::cursor::InFlight`].
+    pub fn cursor(&self) -> Option<i64> {
+        self.state.lock().unwrap().cursor
+    }
+
+    pub fn record_sink<T, E: std::fmt::Display>(&self, result: &Result<T, E>) {
+        self.state.lock().unwrap().sink_error = result.as_ref().err().map(|e| e.to_string());
+    }
+
+    /// Starts reporting the pipeline sink `name`, before it has delivered anything.
+    pub fn register_pipeline_sink(&self, name: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .pipeline_sinks
+            .entry(name.to_string())
+            .or_default();
+    }
+
+    pub fn record_pipeline_sink<T, E: Display>(&self, name: &str, result: &Result<T, E>) {
+        let mut state = self.state.lock().unwrap();
+        let status = state.pipeline_sinks.entry(name.to_string()).or_default();
+        match result {
+            Ok(_) => {
+                status.delivered += 1;
+                status.error = None;
+            }
+            Err(e) => {
+                status.failed += 1;
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn record_pipeline_sink_drop(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .pipeline_sinks
+            .entry(name.to_string())
+            .or_default()
+            .dropped += 1;
+    }
+
+    /// Ready means connected, with a message received within `max_age` and no failing sink.
+    pub fn report(&self, max_age: Duration) -> Report {
+        let state = self.state.lock().unwrap();
+        let age = state.last_message.map(|at| at.elapsed());
+        Report {
+            ready: !state.connections.is_empty()
+                && age.is_some_and(|age| age < max_age)
+                && state.sink_error.is_none(),
+            relay: state.connections.first().cloned(),
+            connections: state.connections.len(),
+            last_message_age_secs: age.map(|age| age.as_secs_f64()),
+            ping_rtt_ms: state.ping_rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            cursor: state.cursor,
+            sink_error: state.sink_error.clone(),
+            pipeline_sinks: state.pipeline_sinks.clone(),
+        }
+    }
+}
+
+/// `/healthz` always answers 200 while the process is responsive; `/readyz` answers 503 until
+/// the listener is [ready](Health::report). Both return the full [`Report`].
+pub fn routes(health: Arc<Health>, max_age: Duration) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state((health, max_age))
+}
+
+async fn healthz(State((health, max_age)): State<(Arc<Health>, Duration)>) -> impl IntoResponse {
+    Json(health.report(max_age))
+}
+
+async fn readyz(State((health, max_age)): State<(Arc<Health>, Duration)>) -> impl IntoResponse {
+    let report = health.report(max_age);
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
@@ -0,0 +1,17 @@
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Builds the HTTP client shared by everything talking to XRPC or other web services, with the
+/// configured user agent and going through the configured proxy if any.
+///
+/// Extra relay headers aren't sent, so relay credentials don't leak to other hosts.
+pub fn client(config: &Config) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .timeout(Duration::from_secs(30));
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(&proxy.url).expect("Invalid proxy URL"));
+    }
+    builder.build().expect("Unable to build HTTP client")
+}
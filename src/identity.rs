@@ -0,0 +1,314 @@
+//! Handle and DID resolution.
+
+use std::collections::HashMap;
+
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// Public AppView used for unauthenticated identity lookups
+const APPVIEW_URL: &str = "https://public.api.bsky.app";
+const PLC_DIRECTORY_URL: &str = "https://plc.directory";
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("DID document has no #atproto_pds service")]
+    MissingPds,
+    #[error("PLC audit log has no operations")]
+    EmptyAuditLog,
+}
+
+#[derive(Deserialize)]
+struct ResolveHandleOutput {
+    did: String,
+}
+
+/// Resolves `handle` to a DID through `com.atproto.identity.resolveHandle`.
+pub async fn resolve_handle(http: &reqwest::Client, handle: &str) -> Result<String, IdentityError> {
+    let output = http
+        .get(format!(
+            "{APPVIEW_URL}/xrpc/com.atproto.identity.resolveHandle"
+        ))
+        .query(&[("handle", handle)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ResolveHandleOutput>()
+        .await?;
+
+    Ok(output.did)
+}
+
+#[derive(Deserialize)]
+struct ProfileOutput {
+    handle: String,
+}
+
+/// Looks up the current handle of `did` through `app.bsky.actor.getProfile`.
+pub async fn handle_for_did(http: &reqwest::Client, did: &str) -> Result<String, IdentityError> {
+    let output = http
+        .get(format!("{APPVIEW_URL}/xrpc/app.bsky.actor.getProfile"))
+        .query(&[("actor", did)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ProfileOutput>()
+        .await?;
+
+    Ok(output.handle)
+}
+
+/// A DID's handle, as reported by the AppView.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedHandle {
+    pub handle: String,
+    /// Whether the handle resolves back to the DID; `None` when verification is disabled
+    pub verified: Option<bool>,
+}
+
+impl ResolvedHandle {
+    /// The handle, unless it failed verification. Anything shown to people should use this,
+    /// as a stale or spoofed handle can make a post look like it's from someone else.
+    pub fn display(&self) -> Option<&str> {
+        (self.verified != Some(false)).then_some(self.handle.as_str())
+    }
+}
+
+/// Looks up DIDs' handles, optionally checking that each handle resolves back to the DID
+/// through its `_atproto` DNS TXT record or its `/.well-known/atproto-did`. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct HandleResolver {
+    http: reqwest::Client,
+    /// `None` when verification is disabled
+    dns: Option<TokioAsyncResolver>,
+}
+
+impl HandleResolver {
+    pub fn new(http: reqwest::Client, verify: bool) -> Self {
+        let dns = verify.then(|| {
+            TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+                warn!("Unable to read system DNS configuration, using defaults: {e}");
+                TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+            })
+        });
+        Self { http, dns }
+    }
+
+    pub async fn resolve(&self, did: &str) -> Result<ResolvedHandle, IdentityError> {
+        let handle = handle_for_did(&self.http, did).await?;
+        let verified = match &self.dns {
+            Some(dns) => Some(self.verify(dns, &handle, did).await),
+            None => None,
+        };
+        if verified == Some(false) {
+            warn!("Handle {handle} does not resolve back to {did}");
+        }
+        Ok(ResolvedHandle { handle, verified })
+    }
+
+    /// Whether `handle` claims `did`, by DNS first then over HTTPS.
+    async fn verify(&self, dns: &TokioAsyncResolver, handle: &str, did: &str) -> bool {
+        match dns.txt_lookup(format!("_atproto.{handle}.")).await {
+            Ok(records) => {
+                let claimed = records
+                    .iter()
+                    .flat_map(|record| record.txt_data())
+                    .filter_map(|data| std::str::from_utf8(data).ok())
+                    .filter_map(|data| data.strip_prefix("did="))
+                    .collect::<Vec<_>>();
+                if !claimed.is_empty() {
+                    return claimed == [did];
+                }
+            }
+            Err(e) => debug!("No _atproto TXT record for {handle}: {e}"),
+        }
+
+        let response = self
+            .http
+            .get(format!("https://{handle}/.well-known/atproto-did"))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        match response {
+            Ok(response) => response.text().await.is_ok_and(|body| body.trim() == did),
+            Err(e) => {
+                debug!("Unable to fetch atproto-did of {handle}: {e}");
+                false
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidDocument {
+    #[serde(default)]
+    also_known_as: Vec<String>,
+    #[serde(default)]
+    service: Vec<DidService>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidService {
+    id: String,
+    service_endpoint: String,
+}
+
+/// Resolves the PDS hosting `did` from its DID document (`did:plc` or `did:web`).
+pub async fn resolve_pds(http: &reqwest::Client, did: &str) -> Result<String, IdentityError> {
+    let url = match did.strip_prefix("did:web:") {
+        Some(host) => format!("https://{host}/.well-known/did.json"),
+        None => format!("{PLC_DIRECTORY_URL}/{did}"),
+    };
+    let document = http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<DidDocument>()
+        .await?;
+
+    document
+        .service
+        .into_iter()
+        .find(|service| service.id.ends_with("#atproto_pds"))
+        .map(|service| service.service_endpoint.trim_end_matches('/').to_string())
+        .ok_or(IdentityError::MissingPds)
+}
+
+/// What a DID resolves to: its handle, PDS and keys, as of its latest PLC operation for
+/// `did:plc`, or its DID document for `did:web`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IdentityState {
+    /// Claimed in `alsoKnownAs`, unverified
+    pub handle: Option<String>,
+    pub pds: Option<String>,
+    /// Keys allowed to sign PLC operations, most authoritative first; always empty for
+    /// `did:web`
+    pub rotation_keys: Vec<String>,
+    /// Key repo commits are signed with
+    pub signing_key: Option<String>,
+    /// CID of the PLC operation this state comes from
+    pub operation: Option<String>,
+    /// When the PLC directory accepted that operation
+    pub operation_at: Option<String>,
+    /// Set when the DID was deactivated through a PLC tombstone
+    pub tombstoned: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditEntry {
+    cid: String,
+    #[serde(default)]
+    nullified: bool,
+    created_at: String,
+    operation: PlcOperation,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlcOperation {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    rotation_keys: Vec<String>,
+    #[serde(default)]
+    verification_methods: HashMap<String, String>,
+    #[serde(default)]
+    also_known_as: Vec<String>,
+    #[serde(default)]
+    services: HashMap<String, PlcService>,
+    // Legacy `create` operations
+    signing_key: Option<String>,
+    recovery_key: Option<String>,
+    handle: Option<String>,
+    service: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PlcService {
+    endpoint: String,
+}
+
+/// Resolves the current state of `did`, from the PLC directory's audit log for `did:plc`.
+pub async fn resolve_identity(
+    http: &reqwest::Client,
+    did: &str,
+) -> Result<IdentityState, IdentityError> {
+    if let Some(host) = did.strip_prefix("did:web:") {
+        let document = http
+            .get(format!("https://{host}/.well-known/did.json"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<DidDocument>()
+            .await?;
+        return Ok(IdentityState {
+            handle: aka_handle(&document.also_known_as),
+            pds: document
+                .service
+                .into_iter()
+                .find(|service| service.id.ends_with("#atproto_pds"))
+                .map(|service| service.service_endpoint),
+            ..IdentityState::default()
+        });
+    }
+
+    let log = http
+        .get(format!("{PLC_DIRECTORY_URL}/{did}/log/audit"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Vec<AuditEntry>>()
+        .await?;
+    let latest = log
+        .into_iter()
+        .rev()
+        .find(|entry| !entry.nullified)
+        .ok_or(IdentityError::EmptyAuditLog)?;
+
+    let op = latest.operation;
+    let mut state = IdentityState {
+        operation: Some(latest.cid),
+        operation_at: Some(latest.created_at),
+        ..IdentityState::default()
+    };
+    match op.kind.as_str() {
+        "plc_tombstone" => state.tombstoned = true,
+        "create" => {
+            state.handle = op.handle;
+            state.pds = op.service;
+            state.rotation_keys = op
+                .recovery_key
+                .into_iter()
+                .chain(op.signing_key.clone())
+                .collect();
+            state.signing_key = op.signing_key;
+        }
+        _ => {
+            state.handle = aka_handle(&op.also_known_as);
+            state.pds = op
+                .services
+                .get("atproto_pds")
+                .map(|service| service.endpoint.clone());
+            state.rotation_keys = op.rotation_keys;
+            state.signing_key = op.verification_methods.get("atproto").cloned();
+        }
+    }
+    Ok(state)
+}
+
+/// The handle in an `alsoKnownAs` list, e.g. `at://alice.bsky.social`.
+fn aka_handle(also_known_as: &[String]) -> Option<String> {
+    also_known_as
+        .iter()
+        .find_map(|aka| aka.strip_prefix("at://"))
+        .map(str::to_string)
+}
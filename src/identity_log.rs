@@ -0,0 +1,104 @@
+//! Append-only history of identity changes from `#identity` frames, for identity research.
+//!
+//! Each line is a JSON object with the frame's sequence number, DID, time and handle. With
+//! lookups enabled it also has what the DID resolved to right after the change (handle, PDS,
+//! rotation and signing keys, see [`IdentityState`]), or the lookup's error.
+
+use std::sync::Arc;
+
+use atrium_api::com::atproto::sync::subscribe_repos::Identity;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Semaphore,
+};
+use tracing::{error, warn};
+
+use crate::{
+    identity::{self, IdentityState},
+    jsonl::JsonlWriter,
+    task,
+};
+
+/// Identity changes waiting for a lookup before new ones are dropped
+const QUEUE_SIZE: usize = 10_000;
+
+#[derive(Debug, Serialize)]
+struct IdentityRecord {
+    seq: i64,
+    did: String,
+    /// When the relay saw the change
+    time: String,
+    /// Handle carried by the frame, if any
+    handle: Option<String>,
+    observed_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved: Option<IdentityState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Writes identity changes in the background, looking them up first if configured to.
+#[derive(Debug)]
+pub struct IdentityLog {
+    queue: mpsc::Sender<IdentityRecord>,
+}
+
+impl IdentityLog {
+    /// Appends to `output`, resolving each DID with up to `concurrency` lookups at a time
+    /// through `http`, or not at all when `http` is `None`.
+    pub fn spawn(output: JsonlWriter, http: Option<reqwest::Client>, concurrency: usize) -> Self {
+        let (queue, mut records) = mpsc::channel::<IdentityRecord>(QUEUE_SIZE);
+        let output = Arc::new(output);
+        let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        task::spawn("identity-log", async move {
+            while let Some(mut record) = records.recv().await {
+                let Some(http) = http.clone() else {
+                    write(&output, &record);
+                    continue;
+                };
+                let permit = permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("Semaphore is never closed");
+                let output = output.clone();
+                tokio::spawn(async move {
+                    match identity::resolve_identity(&http, &record.did).await {
+                        Ok(state) => record.resolved = Some(state),
+                        Err(e) => record.error = Some(e.to_string()),
+                    }
+                    write(&output, &record);
+                    drop(permit);
+                });
+            }
+        });
+        Self { queue }
+    }
+
+    /// Queues the change carried by an `#identity` frame.
+    pub fn push(&self, frame: &Identity) {
+        let record = IdentityRecord {
+            seq: frame.seq,
+            did: frame.did.as_str().to_string(),
+            time: frame.time.as_str().to_string(),
+            handle: frame
+                .handle
+                .as_ref()
+                .map(|handle| handle.as_str().to_string()),
+            observed_at: Utc::now().to_rfc3339(),
+            resolved: None,
+            error: None,
+        };
+        if let Err(TrySendError::Full(record)) = self.queue.try_send(record) {
+            warn!("Identity log is behind, dropping change of {}", record.did);
+        }
+    }
+}
+
+fn write(output: &JsonlWriter, record: &IdentityRecord) {
+    if let Err(e) = output.append(record) {
+        error!("Unable to log identity change of {}: {e}", record.did);
+    }
+}
@@ -0,0 +1,34 @@
+use std::{path::Path, sync::Arc};
+
+use serde::Serialize;
+
+use crate::{
+    appender::{AppendError, Appender},
+    health::Health,
+    rotate::RotationPolicy,
+};
+
+/// Appends serialized records to a file, one JSON object per line.
+#[derive(Debug)]
+pub struct JsonlWriter {
+    appender: Appender,
+}
+
+impl JsonlWriter {
+    pub fn open(
+        path: &Path,
+        rotation: RotationPolicy,
+        health: Arc<Health>,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            appender: Appender::open(path, rotation, Vec::new(), health)?,
+        })
+    }
+
+    /// Queues `record` to be written in the background.
+    pub fn append<T: Serialize>(&self, record: &T) -> Result<(), AppendError> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.appender.append(line)
+    }
+}
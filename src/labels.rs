@@ -0,0 +1,231 @@
+//! Content labels, e.g. `porn` or `graphic-media`: self-labels authors put on their records,
+//! and labels subscribed labelers put on records and accounts. Filters include or exclude posts
+//! by them before they reach sinks or bot actions.
+//!
+//! Labelers are followed over `com.atproto.label.subscribeLabels` from the start of their
+//! history, so labels put on accounts long ago apply too. Labels on a post usually arrive a
+//! little after it, so only account labels and labels on posts seen before (e.g. an edited
+//! one) catch a post as it's created.
+
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use lru::LruCache;
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+use crate::{
+    client::{glob_match, Event},
+    config::Config,
+    firehose,
+    frame::{self, FrameError},
+    task,
+};
+
+/// Wait before reconnecting to a labeler, doubling with each failure after
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The `val`s of a record's self-labels, from its JSON form.
+pub fn self_labels(record: &serde_json::Value) -> Vec<String> {
+    record
+        .pointer("/labels/values")
+        .and_then(serde_json::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|label| label.get("val")?.as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The self-labels of `evt`'s record, as JSON when given, and what labelers put on it or
+/// its author.
+pub fn of_event(
+    evt: &Event,
+    record: Option<&serde_json::Value>,
+    labelers: Option<&Labelers>,
+) -> Vec<String> {
+    let mut labels = record.map(self_labels).unwrap_or_default();
+    if let Some(labelers) = labelers {
+        let did = evt.repo.as_str();
+        let uri = format!("at://{did}/{}/{}", evt.collection, evt.rkey);
+        labels.extend(labelers.labels(&uri, did));
+    }
+    labels
+}
+
+/// Label globs a post must carry one of, and label globs it must carry none of.
+///
+/// An empty filter lets every post through.
+#[derive(Debug, Clone, Default)]
+pub struct LabelFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl LabelFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self { include, exclude }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether a post labelled `labels` carries an included label, when any are set, and no
+    /// excluded one.
+    pub fn allows(&self, labels: &[String]) -> bool {
+        let any = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| labels.iter().any(|label| glob_match(pattern, label)))
+        };
+        (self.include.is_empty() || any(&self.include)) && !any(&self.exclude)
+    }
+}
+
+/// One label as a labeler sends it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubjectLabel {
+    /// DID of the labeler
+    pub src: String,
+    /// A record's `at://` URI, or an account's DID
+    pub uri: String,
+    pub val: String,
+    /// Removes the label instead of adding it
+    #[serde(default)]
+    pub neg: bool,
+    /// When the label stops applying, if ever
+    #[serde(default)]
+    pub exp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelsMessage {
+    seq: i64,
+    labels: Vec<SubjectLabel>,
+}
+
+/// Labels on one subject by labeler DID and value, and when each expires if it does.
+type Values = HashMap<(String, String), Option<DateTime<Utc>>>;
+
+/// Labels from subscribed labelers, by subject. Subjects labelled the longest ago are
+/// forgotten first once `capacity` are kept.
+#[derive(Debug)]
+pub struct Labelers {
+    subjects: Mutex<LruCache<String, Values>>,
+}
+
+impl Labelers {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            subjects: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Follows every labeler in `config.labelers` in the background.
+    pub fn subscribe(config: &Config) -> Arc<Self> {
+        let labelers = Arc::new(Self::new(config.labeler_capacity));
+        for host in &config.labelers {
+            labelers
+                .clone()
+                .follow(firehose::subscribe_labels_url(host), config.clone());
+        }
+        labelers
+    }
+
+    /// Adds or, when negated, removes `label`. A labeler can only remove its own labels, so
+    /// the same value from another labeler stays.
+    pub fn apply(&self, label: &SubjectLabel) {
+        let key = (label.src.clone(), label.val.clone());
+        let mut subjects = self.subjects.lock().unwrap();
+        if label.neg {
+            if let Some(values) = subjects.get_mut(&label.uri) {
+                values.remove(&key);
+            }
+            return;
+        }
+        if label.exp.is_some_and(|exp| exp <= Utc::now()) {
+            return;
+        }
+        subjects
+            .get_or_insert_mut(label.uri.clone(), Values::new)
+            .insert(key, label.exp);
+    }
+
+    /// Unexpired labels on the record at `uri` and on the account `did`, each value once
+    /// however many labelers put it there.
+    pub fn labels(&self, uri: &str, did: &str) -> Vec<String> {
+        let now = Utc::now();
+        let mut subjects = self.subjects.lock().unwrap();
+        let mut labels = Vec::new();
+        for subject in [uri, did] {
+            let Some(values) = subjects.get(subject) else {
+                continue;
+            };
+            labels.extend(
+                values
+                    .iter()
+                    .filter(|(_, exp)| exp.is_none_or(|exp| exp > now))
+                    .map(|((_, val), _)| val.clone()),
+            );
+        }
+        labels.sort();
+        labels.dedup();
+        labels
+    }
+
+    /// Reads labels from `url`, reconnecting where it left off until the process exits.
+    fn follow(self: Arc<Self>, url: String, config: Config) {
+        task::spawn(&format!("labeler/{url}"), async move {
+            let mut cursor = 0;
+            let mut backoff = RECONNECT_BACKOFF;
+            loop {
+                let error = match firehose::connect(&url, Some(cursor), &config).await {
+                    Ok(mut stream) => {
+                        info!("Following labels from {url}");
+                        backoff = RECONNECT_BACKOFF;
+                        loop {
+                            match stream.next().await {
+                                Some(Ok(Message::Binary(data))) => match self.read(&data) {
+                                    Ok(Some(seq)) => cursor = seq,
+                                    Ok(None) => {}
+                                    Err(e) => warn!("Malformed label frame from {url}: {e}"),
+                                },
+                                Some(Ok(_)) => {}
+                                Some(Err(e)) => break e.to_string(),
+                                None => break "connection closed".to_string(),
+                            }
+                        }
+                    }
+                    Err(e) => e.to_string(),
+                };
+                warn!("Lost labeler {url}, reconnecting in {backoff:?}: {error}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+        });
+    }
+
+    /// Applies the labels in a `#labels` frame, returning its sequence number. Other frames
+    /// are skipped.
+    fn read(&self, data: &[u8]) -> Result<Option<i64>, FrameError> {
+        let (header, body) = frame::split_frame(data)?;
+        if header.t.as_deref() != Some("#labels") {
+            return Ok(None);
+        }
+        let message = serde_ipld_dagcbor::from_slice::<LabelsMessage>(body)
+            .map_err(|e| FrameError::Record(e.to_string()))?;
+        for label in &message.labels {
+            self.apply(label);
+        }
+        Ok(Some(message.seq))
+    }
+}
@@ -0,0 +1,51 @@
+//! Language detection on post text, since the self-reported `langs` field is often missing or
+//! wrong.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Detection {
+    /// ISO 639-3 code, e.g. `eng`
+    pub language: String,
+    pub confidence: f64,
+}
+
+/// Accepts posts detected as one of a set of languages with enough confidence.
+#[derive(Debug, Clone)]
+pub struct LanguageFilter {
+    /// ISO 639-3 codes; empty accepts every language
+    accepted: Vec<String>,
+    min_confidence: f64,
+}
+
+impl LanguageFilter {
+    pub fn new(accepted: Vec<String>, min_confidence: f64) -> Self {
+        Self {
+            accepted: accepted
+                .into_iter()
+                .map(|code| code.to_lowercase())
+                .collect(),
+            min_confidence,
+        }
+    }
+
+    pub fn detect(text: &str) -> Option<Detection> {
+        let info = whatlang::detect(text)?;
+        Some(Detection {
+            language: info.lang().code().to_string(),
+            confidence: info.confidence(),
+        })
+    }
+
+    /// Whether `detection` is confident enough and in an accepted language.
+    ///
+    /// Undetectable text is only accepted when every language is.
+    pub fn accepts(&self, detection: Option<&Detection>) -> bool {
+        let Some(detection) = detection else {
+            return self.accepted.is_empty();
+        };
+
+        detection.confidence >= self.min_confidence
+            && (self.accepted.is_empty() || self.accepted.contains(&detection.language))
+    }
+}
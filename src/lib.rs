@@ -0,0 +1,90 @@
+//! Listens to the Bluesky firehose (`com.atproto.sync.subscribeRepos`) and dispatches repo
+//! operations to handlers registered with [`client::Client::on`], or streams them with
+//! [`subscription::Firehose::subscribe`].
+
+pub mod accessibility;
+pub mod accounts;
+pub mod anomaly;
+pub mod appender;
+pub mod archive;
+pub mod atom;
+pub mod blobs;
+pub mod blockstore;
+pub mod bot;
+pub mod classify;
+pub mod clickhouse;
+pub mod client;
+pub mod compress;
+pub mod config;
+pub mod counters;
+pub mod crawl;
+pub mod csv;
+pub mod cursor;
+pub mod dashboard;
+pub mod deadletter;
+pub mod dedup;
+pub mod desktop;
+pub mod digest;
+pub mod discord;
+pub mod embed;
+pub mod embedding;
+pub mod emoji;
+pub mod engagement;
+pub mod facets;
+pub mod fanout;
+pub mod feedgen;
+pub mod filter;
+pub mod firehose;
+pub mod follows;
+pub mod frame;
+pub mod gallery;
+pub mod graphql;
+pub mod grpc;
+pub mod haiku;
+pub mod health;
+pub mod http;
+pub mod identity;
+pub mod identity_log;
+pub mod jsonl;
+pub mod labels;
+pub mod language;
+pub mod logging;
+pub mod mirror;
+pub mod moderation;
+pub mod mqtt;
+pub mod mst;
+pub mod neardup;
+pub mod notify;
+pub mod parquet;
+pub mod pipeline;
+pub mod plugin;
+pub mod proxy;
+pub mod quarantine;
+pub mod query;
+pub mod queue;
+pub mod ratelimit;
+pub mod rebroadcast;
+pub mod relay;
+pub mod repo;
+pub mod revisions;
+pub mod rhyme;
+pub mod rotate;
+pub mod script;
+pub mod seen;
+pub mod selftest;
+pub mod sentiment;
+pub mod server;
+pub mod session;
+pub mod shedding;
+pub mod sqlite;
+pub mod stats;
+pub mod subscription;
+pub mod syllables;
+pub mod task;
+pub mod telegram;
+pub mod telemetry;
+pub mod thread;
+pub mod trending;
+pub mod watch;
+pub mod watchlist;
+pub mod xrpc;
@@ -0,0 +1,91 @@
+//! Log output setup: human-readable lines by default, or one JSON object per line for log
+//! aggregators such as Loki or Elasticsearch.
+
+use std::str::FromStr;
+
+use tracing::Subscriber;
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{
+        format::{DefaultFields, Format},
+        MakeWriter,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::{dashboard::LogLines, telemetry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!(
+                "unknown log format {other:?}, expected text or json"
+            )),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber, exporting spans over OTLP when configured (see
+/// [`telemetry`]). Lines are printed to stdout, or kept in `capture` without colours while
+/// the [dashboard](crate::dashboard) is shown.
+///
+/// In JSON mode the fields of the enclosing `event` span (`seq`, `repo`, `collection` and
+/// `rkey`) are attached to every line logged while handling a repo operation.
+///
+/// Built with the `console` feature, task state is also served to tokio-console on
+/// `127.0.0.1:6669` (see the `TOKIO_CONSOLE_*` variables of `console-subscriber`).
+pub fn init(format: LogFormat, capture: Option<LogLines>) {
+    let fmt = match capture {
+        None => fmt_layer(format, tracing_subscriber::fmt::layer()),
+        Some(lines) => fmt_layer(
+            format,
+            tracing_subscriber::fmt::layer()
+                .with_writer(lines)
+                .with_ansi(false),
+        ),
+    };
+    let otel = telemetry::init().unwrap_or_else(|e| {
+        eprintln!("OpenTelemetry export disabled: {e}");
+        None
+    });
+
+    let registry =
+        tracing_subscriber::registry().with(fmt.and_then(otel).with_filter(LevelFilter::INFO));
+    // Outside the INFO filter, as it reads tokio's own trace-level task events
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+}
+
+fn fmt_layer<S, W>(
+    format: LogFormat,
+    fmt: tracing_subscriber::fmt::Layer<S, DefaultFields, Format, W>,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
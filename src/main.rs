@@ -1,153 +1,1579 @@
-use std::io::Cursor;
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
 use atrium_api::{
     app::bsky::feed::post,
-    com::atproto::sync::subscribe_repos::Commit,
-    types::{CidLink, Collection},
+    types::{string::Did, CidLink},
 };
-use futures_util::{SinkExt, StreamExt};
-
-use ipld_core::ipld::Ipld;
-use native_tls::TlsConnector;
-use tokio_tungstenite::{
-    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
-    Connector,
+use bsky_firehose_listener::{
+    accessibility::AltTextStats,
+    accounts::AccountStatuses,
+    anomaly::AnomalyDetector,
+    archive::{ArchiveFormat, Archiver},
+    atom::AtomFeed,
+    blobs::BlobFetcher,
+    blockstore::{BlockStore, BlockWriter},
+    bot::Bot,
+    classify::ClassifierRegistry,
+    clickhouse::{ClickHouse, ClickHouseConfig},
+    client::{glob_match, Client, Event},
+    config::Config,
+    counters::{self, Counters},
+    crawl,
+    csv::CsvWriter,
+    cursor::{self, StartAt},
+    dashboard::Dashboard,
+    deadletter::{self, DeadLetters},
+    dedup::DedupStore,
+    desktop::Desktop,
+    digest::Digest,
+    discord::Discord,
+    embed::Embed,
+    embedding::{Embedder, EmbeddingConfig, EmbeddingWriter, VectorIndex},
+    emoji::EmojiUsage,
+    engagement::Engagement,
+    facets::Facets,
+    fanout::{DropPolicy, Fanout},
+    feedgen::FeedGenerator,
+    filter::PostFilter,
+    firehose,
+    follows::{self, FollowLog},
+    frame,
+    gallery::Gallery,
+    graphql::GraphQl,
+    grpc::{self, EventService},
+    haiku::{self, HaikuRecord, SyllablePattern},
+    health::{self, Health},
+    http,
+    identity::{self, HandleResolver},
+    identity_log::IdentityLog,
+    jsonl::JsonlWriter,
+    labels::{self, Labelers},
+    language::LanguageFilter,
+    logging::{self, LogFormat},
+    mirror::{MirrorWriter, RepoMirror},
+    moderation::{Moderation, ModerationApiConfig},
+    neardup::NearDuplicates,
+    notify::{Notification, NotifyOn},
+    parquet::{ParquetWriter, Rotation},
+    pipeline::Router,
+    plugin::{Action, Emitted, Plugin, PluginLimits},
+    quarantine::Quarantine,
+    query,
+    ratelimit::RateLimiter,
+    rebroadcast::Rebroadcaster,
+    repo::{self, RepoError},
+    revisions::RevTracker,
+    script::Script,
+    selftest,
+    sentiment::SentimentAnalyzer,
+    server,
+    session::Session,
+    shedding::LoadShedder,
+    sqlite::{PostCapture, PostSearch, Window},
+    stats::Stats,
+    syllables::SyllableCounter,
+    task,
+    telegram::Telegram,
+    thread::{Reply, ThreadIndex},
+    trending::Trending,
+    watchlist::Watchlist,
 };
-use tracing::{debug, error, info};
-
-const FIREHOSE_URL: &str = "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos";
-const USER_AGENT: &str =
-    "bsky-firehose-listener (https://github.com/angeloanan/bsky-firehose-listener)";
-
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
-
-    let mut firehose_request = FIREHOSE_URL.into_client_request().unwrap();
-    firehose_request
-        .headers_mut()
-        .append("User-Agent", HeaderValue::from_str(USER_AGENT).unwrap());
-    let (mut stream, _response) = tokio_tungstenite::connect_async_tls_with_config(
-        firehose_request,
-        None,
-        true,
-        Some(Connector::NativeTls(TlsConnector::new().expect(
-            "Unable to use Native TLS. Does your system have it installed?",
-        ))),
-    )
-    .await
-    .unwrap();
-    info!("Connected to Firehose.");
+use tokio::{sync::OnceCell, task::JoinHandle};
+use tracing::{error, info, warn, Instrument};
+
+/// How long `redeliver` waits for background sinks to finish before exiting
+const REDELIVER_GRACE: Duration = Duration::from_secs(5);
+/// Options taking a value, as `--name value` or `--name=value`
+const FLAGS: [&str; 3] = ["--log-format", "--cursor", "--start-from"];
+
+fn main() {
+    let config = Config::from_env();
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime
+        .enable_all()
+        .max_blocking_threads(config.blocking_threads);
+    if let Some(threads) = config.runtime_threads {
+        runtime.worker_threads(threads);
+    }
+    runtime
+        .build()
+        .expect("Unable to start the tokio runtime")
+        .block_on(start(config));
+}
+
+/// Parses the value of `flag`, exiting if it is invalid.
+fn flag_value<T>(flag: &str, value: &str) -> T
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse().unwrap_or_else(|e| {
+        eprintln!("Invalid value for {flag}: {e}");
+        std::process::exit(2);
+    })
+}
 
-    while let Some(msg) = stream.next().await {
-        if let Err(e) = msg {
-            info!("Error connecting to Firehose: {:?}", e);
+async fn start(mut config: Config) {
+    let mut log_format = LogFormat::default();
+    let mut start_at = None;
+    let mut tui = false;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--tui" {
+            tui = true;
             continue;
         }
+        let flag = FLAGS.iter().find_map(|&flag| match arg.split_once('=') {
+            Some((name, value)) if name == flag => Some((flag, value.to_string())),
+            _ if arg == flag => Some((flag, args.next().unwrap_or_default())),
+            _ => None,
+        });
+        match flag {
+            Some(("--log-format", value)) => log_format = flag_value("--log-format", &value),
+            Some(("--cursor", value)) => {
+                start_at = Some(StartAt::Cursor(flag_value("--cursor", &value)))
+            }
+            Some((flag, value)) => start_at = Some(StartAt::Time(flag_value(flag, &value))),
+            None => positional.push(arg),
+        }
+    }
+    // Only following the firehose has anything to show
+    let dashboard = (tui
+        && matches!(
+            positional.first().map(String::as_str),
+            None | Some("listen" | "crawl")
+        ))
+    .then(|| Arc::new(Dashboard::new()));
+    if dashboard.is_some() {
+        // The dashboard takes the stats windows itself
+        config.stats_interval = None;
+    }
+    logging::init(
+        log_format,
+        dashboard.as_ref().map(|dashboard| dashboard.logs()),
+    );
 
-        let msg = msg.unwrap();
-        match msg {
-            Message::Binary(data) => {
-                // Handle each binary data in a separate task
-                tokio::task::spawn(async move {
-                    // On a single WS binary data, message will contain two ipld dagcbor frames:
-                    // The first frame is the type of message (metadata)
-                    // The second frame is the actual data of the message
-                    //
-                    // We need to split the data into two parts but don't know the size of each
-                    // frame ahead of time. For now, we'll just try to parse the data as-is; We'll
-                    // exploit how std::io::Cursor's position will be updated when we read from it.
-                    let buf = data.clone();
-                    let mut cursor = Cursor::new(data.as_slice());
-                    serde_ipld_dagcbor::from_reader::<Ipld, _>(&mut cursor)
-                        .expect_err("Somehow bsky only sends 1 frame.");
-                    let (metadata, data) = data.split_at(cursor.position() as usize);
-
-                    // Parse the metadata half
-                    let Ipld::Map(map) = serde_ipld_dagcbor::from_slice::<Ipld>(metadata)
-                        .expect("Valid data turns out to be invalid")
-                    else {
-                        error!("Expected a map, got something else: {:?}", data);
-                        return;
-                    };
+    match positional.first().map(String::as_str) {
+        None | Some("listen") => listen(config, start_at, dashboard, None).await,
+        Some("crawl") => crawl(config, dashboard).await,
+        Some("redeliver") => {
+            if !redeliver(config).await {
+                std::process::exit(1);
+            }
+        }
+        Some("backfill") => {
+            let Some(repo) = positional.get(1) else {
+                error!("Usage: backfill <did or handle>");
+                std::process::exit(2);
+            };
+            if !backfill(config, repo).await {
+                std::process::exit(1);
+            }
+        }
+        Some("dump") => {
+            let Some(repo) = positional.get(1) else {
+                error!("Usage: dump <did or handle>");
+                std::process::exit(2);
+            };
+            if !dump(config, repo).await {
+                std::process::exit(1);
+            }
+        }
+        Some("search") => {
+            let Some(query) = positional.get(1) else {
+                error!("Usage: search <query> [since, e.g. 1d]");
+                std::process::exit(2);
+            };
+            if !search(config, query, positional.get(2)) {
+                std::process::exit(1);
+            }
+        }
+        Some("query") => {
+            let Some(sql) = positional.get(1) else {
+                error!("Usage: query <sql>");
+                std::process::exit(2);
+            };
+            if !query(config, sql) {
+                std::process::exit(1);
+            }
+        }
+        Some("similar") => {
+            let Some(query) = positional.get(1) else {
+                error!("Usage: similar <text or at:// uri> [count]");
+                std::process::exit(2);
+            };
+            let count = match positional.get(2).map(|count| count.parse()).transpose() {
+                Ok(count) => count.unwrap_or(10),
+                Err(e) => {
+                    error!("Invalid count: {e}");
+                    std::process::exit(2);
+                }
+            };
+            if !similar(config, query, count).await {
+                std::process::exit(1);
+            }
+        }
+        Some("selftest") => {
+            if !selftest::run(&config).await {
+                std::process::exit(1);
+            }
+        }
+        Some(other) => {
+            error!(
+                "Unknown subcommand {other:?}. Expected one of: listen, backfill, crawl, dump, redeliver, search, query, similar, selftest"
+            );
+            std::process::exit(2);
+        }
+    }
+}
 
-                    // Parse `op`
-                    //  1 = Message
-                    // -1 = Error
-                    let Ipld::Integer(op_id) =
-                        map.get("op").expect("Malformed frame, \"op\" is missing")
-                    else {
-                        error!("Malformed bsky data. Expected \"op\" to be an integer, got something else: {:?}", data);
-                        return;
-                    };
+/// Everything the post handler needs, shared between handler invocations.
+struct App {
+    http: reqwest::Client,
+    handles: HandleResolver,
+    filter: PostFilter,
+    /// Labels from `FIREHOSE_LABELERS`, shared with pipelines
+    labelers: Option<Arc<Labelers>>,
+    script: Option<Arc<Script>>,
+    haikus: JsonlWriter,
+    gallery: Option<Gallery>,
+    /// Captures posts and haikus into `FIREHOSE_SQLITE_PATH`
+    capture: Option<PostCapture>,
+    graphql: Option<GraphQl>,
+    feed: Option<Arc<AtomFeed>>,
+    feedgen: Option<Arc<FeedGenerator>>,
+    /// Replies by parent and thread root, served at `/thread`
+    threads: Option<Arc<ThreadIndex>>,
+    digest: Option<Arc<Digest>>,
+    csv: Option<CsvWriter>,
+    forms: Vec<SyllablePattern>,
+    limericks: bool,
+    syllables: Arc<SyllableCounter>,
+    classifiers: ClassifierRegistry,
+    sentiment: Option<SentimentAnalyzer>,
+    /// Posts scoring outside this range are skipped
+    sentiment_range: (Option<f64>, Option<f64>),
+    languages: LanguageFilter,
+    dedup: Option<DedupStore>,
+    near_duplicates: Option<NearDuplicates>,
+    /// Skip haikus with near-duplicate text instead of only annotating them
+    skip_near_duplicates: bool,
+    blobs: Option<BlobFetcher>,
+    alt_text: Option<Arc<AltTextStats>>,
+    stats: Arc<Stats>,
+    shedder: Arc<LoadShedder>,
+    /// Keeps abusive posts from public sinks, notifications, the bot and the digest
+    moderation: Option<Moderation>,
+    bot: Option<Bot>,
+    /// The bot and digest account, whose own posts (quotes, digest replies) are skipped
+    own_did: Option<String>,
+    notify_on: NotifyOn,
+    discord: Option<Discord>,
+    telegram: Option<Telegram>,
+    desktop: Option<Desktop>,
+    embeddings: Option<EmbeddingWriter>,
+    /// Shows found haikus when `--tui` is given
+    dashboard: Option<Arc<Dashboard>>,
+    /// Revision each crawled repo was backfilled at; older live commits are skipped
+    backfilled: HashMap<String, String>,
+}
 
-                    if *op_id == -1 {
-                        error!("Bluesky sent op=-1 (error). Ignoring message.");
-                        return;
+/// Where `crawl` hands over to live consumption.
+struct Crawled {
+    cursor: i64,
+    backfilled: HashMap<String, String>,
+    /// Shares its stats and health with `app`
+    client: Client,
+    app: App,
+}
+
+/// Follows the firehose from `start_at`, or from where `crawl` left off, showing `dashboard`
+/// until it is quit.
+async fn listen(
+    config: Config,
+    start_at: Option<StartAt>,
+    dashboard: Option<Arc<Dashboard>>,
+    crawled: Option<Crawled>,
+) {
+    let cursor = match crawled {
+        Some(_) => None,
+        None => cursor::resolve(start_at, &config).await,
+    };
+    let cursor_file = config.cursor_file.clone();
+    let pds_hosts = !config.pds_hosts.is_empty();
+    let stall_timeout = config.stall_timeout;
+    let (mut client, archive) = prepare(config, crawled, dashboard.clone()).await;
+    if let Some(cursor) = cursor {
+        client.cursor(cursor);
+    }
+    match cursor_file {
+        Some(path) if !pds_hosts => cursor::keep_saved(path, client.health()),
+        Some(path) => warn!(
+            "Not saving the cursor to {}: PDS hosts each number their events separately",
+            path.display()
+        ),
+        None => {}
+    }
+    // Dropping the running client drops its handlers, so the archive can complete its object
+    let Some(dashboard) = dashboard else {
+        tokio::select! {
+            () = client.run() => {}
+            () = shutdown_signal() => info!("Shutting down"),
+        }
+        finish_archive(archive).await;
+        return;
+    };
+
+    let (stats, health) = (client.stats(), client.health());
+    let shown = tokio::task::spawn_blocking(move || dashboard.run(&stats, &health, stall_timeout));
+    let quit = tokio::select! {
+        () = client.run() => false,
+        () = shutdown_signal() => true,
+        shown = shown => {
+            if let Ok(Err(e)) = shown {
+                error!("Unable to show the dashboard: {e}");
+            }
+            true
+        }
+    };
+    finish_archive(archive).await;
+    if quit {
+        // Quitting the dashboard quits the listener, whatever is still in flight
+        std::process::exit(0);
+    }
+}
+
+/// Resolves once the process is asked to stop, by Ctrl-C or (on Unix) SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.expect("Unable to listen for Ctrl-C"),
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Unable to listen for Ctrl-C");
+}
+
+/// Waits for the archive task, if any, to complete the object being written. Every handler
+/// holding the archiver must be dropped first.
+async fn finish_archive(archive: Option<JoinHandle<()>>) {
+    let Some(archive) = archive else {
+        return;
+    };
+    info!("Completing the archive upload");
+    if let Err(e) = archive.await {
+        error!("Unable to complete the archive upload: {e}");
+    }
+}
+
+/// Builds the client for `listen` with every configured handler and sink registered, along
+/// with the archive task when archiving is enabled.
+async fn prepare(
+    config: Config,
+    crawled: Option<Crawled>,
+    dashboard: Option<Arc<Dashboard>>,
+) -> (Client, Option<JoinHandle<()>>) {
+    let http = http::client(&config);
+    let watchlist = match &config.watchlist {
+        Some(path) => {
+            let watchlist = Watchlist::load(path.clone(), config.watchlist_mode, &http)
+                .await
+                .expect("Unable to load watchlist");
+            let watchlist = Arc::new(watchlist);
+            watchlist.clone().watch(http.clone());
+            Some(watchlist)
+        }
+        None => None,
+    };
+
+    let (mut client, app) = match crawled {
+        Some(crawled) => {
+            let mut client = crawled.client;
+            client.cursor(crawled.cursor);
+            let app = App {
+                backfilled: crawled.backfilled,
+                ..crawled.app
+            };
+            (client, app)
+        }
+        None => {
+            let client = Client::new(config.clone());
+            let app = App::from_config(&config, http.clone(), &client).await;
+            (client, app)
+        }
+    };
+    if let Some(watchlist) = watchlist {
+        client.watchlist(watchlist);
+    }
+    if let Some(path) = &config.account_status {
+        let accounts = AccountStatuses::open(path).expect("Unable to open account status file");
+        client.accounts(Arc::new(accounts));
+    }
+    let dead_letters = config
+        .dead_letters
+        .as_ref()
+        .map(|path| Arc::new(DeadLetters::open(path).expect("Unable to open dead letter file")));
+    if let Some(dead_letters) = &dead_letters {
+        client.dead_letters(dead_letters.clone());
+    }
+    if let Some(dir) = &config.quarantine_dir {
+        let quarantine = Quarantine::open(dir, config.quarantine_limit)
+            .expect("Unable to open quarantine directory");
+        client.quarantine(Arc::new(quarantine));
+    }
+    if config.validate_revs {
+        client.validate_revs(Arc::new(RevTracker::new(config.rev_capacity)));
+        if let Some(path) = &config.rev_violations {
+            let output = JsonlWriter::open(path, config.output_rotation, client.health())
+                .expect("Unable to open rev violation output");
+            client.on_rev_violation(move |violation| {
+                if let Err(e) = output.append(violation) {
+                    error!("Unable to write rev violation: {e}");
+                }
+            });
+        }
+    }
+    let app = Arc::new(App { dashboard, ..app });
+    if let Some(digest) = &app.digest {
+        digest.clone().schedule();
+        let digest = digest.clone();
+        client.on("app.bsky.feed.*", move |evt| {
+            digest.record(&evt);
+            async {}
+        });
+    }
+
+    let block_store = config
+        .block_store
+        .as_ref()
+        .map(|dir| Arc::new(BlockStore::open(dir).expect("Unable to open block store")));
+    if let Some(store) = &block_store {
+        let blocks = BlockWriter::spawn(store.clone());
+        client.on_frame(move |frame| blocks.push(frame.clone()));
+    }
+    let mirror = config.mirror_dir.as_ref().map(|dir| {
+        Arc::new(RepoMirror::open(dir, block_store.clone()).expect("Unable to open repo mirror"))
+    });
+    if let Some(mirror) = &mirror {
+        let mirror = MirrorWriter::spawn(mirror.clone());
+        client.on_frame(move |frame| mirror.push(frame.clone()));
+    }
+    let post_search = config.sqlite_path.as_ref().map(|path| {
+        if let Some(capture) = app.capture.clone() {
+            client.on("app.bsky.feed.post", move |evt| {
+                capture.push(&evt);
+                async {}
+            });
+        }
+        PostSearch::open(path).expect("Unable to open SQLite database")
+    });
+
+    let counters = Arc::new(Counters::default());
+    if let Some(addr) = config.http_addr {
+        let rebroadcaster = Rebroadcaster::new(config.rebroadcast_capacity);
+        let mut routes = health::routes(client.health(), config.stall_timeout)
+            .merge(counters::routes(counters.clone()))
+            .merge(rebroadcaster.routes());
+        if let Some(gallery) = &app.gallery {
+            routes = routes.merge(gallery.routes());
+        }
+        if let Some(feed) = &app.feed {
+            routes = routes.merge(feed.clone().routes());
+        }
+        if let Some(feedgen) = &app.feedgen {
+            routes = routes.merge(feedgen.clone().routes());
+        }
+        if let Some(threads) = &app.threads {
+            routes = routes.merge(threads.clone().routes());
+        }
+        if let Some(mirror) = mirror {
+            routes = routes.merge(mirror.routes());
+        }
+        if let Some(post_search) = post_search {
+            routes = routes.merge(post_search.routes());
+        }
+        if let Some(graphql) = &app.graphql {
+            routes = routes.merge(graphql.clone().routes());
+        }
+        server::spawn(addr, routes);
+        let counters = counters.clone();
+        client.on("*", move |evt| {
+            counters.record_op(&evt.collection);
+            rebroadcaster.publish(&evt);
+            async {}
+        });
+    }
+
+    if let Some(addr) = config.grpc_addr {
+        let service = EventService::new(config.rebroadcast_capacity);
+        grpc::spawn(addr, service.clone());
+        client.on("*", move |evt| {
+            service.publish(&evt);
+            async {}
+        });
+    }
+
+    let archive = Archiver::from_config(&config).expect("Unable to set up archiving");
+    let archive = archive.map(|(archiver, task)| {
+        match archiver.format() {
+            ArchiveFormat::Frames => {
+                client.on_frame(move |frame| archiver.push(frame.clone()));
+            }
+            ArchiveFormat::Ndjson => {
+                client.on("*", move |evt| {
+                    match evt.to_json() {
+                        Ok(json) => archiver.push(json.into()),
+                        Err(e) => warn!("Unable to archive {}/{}: {e}", evt.collection, evt.rkey),
                     }
+                    async {}
+                });
+            }
+        }
+        task
+    });
 
-                    // Parse `t`: https://github.com/bluesky-social/atproto/blob/c307a75db11503eedf743c01e62f90413f07fe2a/lexicons/com/atproto/sync/subscribeRepos.json#L20-L27
-                    let Ipld::String(message) =
-                        map.get("t").expect("Malformed frame, \"t\" is missing")
-                    else {
-                        error!("Malformed bsky data. Expected \"t\" to be a string, got something else: {:?}", data);
-                        return;
-                    };
+    if let Some(dir) = &config.parquet_dir {
+        let parquet = ParquetWriter::spawn(
+            dir.clone(),
+            Rotation {
+                max_rows: config.parquet_max_rows,
+                max_age: config.parquet_max_age,
+            },
+        );
+        let collections = config.parquet_collections.clone();
+        client.on("*", move |evt| {
+            if collections
+                .iter()
+                .any(|pattern| glob_match(pattern, &evt.collection))
+            {
+                parquet.push(&evt);
+            }
+            async {}
+        });
+    }
+
+    if let Some(url) = &config.clickhouse_url {
+        let clickhouse = ClickHouse::spawn(
+            http.clone(),
+            ClickHouseConfig {
+                url: url.clone(),
+                table: config.clickhouse_table.clone(),
+                user: config.clickhouse_user.clone(),
+                password: config.clickhouse_password.clone(),
+                batch_rows: config.clickhouse_batch_rows,
+                flush_interval: config.clickhouse_flush_interval,
+            },
+        );
+        let collections = config.clickhouse_collections.clone();
+        client.on("*", move |evt| {
+            if collections
+                .iter()
+                .any(|pattern| glob_match(pattern, &evt.collection))
+            {
+                clickhouse.push(&evt);
+            }
+            async {}
+        });
+    }
+
+    if let Some(path) = &config.pipelines {
+        let classifiers = ClassifierRegistry::builtin(&config, app.syllables.clone());
+        let router = Router::load(
+            path,
+            classifiers,
+            &http,
+            config.output_rotation,
+            client.health(),
+            dead_letters,
+            app.labelers.clone(),
+        )
+        .expect("Unable to load pipelines");
+        client.on("*", move |evt| {
+            router.dispatch(&evt);
+            async {}
+        });
+    }
 
-                    // Only going to parse #commit
-                    // info!("Received message from Firehose: {:?}", message);
-                    if message != "#commit" {
+    if let Some(detector) = AnomalyDetector::from_config(&config) {
+        let detector = Arc::new(detector);
+        let output = config.anomaly_output.as_ref().map(|path| {
+            Arc::new(
+                JsonlWriter::open(path, config.output_rotation, client.health())
+                    .expect("Unable to open anomaly output"),
+            )
+        });
+        for collection in detector.collections() {
+            let detector = detector.clone();
+            let output = output.clone();
+            let app = app.clone();
+            let notify = config.anomaly_notify;
+            client.on(collection, move |evt| {
+                let anomaly = detector.record(&evt);
+                if let Some(anomaly) = &anomaly {
+                    warn!(
+                        "{} created {} {} within {}s",
+                        anomaly.did,
+                        anomaly.count,
+                        anomaly.activity.name(),
+                        anomaly.window_secs
+                    );
+                    if let Some(output) = &output {
+                        if let Err(e) = output.append(anomaly) {
+                            error!("Unable to write anomaly: {e}");
+                        }
+                    }
+                }
+                let notification = anomaly
+                    .filter(|_| notify)
+                    .map(|anomaly| Notification::anomaly(&anomaly));
+                let app = app.clone();
+                async move {
+                    if let Some(notification) = notification {
+                        app.notify_checked(None, notification).await;
+                    }
+                }
+            });
+        }
+    }
+
+    if !config.plugins.is_empty() {
+        let limits = PluginLimits {
+            fuel: config.plugin_fuel,
+            memory_bytes: config.plugin_max_memory,
+            max_action_bytes: config.plugin_max_action,
+        };
+        let output = config.plugin_output.as_ref().map(|path| {
+            Arc::new(
+                JsonlWriter::open(path, config.output_rotation, client.health())
+                    .expect("Unable to open plugin output"),
+            )
+        });
+        for spec in &config.plugins {
+            let plugin = Plugin::load(&spec.path, limits).expect("Unable to load plugin");
+            // Each plugin gets its own fan-out so a slow one only drops its own events
+            let fanout = Arc::new(Fanout::new(config.fanout_capacity));
+            if let Some(interval) = config.stats_interval {
+                fanout.clone().log_every(interval);
+            }
+            let events = fanout.subscribe(&format!("plugin:{}", plugin.name()), DropPolicy::Skip);
+            let output = output.clone();
+            let app = app.clone();
+            plugin.run(events, move |name, evt, action| match action {
+                Action::None => {}
+                Action::Emit { record } => {
+                    let Some(output) = &output else {
                         return;
+                    };
+                    let emitted = Emitted {
+                        plugin: name,
+                        seq: evt.seq,
+                        repo: evt.repo.as_str(),
+                        collection: &evt.collection,
+                        rkey: &evt.rkey,
+                        record: &record,
+                    };
+                    if let Err(e) = output.append(&emitted) {
+                        error!("Unable to write record emitted by plugin {name}: {e}");
                     }
+                }
+                Action::Notify { title, text } => {
+                    let notification = Notification::plugin(name, evt, title, text);
+                    let (app, evt) = (app.clone(), evt.clone());
+                    // Moderation may ask its API, which shouldn't hold up the plugin
+                    task::spawn("plugin-notify", async move {
+                        app.notify_checked(Some(&evt), notification).await;
+                    });
+                }
+            });
+            client.on(&spec.pattern, move |evt| {
+                fanout.publish(evt);
+                async {}
+            });
+        }
+    }
 
-                    // Parse the data half
-                    let commit = serde_ipld_dagcbor::from_slice::<Commit>(data)
-                        .expect("Malformed bsky \"#commit\" data");
-
-                    // Parse CAR file
-                    let (items, _header) =
-                        rs_car::car_read_all(&mut commit.blocks.as_slice(), true)
-                            .await
-                            .expect("CAR file is invalid");
-                    let items_iter = items.iter();
-                    for operation in &commit.ops {
-                        // Only parse CREATE action
-                        if operation.action != "create" {
-                            continue;
-                        }
+    // Posts go through the fan-out so a slow haiku handler (handle resolution, blob downloads,
+    // bot actions) never holds up the firehose
+    let fanout = Arc::new(Fanout::new(config.fanout_capacity));
+    if let Some(interval) = config.stats_interval {
+        fanout.clone().log_every(interval);
+    }
+    let mut posts = fanout.subscribe("haiku", DropPolicy::Skip);
+    task::spawn("haiku", async move {
+        while let Some(evt) = posts.recv().await {
+            app.handle_post(&evt).instrument(evt.span()).await;
+        }
+    });
 
-                        // Only parse post
-                        if !operation.path.starts_with("app.bsky.feed.post") {
-                            // info!("Skipping non-post: {:?}", operation.path);
-                            continue;
-                        }
+    if config.language_stats {
+        let mut posts = fanout.subscribe("languages", DropPolicy::Skip);
+        task::spawn("languages", async move {
+            while let Some(evt) = posts.recv().await {
+                if evt.action != "create" {
+                    continue;
+                }
+                if let Ok(Some(record)) = evt.record::<post::Record>() {
+                    counters.record_post(&record.text);
+                }
+            }
+        });
+    }
+
+    if let Some(path) = &config.identity_log {
+        let output = JsonlWriter::open(path, config.output_rotation, client.health())
+            .expect("Unable to open identity log");
+        let lookups = config.identity_lookups.then(|| http.clone());
+        let identities = IdentityLog::spawn(output, lookups, config.identity_concurrency);
+        client.on_identity(move |identity| identities.push(identity));
+    }
+
+    if let Some(path) = &config.follow_log {
+        let follows = FollowLog::open(path, config.output_rotation, client.health())
+            .expect("Unable to open follow log");
+        client.on(follows::COLLECTION, move |evt| {
+            if let Err(e) = follows.record(&evt) {
+                error!(
+                    "Unable to log follow {}/{}: {e}",
+                    evt.repo.as_str(),
+                    evt.rkey
+                );
+            }
+            async {}
+        });
+    }
+
+    if let Some(interval) = config.engagement_interval {
+        let output = config.engagement_output.as_ref().map(|path| {
+            JsonlWriter::open(path, config.output_rotation, client.health())
+                .expect("Unable to open engagement output")
+        });
+        let engagement = Arc::new(Engagement::new(
+            config.engagement_capacity,
+            config.engagement_window,
+        ));
+        engagement
+            .clone()
+            .report_every(interval, config.engagement_top, output);
+        client.on("app.bsky.feed.*", move |evt| {
+            engagement.record(&evt);
+            async {}
+        });
+    }
+
+    if let Some(interval) = config.trending_interval {
+        let output = config.trending_output.as_ref().map(|path| {
+            JsonlWriter::open(path, config.output_rotation, client.health())
+                .expect("Unable to open trending output")
+        });
+        let trending = Arc::new(Trending::default());
+        trending
+            .clone()
+            .report_every(interval, config.trending_top, output);
+        let mut posts = fanout.subscribe("trending", DropPolicy::Skip);
+        task::spawn("trending", async move {
+            while let Some(evt) = posts.recv().await {
+                if evt.action != "create" {
+                    continue;
+                }
+                if let Ok(Some(record)) = evt.record::<post::Record>() {
+                    trending.record(&Facets::from_record(&record));
+                }
+            }
+        });
+    }
+
+    if let Some(interval) = config.emoji_interval {
+        let output = config.emoji_output.as_ref().map(|path| {
+            JsonlWriter::open(path, config.output_rotation, client.health())
+                .expect("Unable to open emoji output")
+        });
+        let usage = Arc::new(EmojiUsage::default());
+        usage
+            .clone()
+            .report_every(interval, config.emoji_top, output);
+        let mut posts = fanout.subscribe("emoji", DropPolicy::Skip);
+        task::spawn("emoji", async move {
+            while let Some(evt) = posts.recv().await {
+                if evt.action != "create" {
+                    continue;
+                }
+                if let Ok(Some(record)) = evt.record::<post::Record>() {
+                    usage.record(&record.text);
+                }
+            }
+        });
+    }
+
+    client.on("app.bsky.feed.post", move |evt| {
+        fanout.publish(evt);
+        async {}
+    });
+    (client, archive)
+}
 
-                        let Some((_header, data)) = items_iter.clone().find(|(cid, _value)| {
-                            Some(cid.to_string())
-                                == operation.cid.as_ref().map(|cid| cid.0.to_string())
-                        }) else {
-                            error!("Could not find block for CID {:?}", operation.cid);
-                            continue;
-                        };
-
-                        let record =
-                            serde_ipld_dagcbor::from_reader::<post::Record, _>(data.as_slice())
-                                .expect("Malformed bsky \"#commit\" data");
-                        info!(
-                            "{} {:?} - {}",
-                            operation.action.to_uppercase(),
-                            operation.cid,
-                            record.text
-                        )
+/// Prints the mirrored state of `repo` as JSON. Returns whether it could be read.
+async fn dump(config: Config, repo: &str) -> bool {
+    let Some(dir) = &config.mirror_dir else {
+        error!("FIREHOSE_MIRROR_DIR must be set to dump a repo");
+        return false;
+    };
+    let did = if repo.starts_with("did:") {
+        repo.to_string()
+    } else {
+        match identity::resolve_handle(&http::client(&config), repo).await {
+            Ok(did) => did,
+            Err(e) => {
+                error!("Unable to resolve {repo}: {e}");
+                return false;
+            }
+        }
+    };
+
+    let blocks = config
+        .block_store
+        .as_ref()
+        .map(|dir| BlockStore::open(dir).map(Arc::new))
+        .transpose();
+    let dumped = blocks.and_then(|blocks| RepoMirror::open(dir, blocks)?.dump(&did));
+    match dumped {
+        Ok(Some(dump)) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&dump).expect("dumps are always serializable")
+            );
+            true
+        }
+        Ok(None) => {
+            error!("{did} has not been mirrored");
+            false
+        }
+        Err(e) => {
+            error!("Unable to read the mirror of {did}: {e}");
+            false
+        }
+    }
+}
+
+/// Prints the captured posts matching `query` as JSON. Returns whether the search ran.
+fn search(config: Config, query: &str, since: Option<&String>) -> bool {
+    let Some(path) = &config.sqlite_path else {
+        error!("FIREHOSE_SQLITE_PATH must be set to search");
+        return false;
+    };
+    let window = match since.map(|since| since.parse::<Window>()).transpose() {
+        Ok(window) => window,
+        Err(e) => {
+            error!("{e}");
+            return false;
+        }
+    };
+    match PostSearch::open(path).and_then(|posts| posts.search(query, window, usize::MAX)) {
+        Ok(hits) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&hits).expect("search hits are always serializable")
+            );
+            true
+        }
+        Err(e) => {
+            error!("Unable to search {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+/// Prints the results of `sql` over the Parquet output. Returns whether the query ran.
+fn query(config: Config, sql: &str) -> bool {
+    let Some(dir) = &config.parquet_dir else {
+        error!("FIREHOSE_PARQUET_DIR must be set to query");
+        return false;
+    };
+    match query::run(dir, sql) {
+        Ok(table) => {
+            println!("{table}");
+            true
+        }
+        Err(e) => {
+            error!("Unable to query {}: {e}", dir.display());
+            false
+        }
+    }
+}
+
+fn moderation(config: &Config, http: reqwest::Client, health: Arc<Health>) -> Option<Moderation> {
+    let mut terms = config.moderation_terms.clone();
+    if let Some(path) = &config.moderation_lexicon {
+        terms.extend(Moderation::load_lexicon(path).expect("Unable to load moderation lexicon"));
+    }
+    if terms.is_empty() && config.moderation_url.is_none() {
+        return None;
+    }
+    let mut moderation = Moderation::new(terms).expect("Invalid moderation lexicon");
+    if let Some(url) = config.moderation_url.clone() {
+        moderation = moderation.with_api(
+            http,
+            ModerationApiConfig {
+                url,
+                model: config.moderation_model.clone(),
+                api_key: config.moderation_api_key.clone(),
+            },
+        );
+    }
+    if let Some(path) = &config.moderation_audit {
+        moderation = moderation.with_audit(
+            JsonlWriter::open(path, config.output_rotation, health)
+                .expect("Unable to open moderation audit"),
+        );
+    }
+    Some(moderation)
+}
+
+fn embedder(config: &Config, http: reqwest::Client) -> Option<Embedder> {
+    let url = config.embedding_url.clone()?;
+    Some(Embedder::new(
+        http,
+        EmbeddingConfig {
+            url,
+            model: config.embedding_model.clone(),
+            api_key: config.embedding_api_key.clone(),
+        },
+    ))
+}
+
+/// Prints the `count` stored haikus closest in meaning to `query`, a text or the URI of a
+/// stored haiku, as JSON. Returns whether the search ran.
+async fn similar(config: Config, query: &str, count: usize) -> bool {
+    let Some(embedder) = embedder(&config, http::client(&config)) else {
+        error!("FIREHOSE_EMBEDDING_URL must be set to search by meaning");
+        return false;
+    };
+    let index = match VectorIndex::open(&config.embedding_index) {
+        Ok(index) => index,
+        Err(e) => {
+            error!("Unable to open {}: {e}", config.embedding_index.display());
+            return false;
+        }
+    };
+    let stored = if query.starts_with("at://") {
+        index.get(query)
+    } else {
+        Ok(None)
+    };
+    let vector = match stored {
+        Ok(Some(vector)) => vector,
+        Ok(None) => match embedder.embed(&[query.to_string()]).await {
+            Ok(mut vectors) => vectors.remove(0),
+            Err(e) => {
+                error!("Unable to embed {query:?}: {e}");
+                return false;
+            }
+        },
+        Err(e) => {
+            error!("Unable to read {query}: {e}");
+            return false;
+        }
+    };
+    match index.nearest(&vector, embedder.model(), count) {
+        Ok(neighbors) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&neighbors)
+                    .expect("neighbors are always serializable")
+            );
+            true
+        }
+        Err(e) => {
+            error!("Unable to search {}: {e}", config.embedding_index.display());
+            false
+        }
+    }
+}
+
+/// Runs every dead letter through the same handlers and sinks as `listen`. Letters that fail
+/// again are kept in a fresh dead letter file. Returns whether the letters could be read.
+async fn redeliver(mut config: Config) -> bool {
+    let Some(path) = config.dead_letters.clone() else {
+        error!("FIREHOSE_DEAD_LETTERS must be set to redeliver");
+        return false;
+    };
+    // Moved aside first so letters failing again don't land in the file being read. A file
+    // left over from an interrupted run is picked up as is.
+    let mut pending = path.clone().into_os_string();
+    pending.push(".redelivering");
+    let pending = PathBuf::from(pending);
+    if !pending.exists() {
+        if let Err(e) = std::fs::rename(&path, &pending) {
+            error!("Unable to move aside {}: {e}", path.display());
+            return false;
+        }
+    }
+    let letters = match deadletter::read(&pending) {
+        Ok(letters) => letters,
+        Err(e) => {
+            error!("Unable to read {}: {e}", pending.display());
+            return false;
+        }
+    };
+    info!("Redelivering {} dead letters", letters.len());
+
+    config.http_addr = None;
+    let (client, archive) = prepare(config, None, None).await;
+    client.redeliver(letters).await;
+    finish_archive(archive).await;
+    // Sinks write in the background; give them a moment to drain
+    tokio::time::sleep(REDELIVER_GRACE).await;
+    if let Err(e) = std::fs::remove_file(&pending) {
+        warn!("Unable to remove {}: {e}", pending.display());
+    }
+    true
+}
+
+/// Runs the post handler over every post already in `repo`, writing to the same outputs as
+/// `listen`. Returns whether the repo could be downloaded and read.
+async fn backfill(config: Config, repo: &str) -> bool {
+    let http = http::client(&config);
+    let did = if repo.starts_with("did:") {
+        repo.to_string()
+    } else {
+        match identity::resolve_handle(&http, repo).await {
+            Ok(did) => did,
+            Err(e) => {
+                error!("Unable to resolve {repo}: {e}");
+                return false;
+            }
+        }
+    };
+
+    let app = App::from_config(&config, http, &Client::new(config.clone())).await;
+    match app.backfill(&did).await {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Unable to backfill {did}: {e}");
+            false
+        }
+    }
+}
+
+/// Backfills every repo on the configured hosts, then listens live from where the firehose
+/// was when the crawl started.
+async fn crawl(config: Config, dashboard: Option<Arc<Dashboard>>) {
+    let cursor = match firehose::current_seq(&config).await {
+        Ok((_, cursor)) => cursor,
+        Err(e) => {
+            error!("Unable to read the current firehose cursor: {e}");
+            std::process::exit(1);
+        }
+    };
+    info!("Crawl starting at firehose cursor {cursor}");
+
+    let http = http::client(&config);
+    let client = Client::new(config.clone());
+    let app = Arc::new(App::from_config(&config, http.clone(), &client).await);
+    let backfilled = crawl::crawl(
+        &http,
+        &config.crawl_hosts,
+        config.crawl_concurrency,
+        |did| {
+            let app = app.clone();
+            async move {
+                match app.backfill(&did).await {
+                    Ok(rev) => Some(rev),
+                    Err(e) => {
+                        warn!("Unable to backfill {did}: {e}");
+                        None
                     }
-                });
+                }
+            }
+        },
+    )
+    .await;
+    let app = Arc::into_inner(app).expect("every backfill has finished");
+
+    listen(
+        config,
+        None,
+        dashboard,
+        Some(Crawled {
+            cursor,
+            backfilled,
+            client,
+            app,
+        }),
+    )
+    .await;
+}
+
+impl App {
+    /// Builds the post handler, sharing stats, health and load shedding state with `client`.
+    async fn from_config(config: &Config, http: reqwest::Client, client: &Client) -> Self {
+        let handles = HandleResolver::new(http.clone(), config.verify_handles);
+        let session = Session::from_config(config, http.clone())
+            .await
+            .expect("Unable to log in bot account");
+        let bot = Bot::from_config(config, session.clone());
+        let own_did = session.as_ref().map(|session| session.did());
+
+        let syllables = Arc::new(match &config.cmudict {
+            Some(path) => SyllableCounter::load_cmudict(path)
+                .expect("Unable to load CMU pronouncing dictionary"),
+            None => SyllableCounter::Estimate,
+        });
+
+        let mut classifiers = ClassifierRegistry::default();
+        if !config.label_routes.is_empty() || (config.feed && !config.feed_labels.is_empty()) {
+            classifiers = ClassifierRegistry::builtin(config, syllables.clone());
+            for route in &config.label_routes {
+                let output =
+                    JsonlWriter::open(&route.path, config.output_rotation, client.health())
+                        .expect("Unable to open label route output");
+                classifiers.route(&route.pattern, output);
+            }
+        }
+
+        let feedgen = match (&config.feedgen_hostname, &config.http_addr) {
+            (Some(hostname), Some(_)) => {
+                let publisher = config
+                    .feedgen_publisher
+                    .as_deref()
+                    .expect("FIREHOSE_FEEDGEN_PUBLISHER must be set to serve a feed");
+                let feedgen = FeedGenerator::new(
+                    hostname,
+                    publisher,
+                    &config.feedgen_rkey,
+                    config.feedgen_size,
+                );
+                let loaded = feedgen
+                    .load(&config.haiku_output)
+                    .expect("Unable to read haikus for the feed");
+                info!("Serving a feed of {loaded} haikus as did:web:{hostname}");
+                Some(Arc::new(feedgen))
+            }
+            _ => None,
+        };
+
+        let script = config.script.as_ref().map(|path| {
+            let script = Arc::new(Script::load(path.clone()).expect("Unable to load script"));
+            script.clone().watch();
+            script
+        });
+
+        let sentiment = match &config.sentiment_lexicon {
+            Some(path) => Some(
+                SentimentAnalyzer::load_lexicon(path).expect("Unable to load sentiment lexicon"),
+            ),
+            None if config.sentiment
+                || config.min_sentiment.is_some()
+                || config.max_sentiment.is_some() =>
+            {
+                Some(SentimentAnalyzer::default())
+            }
+            None => None,
+        };
+
+        let alt_text = config.alt_text_stats_interval.map(|interval| {
+            let stats = Arc::new(AltTextStats::default());
+            stats.clone().log_every(interval);
+            stats
+        });
+
+        Self {
+            stats: client.stats(),
+            shedder: client.load_shedder(),
+            filter: PostFilter::from_config(config).expect("Invalid post filter"),
+            labelers: (!config.labelers.is_empty()).then(|| Labelers::subscribe(config)),
+            feedgen,
+            threads: config
+                .thread_index_size
+                .filter(|_| config.http_addr.is_some())
+                .map(|size| Arc::new(ThreadIndex::new(size))),
+            digest: Digest::from_config(config, session).map(Arc::new),
+            feed: (config.feed && config.http_addr.is_some())
+                .then(|| Arc::new(AtomFeed::new(config.feed_size, config.feed_labels.clone()))),
+            gallery: (config.gallery && config.http_addr.is_some())
+                .then(|| Gallery::new(config.haiku_output.clone(), config.gallery_page_size)),
+            capture: config
+                .sqlite_path
+                .as_ref()
+                .map(|path| PostCapture::spawn(path).expect("Unable to open SQLite database")),
+            graphql: (config.graphql && config.http_addr.is_some()).then(|| {
+                let path = config
+                    .sqlite_path
+                    .as_ref()
+                    .expect("FIREHOSE_SQLITE_PATH must be set to serve GraphQL");
+                GraphQl::new(PostSearch::open(path).expect("Unable to open SQLite database"))
+            }),
+            haikus: JsonlWriter::open(
+                &config.haiku_output,
+                config.output_rotation,
+                client.health(),
+            )
+            .expect("Unable to open haiku output"),
+            csv: config.csv_output.as_ref().map(|path| {
+                CsvWriter::open(
+                    path,
+                    config.csv_columns.clone(),
+                    config.output_rotation,
+                    client.health(),
+                )
+                .expect("Unable to open CSV output")
+            }),
+            script,
+            forms: config.forms.clone(),
+            limericks: config.limericks,
+            syllables,
+            classifiers,
+            sentiment,
+            sentiment_range: (config.min_sentiment, config.max_sentiment),
+            alt_text,
+            blobs: config.blob_dir.as_ref().map(|dir| {
+                BlobFetcher::new(
+                    http.clone(),
+                    dir.clone(),
+                    config.blob_max_bytes,
+                    config.blob_concurrency,
+                )
+                .expect("Unable to create blob directory")
+            }),
+            dedup: config.dedup_file.as_ref().map(|path| {
+                DedupStore::open(path, config.dedup_retention, client.health())
+                    .expect("Unable to open dedup store")
+            }),
+            near_duplicates: config
+                .near_duplicate_threshold
+                .map(|threshold| NearDuplicates::new(threshold, config.near_duplicate_capacity)),
+            skip_near_duplicates: config.skip_near_duplicates,
+            languages: LanguageFilter::new(
+                config.languages.clone(),
+                config.min_language_confidence,
+            ),
+            moderation: moderation(config, http.clone(), client.health()),
+            bot,
+            own_did,
+            notify_on: config.notify_on,
+            discord: config.discord_webhook.clone().map(|webhook| {
+                Discord::spawn(
+                    http.clone(),
+                    handles.clone(),
+                    webhook,
+                    config
+                        .discord_rate_limit
+                        .map(|limit| RateLimiter::new(limit, config.discord_rate_policy)),
+                )
+            }),
+            telegram: config.telegram_token.as_ref().map(|token| {
+                Telegram::spawn(
+                    http.clone(),
+                    handles.clone(),
+                    token,
+                    config.telegram_chat_id.clone(),
+                    config.telegram_template.clone(),
+                    config
+                        .telegram_rate_limit
+                        .map(|limit| RateLimiter::new(limit, config.telegram_rate_policy)),
+                )
+            }),
+            desktop: config.desktop_notify.then(|| {
+                Desktop::spawn(handles.clone(), config.desktop_keywords.clone())
+                    .expect("Invalid desktop notification keywords")
+            }),
+            embeddings: embedder(config, http.clone()).map(|embedder| {
+                let index = VectorIndex::open(&config.embedding_index)
+                    .expect("Unable to open embedding index");
+                EmbeddingWriter::spawn(embedder, Arc::new(index))
+            }),
+            dashboard: None,
+            handles,
+            http,
+            backfilled: HashMap::new(),
+        }
+    }
+
+    /// Runs [`Self::handle_post`] over every post in `did`'s repo, returning the revision it
+    /// was read at.
+    async fn backfill(&self, did: &str) -> Result<String, RepoError> {
+        info!("Downloading repo of {did}");
+        let car = repo::fetch(&self.http, did).await?;
+        let repo = repo::records(&car).await?;
+
+        let did = Did::new(did.to_string()).map_err(|e| RepoError::Malformed(e.to_string()))?;
+        let mut posts = 0;
+        for record in repo.records {
+            if record.collection != "app.bsky.feed.post" {
+                continue;
             }
-            Message::Close(_) => {
-                info!("Firehose disconnected us.");
+            posts += 1;
+            let evt = Event {
+                seq: 0,
+                repo: did.clone(),
+                rev: repo.rev.clone(),
+                since: None,
+                action: "create".to_string(),
+                collection: record.collection,
+                rkey: record.rkey,
+                cid: Some(CidLink(record.cid)),
+                block: Some(record.block),
+                account_status: None,
+            };
+            self.handle_post(&evt).instrument(evt.span()).await;
+        }
+        info!("Backfilled {posts} posts of {}", did.as_str());
+        Ok(repo.rev)
+    }
+
+    async fn handle_post(&self, evt: &Event) {
+        // Only parse CREATE action
+        if evt.action != "create" {
+            return;
+        }
+        // Our own quotes and digest replies would be found again, and acted on in a loop
+        if self.own_did.as_deref() == Some(evt.repo.as_str()) {
+            return;
+        }
+        // Already seen while crawling
+        if self
+            .backfilled
+            .get(evt.repo.as_str())
+            .is_some_and(|rev| evt.rev <= *rev)
+        {
+            return;
+        }
+
+        let mut record = match evt.record::<post::Record>() {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                error!("Could not find block for CID {:?}", evt.cid);
+                return;
             }
-            _ => {}
+            Err(e) => {
+                error!("Malformed post record: {e}");
+                return;
+            }
+        };
+
+        self.stats.record_created_at(&record.created_at);
+
+        if let (Some(threads), Some(reply)) = (&self.threads, Reply::from_record(&record)) {
+            let uri = format!("at://{}/{}/{}", evt.repo.as_str(), evt.collection, evt.rkey);
+            threads.insert(&uri, reply);
         }
+
+        let embed = Embed::from_record(&record);
+        if let Some(alt_text) = &self.alt_text {
+            alt_text.record(&embed);
+        }
+
+        let Some(matched) = self.filter.matches(&record.text) else {
+            return;
+        };
+        if !self.filter.allows_facets(&Facets::from_record(&record))
+            || !self.filter.allows_embed(&embed)
+        {
+            return;
+        }
+        if !self.filter.labels().is_empty() {
+            let json = evt
+                .block
+                .as_deref()
+                .and_then(|block| frame::record_json(block).ok());
+            let labels = labels::of_event(evt, json.as_ref(), self.labelers.as_deref());
+            if !self.filter.labels().allows(&labels) {
+                return;
+            }
+        }
+        if let Some(script) = &self.script {
+            let Some(text) = script.apply(evt, &record) else {
+                return;
+            };
+            record.text = text;
+        }
+        let sentiment = self.sentiment.as_ref().map(|sentiment| {
+            let score = sentiment.score(&record.text);
+            self.stats.record_sentiment(score);
+            score
+        });
+        if let Some(score) = sentiment {
+            let (min, max) = self.sentiment_range;
+            if min.is_some_and(|min| score < min) || max.is_some_and(|max| score > max) {
+                return;
+            }
+        }
+        if matched.is_empty() {
+            info!("CREATE {:?} - {}", evt.cid, record.text)
+        } else {
+            info!("CREATE {:?} {matched:?} - {}", evt.cid, record.text)
+        }
+        let near_duplicate = self
+            .near_duplicates
+            .as_ref()
+            .and_then(|near_duplicates| near_duplicates.insert(&record.text));
+        if let Some(duplicate) = near_duplicate {
+            info!(
+                "Near-duplicate in cluster {} ({:.0}% similar): {}",
+                duplicate.cluster,
+                duplicate.similarity * 100.0,
+                record.text
+            );
+        }
+        if let Some(csv) = &self.csv {
+            if let Err(e) = csv.append(evt, &record) {
+                error!("Unable to write CSV row: {e}");
+            }
+        }
+        // Moderated at most once, and only when something would publish or act on the post
+        let moderated = OnceCell::new();
+        if self.notify_on == NotifyOn::Matches && self.allows(evt, &record, &moderated).await {
+            self.notify(Notification::filter_match(evt, &record, &matched));
+        }
+
+        if !self.shedder.admit() {
+            return;
+        }
+        let feed_labels = self.feed.as_ref().filter(|feed| feed.wants_labels());
+        if self.classifiers.is_routed() || feed_labels.is_some() {
+            let labels = self.classifiers.dispatch(evt, &record);
+            if let Some(feed) = feed_labels {
+                if self.allows(evt, &record, &moderated).await {
+                    feed.push_labeled(evt, &record, &labels);
+                }
+            }
+        }
+        let Some(haiku) = haiku::detect(&record.text, &self.forms, &self.syllables).or_else(|| {
+            self.limericks
+                .then(|| haiku::detect_limerick(&record.text, &self.syllables))
+                .flatten()
+        }) else {
+            return;
+        };
+        let detection = LanguageFilter::detect(&record.text);
+        if !self.languages.accepts(detection.as_ref()) {
+            info!(
+                "Skipping {} in unaccepted language {detection:?}: {}",
+                haiku.form, record.text
+            );
+            return;
+        }
+        if let Some(dedup) = &self.dedup {
+            match dedup.insert(&record.text) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Skipping duplicate {}: {}", haiku.form, record.text);
+                    return;
+                }
+                Err(e) => error!("Unable to record haiku in dedup store: {e}"),
+            }
+        }
+        if near_duplicate.is_some() && self.skip_near_duplicates {
+            info!("Skipping near-duplicate {}: {}", haiku.form, record.text);
+            return;
+        }
+
+        let handle = match self.handles.resolve(evt.repo.as_str()).await {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                warn!("Unable to resolve handle for {}: {e}", evt.repo.as_str());
+                None
+            }
+        };
+
+        let mut haiku = HaikuRecord::new(evt, &record, haiku, detection, handle);
+        haiku.sentiment = sentiment;
+        haiku.near_duplicate = near_duplicate;
+        // Still written to the haiku output, marked so public sinks reading it leave it out
+        haiku.held_back = !self.allows(evt, &record, &moderated).await;
+        if let Some(blobs) = &self.blobs {
+            haiku.blob_paths = blobs.fetch_images(&haiku.did, &haiku.embed).await;
+        }
+        info!("Found {}: {}", haiku.form, haiku.url);
+        if let Err(e) = self.haikus.append(&haiku) {
+            error!("Unable to write haiku: {e}");
+        }
+        if let Some(embeddings) = &self.embeddings {
+            embeddings.push(&haiku.uri, &haiku.did, &haiku.text);
+        }
+        if let Some(dashboard) = &self.dashboard {
+            let author = match haiku.display_handle() {
+                Some(handle) => format!("@{handle}"),
+                None => haiku.did.clone(),
+            };
+            dashboard.push_match(&haiku.form, &author, &haiku.text);
+        }
+        if let Some(gallery) = &self.gallery {
+            gallery.publish(&haiku);
+        }
+        if let Some(capture) = &self.capture {
+            capture.push_haiku(&haiku);
+        }
+        if let Some(graphql) = &self.graphql {
+            graphql.publish(&haiku);
+        }
+        if let Some(feed) = &self.feed {
+            feed.push_haiku(&haiku);
+        }
+        if let Some(feedgen) = &self.feedgen {
+            feedgen.push_haiku(&haiku);
+        }
+        if haiku.held_back {
+            return;
+        }
+        if let Some(digest) = &self.digest {
+            digest.add(&haiku);
+        }
+        if self.notify_on == NotifyOn::Haikus {
+            self.notify(Notification::haiku(&haiku));
+        }
+        if let Some(bot) = &self.bot {
+            bot.act(&haiku);
+        }
+    }
+
+    /// Whether moderation lets the post be published, notified about or acted on, checking it on
+    /// the first call for `moderated` only.
+    async fn allows(&self, evt: &Event, record: &post::Record, moderated: &OnceCell<bool>) -> bool {
+        let Some(moderation) = &self.moderation else {
+            return true;
+        };
+        *moderated
+            .get_or_init(|| moderation.allows(evt, record))
+            .await
+    }
+
+    /// Hands `notification`, which isn't about a found post, to every configured sink unless
+    /// moderation holds back its text or, when `evt` is a post, the post.
+    async fn notify_checked(&self, evt: Option<&Event>, notification: Notification) {
+        if let Some(moderation) = &self.moderation {
+            let post = evt
+                .filter(|evt| evt.collection == "app.bsky.feed.post")
+                .and_then(|evt| Some((evt, evt.record::<post::Record>().ok()??)));
+            if let Some((evt, record)) = post {
+                if !moderation.allows(evt, &record).await {
+                    return;
+                }
+            }
+            if !moderation.allows_notification(&notification).await {
+                return;
+            }
+        }
+        self.notify(notification);
     }
 
-    info!("Disconnected from Firehose.");
+    /// Hands `notification` to every configured sink.
+    fn notify(&self, notification: Notification) {
+        if let Some(discord) = &self.discord {
+            discord.notify(notification.clone());
+        }
+        if let Some(telegram) = &self.telegram {
+            telegram.notify(notification.clone());
+        }
+        if let Some(desktop) = &self.desktop {
+            desktop.notify(notification);
+        }
+    }
 }
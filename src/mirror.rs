@@ -0,0 +1,219 @@
+//! Repo state mirror: the current records of every repo seen, kept up to date from its commits,
+//! like a minimal AppView index.
+//!
+//! Each repo has an append-only log of its operations in `<dir>/<did>.jsonl` (with `:` as
+//! `_`); its state is the log replayed. Record values are read from the
+//! [block store](crate::blockstore) by CID, so without one only CIDs are known.
+
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, warn};
+
+use crate::{
+    blockstore::BlockStore,
+    frame::{self, Frame},
+    task,
+};
+
+/// Frames waiting to be applied before new ones are dropped
+const QUEUE_SIZE: usize = 10_000;
+
+/// One line of a repo's log.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogEntry {
+    rev: String,
+    action: String,
+    path: String,
+    cid: Option<String>,
+}
+
+/// A repo as of its latest mirrored commit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoState {
+    pub rev: String,
+    /// Record CIDs by `collection/rkey`
+    pub records: BTreeMap<String, String>,
+}
+
+/// A record in a [`RepoState`], with its value if the block store has it.
+#[derive(Debug, Serialize)]
+pub struct MirroredRecord {
+    pub path: String,
+    pub cid: String,
+    pub value: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepoDump {
+    pub did: String,
+    pub rev: String,
+    pub records: Vec<MirroredRecord>,
+}
+
+#[derive(Debug)]
+pub struct RepoMirror {
+    dir: PathBuf,
+    blocks: Option<Arc<BlockStore>>,
+}
+
+impl RepoMirror {
+    pub fn open(dir: &Path, blocks: Option<Arc<BlockStore>>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            blocks,
+        })
+    }
+
+    fn log_path(&self, did: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", did.replace(':', "_")))
+    }
+
+    /// Appends `commit`'s operations to its repo's log.
+    pub fn apply(&self, commit: &Commit) -> std::io::Result<()> {
+        if commit.ops.is_empty() {
+            return Ok(());
+        }
+        let mut lines = Vec::new();
+        for operation in &commit.ops {
+            let entry = LogEntry {
+                rev: commit.rev.clone(),
+                action: operation.action.clone(),
+                path: operation.path.clone(),
+                cid: operation.cid.as_ref().map(|cid| cid.0.to_string()),
+            };
+            serde_json::to_writer(&mut lines, &entry)?;
+            lines.push(b'\n');
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.log_path(commit.repo.as_str()))?
+            .write_all(&lines)
+    }
+
+    /// The current state of `did`, or `None` if none of its commits were mirrored.
+    pub fn state(&self, did: &str) -> std::io::Result<Option<RepoState>> {
+        let log = match std::fs::read_to_string(self.log_path(did)) {
+            Ok(log) => log,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut state = RepoState::default();
+        for line in log.lines() {
+            // A crash can leave the last line cut short
+            let Ok(entry) = serde_json::from_str::<LogEntry>(line) else {
+                warn!("Skipping malformed line in the mirror of {did}");
+                continue;
+            };
+            match (entry.action.as_str(), entry.cid) {
+                ("delete", _) | (_, None) => state.records.remove(&entry.path),
+                (_, Some(cid)) => state.records.insert(entry.path, cid),
+            };
+            state.rev = entry.rev;
+        }
+        Ok(Some(state))
+    }
+
+    /// The current records of `did` with their values, or `None` if none of its commits were
+    /// mirrored.
+    pub fn dump(&self, did: &str) -> std::io::Result<Option<RepoDump>> {
+        let Some(state) = self.state(did)? else {
+            return Ok(None);
+        };
+        let mut records = Vec::with_capacity(state.records.len());
+        for (path, cid) in state.records {
+            let value = match &self.blocks {
+                Some(blocks) => blocks
+                    .get(&cid)?
+                    .and_then(|block| frame::record_json(&block).ok()),
+                None => None,
+            };
+            records.push(MirroredRecord { path, cid, value });
+        }
+        Ok(Some(RepoDump {
+            did: did.to_string(),
+            rev: state.rev,
+            records,
+        }))
+    }
+
+    /// `/repos/<did>` returns the repo's [`RepoDump`].
+    pub fn routes(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/repos/:did", get(repo))
+            .with_state(self)
+    }
+}
+
+async fn repo(
+    State(mirror): State<Arc<RepoMirror>>,
+    UrlPath(did): UrlPath<String>,
+) -> axum::response::Response {
+    let dumped = tokio::task::spawn_blocking(move || mirror.dump(&did))
+        .await
+        .expect("dumping a repo doesn't panic");
+    match dumped {
+        Ok(Some(dump)) => Json(dump).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Repo not mirrored").into_response(),
+        Err(e) => {
+            error!("Unable to read mirrored repo: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Unable to read repo").into_response()
+        }
+    }
+}
+
+/// Applies every commit frame pushed to it to the mirror, in the background. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct MirrorWriter {
+    queue: mpsc::Sender<Bytes>,
+}
+
+impl MirrorWriter {
+    pub fn spawn(mirror: Arc<RepoMirror>) -> Self {
+        let (queue, frames) = mpsc::channel(QUEUE_SIZE);
+        task::spawn("mirror", run(mirror, frames));
+        Self { queue }
+    }
+
+    /// Queues a raw frame; frames other than commits are ignored. Frames are dropped when the
+    /// mirror falls too far behind, leaving it out of date until the records are touched again.
+    pub fn push(&self, frame: Bytes) {
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(frame) {
+            warn!("Repo mirror is behind, dropping a frame");
+        }
+    }
+}
+
+async fn run(mirror: Arc<RepoMirror>, mut frames: mpsc::Receiver<Bytes>) {
+    while let Some(data) = frames.recv().await {
+        let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+            continue;
+        };
+        let mirror = mirror.clone();
+        let applied =
+            tokio::task::spawn_blocking(move || mirror.apply(&commit).map_err(|e| (commit.seq, e)))
+                .await
+                .expect("mirror writes don't panic");
+        if let Err((seq, e)) = applied {
+            error!("Unable to mirror commit {seq}: {e}");
+        }
+    }
+}
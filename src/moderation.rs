@@ -0,0 +1,243 @@
+//! Keeps abusive posts away from everything that speaks for us: public sinks (the gallery,
+//! GraphQL, the Atom feed and the feed generator), notifications, bot actions and the daily
+//! digest. Posts are checked against a lexicon of blocked terms and, when configured, an
+//! OpenAI-compatible `/v1/moderations` API. Held back posts are logged, and appended with what
+//! they matched to an audit file when one is set.
+
+use std::{collections::BTreeMap, path::Path};
+
+use atrium_api::app::bsky::feed::post;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{client::Event, filter::WordMatcher, jsonl::JsonlWriter, notify::Notification};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ModerationError {
+    #[error("unable to read lexicon: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid lexicon: {0}")]
+    Lexicon(#[from] aho_corasick::BuildError),
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("moderation API returned no results")]
+    NoResults,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModerationApiConfig {
+    /// Moderations endpoint, e.g. `https://api.openai.com/v1/moderations`
+    pub url: String,
+    /// Left to the API's default when unset
+    pub model: Option<String>,
+    /// Sent as a bearer token when set
+    pub api_key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ModerationRequest<'a> {
+    input: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: BTreeMap<String, bool>,
+}
+
+/// Why a post was held back, if it was.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Verdict {
+    /// Lexicon terms found in the text, lowercased
+    pub terms: Vec<String>,
+    /// Categories the moderation API flagged
+    pub categories: Vec<String>,
+    /// Set when the moderation API couldn't be asked, in which case the post is held back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Verdict {
+    pub fn is_clean(&self) -> bool {
+        self.terms.is_empty() && self.categories.is_empty() && self.error.is_none()
+    }
+}
+
+/// One line of the audit file.
+#[derive(Debug, Serialize)]
+struct HeldBack<'a> {
+    at: DateTime<Utc>,
+    uri: String,
+    did: &'a str,
+    text: &'a str,
+    #[serde(flatten)]
+    verdict: &'a Verdict,
+}
+
+pub struct Moderation {
+    terms: Option<WordMatcher>,
+    api: Option<(reqwest::Client, ModerationApiConfig)>,
+    audit: Option<JsonlWriter>,
+}
+
+impl Moderation {
+    /// Holds back text containing any of `terms` as whole words, ignoring ASCII case.
+    pub fn new(terms: Vec<String>) -> Result<Self, ModerationError> {
+        let terms = terms
+            .into_iter()
+            .map(|term| term.trim().to_lowercase())
+            .filter(|term| !term.is_empty())
+            .collect::<Vec<_>>();
+        Ok(Self {
+            terms: WordMatcher::new(terms)?,
+            api: None,
+            audit: None,
+        })
+    }
+
+    /// Reads a lexicon of one term per line. Blank lines and lines starting with `#` are
+    /// skipped.
+    pub fn load_lexicon(path: &Path) -> Result<Vec<String>, ModerationError> {
+        Ok(std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Also asks the moderation API at `config.url` about text the lexicon lets through.
+    pub fn with_api(mut self, http: reqwest::Client, config: ModerationApiConfig) -> Self {
+        self.api = Some((http, config));
+        self
+    }
+
+    /// Appends held back posts to `audit`.
+    pub fn with_audit(mut self, audit: JsonlWriter) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Lexicon terms found in `text` as whole words, each once, in order of appearance.
+    pub fn matches(&self, text: &str) -> Vec<String> {
+        self.terms
+            .as_ref()
+            .map(|terms| terms.find(text).into_iter().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// Checks `text` against the lexicon, then the moderation API if it passed.
+    pub async fn check(&self, text: &str) -> Verdict {
+        let terms = self.matches(text);
+        if !terms.is_empty() {
+            return Verdict {
+                terms,
+                ..Default::default()
+            };
+        }
+        let Some((http, api)) = &self.api else {
+            return Verdict::default();
+        };
+        match ask(http, api, text).await {
+            Ok(categories) => Verdict {
+                categories,
+                ..Default::default()
+            },
+            Err(e) => {
+                warn!("Unable to moderate post, holding it back: {e}");
+                Verdict {
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    /// Whether the post may be published, notified about or acted on, recording it when it may
+    /// not.
+    pub async fn allows(&self, evt: &Event, record: &post::Record) -> bool {
+        let verdict = self.check(&record.text).await;
+        if verdict.is_clean() {
+            return true;
+        }
+        let did = evt.repo.as_str();
+        let uri = format!("at://{did}/{}/{}", evt.collection, evt.rkey);
+        self.hold_back(uri, did, &record.text, &verdict);
+        false
+    }
+
+    /// Whether a notification not about a found post, e.g. one a plugin asked for, may be sent,
+    /// judging by its title and text. Records it when it may not.
+    pub async fn allows_notification(&self, notification: &Notification) -> bool {
+        let text = format!("{}\n{}", notification.title, notification.text);
+        let verdict = self.check(&text).await;
+        if verdict.is_clean() {
+            return true;
+        }
+        self.hold_back(notification.url.clone(), &notification.did, &text, &verdict);
+        false
+    }
+
+    fn hold_back(&self, uri: String, did: &str, text: &str, verdict: &Verdict) {
+        info!(
+            "Holding back {uri} (terms {:?}, categories {:?})",
+            verdict.terms, verdict.categories
+        );
+        if let Some(audit) = &self.audit {
+            let held = HeldBack {
+                at: Utc::now(),
+                uri,
+                did,
+                text,
+                verdict,
+            };
+            if let Err(e) = audit.append(&held) {
+                error!("Unable to write moderation audit: {e}");
+            }
+        }
+    }
+}
+
+/// The categories the API flags `text` for, empty when it isn't flagged.
+async fn ask(
+    http: &reqwest::Client,
+    api: &ModerationApiConfig,
+    text: &str,
+) -> Result<Vec<String>, ModerationError> {
+    let mut request = http.post(&api.url).json(&ModerationRequest {
+        input: text,
+        model: api.model.as_deref(),
+    });
+    if let Some(key) = &api.api_key {
+        request = request.bearer_auth(key);
+    }
+    let response: ModerationResponse = request.send().await?.error_for_status()?.json().await?;
+    let result = response
+        .results
+        .into_iter()
+        .next()
+        .ok_or(ModerationError::NoResults)?;
+    if !result.flagged {
+        return Ok(Vec::new());
+    }
+    let categories = result
+        .categories
+        .into_iter()
+        .filter_map(|(category, flagged)| flagged.then_some(category))
+        .collect::<Vec<_>>();
+    // Flagged without saying why
+    Ok(if categories.is_empty() {
+        vec!["flagged".to_string()]
+    } else {
+        categories
+    })
+}
@@ -0,0 +1,118 @@
+//! Publishes pipeline events to an MQTT broker, for home automation and other IoT-style
+//! consumers. The connection is kept up in the background: rumqttc reconnects on the next poll
+//! after a failure, resending QoS 1 and 2 messages the broker hadn't acknowledged.
+
+use std::{sync::Arc, time::Duration};
+
+use rumqttc::{AsyncClient, MqttOptions, Outgoing, Packet, QoS};
+use tracing::{info, warn};
+
+use crate::{client::Event, health::Health, pipeline, task, telemetry::Metrics};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Largest message sent or accepted; rumqttc's default of 10 KiB is smaller than some records
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+/// Wait before the first reconnect, doubling with each failed one after
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// See [`pipeline::topic`]
+    pub topic: String,
+    pub qos: QoS,
+    pub retain: bool,
+    /// Messages waiting to be sent before new ones are dropped
+    pub queue: usize,
+}
+
+/// The QoS for level `0`, `1` or `2`.
+pub fn qos(level: u8) -> Option<QoS> {
+    match level {
+        0 => Some(QoS::AtMostOnce),
+        1 => Some(QoS::AtLeastOnce),
+        2 => Some(QoS::ExactlyOnce),
+        _ => None,
+    }
+}
+
+/// Publishes to one broker from its own queue. Deliveries and connection errors are reported
+/// to [`Health`] under `name`.
+pub struct Mqtt {
+    name: String,
+    client: AsyncClient,
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    health: Arc<Health>,
+}
+
+impl Mqtt {
+    pub fn spawn(name: String, config: MqttConfig, health: Arc<Health>) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options
+            .set_keep_alive(KEEP_ALIVE)
+            .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+        let (client, mut events) = AsyncClient::new(options, config.queue);
+        health.register_pipeline_sink(&name);
+
+        let worker = Self {
+            name: name.clone(),
+            client,
+            topic: config.topic,
+            qos: config.qos,
+            retain: config.retain,
+            health: health.clone(),
+        };
+
+        let broker = format!("{}:{}", config.host, config.port);
+        task::spawn(&format!("sink/{name}"), async move {
+            let mut backoff = RECONNECT_BACKOFF;
+            loop {
+                let result = match events.poll().await {
+                    Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Sink {name} connected to {broker}");
+                        backoff = RECONNECT_BACKOFF;
+                        continue;
+                    }
+                    Ok(rumqttc::Event::Outgoing(Outgoing::Publish(_))) => Ok(()),
+                    Ok(_) => continue,
+                    Err(e) => Err(e),
+                };
+                health.record_pipeline_sink(&name, &result);
+                Metrics::get().record_pipeline_delivery(&name, result.is_ok());
+                if let Err(e) = result {
+                    warn!("Sink {name} lost {broker}, reconnecting in {backoff:?}: {e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                }
+            }
+        });
+        worker
+    }
+
+    /// Queues `payload` for `evt` on its topic, dropping it if the broker is too far behind.
+    pub fn publish(&self, pipeline: &str, evt: &Event, payload: Vec<u8>) {
+        let topic = pipeline::topic(&self.topic, pipeline, evt);
+        if self
+            .client
+            .try_publish(topic, self.qos, self.retain, payload)
+            .is_err()
+        {
+            self.health.record_pipeline_sink_drop(&self.name);
+            Metrics::get().record_pipeline_drop(&self.name);
+            warn!(
+                "Sink {} is behind, dropping {}/{}",
+                self.name, evt.collection, evt.rkey
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Merkle search tree proofs: checks that the record CIDs a commit's `ops` claim are what its
+//! signed commit object's MST root actually holds, using only the blocks sent with the commit.
+//!
+//! Relays are expected to send every MST node on the path to each changed record. A relay
+//! that drops or makes up blocks, or ops that don't match the tree, fails the check. The
+//! signature itself is not verified.
+
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use ipld_core::{cid::Cid, ipld::Ipld};
+
+use crate::frame::Blocks;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProofError {
+    #[error("block {0} is missing from the commit")]
+    MissingBlock(String),
+    #[error("malformed block {cid}: {message}")]
+    Malformed { cid: String, message: String },
+    #[error("commit object is for {0}, not the committing repo")]
+    WrongRepo(String),
+    #[error("commit object has rev {0}, not the frame's")]
+    WrongRev(String),
+    #[error("{path} is {found:?} in the MST, not {expected}")]
+    Mismatch {
+        path: String,
+        expected: String,
+        found: Option<String>,
+    },
+}
+
+/// One MST node: keys in `left` sort before every entry, and keys in an entry's `tree`
+/// between that entry and the next.
+struct Node {
+    left: Option<Cid>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    key: Vec<u8>,
+    value: Cid,
+    tree: Option<Cid>,
+}
+
+/// Checks every created or updated record in `commit` is reachable from its MST root at the
+/// CID its op claims. `blocks` are the commit's CAR blocks, see [`crate::frame::blocks`].
+///
+/// `tooBig` commits carry no blocks and pass as is.
+pub fn verify(commit: &Commit, blocks: &Blocks) -> Result<(), ProofError> {
+    if commit.too_big {
+        return Ok(());
+    }
+
+    let commit_cid = &commit.commit.0;
+    let signed = map(commit_cid, block(blocks, commit_cid)?)?;
+    match signed.get("did") {
+        Some(Ipld::String(did)) if did == commit.repo.as_str() => {}
+        Some(Ipld::String(did)) => return Err(ProofError::WrongRepo(did.clone())),
+        _ => return Err(malformed(commit_cid, "commit object has no did")),
+    }
+    match signed.get("rev") {
+        Some(Ipld::String(rev)) if *rev == commit.rev => {}
+        Some(Ipld::String(rev)) => return Err(ProofError::WrongRev(rev.clone())),
+        _ => return Err(malformed(commit_cid, "commit object has no rev")),
+    }
+    let Some(Ipld::Link(root)) = signed.get("data") else {
+        return Err(malformed(commit_cid, "commit object has no data root"));
+    };
+
+    for operation in &commit.ops {
+        let Some(expected) = &operation.cid else {
+            continue;
+        };
+        let found = lookup(blocks, root, operation.path.as_bytes())?;
+        if found != Some(expected.0) {
+            return Err(ProofError::Mismatch {
+                path: operation.path.clone(),
+                expected: expected.0.to_string(),
+                found: found.map(|cid| cid.to_string()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The value stored under `key` in the tree rooted at `root`.
+fn lookup(blocks: &Blocks, root: &Cid, key: &[u8]) -> Result<Option<Cid>, ProofError> {
+    let mut next = Some(*root);
+    while let Some(cid) = next {
+        let node = node(&cid, block(blocks, &cid)?)?;
+        next = node.left;
+        for entry in node.entries {
+            match key.cmp(&entry.key) {
+                Ordering::Less => break,
+                Ordering::Equal => return Ok(Some(entry.value)),
+                Ordering::Greater => next = entry.tree,
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn block<'a>(blocks: &'a Blocks, cid: &Cid) -> Result<&'a [u8], ProofError> {
+    blocks
+        .get(cid)
+        .map(|block| &block[..])
+        .ok_or_else(|| ProofError::MissingBlock(cid.to_string()))
+}
+
+fn map(cid: &Cid, data: &[u8]) -> Result<BTreeMap<String, Ipld>, ProofError> {
+    match serde_ipld_dagcbor::from_slice::<Ipld>(data) {
+        Ok(Ipld::Map(map)) => Ok(map),
+        Ok(_) => Err(malformed(cid, "not a map")),
+        Err(e) => Err(malformed(cid, &e.to_string())),
+    }
+}
+
+/// Decodes an MST node, expanding its prefix-compressed keys.
+fn node(cid: &Cid, data: &[u8]) -> Result<Node, ProofError> {
+    let mut node = map(cid, data)?;
+    let left = match node.remove("l") {
+        Some(Ipld::Link(left)) => Some(left),
+        Some(Ipld::Null) | None => None,
+        Some(_) => return Err(malformed(cid, "l is not a link")),
+    };
+    let Some(Ipld::List(raw)) = node.remove("e") else {
+        return Err(malformed(cid, "e is not a list"));
+    };
+
+    let mut entries: Vec<Entry> = Vec::with_capacity(raw.len());
+    for entry in raw {
+        let Ipld::Map(mut entry) = entry else {
+            return Err(malformed(cid, "entry is not a map"));
+        };
+        let (Some(Ipld::Integer(prefix)), Some(Ipld::Bytes(suffix)), Some(Ipld::Link(value))) =
+            (entry.remove("p"), entry.remove("k"), entry.remove("v"))
+        else {
+            return Err(malformed(cid, "entry is missing p, k or v"));
+        };
+        let tree = match entry.remove("t") {
+            Some(Ipld::Link(tree)) => Some(tree),
+            Some(Ipld::Null) | None => None,
+            Some(_) => return Err(malformed(cid, "t is not a link")),
+        };
+
+        let previous = entries.last().map_or(&[][..], |entry| &entry.key);
+        let prefix = usize::try_from(prefix)
+            .ok()
+            .filter(|prefix| *prefix <= previous.len())
+            .ok_or_else(|| malformed(cid, "key prefix is longer than the previous key"))?;
+        let mut key = previous[..prefix].to_vec();
+        key.extend(suffix);
+        entries.push(Entry { key, value, tree });
+    }
+    Ok(Node { left, entries })
+}
+
+fn malformed(cid: &Cid, message: &str) -> ProofError {
+    ProofError::Malformed {
+        cid: cid.to_string(),
+        message: message.to_string(),
+    }
+}
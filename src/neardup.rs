@@ -0,0 +1,167 @@
+//! Near-duplicate detection over recent post texts, for spotting copypasta that an exact hash
+//! misses: a changed word, different punctuation or an added hashtag.
+//!
+//! Texts are split into overlapping word shingles and summarized by a MinHash signature.
+//! Signatures are banded for locality-sensitive lookup, so only posts sharing a band are
+//! compared.
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    num::NonZeroUsize,
+    sync::Mutex,
+};
+
+use lru::LruCache;
+use serde::Serialize;
+
+/// MinHash functions per signature
+const HASHES: usize = 64;
+/// Signature bands; two texts become candidates when any band matches exactly
+const BANDS: usize = 16;
+const ROWS: usize = HASHES / BANDS;
+/// Words per shingle
+const SHINGLE_WORDS: usize = 3;
+
+type Signature = [u64; HASHES];
+
+/// A post similar enough to an earlier one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NearDuplicate {
+    /// Shared by every post found to duplicate the first one seen. Only meaningful within one
+    /// run of the listener.
+    pub cluster: u64,
+    /// Estimated Jaccard similarity of the two texts' shingles
+    pub similarity: f64,
+}
+
+/// Remembers the signatures of the most recent posts, forgetting the oldest first.
+#[derive(Debug)]
+pub struct NearDuplicates {
+    threshold: f64,
+    index: Mutex<Index>,
+}
+
+#[derive(Debug)]
+struct Index {
+    next_id: u64,
+    posts: LruCache<u64, Entry>,
+    /// Hash of a band of a signature to the latest post with that band
+    bands: LruCache<u64, u64>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    signature: Signature,
+    cluster: u64,
+}
+
+impl NearDuplicates {
+    /// Texts at least `threshold` similar (between 0 and 1) count as duplicates. The last
+    /// `capacity` posts are compared against.
+    pub fn new(threshold: f64, capacity: NonZeroUsize) -> Self {
+        let bands = capacity.saturating_mul(NonZeroUsize::new(BANDS).expect("not zero"));
+        Self {
+            threshold,
+            index: Mutex::new(Index {
+                next_id: 0,
+                posts: LruCache::new(capacity),
+                bands: LruCache::new(bands),
+            }),
+        }
+    }
+
+    /// Remembers `text`, returning the closest recent post it duplicates, if any. Texts without
+    /// any words are ignored.
+    pub fn insert(&self, text: &str) -> Option<NearDuplicate> {
+        let signature = signature(text)?;
+        let keys = band_keys(&signature);
+
+        let mut index = self.index.lock().unwrap();
+        let Index {
+            next_id,
+            posts,
+            bands,
+        } = &mut *index;
+        let mut closest: Option<NearDuplicate> = None;
+        for key in &keys {
+            let Some(entry) = bands.get(key).and_then(|id| posts.peek(id)) else {
+                continue;
+            };
+            let similarity = similarity(&signature, &entry.signature);
+            if similarity >= self.threshold
+                && closest.is_none_or(|closest| similarity > closest.similarity)
+            {
+                closest = Some(NearDuplicate {
+                    cluster: entry.cluster,
+                    similarity,
+                });
+            }
+        }
+
+        let id = *next_id;
+        *next_id += 1;
+        posts.put(
+            id,
+            Entry {
+                signature,
+                cluster: closest.map_or(id, |closest| closest.cluster),
+            },
+        );
+        for key in keys {
+            bands.put(key, id);
+        }
+        closest
+    }
+}
+
+fn signature(text: &str) -> Option<Signature> {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+
+    let mut signature = [u64::MAX; HASHES];
+    // Texts shorter than a shingle are one shingle
+    for shingle in words.windows(SHINGLE_WORDS.min(words.len())) {
+        let mut hasher = DefaultHasher::new();
+        shingle.hash(&mut hasher);
+        let shingle = hasher.finish();
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(splitmix64(
+                shingle ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            ));
+        }
+    }
+    Some(signature)
+}
+
+fn band_keys(signature: &Signature) -> Vec<u64> {
+    signature
+        .chunks(ROWS)
+        .enumerate()
+        .map(|(band, rows)| {
+            let mut hasher = DefaultHasher::new();
+            (band, rows).hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Fraction of matching MinHash values, which estimates the Jaccard similarity.
+fn similarity(a: &Signature, b: &Signature) -> f64 {
+    let matching = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    matching as f64 / HASHES as f64
+}
+
+/// Mixes `x` into a well-distributed 64-bit value, standing in for one of a family of
+/// independent hash functions.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,155 @@
+//! Notifications about detected posts, anomalous repos and plugin findings, sent to chat sinks such as Discord.
+
+use std::str::FromStr;
+
+use atrium_api::app::bsky::feed::post;
+use chrono::Utc;
+
+use crate::{
+    anomaly::AnomalyDetected, client::Event, haiku::HaikuRecord, identity::HandleResolver,
+};
+
+/// Which posts are sent to notification sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyOn {
+    /// Posts in one of the configured forms
+    Haikus,
+    /// Every post passing the keyword, regex, tag and embed filters
+    Matches,
+}
+
+impl FromStr for NotifyOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "haikus" => Ok(Self::Haikus),
+            "matches" => Ok(Self::Matches),
+            other => Err(format!("expected \"haikus\" or \"matches\", got {other:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// What was detected, e.g. the form name
+    pub title: String,
+    pub did: String,
+    /// Resolved by the sink when unknown
+    pub handle: Option<String>,
+    pub text: String,
+    /// Link to the post (or for anomalies, the profile) on bsky.app
+    pub url: String,
+    pub created_at: String,
+}
+
+impl Notification {
+    pub fn haiku(haiku: &HaikuRecord) -> Self {
+        Self {
+            title: haiku.form.clone(),
+            did: haiku.did.clone(),
+            handle: haiku.display_handle().map(str::to_string),
+            text: haiku.lines.join("\n"),
+            url: haiku.url.clone(),
+            created_at: haiku.created_at.clone(),
+        }
+    }
+
+    /// A post that passed the filters, having matched the `matched` keywords or patterns.
+    pub fn filter_match(evt: &Event, record: &post::Record, matched: &[&str]) -> Self {
+        let did = evt.repo.as_str().to_string();
+        Self {
+            title: if matched.is_empty() {
+                "Filter match".to_string()
+            } else {
+                format!("Matched {}", matched.join(", "))
+            },
+            url: format!("https://bsky.app/profile/{did}/post/{}", evt.rkey),
+            did,
+            handle: None,
+            text: record.text.clone(),
+            created_at: record.created_at.as_str().to_string(),
+        }
+    }
+
+    pub fn anomaly(anomaly: &AnomalyDetected) -> Self {
+        let activity = anomaly.activity.name();
+        Self {
+            title: format!("Unusual {activity} rate"),
+            did: anomaly.did.clone(),
+            handle: None,
+            text: format!(
+                "Created {} {activity} within {}s",
+                anomaly.count, anomaly.window_secs
+            ),
+            url: format!("https://bsky.app/profile/{}", anomaly.did),
+            created_at: anomaly.detected_at.to_rfc3339(),
+        }
+    }
+
+    /// An event a plugin asked to be notified about.
+    pub fn plugin(plugin: &str, evt: &Event, title: String, text: String) -> Self {
+        let did = evt.repo.as_str().to_string();
+        Self {
+            title: format!("{plugin}: {title}"),
+            url: match evt.collection.as_str() {
+                "app.bsky.feed.post" => {
+                    format!("https://bsky.app/profile/{did}/post/{}", evt.rkey)
+                }
+                _ => format!("https://bsky.app/profile/{did}"),
+            },
+            did,
+            handle: None,
+            text,
+            created_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Fills in the author's handle if it isn't known yet, keeping the DID when it can't be
+    /// resolved or fails verification.
+    pub async fn resolve_handle(&mut self, handles: &HandleResolver) {
+        if self.handle.is_none() {
+            if let Ok(resolved) = handles.resolve(&self.did).await {
+                self.handle = resolved.display().map(str::to_string);
+            }
+        }
+    }
+
+    /// `@handle`, or the DID when the handle is unknown.
+    pub fn author(&self) -> String {
+        match &self.handle {
+            Some(handle) => format!("@{handle}"),
+            None => self.did.clone(),
+        }
+    }
+
+    pub fn profile_url(&self) -> String {
+        format!("https://bsky.app/profile/{}", self.did)
+    }
+
+    /// Fills in the `{title}`, `{author}`, `{did}`, `{text}`, `{url}` and `{created_at}`
+    /// placeholders of `template`.
+    pub fn render(&self, template: &str) -> String {
+        let mut rendered = String::with_capacity(template.len() + self.text.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            match &rest[1..end] {
+                "title" => rendered.push_str(&self.title),
+                "author" => rendered.push_str(&self.author()),
+                "did" => rendered.push_str(&self.did),
+                "text" => rendered.push_str(&self.text),
+                "url" => rendered.push_str(&self.url),
+                "created_at" => rendered.push_str(&self.created_at),
+                _ => rendered.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
@@ -0,0 +1,422 @@
+//! Writes repo operations to Parquet files for analytics, partitioned Hive-style as
+//! `<dir>/collection=<nsid>/date=YYYY-MM-DD/<HHMMSS>.parquet` so DuckDB and Spark pick the
+//! partitions up on their own.
+//!
+//! Posts, likes, reposts, follows and blocks get typed columns; other collections keep their
+//! record as a JSON string. Files are written under a `.tmp` name and renamed once complete.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ::parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use arrow::{
+    array::{
+        ArrayRef, Int64Array, ListBuilder, StringArray, StringBuilder, TimestampMicrosecondArray,
+        TimestampMillisecondArray,
+    },
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tracing::{error, info, info_span, warn};
+
+use crate::{client::Event, frame, telemetry::Metrics};
+
+/// Rows waiting to be written before new ones are dropped
+const QUEUE_SIZE: usize = 100_000;
+/// Rows buffered per collection before they are written out as a row group
+const BATCH_ROWS: usize = 8192;
+/// How often buffered rows are written and old files closed when the firehose is quiet
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParquetWriterError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unable to build record batch: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("unable to write Parquet: {0}")]
+    Parquet(#[from] ParquetError),
+}
+
+/// Column layout of a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Post,
+    /// Likes and reposts
+    Subject,
+    /// Follows and blocks
+    Graph,
+    Other,
+}
+
+impl Kind {
+    fn of(collection: &str) -> Self {
+        match collection {
+            "app.bsky.feed.post" => Self::Post,
+            "app.bsky.feed.like" | "app.bsky.feed.repost" => Self::Subject,
+            "app.bsky.graph.follow" | "app.bsky.graph.block" => Self::Graph,
+            _ => Self::Other,
+        }
+    }
+
+    /// Text columns specific to this kind, with the JSON pointer they are read from. An empty
+    /// pointer stands for the whole record.
+    fn columns(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Post => &[
+                ("text", "/text"),
+                ("reply_root", "/reply/root/uri"),
+                ("reply_parent", "/reply/parent/uri"),
+                ("embed_type", "/embed/$type"),
+            ],
+            Self::Subject => &[
+                ("subject_uri", "/subject/uri"),
+                ("subject_cid", "/subject/cid"),
+            ],
+            Self::Graph => &[("subject", "/subject")],
+            Self::Other => &[("record", "")],
+        }
+    }
+
+    fn schema(self) -> SchemaRef {
+        let utc = Some("UTC".into());
+        let mut fields = vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("repo", DataType::Utf8, false),
+            Field::new("rev", DataType::Utf8, false),
+            Field::new("action", DataType::Utf8, false),
+            Field::new("rkey", DataType::Utf8, false),
+            Field::new("cid", DataType::Utf8, true),
+            Field::new(
+                "indexed_at",
+                DataType::Timestamp(TimeUnit::Microsecond, utc.clone()),
+                false,
+            ),
+            Field::new(
+                "created_at",
+                DataType::Timestamp(TimeUnit::Millisecond, utc),
+                true,
+            ),
+        ];
+        fields.extend(
+            self.columns()
+                .iter()
+                .map(|(name, _)| Field::new(*name, DataType::Utf8, true)),
+        );
+        if self == Self::Post {
+            fields.push(Field::new(
+                "langs",
+                DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+                true,
+            ));
+        }
+        Arc::new(Schema::new(fields))
+    }
+}
+
+/// One repo operation, flattened.
+#[derive(Debug)]
+struct Row {
+    collection: String,
+    seq: i64,
+    repo: String,
+    rev: String,
+    action: String,
+    rkey: String,
+    cid: Option<String>,
+    indexed_at: DateTime<Utc>,
+    created_at: Option<i64>,
+    /// Values of [`Kind::columns`]
+    fields: Vec<Option<String>>,
+    langs: Option<Vec<String>>,
+}
+
+impl Row {
+    fn new(evt: &Event) -> Self {
+        let kind = Kind::of(&evt.collection);
+        let record = match evt.block.as_deref().map(frame::record_json).transpose() {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Unable to read {}/{}: {e}", evt.collection, evt.rkey);
+                None
+            }
+        };
+        let fields = kind
+            .columns()
+            .iter()
+            .map(|(_, pointer)| {
+                let record = record.as_ref()?;
+                if pointer.is_empty() {
+                    return Some(record.to_string());
+                }
+                record.pointer(pointer)?.as_str().map(String::from)
+            })
+            .collect();
+
+        Self {
+            collection: evt.collection.clone(),
+            seq: evt.seq,
+            repo: evt.repo.as_str().to_string(),
+            rev: evt.rev.clone(),
+            action: evt.action.clone(),
+            rkey: evt.rkey.clone(),
+            cid: evt.cid.as_ref().map(|cid| cid.0.to_string()),
+            indexed_at: Utc::now(),
+            created_at: record
+                .as_ref()
+                .and_then(|record| record.get("createdAt")?.as_str())
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.timestamp_millis()),
+            fields,
+            langs: (kind == Kind::Post)
+                .then(|| record.as_ref()?.get("langs")?.as_array().cloned())
+                .flatten()
+                .map(|langs| {
+                    langs
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect()
+                }),
+        }
+    }
+}
+
+fn batch(kind: Kind, schema: SchemaRef, rows: &[Row]) -> Result<RecordBatch, ArrowError> {
+    let text = |value: fn(&Row) -> &str| -> ArrayRef {
+        Arc::new(rows.iter().map(value).map(Some).collect::<StringArray>())
+    };
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter_values(rows.iter().map(|row| row.seq))),
+        text(|row| &row.repo),
+        text(|row| &row.rev),
+        text(|row| &row.action),
+        text(|row| &row.rkey),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.cid.as_deref())
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            TimestampMicrosecondArray::from_iter_values(
+                rows.iter().map(|row| row.indexed_at.timestamp_micros()),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|row| row.created_at)
+                .collect::<TimestampMillisecondArray>()
+                .with_timezone("UTC"),
+        ),
+    ];
+    for column in 0..kind.columns().len() {
+        columns.push(Arc::new(
+            rows.iter()
+                .map(|row| row.fields[column].as_deref())
+                .collect::<StringArray>(),
+        ));
+    }
+    if kind == Kind::Post {
+        let mut langs = ListBuilder::new(StringBuilder::new());
+        for row in rows {
+            match &row.langs {
+                Some(row_langs) => {
+                    for lang in row_langs {
+                        langs.values().append_value(lang);
+                    }
+                    langs.append(true);
+                }
+                None => langs.append(false),
+            }
+        }
+        columns.push(Arc::new(langs.finish()));
+    }
+    RecordBatch::try_new(schema, columns)
+}
+
+/// A Parquet file being written, under a temporary name until it is closed.
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    rows: usize,
+    opened_at: Instant,
+}
+
+impl OpenFile {
+    fn create(dir: &Path, collection: &str, schema: SchemaRef) -> Result<Self, ParquetWriterError> {
+        let now = Utc::now();
+        let partition = dir
+            .join(format!("collection={collection}"))
+            .join(format!("date={}", now.format("%Y-%m-%d")));
+        std::fs::create_dir_all(&partition)?;
+        let path = partition.join(format!("{}.parquet", now.format("%H%M%S%.3f")));
+        let tmp_path = path.with_extension("parquet.tmp");
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(File::create(&tmp_path)?, schema, Some(properties))?;
+        Ok(Self {
+            writer,
+            path,
+            tmp_path,
+            rows: 0,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn close(self) -> Result<PathBuf, ParquetWriterError> {
+        self.writer.close()?;
+        std::fs::rename(&self.tmp_path, &self.path)?;
+        Ok(self.path)
+    }
+}
+
+/// Buffered rows and the open file of one collection.
+struct Partition {
+    kind: Kind,
+    schema: SchemaRef,
+    rows: Vec<Row>,
+    file: Option<OpenFile>,
+}
+
+/// When files are closed and new ones started.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    pub max_rows: usize,
+    pub max_age: Duration,
+}
+
+/// Writes rows on a dedicated thread, since encoding and compressing Parquet is CPU-heavy and
+/// the file I/O is blocking. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct ParquetWriter {
+    queue: SyncSender<Row>,
+}
+
+impl ParquetWriter {
+    pub fn spawn(dir: PathBuf, rotation: Rotation) -> Self {
+        let (queue, rows) = mpsc::sync_channel(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("parquet".to_string())
+            .spawn(move || run(&dir, rotation, rows))
+            .expect("Unable to start Parquet writer thread");
+        Self { queue }
+    }
+
+    /// Queues `evt` to be written. Operations are dropped when writing falls too far behind.
+    pub fn push(&self, evt: &Event) {
+        if let Err(TrySendError::Full(row)) = self.queue.try_send(Row::new(evt)) {
+            warn!(
+                "Parquet writer is behind, dropping {}/{}",
+                row.collection, row.rkey
+            );
+        }
+    }
+}
+
+fn run(dir: &Path, rotation: Rotation, rows: mpsc::Receiver<Row>) {
+    let mut partitions = HashMap::<String, Partition>::new();
+    let mut last_flush = Instant::now();
+    loop {
+        match rows.recv_timeout(FLUSH_INTERVAL) {
+            Ok(row) => {
+                let partition = partitions.entry(row.collection.clone()).or_insert_with(|| {
+                    let kind = Kind::of(&row.collection);
+                    Partition {
+                        kind,
+                        schema: kind.schema(),
+                        rows: Vec::with_capacity(BATCH_ROWS),
+                        file: None,
+                    }
+                });
+                let collection = row.collection.clone();
+                partition.rows.push(row);
+                if partition.rows.len() >= BATCH_ROWS {
+                    flush(dir, &collection, partition, rotation);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                for (collection, partition) in &mut partitions {
+                    flush(dir, collection, partition, rotation);
+                    close(partition);
+                }
+                return;
+            }
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            for (collection, partition) in &mut partitions {
+                flush(dir, collection, partition, rotation);
+            }
+            last_flush = Instant::now();
+        }
+    }
+}
+
+/// Writes out `partition`'s buffered rows, then closes its file if it is due for rotation.
+fn flush(dir: &Path, collection: &str, partition: &mut Partition, rotation: Rotation) {
+    if !partition.rows.is_empty() {
+        let _span = info_span!("sink_write").entered();
+        let result = write(dir, collection, partition);
+        Metrics::get().record_sink_write(result.is_ok());
+        if let Err(e) = result {
+            error!("Unable to write {collection} to Parquet: {e}");
+            // The file may be left half-written, so start over with a new one
+            partition.file = None;
+        }
+        partition.rows.clear();
+    }
+
+    if partition.file.as_ref().is_some_and(|file| {
+        file.rows >= rotation.max_rows || file.opened_at.elapsed() >= rotation.max_age
+    }) {
+        close(partition);
+    }
+}
+
+fn write(
+    dir: &Path,
+    collection: &str,
+    partition: &mut Partition,
+) -> Result<(), ParquetWriterError> {
+    let batch = batch(partition.kind, partition.schema.clone(), &partition.rows)?;
+    let file = match &mut partition.file {
+        Some(file) => file,
+        None => partition
+            .file
+            .insert(OpenFile::create(dir, collection, partition.schema.clone())?),
+    };
+    file.writer.write(&batch)?;
+    file.rows += batch.num_rows();
+    Ok(())
+}
+
+fn close(partition: &mut Partition) {
+    let Some(file) = partition.file.take() else {
+        return;
+    };
+    match file.close() {
+        Ok(path) => info!("Wrote {}", path.display()),
+        Err(e) => error!("Unable to finish Parquet file: {e}"),
+    }
+}
@@ -0,0 +1,768 @@
+//! Independent pipelines sharing the firehose connection, defined in a TOML file: each picks
+//! out events with its own filters and sends them to its own sinks.
+//!
+//! ```toml
+//! [[pipeline]]
+//! name = "rust"
+//! collections = ["app.bsky.feed.post"]
+//! keywords = ["rust"]
+//! sinks = [{ type = "webhook", url = "https://example.com/hook", rate_limit = "10/s" }]
+//!
+//! [[pipeline]]
+//! name = "haikus"
+//! labels = ["haiku"]
+//! exclude_content_labels = ["porn", "graphic-media"]
+//! sinks = [{ type = "jsonl", path = "haikus.jsonl" }]
+//! ```
+//!
+//! Each event is decoded once, however many pipelines it goes to. Sinks deliver from their own
+//! queues, so one failing or falling behind never holds up the others.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use async_nats::jetstream;
+use atrium_api::app::bsky::feed::post;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    OnceCell,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    accounts::AccountStatus,
+    appender::AppendError,
+    classify::{ClassifierRegistry, Label},
+    clickhouse::{ClickHouse, ClickHouseConfig},
+    client::{glob_match, Event},
+    deadletter::DeadLetters,
+    filter::{FilterError, PostFilter},
+    frame,
+    health::Health,
+    jsonl::JsonlWriter,
+    labels::{self, LabelFilter, Labelers},
+    mqtt::{self, Mqtt, MqttConfig},
+    ratelimit::{LimitPolicy, RateLimit, RateLimiter},
+    rotate::RotationPolicy,
+    task,
+    telemetry::Metrics,
+};
+
+/// Wait before a sink's first retry, doubling with each one after
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error("unable to read pipelines: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid pipelines: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("invalid text filter in pipeline {name}: {source}")]
+    Filter { name: String, source: FilterError },
+    #[error("invalid MQTT QoS {qos} in pipeline {name}, expected 0, 1 or 2")]
+    Qos { name: String, qos: u8 },
+    #[error("unable to open {} for pipeline {name}: {source}", path.display())]
+    Output {
+        name: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct PipelinesFile {
+    #[serde(rename = "pipeline", default)]
+    pipelines: Vec<PipelineConfig>,
+}
+
+/// One `[[pipeline]]` table. Every filter left out lets all events through.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub name: String,
+    /// Collection globs, e.g. `app.bsky.graph.*`
+    #[serde(default = "all_collections")]
+    pub collections: Vec<String>,
+    /// `create`, `update` and/or `delete`
+    #[serde(default)]
+    pub actions: Vec<String>,
+    /// Repo DIDs
+    #[serde(default)]
+    pub dids: Vec<String>,
+    /// Post text keywords, matched as whole words ignoring case; other records never match
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Post text regexes; other records never match
+    #[serde(default)]
+    pub regex: Vec<String>,
+    /// Classifier label globs, see [`crate::classify`]; other records never match
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Content label globs, see [`crate::labels`]; records carrying none of them never match
+    #[serde(default)]
+    pub content_labels: Vec<String>,
+    /// Content label globs records mustn't carry
+    #[serde(default)]
+    pub exclude_content_labels: Vec<String>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+fn all_collections() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    Jsonl {
+        path: PathBuf,
+        /// Events waiting to be written before new ones are dropped
+        #[serde(default = "default_queue")]
+        queue: usize,
+        /// Attempts after the first before an event is given up on, backing off exponentially
+        #[serde(default = "default_retries")]
+        retries: u32,
+    },
+    /// POSTs each event as JSON
+    Webhook {
+        url: String,
+        /// e.g. `10/s`; unlimited when unset
+        #[serde(default)]
+        rate_limit: Option<RateLimit>,
+        /// What happens to events over `rate_limit`
+        #[serde(default)]
+        rate_policy: LimitPolicy,
+        /// Events waiting to be posted before new ones are dropped
+        #[serde(default = "default_queue")]
+        queue: usize,
+        /// Attempts after the first before an event is given up on, backing off exponentially
+        #[serde(default = "default_retries")]
+        retries: u32,
+    },
+    Clickhouse {
+        url: String,
+        table: String,
+        #[serde(default = "default_clickhouse_user")]
+        user: String,
+        #[serde(default)]
+        password: String,
+    },
+    /// Publishes each event as JSON to a topic made from a template
+    Mqtt {
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        /// Placeholders are filled in by [`topic`]
+        #[serde(default = "default_mqtt_topic")]
+        topic: String,
+        /// `0`, `1` or `2`
+        #[serde(default)]
+        qos: u8,
+        #[serde(default)]
+        retain: bool,
+        /// Defaults to `bsky-firehose-listener-<pipeline>-<index>`
+        #[serde(default)]
+        client_id: Option<String>,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        /// Messages waiting to be sent before new ones are dropped
+        #[serde(default = "default_queue")]
+        queue: usize,
+    },
+    /// Publishes each event as JSON to a JetStream stream, deduplicated by sequence number
+    Nats {
+        /// e.g. `nats://localhost:4222`, with `user:password@` if needed
+        url: String,
+        /// Placeholders are filled in by [`topic`]; a stream must capture the subjects
+        #[serde(default = "default_nats_subject")]
+        subject: String,
+        /// Events waiting to be published before new ones are dropped
+        #[serde(default = "default_queue")]
+        queue: usize,
+        /// Attempts after the first before an event is given up on, backing off exponentially
+        #[serde(default = "default_retries")]
+        retries: u32,
+    },
+}
+
+fn default_queue() -> usize {
+    1000
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_clickhouse_user() -> String {
+    "default".to_string()
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic() -> String {
+    "bsky/{collection}/{did}".to_string()
+}
+
+fn default_nats_subject() -> String {
+    "bsky.{collection}".to_string()
+}
+
+/// Reads the `[[pipeline]]` tables of `path` without opening their sinks.
+pub fn read(path: &Path) -> Result<Vec<PipelineConfig>, PipelineError> {
+    let file: PipelinesFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+    Ok(file.pipelines)
+}
+
+/// Fills in the `{pipeline}`, `{did}`, `{collection}`, `{action}` and `{rkey}` placeholders
+/// of an MQTT topic or NATS subject template, e.g. `bsky/{collection}/{did}`.
+pub fn topic(template: &str, pipeline: &str, evt: &Event) -> String {
+    template
+        .replace("{pipeline}", pipeline)
+        .replace("{did}", evt.repo.as_str())
+        .replace("{collection}", &evt.collection)
+        .replace("{action}", &evt.action)
+        .replace("{rkey}", &evt.rkey)
+}
+
+/// An event as sent to sinks.
+#[derive(Debug, Serialize)]
+struct Routed<'a> {
+    pipeline: &'a str,
+    seq: i64,
+    repo: &'a str,
+    action: &'a str,
+    collection: &'a str,
+    rkey: &'a str,
+    cid: Option<String>,
+    record: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account_status: Option<&'a AccountStatus>,
+    /// Keywords and regexes the post text matched
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    matched: Vec<&'a str>,
+    #[serde(skip_serializing_if = "<[Label]>::is_empty")]
+    labels: &'a [Label],
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    content_labels: &'a [String],
+}
+
+/// Routes every event to the pipelines whose filters it passes.
+pub struct Router {
+    pipelines: Vec<Pipeline>,
+    classifiers: ClassifierRegistry,
+    labelers: Option<Arc<Labelers>>,
+}
+
+struct Pipeline {
+    name: String,
+    collections: Vec<String>,
+    actions: Vec<String>,
+    dids: HashSet<String>,
+    /// `None` when no keywords or regexes are set
+    text: Option<PostFilter>,
+    labels: Vec<String>,
+    content_labels: LabelFilter,
+    sinks: Vec<Sink>,
+}
+
+enum Sink {
+    /// A `jsonl`, `webhook` or `nats` sink, delivered to by its own worker
+    Queued(SinkWorker),
+    /// Batches and retries inserts on its own
+    ClickHouse(ClickHouse),
+    /// Queued and reconnected by its MQTT client
+    Mqtt(Mqtt),
+}
+
+/// What's decoded from an event, at most once and only if a pipeline needs it.
+struct Decoded<'a> {
+    evt: &'a Event,
+    classifiers: &'a ClassifierRegistry,
+    labelers: Option<&'a Labelers>,
+    record: OnceLock<Option<serde_json::Value>>,
+    post: OnceLock<Option<post::Record>>,
+    labels: OnceLock<Vec<Label>>,
+    content_labels: OnceLock<Vec<String>>,
+}
+
+impl Router {
+    /// Reads pipelines from `path`, opening their sinks. `classifiers` label posts for
+    /// pipelines filtering on labels; their routes are ignored. Events a `jsonl`, `webhook` or
+    /// `nats` sink fails to deliver are kept in `dead_letters`, if given. Content labels
+    /// include those from `labelers`, if given, besides self-labels.
+    pub fn load(
+        path: &Path,
+        classifiers: ClassifierRegistry,
+        http: &reqwest::Client,
+        rotation: RotationPolicy,
+        health: Arc<Health>,
+        dead_letters: Option<Arc<DeadLetters>>,
+        labelers: Option<Arc<Labelers>>,
+    ) -> Result<Self, PipelineError> {
+        let pipelines = read(path)?
+            .into_iter()
+            .map(|config| {
+                Pipeline::open(config, http, rotation, health.clone(), dead_letters.clone())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        info!(
+            "Loaded {} pipelines from {}",
+            pipelines.len(),
+            path.display()
+        );
+        Ok(Self {
+            pipelines,
+            classifiers,
+            labelers,
+        })
+    }
+
+    /// Sends `evt` to the sinks of every pipeline it passes.
+    pub fn dispatch(&self, evt: &Event) {
+        let decoded = Decoded {
+            evt,
+            classifiers: &self.classifiers,
+            labelers: self.labelers.as_deref(),
+            record: OnceLock::new(),
+            post: OnceLock::new(),
+            labels: OnceLock::new(),
+            content_labels: OnceLock::new(),
+        };
+        for pipeline in &self.pipelines {
+            let Some(matched) = pipeline.matches(&decoded) else {
+                continue;
+            };
+            let routed = Routed {
+                pipeline: &pipeline.name,
+                seq: evt.seq,
+                repo: evt.repo.as_str(),
+                action: &evt.action,
+                collection: &evt.collection,
+                rkey: &evt.rkey,
+                cid: evt.cid.as_ref().map(|cid| cid.0.to_string()),
+                record: decoded.record().as_ref(),
+                account_status: evt.account_status.as_ref(),
+                matched,
+                labels: if pipeline.labels.is_empty() {
+                    &[]
+                } else {
+                    decoded.labels()
+                },
+                content_labels: if pipeline.content_labels.is_empty() {
+                    &[]
+                } else {
+                    decoded.content_labels()
+                },
+            };
+            for sink in &pipeline.sinks {
+                sink.send(evt, &routed);
+            }
+        }
+    }
+}
+
+impl Pipeline {
+    fn open(
+        config: PipelineConfig,
+        http: &reqwest::Client,
+        rotation: RotationPolicy,
+        health: Arc<Health>,
+        dead_letters: Option<Arc<DeadLetters>>,
+    ) -> Result<Self, PipelineError> {
+        let text = if config.keywords.is_empty() && config.regex.is_empty() {
+            None
+        } else {
+            Some(
+                PostFilter::new(config.keywords, &config.regex).map_err(|source| {
+                    PipelineError::Filter {
+                        name: config.name.clone(),
+                        source,
+                    }
+                })?,
+            )
+        };
+        let sinks = config
+            .sinks
+            .into_iter()
+            .enumerate()
+            .map(|(i, sink)| match sink {
+                SinkConfig::Jsonl {
+                    path,
+                    queue,
+                    retries,
+                } => {
+                    let output =
+                        JsonlWriter::open(&path, rotation, health.clone()).map_err(|source| {
+                            PipelineError::Output {
+                                name: config.name.clone(),
+                                path,
+                                source,
+                            }
+                        })?;
+                    Ok(Sink::Queued(SinkWorker::spawn(
+                        format!("{}/jsonl/{i}", config.name),
+                        Target::Jsonl(output),
+                        queue,
+                        retries,
+                        health.clone(),
+                        dead_letters.clone(),
+                    )))
+                }
+                SinkConfig::Webhook {
+                    url,
+                    rate_limit,
+                    rate_policy,
+                    queue,
+                    retries,
+                } => Ok(Sink::Queued(SinkWorker::spawn(
+                    format!("{}/webhook/{i}", config.name),
+                    Target::Webhook {
+                        http: http.clone(),
+                        url,
+                        limiter: rate_limit.map(|limit| RateLimiter::new(limit, rate_policy)),
+                    },
+                    queue,
+                    retries,
+                    health.clone(),
+                    dead_letters.clone(),
+                ))),
+                SinkConfig::Clickhouse {
+                    url,
+                    table,
+                    user,
+                    password,
+                } => Ok(Sink::ClickHouse(ClickHouse::spawn(
+                    http.clone(),
+                    ClickHouseConfig {
+                        url,
+                        table,
+                        user,
+                        password,
+                        batch_rows: 1000,
+                        flush_interval: Duration::from_secs(5),
+                    },
+                ))),
+                SinkConfig::Nats {
+                    url,
+                    subject,
+                    queue,
+                    retries,
+                } => Ok(Sink::Queued(SinkWorker::spawn(
+                    format!("{}/nats/{i}", config.name),
+                    Target::Nats {
+                        url,
+                        subject,
+                        pipeline: config.name.clone(),
+                        jetstream: OnceCell::new(),
+                    },
+                    queue,
+                    retries,
+                    health.clone(),
+                    dead_letters.clone(),
+                ))),
+                SinkConfig::Mqtt {
+                    host,
+                    port,
+                    topic,
+                    qos,
+                    retain,
+                    client_id,
+                    username,
+                    password,
+                    queue,
+                } => Ok(Sink::Mqtt(Mqtt::spawn(
+                    format!("{}/mqtt/{i}", config.name),
+                    MqttConfig {
+                        host,
+                        port,
+                        client_id: client_id.unwrap_or_else(|| {
+                            format!("bsky-firehose-listener-{}-{i}", config.name)
+                        }),
+                        username,
+                        password,
+                        topic,
+                        qos: mqtt::qos(qos).ok_or_else(|| PipelineError::Qos {
+                            name: config.name.clone(),
+                            qos,
+                        })?,
+                        retain,
+                        queue,
+                    },
+                    health.clone(),
+                ))),
+            })
+            .collect::<Result<Vec<_>, PipelineError>>()?;
+
+        Ok(Self {
+            name: config.name,
+            collections: config.collections,
+            actions: config.actions,
+            dids: config.dids.into_iter().collect(),
+            text,
+            labels: config.labels,
+            content_labels: LabelFilter::new(config.content_labels, config.exclude_content_labels),
+            sinks,
+        })
+    }
+
+    /// The keywords and regexes matched if `decoded` passes every filter.
+    fn matches<'a>(&'a self, decoded: &'a Decoded) -> Option<Vec<&'a str>> {
+        let evt = decoded.evt;
+        if !self
+            .collections
+            .iter()
+            .any(|pattern| glob_match(pattern, &evt.collection))
+            || !(self.actions.is_empty() || self.actions.contains(&evt.action))
+            || !(self.dids.is_empty() || self.dids.contains(evt.repo.as_str()))
+        {
+            return None;
+        }
+
+        let mut matched = Vec::new();
+        if let Some(text) = &self.text {
+            matched = text.matches(&decoded.post()?.text)?;
+        }
+        if !self.labels.is_empty() {
+            decoded.post().as_ref()?;
+            let labels = decoded.labels();
+            if !self
+                .labels
+                .iter()
+                .any(|pattern| labels.iter().any(|label| glob_match(pattern, &label.name)))
+            {
+                return None;
+            }
+        }
+        if !self.content_labels.is_empty() && !self.content_labels.allows(decoded.content_labels())
+        {
+            return None;
+        }
+        Some(matched)
+    }
+}
+
+impl Decoded<'_> {
+    fn record(&self) -> &Option<serde_json::Value> {
+        self.record.get_or_init(|| {
+            let record = self
+                .evt
+                .block
+                .as_deref()
+                .map(frame::record_json)
+                .transpose();
+            record.unwrap_or_else(|e| {
+                warn!(
+                    "Unable to read {}/{}: {e}",
+                    self.evt.collection, self.evt.rkey
+                );
+                None
+            })
+        })
+    }
+
+    /// The post, if the event creates or updates one.
+    fn post(&self) -> Option<&post::Record> {
+        self.post
+            .get_or_init(|| {
+                if self.evt.collection != "app.bsky.feed.post" {
+                    return None;
+                }
+                self.evt.record::<post::Record>().ok().flatten()
+            })
+            .as_ref()
+    }
+
+    /// Self-labels and labels from labelers, for records of any collection.
+    fn content_labels(&self) -> &[String] {
+        self.content_labels
+            .get_or_init(|| labels::of_event(self.evt, self.record().as_ref(), self.labelers))
+    }
+
+    fn labels(&self) -> &[Label] {
+        self.labels.get_or_init(|| match self.post() {
+            Some(post) => self.classifiers.classify(&post.text),
+            None => Vec::new(),
+        })
+    }
+}
+
+impl Sink {
+    fn send(&self, evt: &Event, routed: &Routed) {
+        match self {
+            Self::Queued(worker) => worker.send(evt, routed),
+            Self::ClickHouse(clickhouse) => clickhouse.push(evt),
+            Self::Mqtt(mqtt) => mqtt.publish(
+                routed.pipeline,
+                evt,
+                serde_json::to_vec(routed).expect("routed events are always serializable"),
+            ),
+        }
+    }
+}
+
+/// Where a [`SinkWorker`] delivers events.
+enum Target {
+    Jsonl(JsonlWriter),
+    /// POSTs each event, one request each
+    Webhook {
+        http: reqwest::Client,
+        url: String,
+        limiter: Option<RateLimiter>,
+    },
+    /// Publishes each event to JetStream, waiting for the stream to acknowledge it
+    Nats {
+        url: String,
+        subject: String,
+        pipeline: String,
+        /// Connected on the first delivery, and retried as a failed delivery until it works
+        jetstream: OnceCell<jetstream::Context>,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+enum DeliveryError {
+    #[error(transparent)]
+    Append(#[from] AppendError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("unable to connect to NATS: {0}")]
+    NatsConnect(#[from] async_nats::ConnectError),
+    #[error("JetStream publish failed: {0}")]
+    NatsPublish(#[from] jetstream::context::PublishError),
+}
+
+impl Target {
+    async fn deliver(&self, event: &serde_json::Value, evt: &Event) -> Result<(), DeliveryError> {
+        match self {
+            Self::Jsonl(output) => output.append(event)?,
+            Self::Webhook { http, url, .. } => {
+                http.post(url)
+                    .json(event)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Self::Nats {
+                url,
+                subject,
+                pipeline,
+                jetstream: context,
+            } => {
+                let context = context
+                    .get_or_try_init(|| async {
+                        async_nats::connect(url.as_str()).await.map(jetstream::new)
+                    })
+                    .await?;
+                // Retries, and events resent after resuming from a cursor, are dropped by
+                // the stream within its duplicate window
+                let publish = jetstream::context::Publish::build()
+                    .payload(
+                        serde_json::to_vec(event)
+                            .expect("JSON values are serializable")
+                            .into(),
+                    )
+                    .message_id(format!(
+                        "{pipeline}/{}/{}/{}",
+                        evt.seq, evt.collection, evt.rkey
+                    ));
+                context
+                    .send_publish(topic(subject, pipeline, evt), publish)
+                    .await?
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Delivers to one sink from its own bounded queue, retrying failures, so a sink that is down
+/// or slow only ever drops its own events.
+struct SinkWorker {
+    name: String,
+    queue: mpsc::Sender<(serde_json::Value, Event)>,
+    health: Arc<Health>,
+}
+
+impl SinkWorker {
+    fn spawn(
+        name: String,
+        target: Target,
+        queue: usize,
+        retries: u32,
+        health: Arc<Health>,
+        dead_letters: Option<Arc<DeadLetters>>,
+    ) -> Self {
+        let (queue, mut events) = mpsc::channel::<(serde_json::Value, Event)>(queue);
+        health.register_pipeline_sink(&name);
+        let worker = Self {
+            name: name.clone(),
+            queue,
+            health: health.clone(),
+        };
+        task::spawn(&format!("sink/{name}"), async move {
+            while let Some((event, evt)) = events.recv().await {
+                if let Target::Webhook {
+                    limiter: Some(limiter),
+                    ..
+                } = &target
+                {
+                    if !limiter.admit().await {
+                        warn!("Sink {name} rate limit reached, dropping an event");
+                        continue;
+                    }
+                }
+
+                let mut attempt = 0;
+                let result = loop {
+                    attempt += 1;
+                    match target.deliver(&event, &evt).await {
+                        Err(e) if attempt <= retries => {
+                            let backoff = RETRY_BACKOFF
+                                .saturating_mul(2u32.saturating_pow(attempt - 1))
+                                .min(MAX_RETRY_BACKOFF);
+                            warn!(
+                                "Sink {name} failed (attempt {attempt}), retrying in {backoff:?}: {e}"
+                            );
+                            tokio::time::sleep(backoff).await;
+                        }
+                        result => break result,
+                    }
+                };
+                health.record_pipeline_sink(&name, &result);
+                Metrics::get().record_pipeline_delivery(&name, result.is_ok());
+                if let Err(e) = result {
+                    error!(
+                        "Giving up on {}/{} for sink {name}: {e}",
+                        evt.collection, evt.rkey
+                    );
+                    if let Some(dead_letters) = &dead_letters {
+                        dead_letters.event(&name, &evt, &e);
+                    }
+                }
+            }
+        });
+        worker
+    }
+
+    fn send(&self, evt: &Event, routed: &Routed) {
+        let event = serde_json::to_value(routed).expect("routed events are always serializable");
+        if let Err(TrySendError::Full(_)) = self.queue.try_send((event, evt.clone())) {
+            self.health.record_pipeline_sink_drop(&self.name);
+            Metrics::get().record_pipeline_drop(&self.name);
+            warn!(
+                "Sink {} is behind, dropping {}/{}",
+                self.name, routed.collection, routed.rkey
+            );
+        }
+    }
+}
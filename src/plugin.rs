@@ -0,0 +1,264 @@
+//! WebAssembly plugins handling firehose events, run by [wasmtime](https://wasmtime.dev) in a
+//! sandbox: a plugin can't import anything, so it has no access to the filesystem, network or
+//! clock, and each event gets a bounded amount of fuel (roughly, instructions) and memory.
+//!
+//! A plugin module exports:
+//!
+//! - `memory`
+//! - `alloc(len: i32) -> i32`, returning where the host may write `len` bytes
+//! - `handle_event(ptr: i32, len: i32) -> i64`, given the event as JSON (see
+//!   [`Event::to_json`]) and returning where its [`Action`] JSON was written as
+//!   `ptr << 32 | len`, or 0 to do nothing
+//!
+//! An action outside the plugin's memory or over [`PluginLimits::max_action_bytes`] is an
+//! error rather than read.
+//!
+//! A plugin that traps, e.g. by running out of fuel or memory, is instantiated afresh for the
+//! next event.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use wasmtime::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use crate::{client::Event, fanout::Consumer, frame::FrameError};
+
+/// Sends events from collections matching `pattern` (a glob, see
+/// [`crate::client::glob_match`]) to the plugin at `path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginSpec {
+    pub pattern: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for PluginSpec {
+    type Err = String;
+
+    /// Parses `pattern=path`, e.g. `app.bsky.feed.post=spam.wasm`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((pattern, path)) if !pattern.trim().is_empty() && !path.trim().is_empty() => {
+                Ok(Self {
+                    pattern: pattern.trim().to_string(),
+                    path: path.trim().into(),
+                })
+            }
+            _ => Err(format!("{s:?} is not collection=path")),
+        }
+    }
+}
+
+/// What a plugin asks the host to do with an event.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    None,
+    /// Write `record` to the plugin output
+    Emit {
+        record: serde_json::Value,
+    },
+    /// Send a notification about the event
+    Notify {
+        title: String,
+        text: String,
+    },
+}
+
+/// An [`Action::Emit`] record, as written to the plugin output.
+#[derive(Debug, Serialize)]
+pub struct Emitted<'a> {
+    pub plugin: &'a str,
+    pub seq: i64,
+    pub repo: &'a str,
+    pub collection: &'a str,
+    pub rkey: &'a str,
+    pub record: &'a serde_json::Value,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PluginError {
+    #[error("{0}")]
+    Wasm(wasmtime::Error),
+    #[error("plugin doesn't export {0}")]
+    MissingExport(&'static str),
+    #[error("plugin memory access out of bounds")]
+    OutOfBounds(#[from] wasmtime::MemoryAccessError),
+    #[error("action of {len} bytes at {ptr} is outside plugin memory")]
+    ActionOutOfBounds { ptr: usize, len: usize },
+    #[error("action of {len} bytes is over the {max} byte limit")]
+    ActionTooLarge { len: usize, max: usize },
+    #[error("unable to serialize event: {0}")]
+    Event(#[from] FrameError),
+    #[error("malformed action: {0}")]
+    Action(#[from] serde_json::Error),
+}
+
+impl From<wasmtime::Error> for PluginError {
+    fn from(e: wasmtime::Error) -> Self {
+        Self::Wasm(e)
+    }
+}
+
+/// Sandbox limits applied to every event.
+#[derive(Debug, Clone, Copy)]
+pub struct PluginLimits {
+    pub fuel: u64,
+    pub memory_bytes: usize,
+    /// Largest action JSON the host reads back
+    pub max_action_bytes: usize,
+}
+
+pub struct Plugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    limits: PluginLimits,
+    /// `None` after a trap, until the next event
+    instance: Option<Loaded>,
+}
+
+struct Loaded {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    handle_event: TypedFunc<(i32, i32), i64>,
+}
+
+impl Plugin {
+    /// Compiles the module at `path` and checks that it can be instantiated.
+    pub fn load(path: &Path, limits: PluginLimits) -> Result<Self, PluginError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)?;
+        let name = path.file_stem().map_or_else(
+            || path.display().to_string(),
+            |stem| stem.to_string_lossy().into(),
+        );
+
+        let mut plugin = Self {
+            name,
+            engine,
+            module,
+            limits,
+            instance: None,
+        };
+        plugin.instance = Some(plugin.instantiate()?);
+        info!("Loaded plugin {}", plugin.name);
+        Ok(plugin)
+    }
+
+    /// The module's file name without its extension.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn instantiate(&self) -> Result<Loaded, PluginError> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.limits.fuel)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or(PluginError::MissingExport("memory"))?;
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .map_err(|_| PluginError::MissingExport("alloc(i32) -> i32"))?;
+        let handle_event = instance
+            .get_typed_func(&mut store, "handle_event")
+            .map_err(|_| PluginError::MissingExport("handle_event(i32, i32) -> i64"))?;
+        Ok(Loaded {
+            store,
+            memory,
+            alloc,
+            handle_event,
+        })
+    }
+
+    /// Runs the plugin over `evt`. After an error the plugin is instantiated again for the
+    /// next event, losing any state it kept.
+    pub fn handle(&mut self, evt: &Event) -> Result<Action, PluginError> {
+        let json = evt.to_json()?;
+        let mut loaded = match self.instance.take() {
+            Some(loaded) => loaded,
+            None => self.instantiate()?,
+        };
+        let action = call(&mut loaded, json.as_bytes(), &self.limits)?;
+        self.instance = Some(loaded);
+        Ok(action)
+    }
+
+    /// Spawns a thread running the plugin over every event `consumer` receives, passing the
+    /// actions it returns to `on_action`.
+    pub fn run<F>(mut self, mut consumer: Consumer, mut on_action: F)
+    where
+        F: FnMut(&str, &Event, Action) + Send + 'static,
+    {
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::Builder::new()
+            .name(format!("plugin-{}", self.name))
+            .spawn(move || {
+                let _runtime = runtime.enter();
+                while let Some(evt) = runtime.block_on(consumer.recv()) {
+                    match self.handle(&evt) {
+                        Ok(Action::None) => {}
+                        Ok(action) => on_action(&self.name, &evt, action),
+                        Err(e) => error!(
+                            "Plugin {} failed on {}/{}: {e}",
+                            self.name,
+                            evt.repo.as_str(),
+                            evt.rkey
+                        ),
+                    }
+                }
+            })
+            .expect("Unable to spawn plugin thread");
+    }
+}
+
+fn call(loaded: &mut Loaded, event: &[u8], limits: &PluginLimits) -> Result<Action, PluginError> {
+    let Loaded {
+        store,
+        memory,
+        alloc,
+        handle_event,
+    } = loaded;
+    store.set_fuel(limits.fuel)?;
+
+    let len = event.len() as i32;
+    let ptr = alloc.call(&mut *store, len)?;
+    memory.write(&mut *store, ptr as u32 as usize, event)?;
+    let result = handle_event.call(&mut *store, (ptr, len))?;
+    if result == 0 {
+        return Ok(Action::None);
+    }
+
+    // Both halves come from the guest, so check them before allocating
+    let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+    if len > limits.max_action_bytes {
+        return Err(PluginError::ActionTooLarge {
+            len,
+            max: limits.max_action_bytes,
+        });
+    }
+    if ptr
+        .checked_add(len)
+        .is_none_or(|end| end > memory.data_size(&*store))
+    {
+        return Err(PluginError::ActionOutOfBounds { ptr, len });
+    }
+    let mut action = vec![0; len];
+    memory.read(&*store, ptr, &mut action)?;
+    Ok(serde_json::from_slice(&action)?)
+}
@@ -0,0 +1,147 @@
+//! Outbound proxy for the firehose websocket and every HTTP call, for networks where egress
+//! only goes through one.
+
+use std::str::FromStr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_socks::tcp::Socks5Stream;
+
+/// Longest proxy response to a `CONNECT` accepted before giving up on it
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("unable to reach proxy: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("SOCKS5 proxy failed: {0}")]
+    Socks(#[from] tokio_socks::Error),
+    #[error("proxy refused to connect: {0}")]
+    Refused(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    /// Tunnels through `CONNECT`
+    Http,
+    /// Hostnames are always resolved by the proxy, as with `socks5h`
+    Socks5,
+}
+
+/// An `http://`, `socks5://` or `socks5h://` proxy URL, optionally with `user:password@`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    /// As configured, for HTTP clients
+    pub url: String,
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| format!("expected a proxy URL, got {s:?}"))?;
+        let (kind, default_port) = match scheme {
+            "http" => (ProxyKind::Http, 80),
+            "socks5" | "socks5h" => (ProxyKind::Socks5, 1080),
+            other => {
+                return Err(format!(
+                    "expected an http, socks5 or socks5h proxy, got {other:?}"
+                ))
+            }
+        };
+
+        let authority = rest.trim_end_matches('/');
+        let (credentials, address) = match authority.rsplit_once('@') {
+            Some((userinfo, address)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_string(), password.to_string())), address)
+            }
+            None => (None, authority),
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid proxy port {port:?}"))?,
+            ),
+            _ => (address, default_port),
+        };
+        if host.is_empty() {
+            return Err(format!("proxy URL {s:?} has no host"));
+        }
+
+        Ok(Self {
+            url: s.to_string(),
+            kind,
+            host: host.trim_matches(['[', ']']).to_string(),
+            port,
+            credentials,
+        })
+    }
+}
+
+impl Proxy {
+    /// Opens a TCP connection to `host:port` through the proxy.
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TcpStream, ProxyError> {
+        let proxy = (self.host.as_str(), self.port);
+        let stream = match self.kind {
+            ProxyKind::Socks5 => match &self.credentials {
+                Some((user, password)) => {
+                    Socks5Stream::connect_with_password(proxy, (host, port), user, password)
+                        .await?
+                        .into_inner()
+                }
+                None => Socks5Stream::connect(proxy, (host, port))
+                    .await?
+                    .into_inner(),
+            },
+            ProxyKind::Http => {
+                let mut stream = TcpStream::connect(proxy).await?;
+                self.http_connect(&mut stream, host, port).await?;
+                stream
+            }
+        };
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    /// Asks an HTTP proxy to tunnel `stream` to `host:port`.
+    async fn http_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), ProxyError> {
+        let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            let token = data_encoding::BASE64.encode(format!("{user}:{password}").as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte so nothing past the response, i.e. the tunnelled stream, is consumed
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= MAX_CONNECT_RESPONSE {
+                return Err(ProxyError::Refused("response too long".into()));
+            }
+            let byte = stream.read_u8().await?;
+            response.push(byte);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(ProxyError::Refused(status.to_string())),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Quarantine for frames that fail to decode or dispatch: each is kept byte for byte in a file
+//! of its own, so it can be inspected or fed back to [`frame::decode`](crate::frame::decode)
+//! while debugging.
+//!
+//! Files are named `<dir>/<kind>/<time>-<seq>.frame`, where `kind` is the
+//! [`FrameError::kind`] that failed and `seq` is `unknown` when the frame didn't decode far
+//! enough to tell.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::Utc;
+use tracing::{error, warn};
+
+use crate::frame::FrameError;
+
+/// Frames written to a directory, up to a limit so a relay sending garbage can't fill the disk.
+#[derive(Debug)]
+pub struct Quarantine {
+    dir: PathBuf,
+    limit: usize,
+    kept: AtomicUsize,
+}
+
+impl Quarantine {
+    /// Keeps at most `limit` frames in `dir` for the lifetime of the process.
+    pub fn open(dir: &Path, limit: usize) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            limit,
+            kept: AtomicUsize::new(0),
+        })
+    }
+
+    /// Writes `frame`, which failed with `error`, returning where it was kept.
+    pub fn keep(&self, frame: &[u8], seq: Option<i64>, error: &FrameError) -> Option<PathBuf> {
+        let kept = self.kept.fetch_add(1, Ordering::Relaxed);
+        if kept >= self.limit {
+            if kept == self.limit {
+                warn!("Quarantined {} frames, not keeping any more", self.limit);
+            }
+            return None;
+        }
+
+        let dir = self.dir.join(error.kind());
+        let seq = seq.map_or_else(|| "unknown".to_string(), |seq| seq.to_string());
+        let path = dir.join(format!(
+            "{}-{seq}.frame",
+            Utc::now().format("%Y%m%dT%H%M%S%.6fZ")
+        ));
+        let written = std::fs::create_dir_all(&dir).and_then(|()| {
+            // Never overwrite a frame already kept
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?
+                .write_all(frame)
+        });
+        match written {
+            Ok(()) => Some(path),
+            Err(e) => {
+                error!("Unable to quarantine frame to {}: {e}", path.display());
+                None
+            }
+        }
+    }
+}
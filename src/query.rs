@@ -0,0 +1,99 @@
+//! Runs SQL over the Parquet output with an embedded DuckDB, so captures can be explored without
+//! setting up a database server.
+//!
+//! Each collection with complete files is a view named after its NSID, and `events` is every
+//! collection at once, with the columns of collections that lack them left null:
+//!
+//! ```sql
+//! SELECT date, count(*) FROM "app.bsky.feed.like" GROUP BY date;
+//! SELECT collection, count(*) FROM events GROUP BY collection;
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use arrow::{error::ArrowError, record_batch::RecordBatch, util::pretty};
+use duckdb::Connection;
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("unable to list Parquet files: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    DuckDb(#[from] duckdb::Error),
+    #[error("unable to format results: {0}")]
+    Arrow(#[from] ArrowError),
+}
+
+/// Runs `sql` over the Parquet files in `dir`, returning the results as a table.
+pub fn run(dir: &Path, sql: &str) -> Result<String, QueryError> {
+    let db = Connection::open_in_memory()?;
+    let mut any = false;
+    for (collection, path) in collections(dir)? {
+        db.execute_batch(&format!(
+            "CREATE VIEW {} AS SELECT * FROM {}",
+            identifier(&collection),
+            read_parquet(&path.join("*/*.parquet"))
+        ))?;
+        any = true;
+    }
+    if any {
+        db.execute_batch(&format!(
+            "CREATE VIEW events AS SELECT * FROM {}",
+            read_parquet(&dir.join("*/*/*.parquet"))
+        ))?;
+    }
+
+    let batches: Vec<RecordBatch> = db.prepare(sql)?.query_arrow([])?.collect();
+    Ok(pretty::pretty_format_batches(&batches)?.to_string())
+}
+
+/// The collections in `dir` with at least one complete file, since DuckDB refuses a view over a
+/// pattern matching nothing.
+fn collections(dir: &Path) -> Result<Vec<(String, PathBuf)>, std::io::Error> {
+    let mut collections = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(collection) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("collection="))
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        if has_parquet(&path)? {
+            collections.push((collection, path));
+        }
+    }
+    collections.sort();
+    Ok(collections)
+}
+
+fn has_parquet(collection: &Path) -> Result<bool, std::io::Error> {
+    for date in fs::read_dir(collection)? {
+        let date = date?.path();
+        if !date.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(date)? {
+            if file?.path().extension().is_some_and(|ext| ext == "parquet") {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn read_parquet(pattern: &Path) -> String {
+    format!(
+        "read_parquet('{}', hive_partitioning = true, union_by_name = true)",
+        pattern.to_string_lossy().replace('\'', "''")
+    )
+}
+
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,241 @@
+//! Priority work queues between frame decoding and the handler workers, sharded by repo and
+//! keeping each repo's commits in order.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    str::FromStr,
+    sync::{atomic::AtomicU64, Mutex},
+};
+
+use tokio::sync::Notify;
+
+use crate::{client::glob_match, shedding::ShedPolicy};
+
+/// Priority of a collection, from `pattern=priority` rules where the first matching pattern
+/// wins. Unlisted collections get priority 0.
+pub fn priority(rules: &[(String, i32)], collection: &str) -> i32 {
+    rules
+        .iter()
+        .find(|(pattern, _)| glob_match(pattern, collection))
+        .map_or(0, |(_, priority)| *priority)
+}
+
+/// Picks one of `shards` for `key`, the same one every time.
+pub fn shard<K: Hash + ?Sized>(key: &K, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Which queued item makes room when a queue is over its [limits](QueueLimits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// The item queued first, whatever its priority
+    #[default]
+    Oldest,
+    /// The item queued first among those of the lowest priority
+    LowPriority,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(Self::Oldest),
+            "low-priority" => Ok(Self::LowPriority),
+            _ => Err(format!(
+                "unknown overflow policy {s:?}, expected oldest or low-priority"
+            )),
+        }
+    }
+}
+
+/// Hard bounds on a queue, applying to every priority.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueLimits {
+    pub max_len: Option<usize>,
+    /// Over the sizes given to [`PriorityQueue::push`]
+    pub max_bytes: Option<usize>,
+    pub overflow: OverflowPolicy,
+}
+
+/// Sub-queues of each queue, so a repo's commits only wait behind the few repos sharing its
+/// sub-queue rather than every repo of its worker
+const KEY_LANES: usize = 64;
+
+/// Hands out the highest-priority item first, but never ahead of an item pushed earlier under
+/// the same key: items are kept in FIFO lanes by key, and the priority of the item at the
+/// front of each lane picks the lane popped next, the oldest front winning ties.
+///
+/// Once more than `shed_depth` items are waiting, items of priority 0 or below are dropped or
+/// sampled according to the [`ShedPolicy`]; higher priorities are always queued. Past the
+/// [`QueueLimits`], queued items of any priority are dropped to make room.
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    lanes: Mutex<Lanes<T>>,
+    notify: Notify,
+    shed_depth: usize,
+    policy: ShedPolicy,
+    limits: QueueLimits,
+    offered: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Lanes<T> {
+    /// Queued items by push order
+    entries: HashMap<u64, Entry<T>>,
+    /// Push orders of the items in each key lane, oldest first
+    by_key: Vec<BTreeSet<u64>>,
+    /// Push orders of the items of each priority, oldest first, to pick what's evicted
+    by_priority: BTreeMap<i32, BTreeSet<u64>>,
+    bytes: usize,
+    /// Incremented on every push
+    pushed: u64,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    lane: usize,
+    priority: i32,
+    bytes: usize,
+    item: T,
+}
+
+impl<T> Lanes<T> {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn over(&self, limits: &QueueLimits) -> bool {
+        limits.max_len.is_some_and(|max| self.len() > max)
+            || limits.max_bytes.is_some_and(|max| self.bytes > max)
+    }
+
+    /// Drops the item `overflow` picks.
+    fn evict(&mut self, overflow: OverflowPolicy) -> Option<T> {
+        let order = match overflow {
+            OverflowPolicy::Oldest => self
+                .by_priority
+                .values()
+                .filter_map(|orders| orders.first())
+                .min()
+                .copied(),
+            OverflowPolicy::LowPriority => self
+                .by_priority
+                .values()
+                .next()
+                .and_then(|orders| orders.first().copied()),
+        };
+        self.remove(order?)
+    }
+
+    /// The push order of the item popped next.
+    fn next(&self) -> Option<u64> {
+        self.by_key
+            .iter()
+            .filter_map(|orders| orders.first())
+            .max_by_key(|&&order| (self.entries[&order].priority, Reverse(order)))
+            .copied()
+    }
+
+    fn remove(&mut self, order: u64) -> Option<T> {
+        let entry = self.entries.remove(&order)?;
+        self.by_key[entry.lane].remove(&order);
+        if let Some(orders) = self.by_priority.get_mut(&entry.priority) {
+            orders.remove(&order);
+            if orders.is_empty() {
+                self.by_priority.remove(&entry.priority);
+            }
+        }
+        self.bytes -= entry.bytes;
+        Some(entry.item)
+    }
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new(shed_depth: usize, policy: ShedPolicy, limits: QueueLimits) -> Self {
+        Self {
+            lanes: Mutex::new(Lanes {
+                entries: HashMap::new(),
+                by_key: vec![BTreeSet::new(); KEY_LANES],
+                by_priority: BTreeMap::new(),
+                bytes: 0,
+                pushed: 0,
+            }),
+            notify: Notify::new(),
+            shed_depth,
+            policy,
+            limits,
+            offered: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues `item` after the others pushed under `key`, taking up roughly `bytes` of memory,
+    /// and returns the items dropped: `item` itself if it was shed, or queued ones to stay
+    /// within the limits.
+    ///
+    /// An item over `max_bytes` on its own is still queued when nothing else is.
+    pub fn push(&self, key: &str, priority: i32, item: T, bytes: usize) -> Vec<T> {
+        let mut lanes = self.lanes.lock().unwrap();
+        if priority <= 0 && lanes.len() >= self.shed_depth && !self.policy.admit(&self.offered) {
+            return vec![item];
+        }
+        lanes.pushed += 1;
+        let order = lanes.pushed;
+        // Hashed apart from the worker shard, which would leave a worker's keys few lanes
+        let lane = shard(&(key, KEY_LANES), KEY_LANES);
+        lanes.by_key[lane].insert(order);
+        lanes.by_priority.entry(priority).or_default().insert(order);
+        lanes.entries.insert(
+            order,
+            Entry {
+                lane,
+                priority,
+                bytes,
+                item,
+            },
+        );
+        lanes.bytes += bytes;
+
+        let mut dropped = Vec::new();
+        while lanes.len() > 1 && lanes.over(&self.limits) {
+            dropped.extend(lanes.evict(self.limits.overflow));
+        }
+        drop(lanes);
+
+        self.notify.notify_one();
+        dropped
+    }
+
+    /// Waits for the next item, see [`PriorityQueue`].
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.try_pop() {
+                return item;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let mut lanes = self.lanes.lock().unwrap();
+        let order = lanes.next()?;
+        lanes.remove(order)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.lock().unwrap().len()
+    }
+
+    /// Memory taken by queued items, going by the sizes they were pushed with.
+    pub fn bytes(&self) -> usize {
+        self.lanes.lock().unwrap().bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
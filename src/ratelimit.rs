@@ -0,0 +1,156 @@
+//! Token-bucket rate limiting for sinks posting to outside services (webhooks, Discord,
+//! Telegram, the bot), so a burst of events doesn't turn into a burst of requests.
+
+use std::{num::NonZeroU32, str::FromStr, sync::Mutex, time::Duration};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+/// At most `count` events per `period`, in bursts of up to `count`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RateLimit {
+    pub count: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub fn per_hour(count: NonZeroU32) -> Self {
+        Self {
+            count: count.get(),
+            period: Duration::from_secs(60 * 60),
+        }
+    }
+
+    fn per_sec(&self) -> f64 {
+        f64::from(self.count) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parses `<count>/<s|m|h>`, e.g. `30/m`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, unit) = s
+            .split_once('/')
+            .ok_or_else(|| format!("{s:?} is not count/unit, e.g. 30/m"))?;
+        let count = match count.trim().parse() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                return Err(format!(
+                    "rate limit count {count:?} must be a positive integer"
+                ))
+            }
+        };
+        let period = match unit.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            other => {
+                return Err(format!(
+                    "unknown rate limit unit {other:?}, expected s, m or h"
+                ))
+            }
+        };
+        Ok(Self { count, period })
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// What happens to events over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum LimitPolicy {
+    /// Hold them until the limit allows them through, leaving the sink's queue to fill up
+    #[default]
+    Queue,
+    /// Drop them
+    Drop,
+}
+
+impl FromStr for LimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queue" => Ok(Self::Queue),
+            "drop" => Ok(Self::Drop),
+            other => Err(format!(
+                "unknown rate limit policy {other:?}, expected queue or drop"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for LimitPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A token bucket holding up to `limit.count` tokens, starting full.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    policy: LimitPolicy,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit, policy: LimitPolicy) -> Self {
+        Self {
+            limit,
+            policy,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(limit.count),
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Whether the next event goes through. Under [`LimitPolicy::Queue`] this waits for the
+    /// limit to allow it and is always `true`.
+    pub async fn admit(&self) -> bool {
+        loop {
+            match self.take() {
+                Ok(()) => return true,
+                Err(_) if self.policy == LimitPolicy::Drop => return false,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Takes a token, or returns how long until there is one.
+    fn take(&self) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = (now - bucket.refilled_at).as_secs_f64() * self.limit.per_sec();
+        bucket.tokens = (bucket.tokens + refill).min(f64::from(self.limit.count));
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.limit.per_sec(),
+            ))
+        }
+    }
+}
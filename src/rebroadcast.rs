@@ -0,0 +1,174 @@
+//! Re-broadcasts decoded repo operations to local consumers, over websocket at `/subscribe` or
+//! server-sent events at `/events`, so several of them can share one upstream firehose
+//! connection.
+//!
+//! Each connection can narrow what it receives with query parameters:
+//! `/events?collections=app.bsky.feed.*&dids=did:plc:abc,did:plc:def`.
+
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::get,
+    Router,
+};
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::client::{glob_match, Event};
+
+/// A repo operation serialized once, shared by every consumer.
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub repo: String,
+    pub collection: String,
+    /// The operation as a JSON object
+    pub json: Arc<str>,
+}
+
+/// Per-connection filter, from the `collections` and `dids` query parameters.
+///
+/// Both are comma-separated; collections may be globs as accepted by
+/// [`Client::on`](crate::client::Client::on). Missing parameters match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    collections: Option<String>,
+    dids: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Broadcast) -> bool {
+        let collection = self.collections.as_deref().is_none_or(|patterns| {
+            patterns
+                .split(',')
+                .any(|pattern| glob_match(pattern.trim(), &event.collection))
+        });
+        let did = self
+            .dids
+            .as_deref()
+            .is_none_or(|dids| dids.split(',').any(|did| did.trim() == event.repo));
+        collection && did
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rebroadcaster {
+    tx: broadcast::Sender<Broadcast>,
+}
+
+impl Rebroadcaster {
+    /// `capacity` events are buffered per consumer before the slowest ones start skipping.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Sends `evt` to every connected consumer. Does nothing when nobody is connected.
+    pub fn publish(&self, evt: &Event) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+
+        let json = match evt.to_json() {
+            Ok(json) => json,
+            Err(e) => {
+                warn!(
+                    "Unable to convert {}/{} to JSON: {e}",
+                    evt.collection, evt.rkey
+                );
+                return;
+            }
+        };
+
+        // Only fails when every consumer disconnected in the meantime
+        let _ = self.tx.send(Broadcast {
+            repo: evt.repo.as_str().to_string(),
+            collection: evt.collection.clone(),
+            json: json.into(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Broadcast> {
+        self.tx.subscribe()
+    }
+
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route("/subscribe", get(subscribe))
+            .route("/events", get(events))
+            .with_state(self.clone())
+    }
+}
+
+async fn subscribe(
+    State(rebroadcaster): State<Rebroadcaster>,
+    Query(filter): Query<EventFilter>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let rx = rebroadcaster.subscribe();
+    ws.on_upgrade(move |socket| forward(socket, rx, filter))
+}
+
+async fn events(
+    State(rebroadcaster): State<Rebroadcaster>,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    info!("SSE consumer connected ({filter:?})");
+    let events = stream::unfold(rebroadcaster.subscribe(), move |mut rx| {
+        let filter = filter.clone();
+        async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) if filter.matches(&event) => {
+                        return Some((Ok(sse::Event::default().data(&*event.json)), rx));
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("SSE consumer is too slow, skipped {skipped} events");
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn forward(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<Broadcast>,
+    filter: EventFilter,
+) {
+    info!("Websocket consumer connected ({filter:?})");
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Websocket consumer is too slow, skipped {skipped} events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if !filter.matches(&event) {
+            continue;
+        }
+        if socket
+            .send(Message::Text(event.json.to_string()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    info!("Websocket consumer disconnected");
+}
@@ -0,0 +1,126 @@
+//! Failover between multiple relay endpoints.
+
+use std::{fmt::Display, future::Future, time::Duration};
+
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::config::Config;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error("no relays configured")]
+    NoRelays,
+    #[error("{relay}: {message}")]
+    Failed { relay: String, message: String },
+}
+
+/// Relay endpoints in order of preference, tracking which one is currently in use.
+#[derive(Debug)]
+pub struct RelayPool {
+    urls: Vec<String>,
+    current: usize,
+    failures: u32,
+    failover_after: u32,
+    preferred_retry: Duration,
+    failed_over_at: Option<Instant>,
+}
+
+impl RelayPool {
+    pub fn new(urls: Vec<String>, failover_after: u32, preferred_retry: Duration) -> Self {
+        assert!(!urls.is_empty(), "At least one relay must be configured");
+        Self {
+            urls,
+            current: 0,
+            failures: 0,
+            failover_after: failover_after.max(1),
+            preferred_retry,
+            failed_over_at: None,
+        }
+    }
+
+    /// The relays in `config.relays`, or an error when there are none.
+    pub fn from_config(config: &Config) -> Result<Self, RelayError> {
+        if config.relays.is_empty() {
+            return Err(RelayError::NoRelays);
+        }
+        Ok(Self::new(
+            config.relays.clone(),
+            config.failover_after,
+            config.preferred_retry,
+        ))
+    }
+
+    pub fn current(&self) -> &str {
+        &self.urls[self.current]
+    }
+
+    /// Records a failed connection attempt or dropped connection, failing over to the next
+    /// relay once the current one has failed `failover_after` times in a row.
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+        if self.failures < self.failover_after || self.urls.len() == 1 {
+            return;
+        }
+
+        let failed = self.current;
+        self.current = (self.current + 1) % self.urls.len();
+        self.failures = 0;
+        self.failed_over_at = (self.current != 0).then(Instant::now);
+        warn!(
+            "Relay {} failed repeatedly, failing over to {}",
+            self.urls[failed],
+            self.current()
+        );
+    }
+
+    /// Records that the current relay is delivering data.
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Whether we've been on a fallback relay long enough to retry the preferred one.
+    pub fn should_retry_preferred(&self) -> bool {
+        self.failed_over_at
+            .is_some_and(|at| at.elapsed() >= self.preferred_retry)
+    }
+
+    pub fn retry_preferred(&mut self) {
+        self.current = 0;
+        self.failures = 0;
+        self.failed_over_at = None;
+    }
+
+    /// Runs `attempt` with the current relay until it succeeds, failing over as a dropped
+    /// connection does, and returns the relay that answered with the result. Gives up with the
+    /// last error once every relay has failed `failover_after` times in a row.
+    pub async fn first_ok<T, E, F, Fut>(
+        &mut self,
+        mut attempt: F,
+    ) -> Result<(String, T), RelayError>
+    where
+        E: Display,
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut error = RelayError::NoRelays;
+        for _ in 0..self.urls.len() * self.failover_after as usize {
+            let relay = self.current().to_string();
+            match attempt(relay.clone()).await {
+                Ok(value) => {
+                    self.record_success();
+                    return Ok((relay, value));
+                }
+                Err(e) => {
+                    warn!("Request to relay {relay} failed: {e}");
+                    error = RelayError::Failed {
+                        relay,
+                        message: e.to_string(),
+                    };
+                    self.record_failure();
+                }
+            }
+        }
+        Err(error)
+    }
+}
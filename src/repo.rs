@@ -0,0 +1,168 @@
+//! Whole-repo downloads via `com.atproto.sync.getRepo`, and traversal of the repo's Merkle
+//! Search Tree (MST) into its records.
+
+use bytes::Bytes;
+use ipld_core::{cid::Cid, ipld::Ipld};
+use serde::Deserialize;
+
+use crate::{
+    frame::{self, Blocks, FrameError},
+    identity::{self, IdentityError},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepoError {
+    #[error("unable to resolve PDS: {0}")]
+    Identity(#[from] IdentityError),
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("invalid CAR file: {0}")]
+    Car(String),
+    #[error("malformed repo: {0}")]
+    Malformed(String),
+}
+
+/// A record found in a repo's MST.
+#[derive(Debug, Clone)]
+pub struct RepoRecord {
+    pub collection: String,
+    pub rkey: String,
+    pub cid: Cid,
+    /// Raw DAG-CBOR record
+    pub block: Bytes,
+}
+
+/// A repo's records as of revision `rev`.
+#[derive(Debug, Clone)]
+pub struct Repo {
+    pub rev: String,
+    pub records: Vec<RepoRecord>,
+}
+
+/// A page of `com.atproto.sync.listRepos`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepoPage {
+    pub cursor: Option<String>,
+    pub repos: Vec<ListedRepo>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListedRepo {
+    pub did: String,
+    pub rev: String,
+    /// Absent on hosts predating account status
+    pub active: Option<bool>,
+}
+
+/// Lists the repos hosted by `host` (a PDS or relay), a page at a time.
+pub async fn list_repos(
+    http: &reqwest::Client,
+    host: &str,
+    cursor: Option<&str>,
+) -> Result<RepoPage, RepoError> {
+    let mut request = http
+        .get(format!("{host}/xrpc/com.atproto.sync.listRepos"))
+        .query(&[("limit", "1000")]);
+    if let Some(cursor) = cursor {
+        request = request.query(&[("cursor", cursor)]);
+    }
+    Ok(request.send().await?.error_for_status()?.json().await?)
+}
+
+/// Downloads the CAR file of `did`'s whole repo from its PDS.
+pub async fn fetch(http: &reqwest::Client, did: &str) -> Result<Vec<u8>, RepoError> {
+    let pds = identity::resolve_pds(http, did).await?;
+    let car = http
+        .get(format!("{pds}/xrpc/com.atproto.sync.getRepo"))
+        .query(&[("did", did)])
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(car.to_vec())
+}
+
+/// Walks the MST of a repo CAR file, returning its records in key order.
+pub async fn records(car: &[u8]) -> Result<Repo, RepoError> {
+    let (roots, blocks) = frame::read_car(car).await.map_err(|e| match e {
+        FrameError::Car(e) => RepoError::Car(e),
+        e => RepoError::Car(e.to_string()),
+    })?;
+
+    let root = roots
+        .first()
+        .ok_or_else(|| RepoError::Car("no root".into()))?;
+    let Ipld::Map(commit) = decode(&blocks, root)? else {
+        return Err(RepoError::Malformed("commit is not a map".into()));
+    };
+    let (Some(Ipld::Link(data)), Some(Ipld::String(rev))) = (commit.get("data"), commit.get("rev"))
+    else {
+        return Err(RepoError::Malformed(
+            "commit has no data link or rev".into(),
+        ));
+    };
+
+    let mut records = Vec::new();
+    walk(&blocks, data, &mut records)?;
+    Ok(Repo {
+        rev: rev.clone(),
+        records,
+    })
+}
+
+fn decode(blocks: &Blocks, cid: &Cid) -> Result<Ipld, RepoError> {
+    let block = blocks
+        .get(cid)
+        .ok_or_else(|| RepoError::Malformed(format!("missing block {cid}")))?;
+    serde_ipld_dagcbor::from_slice(block).map_err(|e| RepoError::Malformed(e.to_string()))
+}
+
+/// Visits the MST node `cid` in order: its left subtree (`l`), then each entry followed by the
+/// entry's right subtree (`t`). Entry keys are prefix-compressed against the previous one.
+fn walk(blocks: &Blocks, cid: &Cid, records: &mut Vec<RepoRecord>) -> Result<(), RepoError> {
+    let malformed = |what: &str| RepoError::Malformed(format!("MST node {cid}: {what}"));
+
+    let Ipld::Map(node) = decode(blocks, cid)? else {
+        return Err(malformed("not a map"));
+    };
+    if let Some(Ipld::Link(left)) = node.get("l") {
+        walk(blocks, left, records)?;
+    }
+    let Some(Ipld::List(entries)) = node.get("e") else {
+        return Err(malformed("missing entries"));
+    };
+
+    let mut key = Vec::new();
+    for entry in entries {
+        let Ipld::Map(entry) = entry else {
+            return Err(malformed("entry is not a map"));
+        };
+        let (Some(Ipld::Integer(prefix)), Some(Ipld::Bytes(suffix)), Some(Ipld::Link(value))) =
+            (entry.get("p"), entry.get("k"), entry.get("v"))
+        else {
+            return Err(malformed("incomplete entry"));
+        };
+        key.truncate(usize::try_from(*prefix).map_err(|_| malformed("invalid prefix"))?);
+        key.extend_from_slice(suffix);
+
+        let path = String::from_utf8(key.clone()).map_err(|_| malformed("key is not UTF-8"))?;
+        let Some((collection, rkey)) = path.split_once('/') else {
+            return Err(malformed("key is not a collection/rkey path"));
+        };
+        let block = blocks
+            .get(value)
+            .ok_or_else(|| malformed("missing record block"))?;
+        records.push(RepoRecord {
+            collection: collection.to_string(),
+            rkey: rkey.to_string(),
+            cid: *value,
+            block: block.clone(),
+        });
+
+        if let Some(Ipld::Link(right)) = entry.get("t") {
+            walk(blocks, right, records)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Per-repo commit ordering: each commit's `since` should be the `rev` of the repo's previous
+//! commit, and revs (TIDs, which sort as strings) should only go up. Commits breaking either
+//! rule point to missed commits, a rollback or a forked repo, which matters to anyone
+//! mirroring repo state.
+
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Serialize;
+
+/// Digits of the base32 encoding TIDs use, in order
+const TID_ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+/// A commit out of order with the previous one seen from the same repo.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RevViolation {
+    pub repo: String,
+    pub seq: i64,
+    pub kind: ViolationKind,
+    /// Rev of the previous commit seen from the repo
+    pub last_rev: String,
+    pub rev: String,
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// The rev is not after the previous one: a replay, rollback or clock skew
+    Rollback,
+    /// The commit builds on a rev other than the previous one: commits were missed, or the
+    /// repo was forked
+    Gap,
+}
+
+impl ViolationKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rollback => "rollback",
+            Self::Gap => "gap",
+        }
+    }
+}
+
+/// The latest rev of the most recently active repos. Repos quiet the longest are forgotten
+/// first once `capacity` are tracked, and their next commit is taken as is.
+#[derive(Debug)]
+pub struct RevTracker {
+    revs: Mutex<LruCache<String, Latest>>,
+}
+
+#[derive(Debug)]
+struct Latest {
+    rev: String,
+    /// `seq` of the `#sync` that last reset the repo, if any
+    synced_at: Option<i64>,
+}
+
+impl RevTracker {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            revs: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Records a commit to `repo`, returning how it breaks the order if it does. The latest
+    /// rev is kept either way, except after a rollback.
+    pub fn check(
+        &self,
+        repo: &str,
+        seq: i64,
+        rev: &str,
+        since: Option<&str>,
+    ) -> Option<RevViolation> {
+        let mut revs = self.revs.lock().unwrap();
+        let Some(latest) = revs.get_mut(repo) else {
+            revs.put(
+                repo.to_string(),
+                Latest {
+                    rev: rev.to_string(),
+                    synced_at: None,
+                },
+            );
+            return None;
+        };
+        // Commits sent before a #sync may still be queued behind it; the sync superseded them
+        if latest.synced_at.is_some_and(|synced_at| seq < synced_at) {
+            return None;
+        }
+
+        let kind = if rev <= latest.rev.as_str() {
+            ViolationKind::Rollback
+        } else if since.is_some_and(|since| since != latest.rev) {
+            ViolationKind::Gap
+        } else {
+            latest.rev = rev.to_string();
+            return None;
+        };
+        let violation = RevViolation {
+            repo: repo.to_string(),
+            seq,
+            kind,
+            last_rev: latest.rev.clone(),
+            rev: rev.to_string(),
+            since: since.map(str::to_string),
+        };
+        if kind == ViolationKind::Gap {
+            latest.rev = rev.to_string();
+        }
+        Some(violation)
+    }
+
+    /// Records a `#sync` of `repo` at `seq`, which resets its state to `rev` whatever came
+    /// before: the next commit only has to follow on from `rev`.
+    pub fn reset(&self, repo: &str, seq: i64, rev: &str) {
+        self.revs.lock().unwrap().put(
+            repo.to_string(),
+            Latest {
+                rev: rev.to_string(),
+                synced_at: Some(seq),
+            },
+        );
+    }
+}
+
+/// When the commit with revision `rev` was made: a TID holds microseconds since the epoch
+/// above 10 bits of clock identifier. Returns `None` if `rev` isn't a TID.
+pub fn rev_time(rev: &str) -> Option<DateTime<Utc>> {
+    if rev.len() != 13 {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for digit in rev.bytes() {
+        let digit = TID_ALPHABET.iter().position(|&c| c == digit)?;
+        value = (value << 5) | digit as u64;
+    }
+    // The top bit is always zero
+    if value >> 63 != 0 {
+        return None;
+    }
+    DateTime::from_timestamp_micros((value >> 10) as i64)
+}
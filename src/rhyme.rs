@@ -0,0 +1,78 @@
+//! Rhymes between words, by their phonemes in the CMU pronouncing dictionary when both are in
+//! it, and by spelling otherwise.
+
+use crate::syllables::SyllableCounter;
+
+/// What a word ends with for rhyming purposes, see [`SyllableCounter::rhyme`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rhyme {
+    /// Phonemes from the last stressed vowel on, when the word is in the dictionary
+    pub phonemes: Option<String>,
+    /// See [`spelled_rhyme`]
+    pub spelled: String,
+}
+
+impl Rhyme {
+    /// Whether the two end in the same sound: compared by phonemes when both words are in the
+    /// dictionary, since a pronunciation and a spelling can't be compared, by spelling otherwise.
+    /// A word with no spelled rhyme rhymes with nothing by spelling.
+    pub fn rhymes_with(&self, other: &Rhyme) -> bool {
+        match (&self.phonemes, &other.phonemes) {
+            (Some(phonemes), Some(other)) => phonemes == other,
+            _ => !self.spelled.is_empty() && self.spelled == other.spelled,
+        }
+    }
+}
+
+/// Whether `a` and `b` are different words ending in the same sound. A word doesn't rhyme with
+/// itself, though forms like limericks may repeat one.
+pub fn rhymes(counter: &SyllableCounter, a: &str, b: &str) -> bool {
+    normalize(a) != normalize(b) && counter.rhyme(a).rhymes_with(&counter.rhyme(b))
+}
+
+/// `word` in lowercase with curly apostrophes straightened, as dictionary entries are.
+pub fn normalize(word: &str) -> String {
+    word.to_lowercase().replace('’', "'")
+}
+
+/// Common spellings of vowel sounds ending a word, by the sound's usual spelling. Spellings
+/// said more than one way go with the more common sound.
+const OPEN_VOWELS: &[(&str, &[&str])] = &[
+    ("oo", &["oo", "ew", "ue", "u", "ou", "oe"]),
+    ("ay", &["ay", "ey", "eigh", "ai"]),
+    ("ee", &["ee", "ea"]),
+    ("igh", &["igh", "ie", "uy"]),
+    ("ow", &["ow", "ough"]),
+    ("oh", &["o", "oa"]),
+];
+
+/// The end of an English word from its last vowel group, e.g. `ight` for "night" and `ate`
+/// for "late", so words spelled alike at the end rhyme. Empty for words without Latin letters,
+/// e.g. in other scripts or numbers, whose rhymes can't be told from their spelling.
+pub fn spelled_rhyme(word: &str) -> String {
+    let word = word
+        .to_ascii_lowercase()
+        .replace(|c: char| !c.is_ascii_alphabetic(), "");
+    let bytes = word.as_bytes();
+    let is_vowel = |c: u8| b"aeiouy".contains(&c);
+
+    // A silent final "e" belongs to the vowel before it
+    let mut end = bytes.len();
+    if end > 2 && bytes[end - 1] == b'e' && !is_vowel(bytes[end - 2]) {
+        end -= 1;
+    }
+    let Some(last_vowel) = bytes[..end].iter().rposition(|&c| is_vowel(c)) else {
+        return word;
+    };
+    let start = bytes[..last_vowel]
+        .iter()
+        .rposition(|&c| !is_vowel(c))
+        .map_or(0, |consonant| consonant + 1);
+    let rhyme = &word[start..];
+    // Open vowels at the end of a word are spelled many ways
+    OPEN_VOWELS
+        .iter()
+        .find(|(_, spellings)| spellings.contains(&rhyme))
+        .map_or(rhyme, |(sound, _)| sound)
+        .to_string()
+}
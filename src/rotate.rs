@@ -0,0 +1,230 @@
+//! Output files that roll over by time or size, with optional compression of closed files and
+//! removal of old ones.
+//!
+//! Closed files are renamed to `<stem>.<YYYYMMDDTHHMMSS>.<extension>` after the time they were
+//! started, then compressed in the background.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use tracing::{error, info};
+
+use crate::compress::Compression;
+
+/// Time-based rollover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotateEvery {
+    Never,
+    Hour,
+    Day,
+}
+
+impl RotateEvery {
+    /// Identifies the period `time` falls in; a file is rolled over once this changes.
+    fn period(self, time: DateTime<Utc>) -> String {
+        match self {
+            Self::Never => String::new(),
+            Self::Hour => time.format("%Y%m%d%H").to_string(),
+            Self::Day => time.format("%Y%m%d").to_string(),
+        }
+    }
+}
+
+impl FromStr for RotateEvery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "hourly" => Ok(Self::Hour),
+            "daily" => Ok(Self::Day),
+            other => Err(format!(
+                "expected \"never\", \"hourly\" or \"daily\", got {other:?}"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RotationPolicy {
+    pub every: RotateEvery,
+    /// Roll over before a write would take the file past this size
+    pub max_bytes: Option<u64>,
+    /// Applied to closed files
+    pub compression: Compression,
+    /// Closed files older than this are deleted
+    pub retention: Option<Duration>,
+}
+
+/// An append-only file rolled over according to a [`RotationPolicy`].
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    /// Written at the start of every new file, e.g. a CSV header
+    header: Vec<u8>,
+    file: File,
+    len: u64,
+    started_at: DateTime<Utc>,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, policy: RotationPolicy, header: Vec<u8>) -> std::io::Result<Self> {
+        let (file, len) = open(path, &header)?;
+        // Carry on with a file left by a previous run, unless it belongs to an earlier period
+        let started_at = file
+            .metadata()?
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        let mut rotating = Self {
+            path: path.to_path_buf(),
+            policy,
+            header,
+            file,
+            len,
+            started_at,
+        };
+        let has_rows = rotating.len > rotating.header.len() as u64;
+        if has_rows && policy.every.period(started_at) != policy.every.period(Utc::now()) {
+            rotating.rotate()?;
+        } else {
+            rotating.started_at = Utc::now();
+        }
+        Ok(rotating)
+    }
+
+    /// Appends `data`, rolling over first if the period has ended or the file would grow past
+    /// its maximum size.
+    pub fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let now = Utc::now();
+        let period_ended =
+            self.policy.every.period(self.started_at) != self.policy.every.period(now);
+        let too_large = self.len > self.header.len() as u64
+            && self
+                .policy
+                .max_bytes
+                .is_some_and(|max_bytes| self.len + data.len() as u64 > max_bytes);
+        if period_ended || too_large {
+            self.rotate()?;
+        }
+
+        self.file.write_all(data)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    /// Moves the current file aside and starts a new one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let closed = self.closed_path();
+        std::fs::rename(&self.path, &closed)?;
+        let (file, len) = open(&self.path, &self.header)?;
+        self.file = file;
+        self.len = len;
+        self.started_at = Utc::now();
+        info!("Rotated {} to {}", self.path.display(), closed.display());
+
+        let path = self.path.clone();
+        let policy = self.policy;
+        std::thread::spawn(move || {
+            if let Err(e) = compress(&closed, policy.compression) {
+                error!("Unable to compress {}: {e}", closed.display());
+            }
+            if let Some(retention) = policy.retention {
+                if let Err(e) = remove_expired(&path, retention) {
+                    error!("Unable to remove old rotations of {}: {e}", path.display());
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// `<stem>.<started at>.<extension>`, numbered if several files started in the same second.
+    fn closed_path(&self) -> PathBuf {
+        let (stem, extension) = split_name(&self.path);
+        let started_at = self.started_at.format("%Y%m%dT%H%M%S");
+        let mut closed = self
+            .path
+            .with_file_name(format!("{stem}.{started_at}{extension}"));
+        let taken = |path: &Path| {
+            let mut compressed = path.as_os_str().to_owned();
+            compressed.push(self.policy.compression.extension());
+            path.exists() || Path::new(&compressed).exists()
+        };
+        let mut n = 1;
+        while taken(&closed) {
+            closed = self
+                .path
+                .with_file_name(format!("{stem}.{started_at}-{n}{extension}"));
+            n += 1;
+        }
+        closed
+    }
+}
+
+/// Opens `path` for appending, writing `header` if the file is new.
+fn open(path: &Path, header: &[u8]) -> std::io::Result<(File, u64)> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut len = file.metadata()?.len();
+    if len == 0 && !header.is_empty() {
+        file.write_all(header)?;
+        len = header.len() as u64;
+    }
+    Ok((file, len))
+}
+
+/// Splits a file name into its stem and extension, the latter with its leading dot.
+fn split_name(path: &Path) -> (String, String) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, extension)
+}
+
+fn compress(path: &Path, compression: Compression) -> std::io::Result<()> {
+    if compression == Compression::None {
+        return Ok(());
+    }
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(compression.extension());
+
+    let mut encoder = compression.encoder(File::create(&compressed)?)?;
+    std::io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::remove_file(path)
+}
+
+/// Deletes closed rotations of `path` last modified more than `retention` ago.
+fn remove_expired(path: &Path, retention: Duration) -> std::io::Result<()> {
+    let (stem, _) = split_name(path);
+    let prefix = format!("{stem}.");
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let cutoff = SystemTime::now() - retention;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_rotation = name
+            .to_string_lossy()
+            .strip_prefix(&prefix)
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+        if is_rotation && entry.metadata()?.modified()? < cutoff {
+            std::fs::remove_file(entry.path())?;
+            info!("Removed expired {}", entry.path().display());
+        }
+    }
+    Ok(())
+}
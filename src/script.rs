@@ -0,0 +1,192 @@
+//! User-written [Rhai](https://rhai.rs) scripts filtering and transforming posts, reloaded
+//! whenever the script file changes.
+//!
+//! A script defines `fn filter(post)`, returning whether the post is processed, and/or
+//! `fn transform(post)`, returning the post with its `text` changed. `post` is a map of `text`,
+//! `did`, `rkey`, `uri`, `created_at`, and arrays of `langs`, `tags`, `mentions` and `links`.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use atrium_api::app::bsky::feed::post;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+use tracing::{error, info};
+
+use crate::{client::Event, facets::Facets, watch};
+
+/// Operations a script may run per post before it is stopped, so an endless loop can't stall
+/// the listener
+const MAX_OPERATIONS: u64 = 100_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptError {
+    #[error("unable to read script: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unable to compile script: {0}")]
+    Parse(#[from] rhai::ParseError),
+    #[error("script defines neither fn filter(post) nor fn transform(post)")]
+    NoEntryPoint,
+}
+
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    compiled: RwLock<Compiled>,
+}
+
+struct Compiled {
+    ast: AST,
+    filter: bool,
+    transform: bool,
+}
+
+impl Script {
+    pub fn load(path: PathBuf) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let compiled = compile(&engine, &std::fs::read_to_string(&path)?)?;
+        info!("Loaded script {}", path.display());
+
+        Ok(Self {
+            path,
+            engine,
+            compiled: RwLock::new(compiled),
+        })
+    }
+
+    /// Runs the script over `record`, returning its text as transformed if the post passes
+    /// the filter. Posts the script fails on are filtered out.
+    pub fn apply(&self, evt: &Event, record: &post::Record) -> Option<String> {
+        let compiled = self.compiled.read().unwrap();
+        let mut post = to_map(evt, record);
+
+        if compiled.transform {
+            post = match self.call::<Map>(&compiled.ast, "transform", post) {
+                Ok(post) => post,
+                Err(e) => {
+                    error!("Script transform failed on {}: {e}", evt.rkey);
+                    return None;
+                }
+            };
+        }
+        let text = match post.get("text").cloned().map(Dynamic::into_string) {
+            Some(Ok(text)) => text,
+            _ => {
+                error!(
+                    "Script transform returned a post without text for {}",
+                    evt.rkey
+                );
+                return None;
+            }
+        };
+
+        if compiled.filter {
+            match self.call::<bool>(&compiled.ast, "filter", post) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    error!("Script filter failed on {}: {e}", evt.rkey);
+                    return None;
+                }
+            }
+        }
+        Some(text)
+    }
+
+    fn call<T: Clone + Send + Sync + 'static>(
+        &self,
+        ast: &AST,
+        name: &str,
+        post: Map,
+    ) -> Result<T, Box<rhai::EvalAltResult>> {
+        self.engine
+            .call_fn(&mut Scope::new(), ast, name, (Dynamic::from_map(post),))
+    }
+
+    /// Recompiles the script in the background whenever its file changes. The previous version
+    /// keeps running if the new one doesn't compile.
+    pub fn watch(self: Arc<Self>) {
+        watch::on_change("script", self.path.clone(), move || {
+            let script = self.clone();
+            async move {
+                let compiled = match tokio::fs::read_to_string(&script.path).await {
+                    Ok(source) => compile(&script.engine, &source),
+                    Err(e) => Err(e.into()),
+                };
+                match compiled {
+                    Ok(compiled) => {
+                        info!("Reloaded script {}", script.path.display());
+                        *script.compiled.write().unwrap() = compiled;
+                    }
+                    Err(e) => error!("Unable to reload script: {e}"),
+                }
+            }
+        });
+    }
+}
+
+impl std::fmt::Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Script").field("path", &self.path).finish()
+    }
+}
+
+fn compile(engine: &Engine, source: &str) -> Result<Compiled, ScriptError> {
+    let ast = engine.compile(source)?;
+    let defines = |name: &str| {
+        ast.iter_functions()
+            .any(|function| function.name == name && function.params.len() == 1)
+    };
+    let (filter, transform) = (defines("filter"), defines("transform"));
+    if !filter && !transform {
+        return Err(ScriptError::NoEntryPoint);
+    }
+    Ok(Compiled {
+        ast,
+        filter,
+        transform,
+    })
+}
+
+fn to_map(evt: &Event, record: &post::Record) -> Map {
+    let did = evt.repo.as_str();
+    let facets = Facets::from_record(record);
+    let array = |items: &[String]| {
+        Dynamic::from_array(
+            items
+                .iter()
+                .map(|item| Dynamic::from(item.clone()))
+                .collect::<Array>(),
+        )
+    };
+
+    let mut post = Map::new();
+    post.insert("text".into(), record.text.clone().into());
+    post.insert("did".into(), did.into());
+    post.insert("rkey".into(), evt.rkey.clone().into());
+    post.insert(
+        "uri".into(),
+        format!("at://{did}/{}/{}", evt.collection, evt.rkey).into(),
+    );
+    post.insert(
+        "created_at".into(),
+        record.created_at.as_str().to_string().into(),
+    );
+    post.insert(
+        "langs".into(),
+        array(
+            &record
+                .langs
+                .iter()
+                .flatten()
+                .map(|lang| lang.as_ref().to_string())
+                .collect::<Vec<_>>(),
+        ),
+    );
+    post.insert("tags".into(), array(&facets.tags));
+    post.insert("mentions".into(), array(&facets.mentions));
+    post.insert("links".into(), array(&facets.links));
+    post
+}
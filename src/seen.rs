@@ -0,0 +1,34 @@
+//! Recently delivered repo operations, so the ones a relay sends again after resuming from a
+//! cursor are dropped before they reach any handler or sink.
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    num::NonZeroUsize,
+    sync::Mutex,
+};
+
+use lru::LruCache;
+
+/// The most recently delivered `(seq, repo, path)` operations, remembered by hash. Older
+/// ones are forgotten once `capacity` are remembered.
+#[derive(Debug)]
+pub struct SeenOps {
+    hasher: RandomState,
+    seen: Mutex<LruCache<u64, ()>>,
+}
+
+impl SeenOps {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            hasher: RandomState::new(),
+            seen: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Records the operation on `path` (`<collection>/<rkey>`) in commit `seq` to `repo`,
+    /// returning whether it was already seen.
+    pub fn check(&self, seq: i64, repo: &str, path: &str) -> bool {
+        let key = self.hasher.hash_one((seq, repo, path));
+        self.seen.lock().unwrap().put(key, ()).is_some()
+    }
+}
@@ -0,0 +1,538 @@
+//! `selftest` subcommand: runs the decode pipeline against embedded fixture
+//! frames, loads every configured input and probes every configured sink, so a build and its
+//! configuration can be validated before they are pointed at the live firehose.
+
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+    time::Duration,
+};
+
+use object_store::ObjectStore;
+use rumqttc::{AsyncClient, MqttOptions, Packet};
+use tokio::time::timeout;
+use tracing::{error, info};
+
+use crate::{
+    archive,
+    config::Config,
+    embedding::VectorIndex,
+    filter::PostFilter,
+    frame::{self, ErrorKind, Frame},
+    haiku::{self, SyllablePattern},
+    http,
+    language::LanguageFilter,
+    moderation::Moderation,
+    pipeline::{self, SinkConfig},
+    plugin::{Plugin, PluginLimits},
+    query,
+    script::Script,
+    sqlite::PostSearch,
+    syllables::SyllableCounter,
+    watchlist::Watchlist,
+};
+
+/// Longest a broker is given to accept a connection
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const COMMIT_FRAME: &[u8] = include_bytes!("../fixtures/commit.bin");
+const IDENTITY_FRAME: &[u8] = include_bytes!("../fixtures/identity.bin");
+const ERROR_FRAME: &[u8] = include_bytes!("../fixtures/error.bin");
+
+const COMMIT_POST_TEXT: &str =
+    "an old silent pond\na frog jumps into the pond\nsplash! silence again";
+
+/// Posts with known haiku status, used to validate syllable counting
+const HAIKU_CORPUS: &[(&str, bool)] = &[
+    (COMMIT_POST_TEXT, true),
+    (
+        "the morning sun glows\nbirds are singing in the trees\na new day begins",
+        true,
+    ),
+    (
+        "cold coffee again\nmy code will not compile now\nwaiting after lunch",
+        true,
+    ),
+    ("just setting up my bsky", false),
+    ("read this 5 7 5 thread https://example.com", false),
+    (
+        "this post is not a haiku because it goes on for much too long to fit the pattern",
+        false,
+    ),
+];
+
+/// Texts with a known language (ISO 639-3), used to validate language detection
+const LANGUAGE_CORPUS: &[(&str, &str)] = &[
+    (
+        "The quick brown fox jumps over the lazy dog while the sun is setting",
+        "eng",
+    ),
+    (
+        "El rápido zorro marrón salta sobre el perro perezoso mientras se pone el sol",
+        "spa",
+    ),
+    (
+        "Der schnelle braune Fuchs springt über den faulen Hund, während die Sonne untergeht",
+        "deu",
+    ),
+];
+
+type CheckResult = Result<(), String>;
+
+/// Runs every check, including one per input and sink configured in `config`, logs a
+/// pass/fail summary and returns whether all of them passed.
+pub async fn run(config: &Config) -> bool {
+    let mut checks: Vec<(String, CheckResult)> = vec![
+        ("decode #commit frame".into(), check_commit().await),
+        ("decode #identity frame".into(), check_identity()),
+        ("recognize error frame".into(), check_error()),
+        ("reject truncated frame".into(), check_truncated()),
+        ("language corpus".into(), check_language_corpus()),
+    ];
+    checks.extend(check_inputs(config).await);
+    checks.extend(check_sinks(config).await);
+
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => info!("PASS {name}"),
+            Err(e) => {
+                error!("FAIL {name}: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    info!(
+        "Self-test finished: {} passed, {} failed",
+        checks.len() - failed,
+        failed
+    );
+    failed == 0
+}
+
+async fn check_commit() -> CheckResult {
+    let Frame::Commit(commit) = frame::decode(COMMIT_FRAME).map_err(|e| e.to_string())? else {
+        return Err("expected a #commit frame".into());
+    };
+    let posts = frame::posts(&commit).await.map_err(|e| e.to_string())?;
+
+    match posts.as_slice() {
+        [post] if post.record.text == COMMIT_POST_TEXT => Ok(()),
+        [post] => Err(format!("unexpected post text {:?}", post.record.text)),
+        _ => Err(format!("expected 1 post, got {}", posts.len())),
+    }
+}
+
+fn check_identity() -> CheckResult {
+    match frame::decode(IDENTITY_FRAME).map_err(|e| e.to_string())? {
+        Frame::Identity(_) => Ok(()),
+        other => Err(format!("expected #identity, got {other:?}")),
+    }
+}
+
+fn check_error() -> CheckResult {
+    match frame::decode(ERROR_FRAME).map_err(|e| e.to_string())? {
+        Frame::Error(e) if e.error == ErrorKind::FutureCursor => Ok(()),
+        other => Err(format!(
+            "expected a FutureCursor error frame, got {other:?}"
+        )),
+    }
+}
+
+fn check_truncated() -> CheckResult {
+    match frame::decode(&COMMIT_FRAME[..COMMIT_FRAME.len() / 2]) {
+        Err(_) => Ok(()),
+        Ok(other) => Err(format!("expected a decode error, got {other:?}")),
+    }
+}
+
+/// Runs the corpus through the configured forms, which must include one with the lines of a
+/// haiku.
+fn check_haiku_corpus(forms: &[SyllablePattern], counter: &SyllableCounter) -> CheckResult {
+    let failures = HAIKU_CORPUS
+        .iter()
+        .filter(|(text, expected)| haiku::detect(text, forms, counter).is_some() != *expected)
+        .map(|(text, expected)| format!("{text:?} (expected haiku: {expected})"))
+        .collect::<Vec<_>>();
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join(", "))
+    }
+}
+
+fn check_language_corpus() -> CheckResult {
+    let failures = LANGUAGE_CORPUS
+        .iter()
+        .filter_map(|(text, expected)| {
+            let detected = LanguageFilter::detect(text).map(|detection| detection.language);
+            (detected.as_deref() != Some(*expected))
+                .then(|| format!("{text:?} (expected {expected}, got {detected:?})"))
+        })
+        .collect::<Vec<_>>();
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join(", "))
+    }
+}
+
+/// One check per input file configured in `config`, each loaded the way the listener loads it.
+async fn check_inputs(config: &Config) -> Vec<(String, CheckResult)> {
+    let mut checks = Vec::new();
+
+    let forms = config
+        .forms
+        .iter()
+        .map(|form| {
+            let lines = form.lines.iter().map(usize::to_string).collect::<Vec<_>>();
+            format!("{}={}", form.name, lines.join("-"))
+        })
+        .collect::<Vec<_>>();
+    checks.push((
+        format!("parse FIREHOSE_FORMS {}", forms.join(",")),
+        if forms.is_empty() {
+            Err("no forms configured".into())
+        } else {
+            Ok(())
+        },
+    ));
+
+    let counter = match &config.cmudict {
+        Some(path) => match SyllableCounter::load_cmudict(path) {
+            Ok(counter) => {
+                checks.push((format!("load CMU dictionary {}", path.display()), Ok(())));
+                Some(counter)
+            }
+            Err(e) => {
+                checks.push((
+                    format!("load CMU dictionary {}", path.display()),
+                    Err(e.to_string()),
+                ));
+                None
+            }
+        },
+        None => Some(SyllableCounter::Estimate),
+    };
+    // The corpus is all haiku, so other forms have nothing to be checked against
+    let haiku = SyllablePattern::haiku();
+    if let Some(counter) = counter {
+        if config.forms.iter().any(|form| form.lines == haiku.lines) {
+            checks.push((
+                "haiku corpus".into(),
+                check_haiku_corpus(&config.forms, &counter),
+            ));
+        }
+    }
+
+    if let Some(path) = &config.moderation_lexicon {
+        checks.push((
+            format!("load moderation lexicon {}", path.display()),
+            Moderation::load_lexicon(path)
+                .and_then(|mut terms| {
+                    terms.extend(config.moderation_terms.iter().cloned());
+                    Moderation::new(terms)
+                })
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        ));
+    }
+    if let Some(path) = &config.regex_file {
+        checks.push((
+            format!("load regex file {}", path.display()),
+            PostFilter::from_config(config)
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        ));
+    }
+    if let Some(path) = &config.script {
+        checks.push((
+            format!("compile script {}", path.display()),
+            Script::load(path.clone())
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        ));
+    }
+    let limits = PluginLimits {
+        fuel: config.plugin_fuel,
+        memory_bytes: config.plugin_max_memory,
+        max_action_bytes: config.plugin_max_action,
+    };
+    for spec in &config.plugins {
+        checks.push((
+            format!("load plugin {}", spec.path.display()),
+            Plugin::load(&spec.path, limits)
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        ));
+    }
+    if let Some(path) = &config.watchlist {
+        checks.push((
+            format!("load watchlist {}", path.display()),
+            Watchlist::load(path.clone(), config.watchlist_mode, &http::client(config))
+                .await
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        ));
+    }
+    checks
+}
+
+/// One check per sink configured in `config`, named after what it probes.
+async fn check_sinks(config: &Config) -> Vec<(String, CheckResult)> {
+    let http = http::client(config);
+    let mut checks = Vec::new();
+
+    let files = [
+        Some(&config.haiku_output),
+        config.csv_output.as_ref(),
+        config.cursor_file.as_ref(),
+        config.account_status.as_ref(),
+        config.rev_violations.as_ref(),
+        config.moderation_audit.as_ref(),
+        config.dedup_file.as_ref(),
+        config.trending_output.as_ref(),
+        config.emoji_output.as_ref(),
+        config.engagement_output.as_ref(),
+        config.follow_log.as_ref(),
+        config.identity_log.as_ref(),
+        config.anomaly_output.as_ref(),
+        config.plugin_output.as_ref(),
+        config.dead_letters.as_ref(),
+    ];
+    let routes = config.label_routes.iter().map(|route| &route.path);
+    for path in files.into_iter().flatten().chain(routes) {
+        checks.push((format!("open {}", path.display()), check_file(path)));
+    }
+    let dirs = [
+        config.block_store.as_ref(),
+        config.mirror_dir.as_ref(),
+        config.blob_dir.as_ref(),
+        config.quarantine_dir.as_ref(),
+        config.parquet_dir.as_ref(),
+    ];
+    for dir in dirs.into_iter().flatten() {
+        checks.push((format!("create {}", dir.display()), check_dir(dir)));
+    }
+    if let Some(dir) = &config.parquet_dir {
+        checks.push((
+            format!("query {} with DuckDB", dir.display()),
+            query::run(dir, "SELECT 1").map(drop).map_err(|e| e.to_string()),
+        ));
+    }
+    if let Some(path) = &config.sqlite_path {
+        checks.push((
+            format!("open SQLite {}", path.display()),
+            PostSearch::open(path).map(drop).map_err(|e| e.to_string()),
+        ));
+    }
+    if config.embedding_url.is_some() {
+        checks.push((
+            format!("open SQLite {}", config.embedding_index.display()),
+            VectorIndex::open(&config.embedding_index)
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        ));
+    }
+
+    if let Some(url) = &config.clickhouse_url {
+        checks.push((
+            "reach ClickHouse".into(),
+            check_clickhouse(&http, url, &config.clickhouse_user, &config.clickhouse_password)
+                .await,
+        ));
+    }
+    if let Some(webhook) = &config.discord_webhook {
+        checks.push(("reach Discord webhook".into(), check_discord(&http, webhook).await));
+    }
+    if let Some(token) = &config.telegram_token {
+        checks.push(("reach Telegram bot".into(), check_telegram(&http, token).await));
+    }
+    match archive::store(config) {
+        Ok(Some(store)) => checks.push((
+            "reach archive bucket".into(),
+            check_archive(store.as_ref(), &config.archive_prefix).await,
+        )),
+        Ok(None) => {}
+        Err(e) => checks.push(("reach archive bucket".into(), Err(e.to_string()))),
+    }
+
+    let Some(path) = &config.pipelines else {
+        return checks;
+    };
+    let pipelines = match pipeline::read(path) {
+        Ok(pipelines) => pipelines,
+        Err(e) => {
+            checks.push((format!("read pipelines {}", path.display()), Err(e.to_string())));
+            return checks;
+        }
+    };
+    for pipeline in pipelines {
+        for (i, sink) in pipeline.sinks.iter().enumerate() {
+            let name = &pipeline.name;
+            let check = match sink {
+                SinkConfig::Jsonl { path, .. } => (
+                    format!("pipeline {name}/jsonl/{i}: open {}", path.display()),
+                    check_file(path),
+                ),
+                SinkConfig::Webhook { url, .. } => (
+                    format!("pipeline {name}/webhook/{i}: reach webhook"),
+                    check_webhook(&http, url).await,
+                ),
+                SinkConfig::Clickhouse {
+                    url,
+                    user,
+                    password,
+                    ..
+                } => (
+                    format!("pipeline {name}/clickhouse/{i}: reach ClickHouse"),
+                    check_clickhouse(&http, url, user, password).await,
+                ),
+                SinkConfig::Mqtt {
+                    host,
+                    port,
+                    username,
+                    password,
+                    ..
+                } => (
+                    format!("pipeline {name}/mqtt/{i}: connect to {host}:{port}"),
+                    check_mqtt(host, *port, username.as_deref(), password.as_deref()).await,
+                ),
+                SinkConfig::Nats { url, .. } => (
+                    format!("pipeline {name}/nats/{i}: connect to NATS"),
+                    check_nats(url).await,
+                ),
+            };
+            checks.push(check);
+        }
+    }
+    checks
+}
+
+/// Opens `path` for appending like the writers do. A file that doesn't exist yet is created
+/// and removed again, so the self-test leaves nothing behind.
+fn check_file(path: &Path) -> CheckResult {
+    let existed = path.exists();
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    if !existed {
+        fs::remove_file(path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Creates `dir` if needed and checks a file can be written in it. A directory that didn't
+/// exist yet is removed again, along with any parents created for it.
+fn check_dir(dir: &Path) -> CheckResult {
+    // The first missing ancestor is the top of what create_dir_all adds
+    let created = dir.ancestors().take_while(|dir| !dir.exists()).last();
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let result = check_file(&dir.join(".selftest"));
+    if let Some(created) = created {
+        fs::remove_dir_all(created).map_err(|e| e.to_string())?;
+    }
+    result
+}
+
+/// Any answer will do, since webhooks needn't accept anything but their `POST`s.
+async fn check_webhook(http: &reqwest::Client, url: &str) -> CheckResult {
+    http.head(url)
+        .send()
+        .await
+        .map(drop)
+        .map_err(|e| e.without_url().to_string())
+}
+
+async fn check_clickhouse(
+    http: &reqwest::Client,
+    url: &str,
+    user: &str,
+    password: &str,
+) -> CheckResult {
+    let response = http
+        .post(url)
+        .header("X-ClickHouse-User", user)
+        .header("X-ClickHouse-Key", password)
+        .body("SELECT 1")
+        .send()
+        .await
+        .map_err(|e| e.without_url().to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let message = response.text().await.unwrap_or_default();
+    Err(format!("{status}: {}", message.trim()))
+}
+
+/// Discord answers a `GET` on a webhook with its details, or 404 once it was deleted.
+async fn check_discord(http: &reqwest::Client, webhook: &str) -> CheckResult {
+    http.get(webhook)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map(drop)
+        // The webhook URL carries its token
+        .map_err(|e| e.without_url().to_string())
+}
+
+async fn check_telegram(http: &reqwest::Client, token: &str) -> CheckResult {
+    http.get(format!("https://api.telegram.org/bot{token}/getMe"))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map(drop)
+        // Keeps the bot token out of the logs
+        .map_err(|e| e.without_url().to_string())
+}
+
+/// Heads an object that needn't exist: not finding it still proves the bucket is reachable
+/// with the configured credentials.
+async fn check_archive(store: &dyn ObjectStore, prefix: &str) -> CheckResult {
+    let path = object_store::path::Path::from(format!("{prefix}selftest"));
+    match store.head(&path).await {
+        Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Connects until the broker acknowledges, which also checks the credentials.
+async fn check_mqtt(
+    host: &str,
+    port: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> CheckResult {
+    let mut options = MqttOptions::new("bsky-firehose-listener-selftest", host, port);
+    if let Some(username) = username {
+        options.set_credentials(username, password.unwrap_or_default());
+    }
+    // The event loop stops once its client is dropped, so keep it until connected
+    let (_client, mut events) = AsyncClient::new(options, 1);
+    let connected = async {
+        loop {
+            match events.poll().await {
+                Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
+                Ok(_) => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    };
+    timeout(PROBE_TIMEOUT, connected)
+        .await
+        .map_err(|_| format!("no answer within {PROBE_TIMEOUT:?}"))?
+}
+
+async fn check_nats(url: &str) -> CheckResult {
+    match timeout(PROBE_TIMEOUT, async_nats::connect(url)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no answer within {PROBE_TIMEOUT:?}")),
+    }
+}
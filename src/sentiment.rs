@@ -0,0 +1,195 @@
+//! Lexicon-based sentiment scoring in the style of VADER: word valences adjusted for negation,
+//! intensifiers, capitals and exclamation marks, normalized to a compound score between -1
+//! (most negative) and 1 (most positive).
+
+use std::{collections::HashMap, path::Path};
+
+/// Approximate VADER valences, between -4 and 4, of words common in posts
+const LEXICON: &[(&str, f64)] = &[
+    ("afraid", -2.2),
+    ("amazing", 2.8),
+    ("angry", -2.3),
+    ("annoying", -1.8),
+    ("awesome", 3.1),
+    ("awful", -2.0),
+    ("bad", -2.5),
+    ("beautiful", 2.9),
+    ("best", 3.2),
+    ("boring", -1.3),
+    ("broken", -1.7),
+    ("cool", 1.3),
+    ("crisis", -3.1),
+    ("cry", -2.1),
+    ("cute", 2.0),
+    ("dead", -3.3),
+    ("death", -2.9),
+    ("disappointed", -1.9),
+    ("disgusting", -2.4),
+    ("enjoy", 2.2),
+    ("excellent", 2.7),
+    ("excited", 1.4),
+    ("fail", -2.3),
+    ("fantastic", 2.6),
+    ("fear", -2.2),
+    ("fun", 2.3),
+    ("glad", 2.0),
+    ("good", 1.9),
+    ("great", 3.1),
+    ("haha", 2.0),
+    ("happy", 2.7),
+    ("hate", -2.7),
+    ("hope", 1.9),
+    ("horrible", -2.5),
+    ("hurt", -2.4),
+    ("kill", -3.7),
+    ("lol", 1.8),
+    ("lonely", -2.0),
+    ("lost", -1.3),
+    ("love", 3.2),
+    ("lovely", 2.8),
+    ("miss", -0.6),
+    ("nice", 1.8),
+    ("pain", -2.3),
+    ("peace", 2.5),
+    ("perfect", 2.7),
+    ("problem", -1.7),
+    ("sad", -2.1),
+    ("scared", -1.9),
+    ("sick", -1.9),
+    ("smile", 1.5),
+    ("sorry", -0.3),
+    ("stupid", -2.4),
+    ("terrible", -2.1),
+    ("thank", 1.5),
+    ("thanks", 1.9),
+    ("tired", -1.9),
+    ("ugly", -2.3),
+    ("war", -2.9),
+    ("win", 2.8),
+    ("wonderful", 2.7),
+    ("worry", -1.9),
+    ("worst", -3.1),
+    ("wrong", -2.1),
+];
+
+/// Words that strengthen the next sentiment-laden word
+const BOOSTERS: &[&str] = &[
+    "absolutely",
+    "completely",
+    "especially",
+    "extremely",
+    "highly",
+    "incredibly",
+    "really",
+    "so",
+    "super",
+    "totally",
+    "very",
+];
+/// Words that weaken the next sentiment-laden word
+const DAMPENERS: &[&str] = &["barely", "hardly", "slightly", "somewhat"];
+const NEGATIONS: &[&str] = &[
+    "cannot", "neither", "never", "no", "nobody", "none", "nor", "not", "nothing", "nowhere",
+];
+
+/// Valence added by a booster, or taken away by a dampener
+const BOOST: f64 = 0.293;
+/// Valence added to words written in capitals amid lowercase text
+const CAPS_BOOST: f64 = 0.733;
+/// Valence added per exclamation mark, up to four
+const EXCLAMATION_BOOST: f64 = 0.292;
+/// Negated valences are flipped and scaled by this
+const NEGATION_SCALAR: f64 = -0.74;
+/// Words before a sentiment-laden word that can modify it
+const LOOKBACK: usize = 3;
+/// Normalizes summed valences into -1..1, approaching the bounds as the sum grows
+const ALPHA: f64 = 15.0;
+
+#[derive(Debug)]
+pub struct SentimentAnalyzer {
+    lexicon: HashMap<String, f64>,
+}
+
+impl Default for SentimentAnalyzer {
+    /// Uses the small built-in lexicon.
+    fn default() -> Self {
+        Self {
+            lexicon: LEXICON
+                .iter()
+                .map(|(word, valence)| (word.to_string(), *valence))
+                .collect(),
+        }
+    }
+}
+
+impl SentimentAnalyzer {
+    /// Loads a lexicon in the format of VADER's `vader_lexicon.txt`: a word and its mean
+    /// valence, then any other tab-separated columns, on each line.
+    pub fn load_lexicon(path: &Path) -> std::io::Result<Self> {
+        let mut lexicon = HashMap::new();
+        for line in std::fs::read_to_string(path)?.lines() {
+            let mut columns = line.split('\t');
+            let (Some(word), Some(Ok(valence))) =
+                (columns.next(), columns.next().map(str::parse::<f64>))
+            else {
+                continue;
+            };
+            lexicon.insert(word.to_lowercase(), valence);
+        }
+        Ok(Self { lexicon })
+    }
+
+    /// Compound sentiment of `text`, between -1 and 1.
+    pub fn score(&self, text: &str) -> f64 {
+        let words = text
+            .split_whitespace()
+            .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric() && c != '\''))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        let shouting = words.iter().all(|word| is_capitals(word));
+        let lowercase = words
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>();
+
+        let mut sum = 0.0;
+        for (i, word) in lowercase.iter().enumerate() {
+            let Some(&valence) = self.lexicon.get(word) else {
+                continue;
+            };
+            let sign = valence.signum();
+            let mut valence = valence;
+            if !shouting && is_capitals(words[i]) {
+                valence += sign * CAPS_BOOST;
+            }
+            for (distance, before) in lowercase[i.saturating_sub(LOOKBACK)..i]
+                .iter()
+                .rev()
+                .enumerate()
+            {
+                // Modifiers further away count for a little less
+                let scale = 1.0 - 0.05 * distance as f64;
+                if BOOSTERS.contains(&before.as_str()) {
+                    valence += sign * BOOST * scale;
+                } else if DAMPENERS.contains(&before.as_str()) {
+                    valence -= sign * BOOST * scale;
+                } else if NEGATIONS.contains(&before.as_str()) || before.ends_with("n't") {
+                    valence *= NEGATION_SCALAR;
+                }
+            }
+            sum += valence;
+        }
+
+        if sum != 0.0 {
+            let exclamations = text.matches('!').count().min(4);
+            sum += sum.signum() * EXCLAMATION_BOOST * exclamations as f64;
+        }
+        sum / (sum * sum + ALPHA).sqrt()
+    }
+}
+
+/// Whether `word` is written in capitals, ignoring single letters like "I".
+fn is_capitals(word: &str) -> bool {
+    word.chars().filter(|c| c.is_alphabetic()).count() > 1
+        && !word.chars().any(|c| c.is_lowercase())
+}
@@ -0,0 +1,25 @@
+//! Embedded HTTP server hosting the listener's endpoints.
+
+use std::net::SocketAddr;
+
+use axum::Router;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::task;
+
+/// Serves `router` on `addr` in the background.
+pub fn spawn(addr: SocketAddr, router: Router) {
+    task::spawn("http", async move {
+        let result = match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                info!("Serving HTTP on {addr}");
+                axum::serve(listener, router).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("HTTP server on {addr} failed: {e}");
+        }
+    });
+}
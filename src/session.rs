@@ -0,0 +1,229 @@
+//! The bot account's session: logged in with an app password through
+//! `com.atproto.server.createSession`, refreshed through `com.atproto.server.refreshSession`
+//! when the access token expires, and optionally persisted so restarts resume it instead of
+//! logging in again.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    xrpc::{self, XrpcError},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tokens {
+    did: String,
+    handle: String,
+    access_jwt: String,
+    refresh_jwt: String,
+}
+
+/// What's written to the session file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Stored {
+    pds: String,
+    #[serde(flatten)]
+    tokens: Tokens,
+}
+
+#[derive(Debug)]
+pub struct Session {
+    http: reqwest::Client,
+    pds: String,
+    identifier: String,
+    password: String,
+    /// Where tokens are persisted; kept in memory only when unset
+    path: Option<PathBuf>,
+    tokens: RwLock<Tokens>,
+    /// Held while refreshing, so callers finding the same expired token refresh it once
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl Session {
+    /// Logs in the bot account if a bot feature will post from it, or `None` if none will.
+    pub async fn from_config(
+        config: &Config,
+        http: reqwest::Client,
+    ) -> Result<Option<Arc<Self>>, XrpcError> {
+        let bot = config.bot_action.is_some() && !config.bot_dry_run;
+        let digest = config.digest_time.is_some() && !config.digest_dry_run;
+        if !bot && !digest {
+            return Ok(None);
+        }
+        let session = Self::login(
+            http,
+            &config.bot_pds,
+            &config.bot_identifier,
+            &config.bot_password,
+            config.bot_session.clone(),
+        )
+        .await?;
+        Ok(Some(session))
+    }
+
+    /// Resumes the session stored at `path` if there is one for `pds`, or logs in with
+    /// `identifier` and `password`.
+    pub async fn login(
+        http: reqwest::Client,
+        pds: &str,
+        identifier: &str,
+        password: &str,
+        path: Option<PathBuf>,
+    ) -> Result<Arc<Self>, XrpcError> {
+        let pds = pds.trim_end_matches('/').to_string();
+        let stored = path.as_deref().and_then(|path| load(path, &pds));
+        let tokens = match stored {
+            Some(stored) => match refresh_session(&http, &pds, &stored.refresh_jwt).await {
+                Ok(tokens) => {
+                    info!("Resumed session of {}", tokens.handle);
+                    tokens
+                }
+                Err(e) => {
+                    warn!("Unable to resume stored session, logging in again: {e}");
+                    create_session(&http, &pds, identifier, password).await?
+                }
+            },
+            None => create_session(&http, &pds, identifier, password).await?,
+        };
+
+        let session = Self {
+            http,
+            pds,
+            identifier: identifier.to_string(),
+            password: password.to_string(),
+            path,
+            tokens: RwLock::new(tokens),
+            refreshing: tokio::sync::Mutex::new(()),
+        };
+        session.persist();
+        Ok(Arc::new(session))
+    }
+
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    pub fn pds(&self) -> &str {
+        &self.pds
+    }
+
+    pub fn did(&self) -> String {
+        self.tokens.read().unwrap().did.clone()
+    }
+
+    pub fn handle(&self) -> String {
+        self.tokens.read().unwrap().handle.clone()
+    }
+
+    pub fn access_jwt(&self) -> String {
+        self.tokens.read().unwrap().access_jwt.clone()
+    }
+
+    /// Replaces the expired access token `stale`, unless that already happened. Logs in again
+    /// if the refresh token expired too.
+    pub async fn refresh(&self, stale: &str) -> Result<(), XrpcError> {
+        let _refreshing = self.refreshing.lock().await;
+        if self.access_jwt() != stale {
+            return Ok(());
+        }
+
+        let refresh_jwt = self.tokens.read().unwrap().refresh_jwt.clone();
+        let tokens = match refresh_session(&self.http, &self.pds, &refresh_jwt).await {
+            Ok(tokens) => tokens,
+            Err(e) if e.is_token_error() => {
+                info!("Refresh token expired, logging in again");
+                create_session(&self.http, &self.pds, &self.identifier, &self.password).await?
+            }
+            Err(e) => return Err(e),
+        };
+        *self.tokens.write().unwrap() = tokens;
+        self.persist();
+        Ok(())
+    }
+
+    /// Writes the tokens to the session file, if any. Failing to is logged, as the session
+    /// still works for as long as the process runs.
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let stored = Stored {
+            pds: self.pds.clone(),
+            tokens: self.tokens.read().unwrap().clone(),
+        };
+        let json = serde_json::to_vec(&stored).expect("sessions are always serializable");
+        if let Err(e) = write_private(path, &json) {
+            warn!("Unable to save session to {}: {e}", path.display());
+        }
+    }
+}
+
+/// The session stored at `path`, if it's readable and for `pds`.
+fn load(path: &Path, pds: &str) -> Option<Tokens> {
+    let json = match std::fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Unable to read session from {}: {e}", path.display());
+            return None;
+        }
+    };
+    match serde_json::from_slice::<Stored>(&json) {
+        Ok(stored) if stored.pds == pds => Some(stored.tokens),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Ignoring malformed session in {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Writes to a temporary file only the owner can read first, so tokens are never exposed and
+/// a crash never leaves a truncated session behind.
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("part");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+async fn create_session(
+    http: &reqwest::Client,
+    pds: &str,
+    identifier: &str,
+    password: &str,
+) -> Result<Tokens, XrpcError> {
+    let response = http
+        .post(format!("{pds}/xrpc/com.atproto.server.createSession"))
+        .json(&json!({ "identifier": identifier, "password": password }))
+        .send()
+        .await?;
+    Ok(xrpc::check(response).await?.json().await?)
+}
+
+async fn refresh_session(
+    http: &reqwest::Client,
+    pds: &str,
+    refresh_jwt: &str,
+) -> Result<Tokens, XrpcError> {
+    let response = http
+        .post(format!("{pds}/xrpc/com.atproto.server.refreshSession"))
+        .bearer_auth(refresh_jwt)
+        .send()
+        .await?;
+    Ok(xrpc::check(response).await?.json().await?)
+}
@@ -0,0 +1,108 @@
+//! Load shedding after the relay drops us with `ConsumerTooSlow`: expensive post processing is
+//! skipped or sampled until the firehose lag is back under a threshold.
+
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use tracing::{info, warn};
+
+use crate::stats;
+
+/// What happens to expensive handlers while degraded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShedPolicy {
+    /// Keep processing everything
+    #[default]
+    Off,
+    /// Skip them entirely
+    Skip,
+    /// Only run them for this fraction of events
+    Sample(f64),
+}
+
+impl FromStr for ShedPolicy {
+    type Err = String;
+
+    /// Parses `off`, `skip` or `sample:<fraction>`, e.g. `sample:0.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "off" => Ok(Self::Off),
+            None if s == "skip" => Ok(Self::Skip),
+            Some(("sample", rate)) => match rate.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(Self::Sample(rate)),
+                _ => Err(format!("sample rate {rate:?} must be in (0, 1]")),
+            },
+            _ => Err(format!(
+                "unknown load shedding policy {s:?}, expected off, skip or sample:<fraction>"
+            )),
+        }
+    }
+}
+
+impl ShedPolicy {
+    /// Whether the `seen`-th shed candidate is kept, counting it.
+    pub fn admit(&self, seen: &AtomicU64) -> bool {
+        match self {
+            Self::Off => true,
+            Self::Skip => false,
+            Self::Sample(rate) => {
+                let every = (1.0 / rate).round() as u64;
+                seen.fetch_add(1, Ordering::Relaxed) % every == 0
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LoadShedder {
+    policy: ShedPolicy,
+    /// Degraded mode ends once commits arrive within this long of being made
+    catch_up_lag: Duration,
+    degraded: AtomicBool,
+    seen: AtomicU64,
+}
+
+impl LoadShedder {
+    pub fn new(policy: ShedPolicy, catch_up_lag: Duration) -> Self {
+        Self {
+            policy,
+            catch_up_lag,
+            ..Self::default()
+        }
+    }
+
+    /// Enters degraded mode, unless shedding is off.
+    pub fn enter(&self) {
+        if self.policy != ShedPolicy::Off && !self.degraded.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Entering degraded mode ({:?}) until we catch up",
+                self.policy
+            );
+        }
+    }
+
+    /// Leaves degraded mode once `commit` arrived within the catch-up lag.
+    pub fn record_commit(&self, commit: &Commit) {
+        if !self.degraded.load(Ordering::Relaxed) {
+            return;
+        }
+        let caught_up = stats::lag_secs(commit.time.as_str())
+            .is_some_and(|lag| lag <= self.catch_up_lag.as_secs_f64());
+        if caught_up && self.degraded.swap(false, Ordering::Relaxed) {
+            info!("Caught up with the firehose, leaving degraded mode");
+        }
+    }
+
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Whether an event should go through expensive handlers.
+    pub fn admit(&self) -> bool {
+        !self.degraded() || self.policy.admit(&self.seen)
+    }
+}
@@ -0,0 +1,421 @@
+//! Captures posts into a SQLite database with an FTS5 index over their text, so the capture
+//! can be searched directly: `search` on the command line or `/search` over HTTP. The haikus
+//! found among them are kept in a table of their own.
+//!
+//! Posts are written in batched transactions on a blocking thread, and deleted posts are
+//! removed again.
+
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use atrium_api::app::bsky::feed::post;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, warn};
+
+use crate::{client::Event, haiku::HaikuRecord};
+
+/// Writes waiting for their transaction before new ones are dropped
+const QUEUE_SIZE: usize = 10_000;
+/// Writes per transaction
+const BATCH_SIZE: usize = 500;
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS posts (
+    uri TEXT PRIMARY KEY,
+    did TEXT NOT NULL,
+    rkey TEXT NOT NULL,
+    cid TEXT,
+    text TEXT NOT NULL,
+    created_at TEXT,
+    indexed_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS posts_indexed_at ON posts (indexed_at);
+CREATE INDEX IF NOT EXISTS posts_did ON posts (did, indexed_at);
+CREATE TABLE IF NOT EXISTS haikus (
+    uri TEXT PRIMARY KEY,
+    did TEXT NOT NULL,
+    handle TEXT,
+    form TEXT NOT NULL,
+    lines TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    indexed_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS haikus_created_at ON haikus (created_at);
+CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5 (
+    text, content = 'posts', content_rowid = 'rowid'
+);
+CREATE TRIGGER IF NOT EXISTS posts_insert AFTER INSERT ON posts BEGIN
+    INSERT INTO posts_fts (rowid, text) VALUES (new.rowid, new.text);
+END;
+CREATE TRIGGER IF NOT EXISTS posts_delete AFTER DELETE ON posts BEGIN
+    INSERT INTO posts_fts (posts_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+END;
+CREATE TRIGGER IF NOT EXISTS posts_update AFTER UPDATE ON posts BEGIN
+    INSERT INTO posts_fts (posts_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+    INSERT INTO posts_fts (rowid, text) VALUES (new.rowid, new.text);
+END;
+";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteError {
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("invalid search window {0:?}, expected e.g. 30m, 6h or 1d")]
+    Window(String),
+}
+
+/// A post matching a search, best match first.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub uri: String,
+    pub did: String,
+    pub text: String,
+    pub created_at: Option<String>,
+    /// Unix milliseconds the post was captured at
+    pub indexed_at: i64,
+}
+
+/// A haiku found among the captured posts.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredHaiku {
+    pub uri: String,
+    pub did: String,
+    pub handle: Option<String>,
+    pub form: String,
+    pub lines: Vec<String>,
+    pub created_at: String,
+    /// Unix milliseconds the haiku was captured at
+    pub indexed_at: i64,
+}
+
+/// How far back a search looks, e.g. `1d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window(pub Duration);
+
+impl FromStr for Window {
+    type Err = SqliteError;
+
+    /// Parses `<count><m|h|d>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SqliteError::Window(s.to_string());
+        let unit = match s.chars().last().ok_or_else(invalid)? {
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let count: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        Ok(Self(Duration::from_secs(count * unit)))
+    }
+}
+
+enum Write {
+    Upsert {
+        uri: String,
+        did: String,
+        rkey: String,
+        cid: Option<String>,
+        text: String,
+        created_at: String,
+    },
+    Haiku {
+        uri: String,
+        did: String,
+        handle: Option<String>,
+        form: String,
+        /// Joined with newlines
+        lines: String,
+        created_at: String,
+    },
+    /// Removes the post and the haiku it may have been
+    Delete { uri: String },
+}
+
+/// Queues posts for the database in the background. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct PostCapture {
+    queue: mpsc::Sender<Write>,
+}
+
+impl PostCapture {
+    /// Opens (creating if needed) the database at `path` and starts writing to it.
+    pub fn spawn(path: &Path) -> Result<Self, SqliteError> {
+        let db = open(path)?;
+        let (queue, writes) = mpsc::channel(QUEUE_SIZE);
+        tokio::task::spawn_blocking(move || write_all(db, writes));
+        Ok(Self { queue })
+    }
+
+    /// Queues `evt` if it creates, updates or deletes a post. Posts are dropped when writes
+    /// fall too far behind.
+    pub fn push(&self, evt: &Event) {
+        if evt.collection != "app.bsky.feed.post" {
+            return;
+        }
+        let uri = format!("at://{}/{}/{}", evt.repo.as_str(), evt.collection, evt.rkey);
+        let write = if evt.action == "delete" {
+            Write::Delete { uri }
+        } else {
+            match evt.record::<post::Record>() {
+                Ok(Some(record)) => Write::Upsert {
+                    uri,
+                    did: evt.repo.as_str().to_string(),
+                    rkey: evt.rkey.clone(),
+                    cid: evt.cid.as_ref().map(|cid| cid.0.to_string()),
+                    text: record.text.clone(),
+                    created_at: record.created_at.as_str().to_string(),
+                },
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Unable to read post {uri}: {e}");
+                    return;
+                }
+            }
+        };
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(write) {
+            warn!("SQLite writes are behind, dropping a post");
+        }
+    }
+
+    /// Queues `haiku`, dropping it when writes fall too far behind. Haikus held back by
+    /// moderation are skipped, keeping them out of the GraphQL API.
+    pub fn push_haiku(&self, haiku: &HaikuRecord) {
+        if haiku.held_back {
+            return;
+        }
+        let write = Write::Haiku {
+            uri: haiku.uri.clone(),
+            did: haiku.did.clone(),
+            handle: haiku.display_handle().map(str::to_string),
+            form: haiku.form.clone(),
+            lines: haiku.lines.join("\n"),
+            created_at: haiku.created_at.clone(),
+        };
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(write) {
+            warn!("SQLite writes are behind, dropping haiku {}", haiku.uri);
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<Connection, SqliteError> {
+    let db = Connection::open(path)?;
+    db.execute_batch("PRAGMA journal_mode = WAL;")?;
+    db.execute_batch(SCHEMA)?;
+    Ok(db)
+}
+
+/// Applies writes a transaction at a time, as many as are waiting up to [`BATCH_SIZE`].
+fn write_all(mut db: Connection, mut writes: mpsc::Receiver<Write>) {
+    while let Some(first) = writes.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < BATCH_SIZE {
+            match writes.try_recv() {
+                Ok(write) => batch.push(write),
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = write_batch(&mut db, &batch) {
+            error!("Unable to write {} posts to SQLite: {e}", batch.len());
+        }
+    }
+}
+
+fn write_batch(db: &mut Connection, batch: &[Write]) -> rusqlite::Result<()> {
+    let indexed_at = Utc::now().timestamp_millis();
+    let tx = db.transaction()?;
+    for write in batch {
+        match write {
+            Write::Upsert {
+                uri,
+                did,
+                rkey,
+                cid,
+                text,
+                created_at,
+            } => {
+                tx.execute(
+                    "INSERT INTO posts (uri, did, rkey, cid, text, created_at, indexed_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT (uri) DO UPDATE SET cid = excluded.cid, text = excluded.text",
+                    params![uri, did, rkey, cid, text, created_at, indexed_at],
+                )?;
+            }
+            Write::Haiku {
+                uri,
+                did,
+                handle,
+                form,
+                lines,
+                created_at,
+            } => {
+                tx.execute(
+                    "INSERT INTO haikus (uri, did, handle, form, lines, created_at, indexed_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT (uri) DO UPDATE SET form = excluded.form, lines = excluded.lines",
+                    params![uri, did, handle, form, lines, created_at, indexed_at],
+                )?;
+            }
+            Write::Delete { uri } => {
+                tx.execute("DELETE FROM posts WHERE uri = ?1", params![uri])?;
+                tx.execute("DELETE FROM haikus WHERE uri = ?1", params![uri])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Searches posts captured in `path` for the FTS5 `query`, e.g. `rust` or `"rust lang" OR
+/// ferris`, optionally only those captured within `window`.
+#[derive(Debug, Clone)]
+pub struct PostSearch {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl PostSearch {
+    pub fn open(path: &Path) -> Result<Self, SqliteError> {
+        Ok(Self {
+            db: Arc::new(Mutex::new(open(path)?)),
+        })
+    }
+
+    pub fn search(
+        &self,
+        query: &str,
+        window: Option<Window>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, SqliteError> {
+        let since = window.map_or(0, |Window(window)| {
+            Utc::now().timestamp_millis() - window.as_millis() as i64
+        });
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare_cached(
+            "SELECT posts.uri, posts.did, posts.text, posts.created_at, posts.indexed_at
+             FROM posts_fts JOIN posts ON posts.rowid = posts_fts.rowid
+             WHERE posts_fts MATCH ?1 AND posts.indexed_at >= ?2
+             ORDER BY rank LIMIT ?3",
+        )?;
+        let hits = statement
+            // A negative LIMIT is no limit
+            .query_map(
+                params![query, since, i64::try_from(limit).unwrap_or(-1)],
+                |row| {
+                    Ok(SearchHit {
+                        uri: row.get(0)?,
+                        did: row.get(1)?,
+                        text: row.get(2)?,
+                        created_at: row.get(3)?,
+                        indexed_at: row.get(4)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hits)
+    }
+
+    /// Posts by `did`, most recently captured first.
+    pub fn by_author(&self, did: &str, limit: usize) -> Result<Vec<SearchHit>, SqliteError> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare_cached(
+            "SELECT uri, did, text, created_at, indexed_at FROM posts
+             WHERE did = ?1 ORDER BY indexed_at DESC LIMIT ?2",
+        )?;
+        let posts = statement
+            .query_map(params![did, i64::try_from(limit).unwrap_or(-1)], |row| {
+                Ok(SearchHit {
+                    uri: row.get(0)?,
+                    did: row.get(1)?,
+                    text: row.get(2)?,
+                    created_at: row.get(3)?,
+                    indexed_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(posts)
+    }
+
+    /// Haikus created from `from` up to `to` (RFC 3339 times or dates, compared as text), of
+    /// `form` if given, newest first.
+    pub fn haikus(
+        &self,
+        from: Option<&str>,
+        to: Option<&str>,
+        form: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<StoredHaiku>, SqliteError> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare_cached(
+            "SELECT uri, did, handle, form, lines, created_at, indexed_at FROM haikus
+             WHERE (?1 IS NULL OR created_at >= ?1) AND (?2 IS NULL OR created_at < ?2)
+                 AND (?3 IS NULL OR form = ?3)
+             ORDER BY created_at DESC LIMIT ?4",
+        )?;
+        let haikus = statement
+            .query_map(
+                params![from, to, form, i64::try_from(limit).unwrap_or(-1)],
+                |row| {
+                    Ok(StoredHaiku {
+                        uri: row.get(0)?,
+                        did: row.get(1)?,
+                        handle: row.get(2)?,
+                        form: row.get(3)?,
+                        lines: row
+                            .get::<_, String>(4)?
+                            .split('\n')
+                            .map(str::to_string)
+                            .collect(),
+                        created_at: row.get(5)?,
+                        indexed_at: row.get(6)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(haikus)
+    }
+
+    /// `/search?q=<query>&since=<window>&limit=<n>` returns the matching [`SearchHit`]s.
+    pub fn routes(self) -> Router {
+        Router::new().route("/search", get(search)).with_state(self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: String,
+    since: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn search(
+    State(posts): State<PostSearch>,
+    Query(query): Query<SearchQuery>,
+) -> axum::response::Response {
+    let window = match query.since.as_deref().map(str::parse::<Window>).transpose() {
+        Ok(window) => window,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let hits = tokio::task::spawn_blocking(move || posts.search(&query.q, window, limit))
+        .await
+        .expect("searches don't panic");
+    match hits {
+        Ok(hits) => Json(hits).into_response(),
+        // Mostly FTS5 syntax errors in the query
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
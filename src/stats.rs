@@ -0,0 +1,247 @@
+//! Throughput and ingest lag statistics, logged periodically.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use atrium_api::{com::atproto::sync::subscribe_repos::Commit, types::string::Datetime};
+use chrono::{DateTime, Utc};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{task, telemetry::Metrics};
+
+/// Collections listed in each log line
+const TOP_COLLECTIONS: usize = 5;
+
+#[derive(Debug)]
+pub struct Stats {
+    window: Mutex<Window>,
+    /// Commit lag above which we're considered to be falling behind
+    lag_warning: Option<Duration>,
+    /// Whether the latest commit was over `lag_warning`, so crossing it is only logged once
+    lagging: AtomicBool,
+}
+
+/// Counters accumulated since the last report.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub started_at: Instant,
+    pub frames: u64,
+    pub decode_errors: u64,
+    /// Commits dropped because the work queue was too deep or over its memory bound
+    pub shed: u64,
+    /// Repo operations per collection
+    pub collections: HashMap<String, u64>,
+    /// Wall clock minus `commit.time`
+    pub commit_lag: Lag,
+    /// Wall clock minus post `createdAt`
+    pub created_at_lag: Lag,
+    /// Compound sentiment of posts passing the filters, when scored
+    pub sentiment: Average,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lag {
+    pub count: u64,
+    pub total_secs: f64,
+    pub max_secs: f64,
+}
+
+impl Lag {
+    fn record(&mut self, lag: f64) {
+        self.count += 1;
+        self.total_secs += lag;
+        self.max_secs = self.max_secs.max(lag);
+    }
+
+    pub fn average_secs(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_secs / self.count as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Average {
+    pub count: u64,
+    pub total: f64,
+}
+
+impl Average {
+    fn record(&mut self, value: f64) {
+        self.count += 1;
+        self.total += value;
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total / self.count as f64
+        }
+    }
+}
+
+/// Seconds between the RFC 3339 timestamp `time` and now.
+pub fn lag_secs(time: &str) -> Option<f64> {
+    let time = DateTime::parse_from_rfc3339(time).ok()?;
+    Some((Utc::now() - time.to_utc()).num_milliseconds() as f64 / 1000.0)
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            frames: 0,
+            decode_errors: 0,
+            shed: 0,
+            collections: HashMap::new(),
+            commit_lag: Lag::default(),
+            created_at_lag: Lag::default(),
+            sentiment: Average::default(),
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Stats {
+    /// Warns whenever commit lag rises above `lag_warning`, and again once it's back under.
+    pub fn new(lag_warning: Option<Duration>) -> Self {
+        Self {
+            window: Mutex::new(Window::new()),
+            lag_warning,
+            lagging: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record_frame(&self) {
+        self.window.lock().unwrap().frames += 1;
+    }
+
+    pub fn record_decode_error(&self) {
+        self.window.lock().unwrap().decode_errors += 1;
+    }
+
+    pub fn record_shed(&self, count: u64) {
+        self.window.lock().unwrap().shed += count;
+    }
+
+    pub fn record_commit(&self, commit: &Commit) {
+        let lag = lag_secs(commit.time.as_str());
+        if let Some(lag) = lag {
+            Metrics::get().record_commit_lag(lag);
+            self.check_lag(lag);
+        }
+
+        let mut window = self.window.lock().unwrap();
+        if let Some(lag) = lag {
+            window.commit_lag.record(lag);
+        }
+        for operation in &commit.ops {
+            let collection = operation
+                .path
+                .split_once('/')
+                .map_or(operation.path.as_str(), |(collection, _)| collection);
+            match window.collections.get_mut(collection) {
+                Some(count) => *count += 1,
+                None => {
+                    window.collections.insert(collection.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    pub fn record_created_at(&self, created_at: &Datetime) {
+        let Some(lag) = lag_secs(created_at.as_str()) else {
+            return;
+        };
+        Metrics::get().record_created_at_lag(lag);
+        self.window.lock().unwrap().created_at_lag.record(lag);
+    }
+
+    fn check_lag(&self, lag: f64) {
+        let Some(threshold) = self.lag_warning else {
+            return;
+        };
+        let lagging = lag > threshold.as_secs_f64();
+        if self.lagging.swap(lagging, Ordering::Relaxed) == lagging {
+            return;
+        }
+        if lagging {
+            warn!(
+                "Falling behind the firehose: commits arrive {lag:.1}s after the relay saw them."
+            );
+        } else {
+            info!("Caught up with the firehose, commit lag is {lag:.1}s.");
+        }
+    }
+
+    pub fn record_sentiment(&self, score: f64) {
+        self.window.lock().unwrap().sentiment.record(score);
+    }
+
+    /// Returns the counters accumulated so far and starts a new window.
+    pub fn take(&self) -> Window {
+        std::mem::replace(&mut *self.window.lock().unwrap(), Window::new())
+    }
+
+    /// Logs and resets the counters every `interval` in the background.
+    pub fn log_every(self: Arc<Self>, interval: Duration) {
+        task::spawn("stats", async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                log(&self.take());
+            }
+        });
+    }
+}
+
+fn log(window: &Window) {
+    let elapsed = window.started_at.elapsed().as_secs_f64().max(f64::EPSILON);
+    let error_rate = if window.frames == 0 {
+        0.0
+    } else {
+        window.decode_errors as f64 / window.frames as f64
+    };
+
+    let mut collections = window.collections.iter().collect::<Vec<_>>();
+    collections.sort_unstable_by(|a, b| b.1.cmp(a.1));
+    let top = collections
+        .iter()
+        .take(TOP_COLLECTIONS)
+        .map(|(collection, count)| format!("{collection}={:.1}/s", **count as f64 / elapsed))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let sentiment = if window.sentiment.count == 0 {
+        String::new()
+    } else {
+        format!(", sentiment {:+.2} avg", window.sentiment.average())
+    };
+
+    info!(
+        "{:.1} frames/s, {:.2}% decode errors, {} shed, commit lag {:.1}s avg / {:.1}s max, createdAt lag {:.1}s avg{sentiment}. {top}",
+        window.frames as f64 / elapsed,
+        error_rate * 100.0,
+        window.shed,
+        window.commit_lag.average_secs(),
+        window.commit_lag.max_secs,
+        window.created_at_lag.average_secs(),
+    );
+}
@@ -0,0 +1,133 @@
+//! Firehose events as an async [`Stream`], for embedding the listener in another tokio app.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    client::{Client, Event},
+    config::Config,
+    frame::FrameError,
+    task,
+};
+
+/// What [`Firehose::subscribe`] streams.
+#[derive(Debug, Clone)]
+pub struct SubscribeOptions {
+    pub config: Config,
+    /// Collection NSIDs or globs (see [`Client::on`]); every collection when empty
+    pub collections: Vec<String>,
+    /// Sequence number to start from instead of the live tip
+    pub cursor: Option<i64>,
+    /// Events buffered before the firehose is read more slowly
+    pub buffer: usize,
+}
+
+impl SubscribeOptions {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            collections: Vec::new(),
+            cursor: None,
+            buffer: 1024,
+        }
+    }
+
+    pub fn collection(mut self, pattern: &str) -> Self {
+        self.collections.push(pattern.to_string());
+        self
+    }
+
+    pub fn cursor(mut self, seq: i64) -> Self {
+        self.cursor = Some(seq);
+        self
+    }
+}
+
+pub struct Firehose;
+
+impl Firehose {
+    /// Streams repo operations matching `options`, reconnecting and resuming from the last
+    /// seen cursor whenever the connection drops. Frames that fail to decode are yielded as
+    /// errors and the stream carries on.
+    ///
+    /// Dropping the stream disconnects from the firehose.
+    ///
+    /// ```no_run
+    /// # async fn example() {
+    /// use bsky_firehose_listener::{
+    ///     config::Config,
+    ///     subscription::{Firehose, SubscribeOptions},
+    /// };
+    /// use futures_util::StreamExt;
+    ///
+    /// let options = SubscribeOptions::new(Config::from_env()).collection("app.bsky.feed.post");
+    /// let mut stream = Firehose::subscribe(options);
+    /// while let Some(evt) = stream.next().await {
+    ///     match evt {
+    ///         Ok(evt) => println!("{} {}/{}", evt.action, evt.collection, evt.rkey),
+    ///         Err(e) => eprintln!("{e}"),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn subscribe(options: SubscribeOptions) -> Subscription {
+        let (tx, rx) = mpsc::channel(options.buffer.max(1));
+
+        let mut client = Client::new(options.config);
+        if let Some(cursor) = options.cursor {
+            client.cursor(cursor);
+        }
+        let patterns = if options.collections.is_empty() {
+            vec!["*".to_string()]
+        } else {
+            options.collections
+        };
+        for pattern in &patterns {
+            let tx = tx.clone();
+            client.on(pattern, move |evt| {
+                let tx = tx.clone();
+                async move {
+                    // The receiver only goes away when the subscription is dropped, which also
+                    // stops the client
+                    let _ = tx.send(Ok(evt)).await;
+                }
+            });
+        }
+        client.on_error(move |e| {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+
+        Subscription {
+            rx,
+            task: task::spawn("subscription", client.run()),
+        }
+    }
+}
+
+/// Stream returned by [`Firehose::subscribe`].
+pub struct Subscription {
+    rx: mpsc::Receiver<Result<Event, FrameError>>,
+    task: JoinHandle<()>,
+}
+
+impl Stream for Subscription {
+    type Item = Result<Event, FrameError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,157 @@
+//! Syllable counting, either estimated from spelling or looked up in the CMU pronouncing
+//! dictionary.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::rhyme::{self, spelled_rhyme, Rhyme};
+
+#[derive(Debug, Default)]
+pub enum SyllableCounter {
+    /// Count vowel groups, see [`estimate`]
+    #[default]
+    Estimate,
+    /// Look words up in the CMU pronouncing dictionary, estimating words it doesn't know
+    CmuDict(HashMap<String, Pronunciation>),
+}
+
+/// A word's entry in the CMU pronouncing dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pronunciation {
+    pub syllables: usize,
+    /// Phonemes from the last stressed vowel on, without stress markers; words rhyme when
+    /// these are equal
+    pub rhyme: String,
+}
+
+impl SyllableCounter {
+    /// Loads a CMU pronouncing dictionary (`cmudict-0.7b` or `cmudict.dict` format).
+    ///
+    /// Syllables are counted as the number of stressed phonemes (those ending in a digit).
+    /// Only the first pronunciation of each word is kept.
+    pub fn load_cmudict(path: &Path) -> std::io::Result<Self> {
+        // Older releases aren't valid UTF-8
+        let contents = std::fs::read(path)?;
+        let contents = String::from_utf8_lossy(&contents);
+
+        let mut dict = HashMap::new();
+        for line in contents.lines() {
+            if line.starts_with(";;;") {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let Some(word) = parts.next() else {
+                continue;
+            };
+            // Alternate pronunciations look like `word(1)`
+            if word.ends_with(')') {
+                continue;
+            }
+
+            let phonemes = parts
+                .take_while(|phoneme| !phoneme.starts_with('#'))
+                .collect::<Vec<_>>();
+            let is_vowel = |phoneme: &&str| phoneme.ends_with(|c: char| c.is_ascii_digit());
+            let syllables = phonemes.iter().filter(|phoneme| is_vowel(phoneme)).count();
+            let stressed = phonemes
+                .iter()
+                .rposition(|phoneme| phoneme.ends_with(['1', '2']))
+                .or_else(|| phonemes.iter().rposition(is_vowel))
+                .unwrap_or(0);
+            let rhyme = phonemes[stressed..]
+                .iter()
+                .map(|phoneme| phoneme.trim_end_matches(|c: char| c.is_ascii_digit()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            dict.entry(word.to_lowercase()).or_insert(Pronunciation {
+                syllables: syllables.max(1),
+                rhyme,
+            });
+        }
+
+        Ok(Self::CmuDict(dict))
+    }
+
+    pub fn count(&self, word: &str) -> usize {
+        match self {
+            Self::Estimate => estimate(word),
+            Self::CmuDict(dict) => {
+                let word = word.to_lowercase().replace('’', "'");
+                if let Some(pronunciation) = dict.get(&word) {
+                    return pronunciation.syllables;
+                }
+                word.split('-')
+                    .filter(|part| !part.is_empty())
+                    .map(|part| {
+                        dict.get(part)
+                            .map_or_else(|| estimate(part), |pronunciation| pronunciation.syllables)
+                    })
+                    .sum::<usize>()
+                    .max(1)
+            }
+        }
+    }
+
+    /// What `word` ends with for rhyming purposes: its dictionary phonemes when known, and
+    /// its spelling from its last vowel sound (see [`spelled_rhyme`]).
+    pub fn rhyme(&self, word: &str) -> Rhyme {
+        let word = rhyme::normalize(word);
+        let phonemes = match self {
+            Self::Estimate => None,
+            Self::CmuDict(dict) => dict
+                .get(&word)
+                .map(|pronunciation| pronunciation.rhyme.clone()),
+        };
+        Rhyme {
+            phonemes,
+            spelled: spelled_rhyme(&word),
+        }
+    }
+}
+
+/// Estimates the number of syllables in an English word by counting vowel groups.
+pub fn estimate(word: &str) -> usize {
+    word.to_ascii_lowercase()
+        .replace(['\'', '’'], "")
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .map(part_syllables)
+        .sum::<usize>()
+        .max(1)
+}
+
+fn part_syllables(word: &str) -> usize {
+    let is_vowel = |c: u8| b"aeiouy".contains(&c);
+    let bytes = word.as_bytes();
+
+    let mut count = 0;
+    let mut previous_vowel = false;
+    for &c in bytes {
+        let vowel = is_vowel(c);
+        if vowel && !previous_vowel {
+            count += 1;
+        }
+        previous_vowel = vowel;
+    }
+
+    let len = bytes.len();
+    let before = |suffix: usize| (len > suffix).then(|| bytes[len - suffix - 1]);
+    let silent = if word.ends_with("le") {
+        // "whale", but not "table"
+        before(2).is_some_and(is_vowel)
+    } else if word.ends_with('e') {
+        !word.ends_with("ee")
+    } else if word.ends_with("ed") {
+        // "jumped", but not "wanted"
+        before(2).is_some_and(|c| !matches!(c, b't' | b'd'))
+    } else if word.ends_with("es") {
+        // "makes", but not "boxes"
+        before(2).is_some_and(|c| !matches!(c, b's' | b'x' | b'z' | b'h' | b'c' | b'g'))
+    } else {
+        false
+    };
+    if silent && count > 1 {
+        count -= 1;
+    }
+
+    count.max(1)
+}
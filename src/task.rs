@@ -0,0 +1,27 @@
+//! Spawning long-lived tasks under a name, so they can be told apart in
+//! [tokio-console](https://github.com/tokio-rs/console) when built with the `console` feature
+//! (and `RUSTFLAGS="--cfg tokio_unstable"`, which tokio requires for task names).
+
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+
+/// Like [`tokio::spawn`], naming the task `name` when console support is built in.
+#[track_caller]
+pub fn spawn<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "console", tokio_unstable))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("Unable to spawn task");
+
+    #[cfg(not(all(feature = "console", tokio_unstable)))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
@@ -0,0 +1,213 @@
+//! Pushes notifications to a Telegram chat through the Bot API.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
+use tracing::warn;
+
+use crate::{identity::HandleResolver, notify::Notification, ratelimit::RateLimiter, task};
+
+/// Notifications waiting to be sent before new ones are dropped
+const QUEUE_SIZE: usize = 200;
+/// Telegram allows about 20 messages per minute in a group
+const MIN_INTERVAL: Duration = Duration::from_secs(3);
+/// Most notifications combined into one message when they pile up
+const MAX_BATCH: usize = 20;
+/// Longest message Telegram accepts, in UTF-16 code units
+const MAX_MESSAGE_LEN: usize = 4096;
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TelegramError {
+    #[error("HTTP request failed: {0}")]
+    Http(reqwest::Error),
+    #[error("Bot API error: {0}")]
+    Api(String),
+    #[error("still rate limited after {MAX_ATTEMPTS} attempts")]
+    RateLimited,
+}
+
+impl From<reqwest::Error> for TelegramError {
+    /// Keeps the request URL, and with it the bot token, out of the error and the logs.
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e.without_url())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    ok: bool,
+    description: Option<String>,
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseParameters {
+    retry_after: Option<u64>,
+}
+
+/// Sends notifications in the background, rendered with a template. Notifications arriving
+/// faster than Telegram's rate limit allows are batched into a single message.
+pub struct Telegram {
+    queue: mpsc::Sender<Notification>,
+}
+
+impl Telegram {
+    /// Sends to `chat_id`, further limited by `limiter` if given. A notification over the limit
+    /// is held or dropped, not batched.
+    pub fn spawn(
+        http: reqwest::Client,
+        handles: HandleResolver,
+        token: &str,
+        chat_id: String,
+        template: String,
+        limiter: Option<RateLimiter>,
+    ) -> Self {
+        let (queue, notifications) = mpsc::channel(QUEUE_SIZE);
+        let endpoint = format!("https://api.telegram.org/bot{token}/sendMessage");
+        task::spawn(
+            "telegram",
+            run(
+                http,
+                handles,
+                endpoint,
+                chat_id,
+                template,
+                limiter,
+                notifications,
+            ),
+        );
+        Self { queue }
+    }
+
+    /// Queues `notification`, dropping it if Telegram is too far behind.
+    pub fn notify(&self, notification: Notification) {
+        if let Err(TrySendError::Full(notification)) = self.queue.try_send(notification) {
+            warn!(
+                "Telegram is behind, dropping notification for {}",
+                notification.url
+            );
+        }
+    }
+}
+
+async fn run(
+    http: reqwest::Client,
+    handles: HandleResolver,
+    endpoint: String,
+    chat_id: String,
+    template: String,
+    limiter: Option<RateLimiter>,
+    mut notifications: mpsc::Receiver<Notification>,
+) {
+    let mut next_send = Instant::now();
+    while let Some(first) = notifications.recv().await {
+        if let Some(limiter) = &limiter {
+            if !limiter.admit().await {
+                warn!(
+                    "Telegram rate limit reached, dropping notification for {}",
+                    first.url
+                );
+                continue;
+            }
+        }
+        tokio::time::sleep_until(next_send).await;
+
+        // Whatever arrived while waiting goes out together
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match notifications.try_recv() {
+                Ok(notification) => batch.push(notification),
+                Err(_) => break,
+            }
+        }
+
+        let mut rendered = Vec::with_capacity(batch.len());
+        for notification in &mut batch {
+            notification.resolve_handle(&handles).await;
+            rendered.push(notification.render(&template));
+        }
+        for text in messages(rendered) {
+            if let Err(e) = send(&http, &endpoint, &chat_id, &text, batch.len() > 1).await {
+                warn!("Unable to notify Telegram of {} posts: {e}", batch.len());
+            }
+        }
+        next_send = Instant::now() + MIN_INTERVAL;
+    }
+}
+
+/// Joins rendered notifications into as few messages as fit Telegram's length limit,
+/// truncating any single notification that is too long on its own.
+fn messages(rendered: Vec<String>) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current = String::new();
+    for mut text in rendered {
+        if utf16_len(&text) > MAX_MESSAGE_LEN {
+            while utf16_len(&text) > MAX_MESSAGE_LEN - 1 {
+                text.pop();
+            }
+            text.push('…');
+        }
+        if !current.is_empty() && utf16_len(&current) + 2 + utf16_len(&text) > MAX_MESSAGE_LEN {
+            messages.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(&text);
+    }
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+async fn send(
+    http: &reqwest::Client,
+    endpoint: &str,
+    chat_id: &str,
+    text: &str,
+    batched: bool,
+) -> Result<(), TelegramError> {
+    let body = json!({
+        "chat_id": chat_id,
+        "text": text,
+        // A batch would only preview its first link
+        "link_preview_options": { "is_disabled": batched },
+    });
+    for _ in 0..MAX_ATTEMPTS {
+        let response = http
+            .post(endpoint)
+            .json(&body)
+            .send()
+            .await?
+            .json::<ApiResponse>()
+            .await?;
+        if response.ok {
+            return Ok(());
+        }
+        match response
+            .parameters
+            .and_then(|parameters| parameters.retry_after)
+        {
+            Some(retry_after) => tokio::time::sleep(Duration::from_secs(retry_after)).await,
+            None => {
+                return Err(TelegramError::Api(
+                    response
+                        .description
+                        .unwrap_or_else(|| "unknown error".to_string()),
+                ))
+            }
+        }
+    }
+    Err(TelegramError::RateLimited)
+}
@@ -0,0 +1,233 @@
+//! OpenTelemetry trace and metric export over OTLP.
+//!
+//! Export is enabled by setting `OTEL_EXPORTER_OTLP_ENDPOINT`; the rest of the standard
+//! `OTEL_*` variables (headers, timeouts, `OTEL_TRACES_SAMPLER`, `OTEL_SERVICE_NAME`, ...) are
+//! honoured by the exporter as usual. Pipeline stages are traced through ordinary `tracing`
+//! spans, so they also show up in JSON logs.
+
+use std::{sync::OnceLock, time::Duration};
+
+use opentelemetry::{
+    global,
+    metrics::{Counter, Gauge, Histogram, MetricsError},
+    trace::{TraceError, TracerProvider},
+    KeyValue,
+};
+use opentelemetry_sdk::{runtime, trace::Tracer};
+use thiserror::Error;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+const SERVICE: &str = "bsky-firehose-listener";
+
+#[derive(Debug, Error)]
+pub enum TelemetryError {
+    #[error("unable to install OTLP trace exporter: {0}")]
+    Trace(#[from] TraceError),
+    #[error("unable to install OTLP metric exporter: {0}")]
+    Metrics(#[from] MetricsError),
+}
+
+/// Installs the global OTLP meter provider and returns a layer exporting spans, or `None` when
+/// no OTLP endpoint is configured.
+///
+/// Must be called from within a tokio runtime, before [`Metrics::get`] is first used.
+pub fn init<S>() -> Result<Option<OpenTelemetryLayer<S, Tracer>>, TelemetryError>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(None);
+    }
+
+    let meter_provider = opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .build()?;
+    global::set_meter_provider(meter_provider);
+
+    let tracer_provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .install_batch(runtime::Tokio)?;
+    let tracer = tracer_provider.tracer(SERVICE);
+    global::set_tracer_provider(tracer_provider);
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Pipeline counters and histograms. These are no-ops unless [`init`] installed an exporter.
+pub struct Metrics {
+    frames: Counter<u64>,
+    decode_errors: Counter<u64>,
+    ops: Counter<u64>,
+    duplicates: Counter<u64>,
+    sink_writes: Counter<u64>,
+    fanout_drops: Counter<u64>,
+    post_languages: Counter<u64>,
+    emoji_top: Gauge<u64>,
+    emoji_only_posts: Counter<u64>,
+    pipeline_deliveries: Counter<u64>,
+    pipeline_drops: Counter<u64>,
+    pipeline_sink_up: Gauge<u64>,
+    rev_violations: Counter<u64>,
+    ping_rtt: Histogram<f64>,
+    commit_lag: Histogram<f64>,
+    created_at_lag: Histogram<f64>,
+}
+
+impl Metrics {
+    pub fn get() -> &'static Self {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(|| {
+            let meter = global::meter(SERVICE);
+            Self {
+                frames: meter
+                    .u64_counter("firehose.frames")
+                    .with_description("Frames received from the relay")
+                    .init(),
+                decode_errors: meter
+                    .u64_counter("firehose.decode_errors")
+                    .with_description(
+                        "Frames, CAR files and records that could not be decoded, by kind",
+                    )
+                    .init(),
+                ops: meter
+                    .u64_counter("firehose.ops")
+                    .with_description("Repo operations dispatched to handlers")
+                    .init(),
+                duplicates: meter
+                    .u64_counter("firehose.duplicates")
+                    .with_description("Repo operations dropped as already delivered")
+                    .init(),
+                sink_writes: meter
+                    .u64_counter("firehose.sink_writes")
+                    .with_description("Records written to an output file")
+                    .init(),
+                fanout_drops: meter
+                    .u64_counter("firehose.fanout_drops")
+                    .with_description("Events skipped by fan-out consumers that fell behind")
+                    .init(),
+                post_languages: meter
+                    .u64_counter("firehose.post_languages")
+                    .with_description("Posts by detected language")
+                    .init(),
+                emoji_top: meter
+                    .u64_gauge("firehose.emoji_top")
+                    .with_description("Posts using each of the most used emoji within a window")
+                    .init(),
+                emoji_only_posts: meter
+                    .u64_counter("firehose.emoji_only_posts")
+                    .with_description("Posts made up of nothing but emoji")
+                    .init(),
+                pipeline_deliveries: meter
+                    .u64_counter("firehose.pipeline_deliveries")
+                    .with_description("Events delivered to or given up on by pipeline sinks")
+                    .init(),
+                pipeline_drops: meter
+                    .u64_counter("firehose.pipeline_drops")
+                    .with_description("Events dropped because a pipeline sink's queue was full")
+                    .init(),
+                pipeline_sink_up: meter
+                    .u64_gauge("firehose.pipeline_sink_up")
+                    .with_description("Whether a pipeline sink's latest delivery succeeded")
+                    .init(),
+                rev_violations: meter
+                    .u64_counter("firehose.rev_violations")
+                    .with_description("Commits out of rev order with their repo's previous one")
+                    .init(),
+                ping_rtt: meter
+                    .f64_histogram("firehose.ping_rtt")
+                    .with_unit("s")
+                    .with_description("Round trip of websocket pings to the relay")
+                    .init(),
+                commit_lag: meter
+                    .f64_histogram("firehose.commit_lag")
+                    .with_unit("s")
+                    .with_description("Time between the relay seeing a commit and us receiving it")
+                    .init(),
+                created_at_lag: meter
+                    .f64_histogram("firehose.created_at_lag")
+                    .with_unit("s")
+                    .with_description("Time between a post's createdAt and us receiving it")
+                    .init(),
+            }
+        })
+    }
+
+    pub fn record_frame(&self) {
+        self.frames.add(1, &[]);
+    }
+
+    /// Counts a decode failure of [`FrameError::kind`](crate::frame::FrameError::kind) `kind`.
+    pub fn record_decode_error(&self, kind: &'static str) {
+        self.decode_errors.add(1, &[KeyValue::new("kind", kind)]);
+    }
+
+    pub fn record_op(&self, collection: &str) {
+        self.ops
+            .add(1, &[KeyValue::new("collection", collection.to_string())]);
+    }
+
+    pub fn record_duplicate(&self) {
+        self.duplicates.add(1, &[]);
+    }
+
+    pub fn record_sink_write(&self, ok: bool) {
+        self.sink_writes.add(1, &[KeyValue::new("ok", ok)]);
+    }
+
+    pub fn record_fanout_drop(&self, consumer: &str, skipped: u64) {
+        self.fanout_drops
+            .add(skipped, &[KeyValue::new("consumer", consumer.to_string())]);
+    }
+
+    pub fn record_post_language(&self, language: &str) {
+        self.post_languages
+            .add(1, &[KeyValue::new("language", language.to_string())]);
+    }
+
+    /// Only the top emoji of each report are recorded, as emoji sequences are unbounded and
+    /// every one would otherwise become its own series.
+    pub fn record_top_emoji(&self, window: &'static str, emoji: &str, posts: u64) {
+        self.emoji_top.record(
+            posts,
+            &[
+                KeyValue::new("window", window),
+                KeyValue::new("emoji", emoji.to_string()),
+            ],
+        );
+    }
+
+    pub fn record_emoji_only_post(&self) {
+        self.emoji_only_posts.add(1, &[]);
+    }
+
+    pub fn record_pipeline_delivery(&self, sink: &str, ok: bool) {
+        let sink = KeyValue::new("sink", sink.to_string());
+        self.pipeline_deliveries
+            .add(1, &[sink.clone(), KeyValue::new("ok", ok)]);
+        self.pipeline_sink_up.record(u64::from(ok), &[sink]);
+    }
+
+    pub fn record_pipeline_drop(&self, sink: &str) {
+        self.pipeline_drops
+            .add(1, &[KeyValue::new("sink", sink.to_string())]);
+    }
+
+    pub fn record_rev_violation(&self, kind: &'static str) {
+        self.rev_violations.add(1, &[KeyValue::new("kind", kind)]);
+    }
+
+    pub fn record_ping_rtt(&self, rtt: Duration) {
+        self.ping_rtt.record(rtt.as_secs_f64(), &[]);
+    }
+
+    pub fn record_commit_lag(&self, secs: f64) {
+        self.commit_lag.record(secs, &[]);
+    }
+
+    pub fn record_created_at_lag(&self, secs: f64) {
+        self.created_at_lag.record(secs, &[]);
+    }
+}
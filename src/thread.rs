@@ -0,0 +1,149 @@
+//! Reply-thread context: parent/root references of replies, and an in-memory index to look up
+//! replies without hitting the AppView, served at `/thread`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use atrium_api::app::bsky::feed::post;
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+/// The posts a reply is responding to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Reply {
+    /// `at://` URI of the post being replied to
+    pub parent: String,
+    /// `at://` URI of the post starting the thread
+    pub root: String,
+}
+
+impl Reply {
+    pub fn from_record(record: &post::Record) -> Option<Self> {
+        let reply = record.reply.as_ref()?;
+        Some(Self {
+            parent: reply.parent.uri.clone(),
+            root: reply.root.uri.clone(),
+        })
+    }
+}
+
+/// Index of the most recent replies seen, by parent and by thread root.
+///
+/// ```
+/// use bsky_firehose_listener::thread::{Reply, ThreadIndex};
+///
+/// let index = ThreadIndex::new(100_000);
+/// index.insert(
+///     "at://did:plc:b/app.bsky.feed.post/2",
+///     Reply {
+///         parent: "at://did:plc:a/app.bsky.feed.post/1".into(),
+///         root: "at://did:plc:a/app.bsky.feed.post/1".into(),
+///     },
+/// );
+/// assert_eq!(
+///     index.replies("at://did:plc:a/app.bsky.feed.post/1"),
+///     ["at://did:plc:b/app.bsky.feed.post/2"]
+/// );
+/// ```
+#[derive(Debug)]
+pub struct ThreadIndex {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    by_parent: HashMap<String, Vec<String>>,
+    by_root: HashMap<String, Vec<String>>,
+    /// Insertion order, so the oldest replies are evicted first
+    order: VecDeque<(String, Reply)>,
+}
+
+impl ThreadIndex {
+    /// Creates an index remembering at most `capacity` replies.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn insert(&self, uri: &str, reply: Reply) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .by_parent
+            .entry(reply.parent.clone())
+            .or_default()
+            .push(uri.to_string());
+        inner
+            .by_root
+            .entry(reply.root.clone())
+            .or_default()
+            .push(uri.to_string());
+        inner.order.push_back((uri.to_string(), reply));
+
+        while inner.order.len() > self.capacity {
+            let Some((uri, reply)) = inner.order.pop_front() else {
+                break;
+            };
+            remove(&mut inner.by_parent, &reply.parent, &uri);
+            remove(&mut inner.by_root, &reply.root, &uri);
+        }
+    }
+
+    /// Direct replies to `parent`, oldest first.
+    pub fn replies(&self, parent: &str) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.by_parent.get(parent).cloned().unwrap_or_default()
+    }
+
+    /// Every reply in the thread started by `root`, oldest first.
+    pub fn thread(&self, root: &str) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.by_root.get(root).cloned().unwrap_or_default()
+    }
+
+    /// `/thread?uri=<at-uri>` returns the [`ThreadReport`] of that post.
+    pub fn routes(self: Arc<Self>) -> Router {
+        Router::new().route("/thread", get(thread)).with_state(self)
+    }
+}
+
+/// Replies known for a post.
+#[derive(Debug, Serialize)]
+pub struct ThreadReport {
+    /// Direct replies to the post, oldest first
+    pub replies: Vec<String>,
+    /// Every reply in the thread the post starts, oldest first; empty unless it is a root
+    pub thread: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ThreadQuery {
+    uri: String,
+}
+
+async fn thread(
+    State(index): State<Arc<ThreadIndex>>,
+    Query(query): Query<ThreadQuery>,
+) -> Json<ThreadReport> {
+    Json(ThreadReport {
+        replies: index.replies(&query.uri),
+        thread: index.thread(&query.uri),
+    })
+}
+
+fn remove(map: &mut HashMap<String, Vec<String>>, key: &str, uri: &str) {
+    if let Some(uris) = map.get_mut(key) {
+        uris.retain(|u| u != uri);
+        if uris.is_empty() {
+            map.remove(key);
+        }
+    }
+}
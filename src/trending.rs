@@ -0,0 +1,188 @@
+//! Trending hashtags: per-minute counts of the hashtags posts use, summed over sliding windows
+//! and reported as top-N lists. The per-minute [`MinuteWindow`] is shared with the
+//! [emoji](crate::emoji) and [engagement](crate::engagement) reports.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{facets::Facets, jsonl::JsonlWriter, task};
+
+/// Windows reported on, by name. Counts are kept to the minute, so each window covers the
+/// current minute and the ones before it.
+pub const WINDOWS: [(&str, Duration); 2] = [
+    ("5m", Duration::from_secs(5 * 60)),
+    ("1h", Duration::from_secs(60 * 60)),
+];
+const MINUTE: u64 = 60;
+
+/// Counts kept per minute over a sliding window, oldest first. A minute is only added once
+/// something is counted in it.
+#[derive(Debug)]
+pub struct MinuteWindow<T> {
+    /// Minutes kept, counting the current one
+    len: u64,
+    /// By minutes since the Unix epoch
+    minutes: VecDeque<(u64, T)>,
+}
+
+impl<T: Default> MinuteWindow<T> {
+    /// Keeps enough minutes to sum any window up to `longest`.
+    pub fn new(longest: Duration) -> Self {
+        Self {
+            len: minutes(longest),
+            minutes: VecDeque::new(),
+        }
+    }
+
+    /// The counts of the current minute, forgetting the minutes that fell out of the window.
+    pub fn current(&mut self) -> &mut T {
+        let index = current_minute();
+        if self.minutes.back().is_none_or(|(minute, _)| *minute < index) {
+            self.minutes.push_back((index, T::default()));
+            let oldest = index.saturating_sub(self.len - 1);
+            while self.minutes.front().is_some_and(|(minute, _)| *minute < oldest) {
+                self.minutes.pop_front();
+            }
+        }
+        &mut self.minutes.back_mut().expect("just pushed").1
+    }
+
+    /// The counts of each minute within `window` of now, counting the current one.
+    pub fn recent(&self, window: Duration) -> impl Iterator<Item = &T> {
+        let oldest = current_minute().saturating_sub(minutes(window) - 1);
+        self.minutes
+            .iter()
+            .filter(move |(minute, _)| *minute >= oldest)
+            .map(|(_, counts)| counts)
+    }
+}
+
+impl<T: Default> Default for MinuteWindow<T> {
+    /// Covers the longest of [`WINDOWS`].
+    fn default() -> Self {
+        let longest = WINDOWS.iter().map(|(_, window)| *window).max();
+        Self::new(longest.unwrap_or(Duration::from_secs(MINUTE)))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Trending {
+    minutes: Mutex<MinuteWindow<HashMap<String, u64>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    /// Posts using the tag within the window
+    pub posts: u64,
+}
+
+/// One line of the trending output file.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    at: DateTime<Utc>,
+    window: &'a str,
+    top: Vec<TagCount>,
+}
+
+impl Trending {
+    /// Counts each of the post's hashtags once, case-insensitively.
+    pub fn record(&self, facets: &Facets) {
+        let tags = facets
+            .tags
+            .iter()
+            .map(|tag| tag.trim_start_matches('#').to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect::<HashSet<_>>();
+        if tags.is_empty() {
+            return;
+        }
+
+        let mut minutes = self.minutes.lock().unwrap();
+        let counts = minutes.current();
+        for tag in tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    /// The `n` most used hashtags within `window` of now, most used first.
+    pub fn top(&self, window: Duration, n: usize) -> Vec<TagCount> {
+        let mut totals = HashMap::<&str, u64>::new();
+        let minutes = self.minutes.lock().unwrap();
+        for counts in minutes.recent(window) {
+            for (tag, count) in counts {
+                *totals.entry(tag).or_default() += count;
+            }
+        }
+
+        let mut top = totals
+            .into_iter()
+            .map(|(tag, posts)| TagCount {
+                tag: tag.to_string(),
+                posts,
+            })
+            .collect::<Vec<_>>();
+        // Ties broken by name, so reports are stable
+        top.sort_unstable_by(|a, b| b.posts.cmp(&a.posts).then_with(|| a.tag.cmp(&b.tag)));
+        top.truncate(n);
+        top
+    }
+
+    /// Logs the top `n` hashtags of every window each `interval` in the background, also
+    /// appending them to `output` when set.
+    pub fn report_every(
+        self: Arc<Self>,
+        interval: Duration,
+        n: usize,
+        output: Option<JsonlWriter>,
+    ) {
+        task::spawn("trending-report", async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let at = Utc::now();
+                for (name, window) in WINDOWS {
+                    let top = self.top(window, n);
+                    let list = top
+                        .iter()
+                        .map(|count| format!("#{} ({})", count.tag, count.posts))
+                        .collect::<Vec<_>>();
+                    info!("Trending over {name}: {}", list.join(", "));
+
+                    if let Some(output) = &output {
+                        let report = Report {
+                            at,
+                            window: name,
+                            top,
+                        };
+                        if let Err(e) = output.append(&report) {
+                            error!("Unable to write trending hashtags: {e}");
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Whole minutes in `window`, at least one.
+fn minutes(window: Duration) -> u64 {
+    (window.as_secs() / MINUTE).max(1)
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / MINUTE
+}
@@ -0,0 +1,39 @@
+//! Polling input files for changes, so they can be reloaded without a restart.
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::task;
+
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Runs `reload` in the background whenever the modification time of `path` changes, which
+/// includes the file being removed or coming back.
+pub fn on_change<F, Fut>(name: &str, path: PathBuf, mut reload: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    task::spawn(name, async move {
+        let mut last_modified = modified(&path).await;
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+
+            let modified = modified(&path).await;
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+            reload().await;
+        }
+    });
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
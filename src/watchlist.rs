@@ -0,0 +1,165 @@
+//! DID allowlist/blocklist loaded from a file, reloaded whenever the file changes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::{error, info, warn};
+
+use crate::{identity, watch};
+
+/// Handles resolved at once when the file is (re)loaded
+const RESOLVE_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchlistMode {
+    /// Only process commits from listed repos
+    Allow,
+    /// Process commits from every repo except listed ones
+    Block,
+}
+
+impl FromStr for WatchlistMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "block" => Ok(Self::Block),
+            other => Err(format!("expected \"allow\" or \"block\", got {other:?}")),
+        }
+    }
+}
+
+/// A set of repo DIDs read from a file with one DID or handle per line.
+///
+/// Handles are resolved to DIDs when the file is (re)loaded; a handle that can't be resolved on
+/// reload keeps the DID it had before. Blank lines and `#` comments are ignored.
+#[derive(Debug)]
+pub struct Watchlist {
+    path: PathBuf,
+    mode: WatchlistMode,
+    dids: RwLock<HashSet<String>>,
+    /// DID each listed handle last resolved to
+    handles: RwLock<HashMap<String, String>>,
+}
+
+impl Watchlist {
+    pub async fn load(
+        path: PathBuf,
+        mode: WatchlistMode,
+        http: &reqwest::Client,
+    ) -> std::io::Result<Self> {
+        let (dids, handles) = read_dids(&path, http, &HashMap::new()).await?;
+        info!(
+            "Loaded {} repos from watchlist {}",
+            dids.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            path,
+            mode,
+            dids: RwLock::new(dids),
+            handles: RwLock::new(handles),
+        })
+    }
+
+    /// Whether commits from `did` should be processed.
+    pub fn allows(&self, did: &str) -> bool {
+        let listed = self.dids.read().unwrap().contains(did);
+        match self.mode {
+            WatchlistMode::Allow => listed,
+            WatchlistMode::Block => !listed,
+        }
+    }
+
+    /// Reads the watchlist file again, returning how many repos it lists.
+    pub async fn reload(&self, http: &reqwest::Client) -> std::io::Result<usize> {
+        let previous = self.handles.read().unwrap().clone();
+        let (dids, handles) = read_dids(&self.path, http, &previous).await?;
+        let len = dids.len();
+        *self.dids.write().unwrap() = dids;
+        *self.handles.write().unwrap() = handles;
+        Ok(len)
+    }
+
+    /// Reloads the watchlist file in the background whenever it changes.
+    pub fn watch(self: Arc<Self>, http: reqwest::Client) {
+        watch::on_change("watchlist", self.path.clone(), move || {
+            let (watchlist, http) = (self.clone(), http.clone());
+            async move {
+                match watchlist.reload(&http).await {
+                    Ok(len) => info!(
+                        "Reloaded {len} repos from watchlist {}",
+                        watchlist.path.display()
+                    ),
+                    Err(e) => error!("Unable to reload watchlist: {e}"),
+                }
+            }
+        });
+    }
+}
+
+/// The DIDs listed in `path`, and the DID each listed handle resolved to. Handles are resolved
+/// a few at a time, falling back to `previous` for those that fail.
+async fn read_dids(
+    path: &Path,
+    http: &reqwest::Client,
+    previous: &HashMap<String, String>,
+) -> std::io::Result<(HashSet<String>, HashMap<String, String>)> {
+    let contents = tokio::fs::read_to_string(path).await?;
+
+    let permits = Arc::new(Semaphore::new(RESOLVE_CONCURRENCY));
+    let mut lookups = JoinSet::new();
+    let mut dids = HashSet::new();
+    for entry in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        if entry.starts_with("did:") {
+            dids.insert(entry.to_string());
+            continue;
+        }
+
+        let handle = entry.trim_start_matches('@').to_string();
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Semaphore is never closed");
+        let http = http.clone();
+        lookups.spawn(async move {
+            let did = identity::resolve_handle(&http, &handle).await;
+            drop(permit);
+            (handle, did)
+        });
+    }
+
+    let mut handles = HashMap::new();
+    while let Some(joined) = lookups.join_next().await {
+        let Ok((handle, did)) = joined else {
+            continue;
+        };
+        let did = match (did, previous.get(&handle)) {
+            (Ok(did), _) => did,
+            (Err(e), Some(did)) => {
+                warn!("Unable to resolve watchlist handle {handle}, keeping {did}: {e}");
+                did.clone()
+            }
+            (Err(e), None) => {
+                warn!("Unable to resolve watchlist handle {handle}: {e}");
+                continue;
+            }
+        };
+        dids.insert(did.clone());
+        handles.insert(handle, did);
+    }
+
+    Ok((dids, handles))
+}
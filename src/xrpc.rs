@@ -0,0 +1,126 @@
+//! Authenticated XRPC calls made on behalf of a configured account.
+
+use std::sync::Arc;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::session::Session;
+
+#[derive(Debug, thiserror::Error)]
+pub enum XrpcError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("XRPC call failed with {status}: {error} {message}")]
+    Xrpc {
+        status: reqwest::StatusCode,
+        error: String,
+        message: String,
+    },
+}
+
+impl XrpcError {
+    /// Whether the call was refused for an expired or invalid token.
+    pub fn is_token_error(&self) -> bool {
+        matches!(self, Self::Xrpc { error, .. } if error == "ExpiredToken" || error == "InvalidToken")
+    }
+}
+
+/// The body of a failed XRPC call.
+#[derive(Debug, Default, Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    message: String,
+}
+
+/// Turns a failed XRPC response into an [`XrpcError::Xrpc`].
+pub async fn check(response: reqwest::Response) -> Result<reqwest::Response, XrpcError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.json::<ErrorBody>().await.unwrap_or_default();
+    Err(XrpcError::Xrpc {
+        status,
+        error: body.error,
+        message: body.message,
+    })
+}
+
+/// A `com.atproto.repo.strongRef`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrongRef {
+    pub uri: String,
+    pub cid: String,
+}
+
+/// XRPC client acting as the account logged in to `session`. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct AuthClient {
+    session: Arc<Session>,
+}
+
+impl AuthClient {
+    pub fn new(session: Arc<Session>) -> Self {
+        Self { session }
+    }
+
+    pub fn did(&self) -> String {
+        self.session.did()
+    }
+
+    pub fn handle(&self) -> String {
+        self.session.handle()
+    }
+
+    /// Creates `record` in `collection` of the logged-in account's repo.
+    pub async fn create_record(
+        &self,
+        collection: &str,
+        record: serde_json::Value,
+    ) -> Result<StrongRef, XrpcError> {
+        let input = json!({
+            "repo": self.session.did(),
+            "collection": collection,
+            "record": record,
+        });
+        self.procedure("com.atproto.repo.createRecord", &input)
+            .await
+    }
+
+    /// Calls the XRPC procedure `nsid` with `input`, refreshing the session and retrying once
+    /// if the access token expired.
+    pub async fn procedure<T: DeserializeOwned>(
+        &self,
+        nsid: &str,
+        input: &serde_json::Value,
+    ) -> Result<T, XrpcError> {
+        let access_jwt = self.session.access_jwt();
+        match self.call(nsid, input, &access_jwt).await {
+            Err(e) if e.is_token_error() => {
+                self.session.refresh(&access_jwt).await?;
+                self.call(nsid, input, &self.session.access_jwt()).await
+            }
+            result => result,
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        nsid: &str,
+        input: &serde_json::Value,
+        access_jwt: &str,
+    ) -> Result<T, XrpcError> {
+        let response = self
+            .session
+            .http()
+            .post(format!("{}/xrpc/{nsid}", self.session.pds()))
+            .bearer_auth(access_jwt)
+            .json(input)
+            .send()
+            .await?;
+        Ok(check(response).await?.json().await?)
+    }
+}
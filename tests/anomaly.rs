@@ -0,0 +1,71 @@
+//! Anomaly detection: repos going over a threshold within the window are flagged once per
+//! window, measured in commit time rather than when the commit was read.
+
+mod support;
+
+use std::time::Duration;
+
+use bsky_firehose_listener::{anomaly::AnomalyDetector, client::Event, config::Config};
+use support::event;
+
+const TID_ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+/// Flags more than `max_posts` posts within a minute.
+fn detector(max_posts: usize) -> AnomalyDetector {
+    let mut config = Config::from_env();
+    config.anomaly_window = Duration::from_secs(60);
+    config.anomaly_max_follows = None;
+    config.anomaly_max_posts = Some(max_posts);
+    AnomalyDetector::from_config(&config).unwrap()
+}
+
+/// A post committed `secs` seconds after a fixed start.
+fn post(secs: i64) -> Event {
+    let mut value = ((1_725_000_000 + secs) as u64 * 1_000_000) << 10;
+    let mut rev = [0; 13];
+    for digit in rev.iter_mut().rev() {
+        *digit = TID_ALPHABET[(value & 31) as usize];
+        value >>= 5;
+    }
+    let mut evt = event("app.bsky.feed.post", "3l3qo2vutsw2b");
+    evt.rev = String::from_utf8(rev.to_vec()).unwrap();
+    evt
+}
+
+#[test]
+fn flags_repos_over_the_threshold() {
+    let detector = detector(3);
+    for secs in 0..3 {
+        assert!(detector.record(&post(secs)).is_none());
+    }
+    let anomaly = detector.record(&post(3)).unwrap();
+    assert_eq!(anomaly.count, 4);
+    assert_eq!(anomaly.window_secs, 60);
+}
+
+#[test]
+fn forgets_commits_older_than_the_window() {
+    let detector = detector(3);
+    for secs in [0, 1, 2, 61, 62] {
+        assert!(detector.record(&post(secs)).is_none());
+    }
+}
+
+#[test]
+fn measures_commit_time_not_read_time() {
+    // A backlog read all at once, each post a minute apart when it was made
+    let detector = detector(1);
+    for minute in 0..10 {
+        assert!(detector.record(&post(minute * 60)).is_none());
+    }
+}
+
+#[test]
+fn stays_quiet_for_a_window_after_flagging() {
+    let detector = detector(1);
+    assert!(detector.record(&post(0)).is_none());
+    assert!(detector.record(&post(1)).is_some());
+    assert!(detector.record(&post(2)).is_none());
+    assert!(detector.record(&post(70)).is_none());
+    assert!(detector.record(&post(71)).is_some());
+}
@@ -0,0 +1,214 @@
+//! Archiving: the object being written is completed once the archiver is dropped, and its
+//! upload aborted when a part fails.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bsky_firehose_listener::{
+    archive::{ArchiveFormat, Archiver},
+    compress::Compression,
+};
+use bytes::Bytes;
+use futures_util::{future::BoxFuture, stream::BoxStream, TryStreamExt};
+use object_store::{
+    memory::InMemory, path::Path, GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta,
+    ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult, UploadPart,
+};
+
+/// An in-memory store whose multipart uploads fail every part.
+#[derive(Debug, Default)]
+struct FailingStore {
+    inner: InMemory,
+    aborted: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+struct FailingUpload {
+    aborted: Arc<AtomicBool>,
+}
+
+impl fmt::Display for FailingStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FailingStore({})", self.inner)
+    }
+}
+
+impl MultipartUpload for FailingUpload {
+    fn put_part(&mut self, _: PutPayload) -> UploadPart {
+        Box::pin(async {
+            Err(object_store::Error::Generic {
+                store: "FailingStore",
+                source: "no space left".into(),
+            })
+        })
+    }
+
+    fn complete<'life0, 'async_trait>(
+        &'life0 mut self,
+    ) -> BoxFuture<'async_trait, object_store::Result<PutResult>>
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        panic!("an upload with failed parts was completed")
+    }
+
+    fn abort<'life0, 'async_trait>(
+        &'life0 mut self,
+    ) -> BoxFuture<'async_trait, object_store::Result<()>>
+    where
+        'life0: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.aborted.store(true, Ordering::SeqCst);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl ObjectStore for FailingStore {
+    fn put_opts<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        location: &'life1 Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> BoxFuture<'async_trait, object_store::Result<PutResult>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.put_opts(location, payload, opts)
+    }
+
+    fn put_multipart_opts<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        _: &'life1 Path,
+        _: PutMultipartOpts,
+    ) -> BoxFuture<'async_trait, object_store::Result<Box<dyn MultipartUpload>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        let upload = FailingUpload {
+            aborted: self.aborted.clone(),
+        };
+        Box::pin(async move { Ok(Box::new(upload) as Box<dyn MultipartUpload>) })
+    }
+
+    fn get_opts<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        location: &'life1 Path,
+        options: GetOptions,
+    ) -> BoxFuture<'async_trait, object_store::Result<GetResult>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.get_opts(location, options)
+    }
+
+    fn delete<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        location: &'life1 Path,
+    ) -> BoxFuture<'async_trait, object_store::Result<()>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.delete(location)
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, object_store::Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_delimiter<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        prefix: Option<&'life1 Path>,
+    ) -> BoxFuture<'async_trait, object_store::Result<ListResult>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.list_with_delimiter(prefix)
+    }
+
+    fn copy<'life0, 'life1, 'life2, 'async_trait>(
+        &'life0 self,
+        from: &'life1 Path,
+        to: &'life2 Path,
+    ) -> BoxFuture<'async_trait, object_store::Result<()>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.copy(from, to)
+    }
+
+    fn copy_if_not_exists<'life0, 'life1, 'life2, 'async_trait>(
+        &'life0 self,
+        from: &'life1 Path,
+        to: &'life2 Path,
+    ) -> BoxFuture<'async_trait, object_store::Result<()>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.copy_if_not_exists(from, to)
+    }
+}
+
+#[tokio::test]
+async fn completes_the_open_object_when_dropped() {
+    let store = Arc::new(InMemory::new());
+    let (archiver, task) = Archiver::spawn(
+        store.clone(),
+        "firehose/".to_string(),
+        ArchiveFormat::Ndjson,
+        Compression::None,
+    );
+    archiver.push(Bytes::from_static(br#"{"seq":1}"#));
+    archiver.push(Bytes::from_static(br#"{"seq":2}"#));
+    drop(archiver);
+    task.await.unwrap();
+
+    let objects = store.list(None).try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!(objects.len(), 1);
+    let path = &objects[0].location;
+    assert!(path.as_ref().starts_with("firehose/"), "{path}");
+    assert!(path.as_ref().ends_with(".ndjson"), "{path}");
+    let data = store.get(path).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&data[..], b"{\"seq\":1}\n{\"seq\":2}\n");
+}
+
+#[tokio::test]
+async fn aborts_the_upload_when_a_part_fails() {
+    let store = Arc::new(FailingStore::default());
+    let (archiver, task) = Archiver::spawn(
+        store.clone(),
+        String::new(),
+        ArchiveFormat::Frames,
+        Compression::None,
+    );
+    // More than a part, so one is uploaded
+    archiver.push(Bytes::from(vec![0; 9 * 1024 * 1024]));
+    drop(archiver);
+    task.await.unwrap();
+
+    assert!(store.aborted.load(Ordering::SeqCst));
+    let objects = store.list(None).try_collect::<Vec<_>>().await.unwrap();
+    assert!(objects.is_empty());
+}
@@ -0,0 +1,59 @@
+//! Block store: blocks written by CID and read back.
+
+use std::{sync::Arc, time::Duration};
+
+use bsky_firehose_listener::{
+    blockstore::{BlockStore, BlockWriter},
+    frame::{self, Frame},
+};
+
+fn store_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn put_is_write_once() {
+    let dir = store_dir("block-store");
+    let store = BlockStore::open(&dir).unwrap();
+    let cid = "bafyreia4fdjf7bcjybtflkvai7hvm4v26f3g7n7xq747ud4mi6vwcyrnze";
+
+    assert_eq!(store.get(cid).unwrap(), None);
+    assert!(store.put(cid, b"block").unwrap());
+    assert!(!store.put(cid, b"block").unwrap());
+    assert_eq!(store.get(cid).unwrap().as_deref(), Some(&b"block"[..]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn stores_every_block_of_a_commit() {
+    let dir = store_dir("block-writer");
+    let store = Arc::new(BlockStore::open(&dir).unwrap());
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    let blocks = frame::blocks(&commit).await.unwrap();
+
+    let writer = BlockWriter::spawn(store.clone());
+    writer.push(std::fs::read("fixtures/identity.bin").unwrap().into());
+    writer.push(data.into());
+    for _ in 0..50 {
+        if blocks
+            .iter()
+            .all(|(cid, _)| store.contains(&cid.to_string()))
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    for (cid, block) in blocks.iter() {
+        assert_eq!(
+            store.get(&cid.to_string()).unwrap().as_deref(),
+            Some(&block[..])
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,47 @@
+//! Bot mode: the bot never acts on the posts it made itself.
+
+mod support;
+
+use atrium_api::{app::bsky::feed::post, types::string::Did};
+use bsky_firehose_listener::{
+    bot::{Bot, BotAction},
+    haiku::{Haiku, HaikuRecord},
+    ratelimit::{LimitPolicy, RateLimiter},
+};
+use serde_json::json;
+use support::event;
+
+const BOT_DID: &str = "did:plc:bot7nxzyoun6zhxrhs64oiz";
+
+/// The fixture haiku, as posted by `did`.
+fn haiku(did: &str) -> HaikuRecord {
+    let mut evt = event("app.bsky.feed.post", "3l3qo2vutsw2b");
+    evt.repo = Did::new(did.to_string()).unwrap();
+    let record: post::Record = serde_json::from_value(json!({
+        "text": "an old silent pond\na frog jumps into the pond\nsplash! silence again",
+        "createdAt": "2024-11-02T09:14:03.000Z",
+    }))
+    .unwrap();
+    let found = Haiku {
+        form: "haiku".to_string(),
+        lines: vec![
+            "an old silent pond".to_string(),
+            "a frog jumps into the pond".to_string(),
+            "splash! silence again".to_string(),
+        ],
+        syllables: vec![5, 7, 5],
+    };
+    let mut haiku = HaikuRecord::new(&evt, &record, found, None, None);
+    haiku.cid = Some("bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm".to_string());
+    haiku
+}
+
+#[tokio::test]
+async fn skips_its_own_quotes() {
+    let limiter = RateLimiter::new("10/h".parse().unwrap(), LimitPolicy::Drop);
+    let bot = Bot::spawn(BotAction::Quote, None, limiter, Some(BOT_DID.to_string()));
+
+    assert!(bot.act(&haiku("did:plc:ewvi7nxzyoun6zhxrhs64oiz")));
+    // The quote comes back through the firehose with the same lines, from the bot's account
+    assert!(!bot.act(&haiku(BOT_DID)));
+}
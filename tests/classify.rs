@@ -0,0 +1,310 @@
+//! Classifiers: syllable forms, forms of an exact word or character count, rhymes, couplets,
+//! sonnets, limericks, acrostics and palindromes.
+
+use std::sync::Arc;
+
+use bsky_firehose_listener::{
+    classify::{
+        AcrosticClassifier, CountClassifier, CountForm, CountUnit, CoupletClassifier,
+        PalindromeClassifier, SonnetClassifier, TextClassifier,
+    },
+    haiku::{self, SyllablePattern},
+    rhyme,
+    syllables::SyllableCounter,
+};
+
+#[test]
+fn parses_syllable_patterns() {
+    assert_eq!(
+        "tanka=5-7-5-7-7".parse(),
+        Ok(SyllablePattern {
+            name: "tanka".to_string(),
+            lines: vec![5, 7, 5, 7, 7],
+        })
+    );
+    assert_eq!(
+        "5-7-5".parse(),
+        Ok(SyllablePattern {
+            name: "5-7-5".to_string(),
+            lines: vec![5, 7, 5],
+        }),
+        "named after itself"
+    );
+    assert_eq!(
+        " haiku = 5 - 7 - 5 ".parse(),
+        Ok(SyllablePattern::haiku())
+    );
+    assert!("haiku=".parse::<SyllablePattern>().is_err());
+    assert!("haiku=5-0-5".parse::<SyllablePattern>().is_err());
+    assert!("haiku=five-seven-five".parse::<SyllablePattern>().is_err());
+}
+
+#[test]
+fn tags_posts_with_their_form() {
+    let estimate = SyllableCounter::default();
+    let haiku = haiku::detect(
+        "an old silent pond\na frog jumps into the pond\nsplash! silence again",
+        &[SyllablePattern::haiku()],
+        &estimate,
+    )
+    .unwrap();
+    assert_eq!(haiku.form, "haiku");
+    assert_eq!(haiku.syllables, [5, 7, 5]);
+    assert_eq!(
+        haiku.lines,
+        ["an old silent pond", "a frog jumps into the pond", "splash! silence again"]
+    );
+
+    assert_eq!(
+        haiku::detect("just setting up my bsky", &[SyllablePattern::haiku()], &estimate),
+        None
+    );
+}
+
+#[test]
+fn first_listed_of_overlapping_forms_wins() {
+    let estimate = SyllableCounter::default();
+    let text = ["cat"; 17].join(" ");
+    let senryu = "senryu=5-7-5".parse::<SyllablePattern>().unwrap();
+    let tanka = "tanka=5-7-5-7-7".parse::<SyllablePattern>().unwrap();
+
+    let form = |patterns: &[SyllablePattern], text: &str| {
+        haiku::detect(text, patterns, &estimate).map(|haiku| haiku.form)
+    };
+    assert_eq!(
+        form(&[senryu.clone(), SyllablePattern::haiku()], &text).as_deref(),
+        Some("senryu")
+    );
+    assert_eq!(
+        form(&[SyllablePattern::haiku(), senryu.clone()], &text).as_deref(),
+        Some("haiku")
+    );
+    assert_eq!(
+        form(&[tanka.clone(), senryu.clone()], &text).as_deref(),
+        Some("senryu"),
+        "too short for a tanka"
+    );
+
+    let text = ["cat"; 31].join(" ");
+    assert_eq!(
+        form(&[senryu.clone(), tanka.clone()], &text).as_deref(),
+        Some("tanka")
+    );
+    assert_eq!(
+        form(&[senryu], &text),
+        None,
+        "leftover words don't fit a haiku"
+    );
+}
+
+const LIMERICK: &str = "A cat who once sat on a mat
+had dreams of a big bag of fat
+it ran for a bun
+and had lots of fun
+and that was the end of the cat";
+
+#[test]
+fn detects_limericks() {
+    let estimate = SyllableCounter::default();
+    let limerick = haiku::detect_limerick(LIMERICK, &estimate).unwrap();
+    assert_eq!(limerick.form, "limerick");
+    assert_eq!(limerick.lines.len(), 5);
+    assert_eq!(limerick.syllables, [8, 8, 5, 5, 8]);
+
+    let lines = LIMERICK.lines().collect::<Vec<_>>();
+    let swapped = [lines[0], lines[2], lines[1], lines[3], lines[4]].join("\n");
+    assert_eq!(
+        haiku::detect_limerick(&swapped, &estimate),
+        None,
+        "breaks the rhyme scheme"
+    );
+    assert_eq!(
+        haiku::detect_limerick(&lines[..4].join("\n"), &estimate),
+        None
+    );
+
+    // The B lines must not rhyme with the A lines
+    let monorhyme = LIMERICK.replace("bun", "hat").replace("fun", "rat");
+    assert_eq!(haiku::detect_limerick(&monorhyme, &estimate), None);
+
+    let long = LIMERICK.replace("it ran for a bun", "it ran to the shop for a bun");
+    assert_eq!(
+        haiku::detect_limerick(&long, &estimate),
+        None,
+        "B line too long"
+    );
+}
+
+#[test]
+fn parses_count_forms() {
+    assert_eq!(
+        "six-word-story=6w".parse(),
+        Ok(CountForm {
+            name: "six-word-story".to_string(),
+            count: 6,
+            unit: CountUnit::Words,
+        })
+    );
+    assert_eq!(
+        "exactly-280 = 280c"
+            .parse::<CountForm>()
+            .map(|form| form.unit),
+        Ok(CountUnit::Chars)
+    );
+    assert!("six=6".parse::<CountForm>().is_err());
+    assert!("none=0w".parse::<CountForm>().is_err());
+    assert!("=6w".parse::<CountForm>().is_err());
+}
+
+#[test]
+fn labels_exact_lengths() {
+    let classifier = CountClassifier::new(vec![
+        "six-word-story=6w".parse().unwrap(),
+        "exactly-20=20c".parse().unwrap(),
+    ]);
+    let label = classifier
+        .classify("For sale: baby shoes —\nnever worn. 👟")
+        .unwrap();
+    assert_eq!(
+        label.name, "six-word-story",
+        "dashes and emoji aren't words"
+    );
+    assert_eq!(label.lines, ["For sale: baby shoes —", "never worn. 👟"]);
+    assert_eq!(
+        classifier.classify("For sale: baby shoes, never worn, sadly"),
+        None
+    );
+
+    assert_eq!(
+        classifier
+            .classify("  exactly twenty chars \n")
+            .map(|label| label.name),
+        Some("exactly-20".to_string()),
+        "surrounding whitespace isn't counted"
+    );
+}
+
+const SONNET_18: &str = "Shall I compare thee to a summer's day?
+Thou art more lovely and more temperate:
+Rough winds do shake the darling buds of May,
+And summer's lease hath all too short a date;
+Sometime too hot the eye of heaven shines,
+And often is his gold complexion dimm'd;
+And every fair from fair sometime declines,
+By chance or nature's changing course untrimm'd;
+But thy eternal summer shall not fade,
+Nor lose possession of that fair thou ow'st;
+Nor shall Death brag thou wander'st in his shade,
+When in eternal lines to time thou grow'st:
+So long as men can breathe or eyes can see,
+So long lives this, and this gives life to thee.";
+
+#[test]
+fn labels_sonnets() {
+    let syllables = Arc::new(SyllableCounter::default());
+    let rhymed = SonnetClassifier::new(syllables.clone(), true);
+    let label = rhymed.classify(SONNET_18).unwrap();
+    assert_eq!(label.name, "sonnet");
+    assert_eq!(label.lines.len(), 14);
+
+    // Swapping two lines keeps the meter but breaks the rhyme scheme
+    let mut lines = SONNET_18.lines().collect::<Vec<_>>();
+    lines.swap(0, 1);
+    let unrhymed = lines.join("\n");
+    assert_eq!(rhymed.classify(&unrhymed), None);
+    assert!(SonnetClassifier::new(syllables.clone(), false)
+        .classify(&unrhymed)
+        .is_some());
+
+    let quatrain = lines[..4].join("\n");
+    assert_eq!(
+        SonnetClassifier::new(syllables, false).classify(&quatrain),
+        None
+    );
+}
+
+#[test]
+fn rhymes_by_phonemes_or_spelling() {
+    let path = std::env::temp_dir().join(format!("cmudict-{}.dict", std::process::id()));
+    std::fs::write(&path, "eight EY1 T\nlate L EY1 T\nnight N AY1 T\n").unwrap();
+    let dict = SyllableCounter::load_cmudict(&path).unwrap();
+    let estimate = SyllableCounter::default();
+
+    assert!(rhyme::rhymes(&dict, "Eight", "late"));
+    assert!(
+        !rhyme::rhymes(&estimate, "eight", "late"),
+        "spelled differently"
+    );
+    assert!(
+        rhyme::rhymes(&dict, "night", "flight"),
+        "flight isn't in the dictionary"
+    );
+    assert!(!rhyme::rhymes(&dict, "night", "Night"));
+    assert!(!rhyme::rhymes(&dict, "night", "late"));
+}
+
+#[test]
+fn doesnt_rhyme_without_latin_letters() {
+    let estimate = SyllableCounter::default();
+    assert_eq!(rhyme::spelled_rhyme("пушистый"), "");
+    assert!(!rhyme::rhymes(&estimate, "пушистый", "кот"));
+    assert!(!rhyme::rhymes(&estimate, "猫", "犬"));
+    assert!(!rhyme::rhymes(&estimate, "2024", "42"));
+    assert!(!rhyme::rhymes(&estimate, "night", "42"));
+}
+
+#[test]
+fn labels_couplets() {
+    let couplets = CoupletClassifier::new(Arc::new(SyllableCounter::default()));
+    let label = couplets
+        .classify("The cat sat on the mat,\nand wore a little hat.\n\nShe dreamt of every mouse\nthat ran about the house!")
+        .unwrap();
+    assert_eq!(label.name, "couplets");
+    assert_eq!(label.lines.len(), 4);
+
+    assert_eq!(
+        couplets.classify("The cat sat on the mat,\nand wore a little hat.\nThe end"),
+        None
+    );
+    assert_eq!(
+        couplets.classify("I said hello\nyou said hello"),
+        None,
+        "the same word twice"
+    );
+    assert_eq!(couplets.classify("a single line"), None);
+}
+
+#[test]
+fn labels_acrostics() {
+    let acrostics = AcrosticClassifier::new(&["no".to_string(), " Pond ".to_string()]);
+    let label = acrostics
+        .classify("🐸 Pale moon\nover water\n\nnight falls\n\"Dusk\"")
+        .unwrap();
+    assert_eq!(label.name, "acrostic:pond");
+    assert_eq!(
+        label.lines,
+        ["🐸 Pale moon", "over water", "night falls", "\"Dusk\""]
+    );
+
+    assert_eq!(acrostics.classify("Pale\nOver"), None, "fewer than three lines");
+    assert_eq!(acrostics.classify("Pale moon\nover water\nnight falls"), None);
+    assert_eq!(
+        acrostics.classify("Pale moon\nover water\n🌙\nDusk"),
+        None,
+        "a line without letters"
+    );
+}
+
+#[test]
+fn labels_palindromes() {
+    let palindromes = PalindromeClassifier::new(10);
+    assert_eq!(
+        palindromes
+            .classify("A man, a plan, a canal: Panama!")
+            .map(|label| label.name),
+        Some("palindrome".to_string())
+    );
+    assert_eq!(palindromes.classify("Wow"), None, "too short");
+    assert_eq!(palindromes.classify("not a palindrome at all"), None);
+    assert_eq!(PalindromeClassifier::new(0).classify("?!"), None);
+}
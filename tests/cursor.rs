@@ -0,0 +1,21 @@
+//! Saved cursors: what a previous run leaves behind for the next one to resume from.
+
+use bsky_firehose_listener::cursor;
+
+#[test]
+fn saved_cursor_round_trips() {
+    let path = std::env::temp_dir().join(format!("cursor-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(cursor::load(&path).unwrap(), None);
+
+    cursor::save(&path, 4212345678).unwrap();
+    assert_eq!(cursor::load(&path).unwrap(), Some(4212345678));
+
+    // Written by hand, with surrounding whitespace
+    std::fs::write(&path, " 42\n").unwrap();
+    assert_eq!(cursor::load(&path).unwrap(), Some(42));
+
+    std::fs::write(&path, "not a cursor").unwrap();
+    assert!(cursor::load(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
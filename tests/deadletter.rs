@@ -0,0 +1,61 @@
+//! Dead letters: what gets kept when a frame fails, and running kept letters through the
+//! handlers again.
+
+mod support;
+
+use std::sync::{Arc, Mutex};
+
+use bsky_firehose_listener::{
+    client::Client,
+    deadletter::{self, DeadLetters, Payload},
+};
+use support::{commit_frame, MockRelay};
+
+#[tokio::test]
+async fn redelivers_kept_frames_and_events() {
+    let path = std::env::temp_dir().join(format!("dead-letters-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let dead_letters = DeadLetters::open(&path).unwrap();
+    dead_letters.frame("dispatch", Some(7), &commit_frame(7), &"sink unavailable");
+    dead_letters.frame("decode", None, b"not a frame", &"invalid header");
+
+    let letters = deadletter::read(&path).unwrap();
+    assert_eq!(letters.len(), 2);
+    assert_eq!(letters[0].stage, "dispatch");
+    assert_eq!(letters[0].seq, Some(7));
+    assert!(matches!(letters[0].payload, Payload::Frame(_)));
+
+    // Events are kept as events, so the same post comes back from either kind of letter
+    let relay = MockRelay::start(Vec::new()).await;
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(relay.config());
+    {
+        let seen = seen.clone();
+        client.on("app.bsky.feed.post", move |evt| {
+            dead_letters.event("webhook", &evt, &"timed out");
+            seen.lock().unwrap().push(evt.rkey.clone());
+            async {}
+        });
+    }
+    client.redeliver(letters).await;
+    assert_eq!(seen.lock().unwrap().len(), 1);
+
+    let letters = deadletter::read(&path).unwrap();
+    let Payload::Event(_) = &letters[2].payload else {
+        panic!("expected an event, got {:?}", letters[2].payload);
+    };
+    let mut client = Client::new(relay.config());
+    {
+        let seen = seen.clone();
+        client.on("app.bsky.feed.post", move |evt| {
+            seen.lock().unwrap().push(evt.rkey.clone());
+            async {}
+        });
+    }
+    client.redeliver(vec![letters[2].clone()]).await;
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[0], seen[1]);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,92 @@
+//! Dedup store: text normalization, the retention window and reloading the store file.
+
+use std::{sync::Arc, time::Duration};
+
+use bsky_firehose_listener::{
+    dedup::{normalize, DedupStore},
+    health::Health,
+};
+
+fn path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("dedup-{name}-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn open(path: &std::path::Path, retention: Duration) -> DedupStore {
+    DedupStore::open(path, retention, Arc::new(Health::default())).unwrap()
+}
+
+#[test]
+fn normalizes_case_and_whitespace() {
+    assert_eq!(
+        normalize("  An Old Silent Pond\n\tA frog  jumps in "),
+        "an old silent pond a frog jumps in"
+    );
+    assert_eq!(normalize(" \n "), "");
+}
+
+#[test]
+fn skips_text_seen_within_the_window() {
+    let path = path("window");
+    let store = open(&path, Duration::from_secs(3600));
+
+    assert!(store.insert("An old silent pond").unwrap());
+    assert!(!store.insert("an  OLD silent\npond").unwrap());
+    assert!(store.insert("A frog jumps into the pond").unwrap());
+
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn accepts_text_again_once_the_window_passes() {
+    let path = path("expiry");
+    let store = open(&path, Duration::from_secs(1));
+
+    assert!(store.insert("An old silent pond").unwrap());
+    std::thread::sleep(Duration::from_millis(1100));
+    assert!(store.insert("An old silent pond").unwrap());
+
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn remembers_text_across_reopening() {
+    let path = path("reload");
+    let store = open(&path, Duration::from_secs(3600));
+    assert!(store.insert("An old silent pond").unwrap());
+    // Waits for the entry to be written
+    drop(store);
+
+    let store = open(&path, Duration::from_secs(3600));
+    assert!(!store.insert("An old silent pond").unwrap());
+    assert!(store.insert("A frog jumps into the pond").unwrap());
+
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn drops_expired_and_malformed_lines_when_opened() {
+    let path = path("compact");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    std::fs::write(
+        &path,
+        format!("00000000000000aa {now}\n00000000000000bb 1\nnot a line\n"),
+    )
+    .unwrap();
+
+    let store = open(&path, Duration::from_secs(3600));
+    drop(store);
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("00000000000000aa {now}\n")
+    );
+    std::fs::remove_file(&path).unwrap();
+}
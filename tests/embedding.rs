@@ -0,0 +1,33 @@
+//! Vector index: nearest neighbors by cosine similarity, per model.
+
+use bsky_firehose_listener::embedding::VectorIndex;
+
+#[test]
+fn finds_nearest_by_direction() {
+    let path = std::env::temp_dir().join(format!("embeddings-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let index = VectorIndex::open(&path).unwrap();
+
+    index
+        .insert("at://a", "did:a", "autumn moon", "m", &[1.0, 0.0])
+        .unwrap();
+    index
+        .insert("at://b", "did:b", "winter snow", "m", &[0.0, 3.0])
+        .unwrap();
+    index
+        .insert("at://c", "did:c", "harvest moon", "m", &[2.0, 1.0])
+        .unwrap();
+    index
+        .insert("at://d", "did:d", "other model", "n", &[1.0, 0.0])
+        .unwrap();
+
+    // Length doesn't matter, only direction
+    let neighbors = index.nearest(&[10.0, 0.0], "m", 2).unwrap();
+    let uris: Vec<_> = neighbors.iter().map(|n| n.uri.as_str()).collect();
+    assert_eq!(uris, ["at://a", "at://c"]);
+    assert!((neighbors[0].similarity - 1.0).abs() < 1e-6);
+
+    let stored = index.get("at://b").unwrap().unwrap();
+    assert!((stored[1] - 1.0).abs() < 1e-6);
+    assert!(index.get("at://missing").unwrap().is_none());
+}
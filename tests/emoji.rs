@@ -0,0 +1,58 @@
+//! Emoji: sequences are found whole, emoji-only posts are told apart, and usage is counted per
+//! post.
+
+use std::time::Duration;
+
+use bsky_firehose_listener::{
+    classify::{EmojiOnlyClassifier, TextClassifier},
+    emoji::{self, EmojiCount, EmojiUsage},
+};
+
+#[test]
+fn finds_whole_sequences() {
+    assert_eq!(
+        emoji::emojis("family 👨‍👩‍👧 in 🇯🇵, thumbs 👍🏽 and 1️⃣ ❤️ ©"),
+        ["👨‍👩‍👧", "🇯🇵", "👍🏽", "1️⃣", "❤️"],
+        "a bare © is text"
+    );
+    assert!(emoji::emojis("plain text, #1 and 50% off").is_empty());
+}
+
+#[test]
+fn recognizes_emoji_only_posts() {
+    assert!(emoji::is_emoji_only("🎉"));
+    assert!(emoji::is_emoji_only(" 😂😂 \n🔥 "));
+    assert!(!emoji::is_emoji_only("lol 😂"));
+    assert!(!emoji::is_emoji_only("   "));
+
+    assert_eq!(
+        EmojiOnlyClassifier.classify("🌸🌸").map(|label| label.name),
+        Some("emoji-only".to_string())
+    );
+    assert_eq!(EmojiOnlyClassifier.classify("spring 🌸"), None);
+}
+
+#[test]
+fn counts_emoji_once_per_post() {
+    let usage = EmojiUsage::default();
+    usage.record("😂😂😂");
+    usage.record("that's 😂 and 🔥");
+    usage.record("no emoji here");
+
+    let window = usage.top(Duration::from_secs(300), 10);
+    assert_eq!(window.emoji_only, 1);
+    assert_eq!(
+        window.top,
+        [
+            EmojiCount {
+                emoji: "😂".to_string(),
+                posts: 2
+            },
+            EmojiCount {
+                emoji: "🔥".to_string(),
+                posts: 1
+            },
+        ]
+    );
+    assert_eq!(usage.top(Duration::from_secs(300), 1).top.len(), 1);
+}
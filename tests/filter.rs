@@ -0,0 +1,49 @@
+//! Post filters: keywords match as whole words ignoring case, regexes match anywhere, and an
+//! empty filter lets everything through.
+
+use bsky_firehose_listener::filter::PostFilter;
+
+fn keywords(keywords: &[&str]) -> PostFilter {
+    PostFilter::new(keywords.iter().map(|k| k.to_string()).collect(), &[]).unwrap()
+}
+
+#[test]
+fn matches_keywords_as_whole_words() {
+    let filter = keywords(&["rust", "meta"]);
+    assert_eq!(filter.matches("Learning rust today"), Some(vec!["rust"]));
+    assert_eq!(filter.matches("(rust)!"), Some(vec!["rust"]));
+    assert_eq!(filter.matches("I trust you, frustrated"), None);
+    assert_eq!(filter.matches("check the metadata"), None);
+}
+
+#[test]
+fn ignores_case() {
+    let filter = keywords(&["Rust"]);
+    assert_eq!(filter.matches("RUST is great"), Some(vec!["Rust"]));
+    assert_eq!(filter.matches("rust is great"), Some(vec!["Rust"]));
+}
+
+#[test]
+fn reports_the_longest_overlapping_keyword() {
+    let filter = keywords(&["rust", "rustacean"]);
+    assert_eq!(filter.matches("hello rustacean"), Some(vec!["rustacean"]));
+    assert_eq!(
+        filter.matches("a rustacean writes rust"),
+        Some(vec!["rust", "rustacean"])
+    );
+}
+
+#[test]
+fn matches_regexes_without_keywords() {
+    let filter = PostFilter::new(Vec::new(), &[r"\d{3}-\d{4}".to_string()]).unwrap();
+    assert!(!filter.is_empty());
+    assert_eq!(filter.matches("call 555-0199"), Some(vec![r"\d{3}-\d{4}"]));
+    assert_eq!(filter.matches("no number here"), None);
+}
+
+#[test]
+fn empty_filter_lets_everything_through() {
+    let filter = PostFilter::new(Vec::new(), &[]).unwrap();
+    assert!(filter.is_empty());
+    assert_eq!(filter.matches("anything at all"), Some(Vec::new()));
+}
@@ -0,0 +1,154 @@
+//! Runs the decoder over captured malformed frames in `fixtures/malformed`; every one of them
+//! must be rejected with an error rather than a panic.
+
+use std::{collections::BTreeMap, fs};
+
+use bsky_firehose_listener::{
+    accounts::{AccountStatus, AccountStatuses},
+    frame::{self, Frame, FrameHeader},
+};
+use ipld_core::ipld::Ipld;
+
+#[tokio::test]
+async fn malformed_frames_are_rejected() {
+    let mut checked = 0;
+    for entry in fs::read_dir("fixtures/malformed").unwrap() {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+
+        // Some frames only turn out to be broken once their CAR file is read
+        let rejected = match frame::decode(&data) {
+            Err(_) => true,
+            Ok(Frame::Commit(commit)) => frame::blocks(&commit).await.is_err(),
+            Ok(_) => false,
+        };
+        assert!(rejected, "{} was accepted", path.display());
+        checked += 1;
+    }
+    assert!(checked > 0, "the corpus is empty");
+}
+
+#[test]
+fn split_frame_never_reads_past_the_input() {
+    let data = fs::read("fixtures/commit.bin").unwrap();
+    for len in 0..data.len() {
+        if let Ok((_, body)) = frame::split_frame(&data[..len]) {
+            assert!(body.len() <= len);
+        }
+    }
+}
+
+#[test]
+fn fixtures_still_decode() {
+    let commit = fs::read("fixtures/commit.bin").unwrap();
+    let (header, _) = frame::split_frame(&commit).unwrap();
+    assert_eq!(header.op, 1);
+    assert_eq!(header.t.as_deref(), Some("#commit"));
+
+    let error = fs::read("fixtures/error.bin").unwrap();
+    assert!(matches!(frame::decode(&error), Ok(Frame::Error(_))));
+
+    let identity = fs::read("fixtures/identity.bin").unwrap();
+    assert!(matches!(frame::decode(&identity), Ok(Frame::Identity(_))));
+}
+
+#[test]
+fn account_status_survives_a_restart() {
+    let data = fs::read("fixtures/account.bin").unwrap();
+    let Ok(Frame::Account(account)) = frame::decode(&data) else {
+        panic!("account.bin is not an #account frame");
+    };
+
+    let path = std::env::temp_dir().join(format!("account-status-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let did = account.did.as_str();
+    AccountStatuses::open(&path)
+        .unwrap()
+        .record(&account)
+        .unwrap();
+
+    let accounts = AccountStatuses::open(&path).unwrap();
+    assert_eq!(accounts.status(did), Some(AccountStatus::Takendown));
+    assert_eq!(accounts.status("did:plc:someoneelse"), None);
+    fs::remove_file(&path).unwrap();
+}
+
+/// A frame of the deprecated type `t`, with `fields` on top of `did`, `seq` and `time`.
+fn legacy_frame(t: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let header = FrameHeader {
+        op: 1,
+        t: Some(t.to_string()),
+    };
+    let mut body = BTreeMap::from([
+        ("did".to_string(), Ipld::String("did:plc:legacy".into())),
+        ("seq".to_string(), Ipld::Integer(42)),
+        (
+            "time".to_string(),
+            Ipld::String("2023-06-01T12:00:00.000Z".into()),
+        ),
+    ]);
+    for (key, value) in fields {
+        body.insert(key.to_string(), Ipld::String(value.to_string()));
+    }
+    let mut frame = serde_ipld_dagcbor::to_vec(&header).unwrap();
+    frame.extend(serde_ipld_dagcbor::to_vec(&Ipld::Map(body)).unwrap());
+    frame
+}
+
+#[test]
+fn legacy_frames_map_onto_identity_and_account() {
+    let handle = legacy_frame("#handle", &[("handle", "alice.example.com")]);
+    let Ok(Frame::Identity(identity)) = frame::decode(&handle) else {
+        panic!("#handle did not decode to an identity");
+    };
+    assert_eq!(identity.did.as_str(), "did:plc:legacy");
+    assert_eq!(identity.seq, 42);
+    assert_eq!(
+        identity.handle.as_ref().map(|handle| handle.as_str()),
+        Some("alice.example.com")
+    );
+
+    let migrate = legacy_frame("#migrate", &[("migrateTo", "https://pds.example.com")]);
+    let Ok(Frame::Identity(identity)) = frame::decode(&migrate) else {
+        panic!("#migrate did not decode to an identity");
+    };
+    assert_eq!(identity.handle, None);
+
+    let tombstone = legacy_frame("#tombstone", &[]);
+    let Ok(Frame::Account(account)) = frame::decode(&tombstone) else {
+        panic!("#tombstone did not decode to an account");
+    };
+    assert!(!account.active);
+    assert_eq!(
+        AccountStatus::from_frame(account.active, account.status.as_deref()),
+        AccountStatus::Deleted
+    );
+}
+
+#[test]
+fn sync_frames_decode() {
+    let header = FrameHeader {
+        op: 1,
+        t: Some("#sync".to_string()),
+    };
+    let body = BTreeMap::from([
+        ("did".to_string(), Ipld::String("did:plc:synced".into())),
+        ("seq".to_string(), Ipld::Integer(7)),
+        ("blocks".to_string(), Ipld::Bytes(b"car".to_vec())),
+        ("rev".to_string(), Ipld::String("3l3qo2vutsw2b".into())),
+        (
+            "time".to_string(),
+            Ipld::String("2025-03-01T12:00:00.000Z".into()),
+        ),
+    ]);
+    let mut data = serde_ipld_dagcbor::to_vec(&header).unwrap();
+    data.extend(serde_ipld_dagcbor::to_vec(&Ipld::Map(body)).unwrap());
+
+    let Ok(Frame::Sync(sync)) = frame::decode(&data) else {
+        panic!("#sync did not decode");
+    };
+    assert_eq!(sync.did.as_str(), "did:plc:synced");
+    assert_eq!(sync.seq, 7);
+    assert_eq!(sync.rev, "3l3qo2vutsw2b");
+    assert_eq!(&sync.blocks[..], b"car");
+}
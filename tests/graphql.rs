@@ -0,0 +1,140 @@
+//! GraphQL API: queries read the SQLite capture, and subscriptions stream haikus as they're
+//! published.
+
+use std::time::Duration;
+
+use async_graphql::Response;
+use atrium_api::app::bsky::feed::post;
+use bsky_firehose_listener::{
+    client::Event,
+    frame::{self, Frame},
+    graphql::{FirehoseSchema, GraphQl},
+    haiku::{Haiku, HaikuRecord},
+    sqlite::{PostCapture, PostSearch},
+};
+use futures_util::{FutureExt, StreamExt};
+use serde_json::{json, Value};
+
+/// The post in the commit fixture.
+async fn fixture_post() -> (Event, post::Record) {
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    let blocks = frame::blocks(&commit).await.unwrap();
+    let operation = &commit.ops[0];
+    let cid = operation.cid.clone().unwrap();
+    let (collection, rkey) = operation.path.split_once('/').unwrap();
+    let evt = Event {
+        seq: commit.seq,
+        repo: commit.repo.clone(),
+        rev: commit.rev.clone(),
+        since: None,
+        action: "create".to_string(),
+        collection: collection.to_string(),
+        rkey: rkey.to_string(),
+        block: blocks.get(&cid.0).cloned(),
+        cid: Some(cid),
+        account_status: None,
+    };
+    let record = evt.record::<post::Record>().unwrap().unwrap();
+    (evt, record)
+}
+
+/// A `form` found in the fixture post, as if it were posted as `rkey`.
+fn haiku(evt: &Event, record: &post::Record, rkey: &str, form: &str) -> HaikuRecord {
+    let evt = Event {
+        rkey: rkey.to_string(),
+        ..evt.clone()
+    };
+    let found = Haiku {
+        form: form.to_string(),
+        lines: vec!["one".to_string(), "two".to_string(), "three".to_string()],
+        syllables: vec![5, 7, 5],
+    };
+    HaikuRecord::new(&evt, record, found, None, None)
+}
+
+fn data(response: Response) -> Value {
+    assert!(response.errors.is_empty(), "query failed");
+    response.data.into_json().unwrap()
+}
+
+/// Runs `query` until it returns `expected`, as the capture writes in the background.
+async fn query_until(schema: &FirehoseSchema, query: &str, expected: Value) {
+    let mut found = Value::Null;
+    for _ in 0..50 {
+        found = data(schema.execute(query).await);
+        if found == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(found, expected, "{query}");
+}
+
+#[tokio::test]
+async fn queries_captured_posts_and_haikus() {
+    let path = std::env::temp_dir().join(format!("graphql-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (evt, record) = fixture_post().await;
+    let did = evt.repo.as_str();
+    let uri = format!("at://{did}/{}/{}", evt.collection, evt.rkey);
+
+    let capture = PostCapture::spawn(&path).unwrap();
+    capture.push(&evt);
+    capture.push_haiku(&haiku(&evt, &record, "haiku", "haiku"));
+    capture.push_haiku(&haiku(&evt, &record, "tanka", "tanka"));
+    let graphql = GraphQl::new(PostSearch::open(&path).unwrap());
+    let schema = graphql.schema();
+
+    query_until(
+        schema,
+        &format!(r#"{{ posts(author: "{did}") {{ uri text }} }}"#),
+        json!({ "posts": [{ "uri": uri, "text": record.text }] }),
+    )
+    .await;
+    query_until(
+        schema,
+        r#"{ haikus(form: "tanka") { uri form lines } }"#,
+        json!({ "haikus": [{
+            "uri": format!("at://{did}/{}/tanka", evt.collection),
+            "form": "tanka",
+            "lines": ["one", "two", "three"],
+        }] }),
+    )
+    .await;
+    let all = data(schema.execute("{ haikus { form } }").await);
+    assert_eq!(all["haikus"].as_array().unwrap().len(), 2);
+
+    let invalid = schema
+        .execute(r#"{ search(query: "hello", since: "soon") { uri } }"#)
+        .await;
+    assert!(!invalid.errors.is_empty(), "since must be a window");
+}
+
+#[tokio::test]
+async fn streams_haikus_to_subscriptions() {
+    let path = std::env::temp_dir().join(format!("graphql-live-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (evt, record) = fixture_post().await;
+    let _capture = PostCapture::spawn(&path).unwrap();
+    let graphql = GraphQl::new(PostSearch::open(&path).unwrap());
+
+    let mut tankas = graphql
+        .schema()
+        .execute_stream(r#"subscription { haikus(form: "tanka") { uri form } }"#);
+    // Polling once runs the resolver, subscribing before anything is published
+    assert!(tankas.next().now_or_never().is_none());
+    graphql.publish(&haiku(&evt, &record, "first", "haiku"));
+    graphql.publish(&haiku(&evt, &record, "second", "tanka"));
+
+    let response = tankas.next().await.unwrap();
+    assert_eq!(
+        data(response),
+        json!({ "haikus": {
+            "uri": format!("at://{}/{}/second", evt.repo.as_str(), evt.collection),
+            "form": "tanka",
+        } })
+    );
+}
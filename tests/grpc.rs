@@ -0,0 +1,84 @@
+//! gRPC: subscribers receive the operations passing their filters as typed events.
+
+use std::time::Duration;
+
+use bsky_firehose_listener::{
+    client::Event,
+    frame::{self, Frame},
+    grpc::{
+        self,
+        proto::{firehose_client::FirehoseClient, FilterRequest},
+        EventService,
+    },
+};
+
+#[tokio::test]
+async fn streams_filtered_events() {
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    let blocks = frame::blocks(&commit).await.unwrap();
+    let operation = &commit.ops[0];
+    let cid = operation.cid.clone().unwrap();
+    let (collection, rkey) = operation.path.split_once('/').unwrap();
+    let post = Event {
+        seq: commit.seq,
+        repo: commit.repo.clone(),
+        rev: commit.rev.clone(),
+        since: None,
+        action: "create".to_string(),
+        collection: collection.to_string(),
+        rkey: rkey.to_string(),
+        block: blocks.get(&cid.0).cloned(),
+        cid: Some(cid),
+        account_status: None,
+    };
+    let like = Event {
+        collection: "app.bsky.feed.like".to_string(),
+        block: None,
+        cid: None,
+        ..post.clone()
+    };
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let service = EventService::new(16);
+    grpc::spawn(addr, service.clone());
+    let mut client = None;
+    for _ in 0..50 {
+        match FirehoseClient::connect(format!("http://{addr}")).await {
+            Ok(connected) => {
+                client = Some(connected);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+        }
+    }
+    let mut events = client
+        .expect("the gRPC server is up")
+        .subscribe(FilterRequest {
+            collections: vec!["app.bsky.feed.post".to_string()],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    service.publish(&like);
+    service.publish(&post);
+    let received = events.message().await.unwrap().unwrap();
+    assert_eq!(received.seq, post.seq);
+    assert_eq!(received.collection, "app.bsky.feed.post");
+    assert_eq!(received.rkey, post.rkey);
+    assert_eq!(
+        received.record_cbor.as_deref(),
+        post.block.as_deref(),
+        "the record is passed on byte for byte"
+    );
+    let record: serde_json::Value =
+        serde_json::from_str(received.record_json.as_deref().unwrap()).unwrap();
+    assert_eq!(record["$type"], "app.bsky.feed.post");
+}
@@ -0,0 +1,127 @@
+//! Content labels: self-labels are read from any record, labeler labels come and go by
+//! subject, and filters include or exclude by them.
+
+use std::num::NonZeroUsize;
+
+use bsky_firehose_listener::{
+    firehose,
+    labels::{self, LabelFilter, Labelers, SubjectLabel},
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+const DID: &str = "did:plc:alice";
+const POST: &str = "at://did:plc:alice/app.bsky.feed.post/3k";
+
+fn label(uri: &str, val: &str) -> SubjectLabel {
+    labeled_by("did:plc:labeler", uri, val)
+}
+
+fn labeled_by(src: &str, uri: &str, val: &str) -> SubjectLabel {
+    SubjectLabel {
+        src: src.to_string(),
+        uri: uri.to_string(),
+        val: val.to_string(),
+        neg: false,
+        exp: None,
+    }
+}
+
+#[test]
+fn reads_self_labels() {
+    let record = json!({
+        "text": "",
+        "labels": {
+            "$type": "com.atproto.label.defs#selfLabels",
+            "values": [{ "val": "porn" }, { "val": "graphic-media" }],
+        },
+    });
+    assert_eq!(labels::self_labels(&record), ["porn", "graphic-media"]);
+    assert!(labels::self_labels(&json!({ "text": "" })).is_empty());
+}
+
+#[test]
+fn includes_and_excludes() {
+    let labels = ["porn".to_string()];
+    assert!(LabelFilter::default().allows(&labels));
+    assert!(LabelFilter::default().allows(&[]));
+
+    let exclude = LabelFilter::new(Vec::new(), vec!["porn".to_string(), "gore".to_string()]);
+    assert!(!exclude.allows(&labels));
+    assert!(exclude.allows(&[]));
+
+    let include = LabelFilter::new(vec!["graphic-*".to_string()], Vec::new());
+    assert!(include.allows(&["graphic-media".to_string()]));
+    assert!(!include.allows(&labels));
+    assert!(!include.allows(&[]), "unlabelled posts carry none of them");
+}
+
+#[test]
+fn tracks_labeler_labels() {
+    let labelers = Labelers::new(NonZeroUsize::new(10).unwrap());
+    labelers.apply(&label(DID, "spam"));
+    labelers.apply(&label(POST, "porn"));
+    labelers.apply(&SubjectLabel {
+        exp: Some(Utc::now() - Duration::hours(1)),
+        ..label(POST, "nudity")
+    });
+
+    let mut found = labelers.labels(POST, DID);
+    found.sort();
+    assert_eq!(
+        found,
+        ["porn", "spam"],
+        "account labels apply to every post"
+    );
+    assert_eq!(
+        labelers.labels("at://did:plc:alice/app.bsky.feed.post/other", DID),
+        ["spam"]
+    );
+
+    labelers.apply(&SubjectLabel {
+        neg: true,
+        ..label(POST, "porn")
+    });
+    assert_eq!(labelers.labels(POST, DID), ["spam"]);
+}
+
+#[test]
+fn keeps_labels_other_labelers_negate() {
+    let labelers = Labelers::new(NonZeroUsize::new(10).unwrap());
+    labelers.apply(&labeled_by("did:plc:a", POST, "spam"));
+    labelers.apply(&labeled_by("did:plc:b", POST, "spam"));
+    assert_eq!(labelers.labels(POST, DID), ["spam"], "each value once");
+
+    labelers.apply(&SubjectLabel {
+        neg: true,
+        ..labeled_by("did:plc:b", POST, "spam")
+    });
+    assert_eq!(
+        labelers.labels(POST, DID),
+        ["spam"],
+        "labeler a still labels it"
+    );
+    labelers.apply(&SubjectLabel {
+        neg: true,
+        ..labeled_by("did:plc:c", POST, "spam")
+    });
+    assert_eq!(labelers.labels(POST, DID), ["spam"]);
+
+    labelers.apply(&SubjectLabel {
+        neg: true,
+        ..labeled_by("did:plc:a", POST, "spam")
+    });
+    assert!(labelers.labels(POST, DID).is_empty());
+}
+
+#[test]
+fn builds_labeler_urls() {
+    assert_eq!(
+        firehose::subscribe_labels_url("mod.bsky.app"),
+        "wss://mod.bsky.app/xrpc/com.atproto.label.subscribeLabels"
+    );
+    assert_eq!(
+        firehose::subscribe_labels_url("http://localhost:2585/"),
+        "ws://localhost:2585/xrpc/com.atproto.label.subscribeLabels"
+    );
+}
@@ -0,0 +1,46 @@
+//! Repo state mirror: records follow their repo's commits.
+
+use std::sync::Arc;
+
+use bsky_firehose_listener::{
+    blockstore::BlockStore,
+    frame::{self, Frame},
+    mirror::RepoMirror,
+};
+
+#[tokio::test]
+async fn follows_creates_and_deletes() {
+    let dir = std::env::temp_dir().join(format!("repo-mirror-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let blocks = Arc::new(BlockStore::open(&dir.join("blocks")).unwrap());
+    let mirror = RepoMirror::open(&dir.join("repos"), Some(blocks.clone())).unwrap();
+
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(mut commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    for (cid, block) in frame::blocks(&commit).await.unwrap().iter() {
+        blocks.put(&cid.to_string(), block).unwrap();
+    }
+    let did = commit.repo.as_str().to_string();
+    assert!(mirror.dump(&did).unwrap().is_none());
+
+    mirror.apply(&commit).unwrap();
+    let dump = mirror.dump(&did).unwrap().unwrap();
+    assert_eq!(dump.rev, commit.rev);
+    assert_eq!(dump.records.len(), 1);
+    assert_eq!(dump.records[0].path, commit.ops[0].path);
+    assert_eq!(
+        dump.records[0].value.as_ref().unwrap()["$type"],
+        "app.bsky.feed.post"
+    );
+
+    commit.rev = "3l7selftest33".to_string();
+    commit.ops[0].action = "delete".to_string();
+    commit.ops[0].cid = None;
+    mirror.apply(&commit).unwrap();
+    let state = mirror.state(&did).unwrap().unwrap();
+    assert_eq!(state.rev, "3l7selftest33");
+    assert!(state.records.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,334 @@
+//! Client behaviour against a scripted local relay: reconnects, cursor handling and error
+//! frames.
+
+mod support;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bsky_firehose_listener::{
+    client::Client, config::Config, firehose, health::Health, relay::RelayError,
+};
+use support::{commit_frame, future_cursor_frame, MockRelay, Step};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+    time::timeout,
+};
+
+/// A client running against a mock relay.
+struct Listener {
+    /// Sequence numbers of the posts the client emits. Handling a post waits while the one
+    /// before it hasn't been taken.
+    events: mpsc::Receiver<i64>,
+    health: Arc<Health>,
+    task: JoinHandle<()>,
+}
+
+/// Runs a client configured by `config`, e.g. [`MockRelay::config`] with a few changes.
+fn listen(config: Config, cursor: Option<i64>) -> Listener {
+    let (tx, events) = mpsc::channel(1);
+    let mut client = Client::new(config);
+    if let Some(cursor) = cursor {
+        client.cursor(cursor);
+    }
+    client.on("app.bsky.feed.post", move |evt| {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(evt.seq).await;
+        }
+    });
+    Listener {
+        events,
+        health: client.health(),
+        task: tokio::spawn(client.run()),
+    }
+}
+
+async fn next(rx: &mut mpsc::Receiver<i64>) -> i64 {
+    timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("timed out waiting for an event")
+        .expect("client stopped")
+}
+
+/// Waits until `relay` has seen `count` connections.
+async fn connections(relay: &MockRelay, count: usize) -> Vec<Option<String>> {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            let cursors = relay.cursors();
+            if cursors.len() >= count {
+                return cursors;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the client to connect")
+}
+
+#[tokio::test]
+async fn reconnects_and_resumes_from_cursor() {
+    let relay = MockRelay::start(vec![
+        vec![Step::Send(commit_frame(10)), Step::Close],
+        vec![Step::Send(commit_frame(11))],
+    ])
+    .await;
+    let mut events = listen(relay.config(), None).events;
+
+    assert_eq!(next(&mut events).await, 10);
+    assert_eq!(next(&mut events).await, 11);
+    assert_eq!(
+        connections(&relay, 2).await,
+        vec![None, Some("10".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn drops_operations_resent_after_reconnecting() {
+    // The relay resends commit 11 to the resumed connection
+    let relay = MockRelay::start(vec![
+        vec![
+            Step::Send(commit_frame(10)),
+            Step::Send(commit_frame(11)),
+            Step::Close,
+        ],
+        vec![Step::Send(commit_frame(11)), Step::Send(commit_frame(12))],
+    ])
+    .await;
+    let mut events = listen(relay.config(), None).events;
+
+    assert_eq!(next(&mut events).await, 10);
+    assert_eq!(next(&mut events).await, 11);
+    assert_eq!(next(&mut events).await, 12);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decodes_on_blocking_pool_with_parallel_ops() {
+    let relay = MockRelay::start(vec![vec![
+        Step::Send(commit_frame(20)),
+        Step::Send(commit_frame(21)),
+    ]])
+    .await;
+    let mut config = relay.config();
+    config.decode_blocking = true;
+    config.commit_parallelism = 4;
+    let mut events = listen(config, None).events;
+
+    assert_eq!(next(&mut events).await, 20);
+    assert_eq!(next(&mut events).await, 21);
+}
+
+#[tokio::test]
+async fn starts_from_configured_cursor() {
+    let relay = MockRelay::start(vec![vec![Step::Send(commit_frame(43))]]).await;
+    let mut events = listen(relay.config(), Some(42)).events;
+
+    assert_eq!(next(&mut events).await, 43);
+    assert_eq!(connections(&relay, 1).await, vec![Some("42".to_string())]);
+}
+
+#[tokio::test]
+async fn replays_commits_left_queued_when_stopped() {
+    let relay = MockRelay::start(vec![
+        vec![
+            Step::Send(commit_frame(1)),
+            Step::Send(commit_frame(2)),
+            Step::Send(commit_frame(3)),
+            Step::Wait(Duration::from_secs(60)),
+        ],
+        vec![Step::Send(commit_frame(2)), Step::Send(commit_frame(3))],
+    ])
+    .await;
+    // Nothing is taken, so 2 is stuck being handled and 3 stays queued
+    let stopped = listen(relay.config(), None);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(stopped.health.cursor(), Some(1));
+    stopped.task.abort();
+
+    let mut events = listen(relay.config(), stopped.health.cursor()).events;
+    assert_eq!(next(&mut events).await, 2);
+    assert_eq!(next(&mut events).await, 3);
+    assert_eq!(connections(&relay, 2).await[1], Some("1".to_string()));
+}
+
+#[tokio::test]
+async fn future_cursor_error_resets_cursor() {
+    let relay = MockRelay::start(vec![
+        vec![
+            Step::Send(future_cursor_frame()),
+            Step::Wait(Duration::from_millis(100)),
+            Step::Close,
+        ],
+        vec![Step::Send(commit_frame(7))],
+    ])
+    .await;
+    let mut events = listen(relay.config(), Some(1_000_000)).events;
+
+    assert_eq!(next(&mut events).await, 7);
+    assert_eq!(
+        connections(&relay, 2).await,
+        vec![Some("1000000".to_string()), None]
+    );
+}
+
+#[tokio::test]
+async fn emits_events_across_sequence_gaps() {
+    let relay = MockRelay::start(vec![vec![
+        Step::Send(commit_frame(100)),
+        Step::Send(commit_frame(250)),
+    ]])
+    .await;
+    let mut events = listen(relay.config(), None).events;
+
+    assert_eq!(next(&mut events).await, 100);
+    assert_eq!(next(&mut events).await, 250);
+}
+
+#[tokio::test]
+async fn pds_hosts_resume_from_their_own_cursors() {
+    let first = MockRelay::start(vec![
+        vec![Step::Send(commit_frame(10)), Step::Close],
+        vec![Step::Send(commit_frame(11))],
+    ])
+    .await;
+    let second = MockRelay::start(vec![vec![Step::Send(commit_frame(500))]]).await;
+
+    let mut config = first.config();
+    config.pds_hosts = vec![first.url.clone(), second.url.clone()];
+    let Listener {
+        mut events, health, ..
+    } = listen(config, None);
+
+    let mut seen = vec![
+        next(&mut events).await,
+        next(&mut events).await,
+        next(&mut events).await,
+    ];
+    seen.sort();
+    assert_eq!(seen, vec![10, 11, 500]);
+    // Each reconnects once its script runs out, from its own latest sequence number
+    assert_eq!(
+        connections(&first, 2).await[..2],
+        [None, Some("10".to_string())]
+    );
+    assert_eq!(
+        connections(&second, 2).await[..2],
+        [None, Some("500".to_string())]
+    );
+    // Their sequence numbers don't share one order, so no cursor is reported for saving
+    assert_eq!(health.cursor(), None);
+}
+
+#[tokio::test]
+async fn reads_the_current_seq_from_the_next_relay() {
+    let relay = MockRelay::start(vec![vec![Step::Send(commit_frame(42))]]).await;
+    let mut config = relay.config();
+    // Nothing listens on port 1
+    config.relays = vec!["ws://127.0.0.1:1".to_string(), relay.url.clone()];
+    config.failover_after = 1;
+    assert_eq!(
+        firehose::current_seq(&config).await.unwrap(),
+        (relay.url.clone(), 42)
+    );
+
+    config.relays.clear();
+    assert!(matches!(
+        firehose::current_seq(&config).await,
+        Err(RelayError::NoRelays)
+    ));
+}
+
+/// Starts an HTTP proxy tunnelling every `CONNECT`, returning its URL and the targets asked for.
+async fn connect_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let targets = Arc::new(Mutex::new(Vec::new()));
+    let seen = targets.clone();
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    request.push(client.read_u8().await.unwrap());
+                }
+                let request = String::from_utf8(request).unwrap();
+                let target = request.split_whitespace().nth(1).unwrap().to_string();
+                seen.lock().unwrap().push(target.clone());
+
+                let mut upstream = TcpStream::connect(&target).await.unwrap();
+                client
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    });
+    (url, targets)
+}
+
+#[tokio::test]
+async fn connects_through_http_proxy() {
+    let relay = MockRelay::start(vec![vec![Step::Send(commit_frame(5))]]).await;
+    let (proxy, targets) = connect_proxy().await;
+
+    let mut config = relay.config();
+    config.proxy = Some(proxy.parse().unwrap());
+    let mut events = listen(config, None).events;
+
+    assert_eq!(next(&mut events).await, 5);
+    let relay_addr = relay.url["ws://".len()..].split('/').next().unwrap();
+    assert_eq!(targets.lock().unwrap()[0], relay_addr);
+}
+
+#[tokio::test]
+async fn sends_configured_user_agent_and_headers() {
+    let relay = MockRelay::start(vec![vec![Step::Send(commit_frame(1))]]).await;
+
+    let mut config = relay.config();
+    config.user_agent = "research-crawler/1.0".to_string();
+    // Values may contain commas, so headers are separated by newlines
+    config.headers = firehose::parse_headers(
+        "Authorization: Bearer secret\nAccept: a, b\n\n X-Team:  haikus \n",
+    )
+    .unwrap();
+    let mut events = listen(config, None).events;
+
+    assert_eq!(next(&mut events).await, 1);
+    let headers = &relay.headers()[0];
+    assert_eq!(headers["user-agent"], "research-crawler/1.0");
+    assert_eq!(headers["authorization"], "Bearer secret");
+    assert_eq!(headers["accept"], "a, b");
+    assert_eq!(headers["x-team"], "haikus");
+}
+
+#[tokio::test]
+async fn reconnects_when_pings_go_unanswered() {
+    // The mock relay never reads from its socket, so it never answers pings
+    let relay = MockRelay::start(vec![
+        vec![
+            Step::Send(commit_frame(1)),
+            Step::Wait(Duration::from_secs(5)),
+        ],
+        vec![Step::Send(commit_frame(2))],
+    ])
+    .await;
+
+    let mut config = relay.config();
+    config.ping_interval = Duration::from_millis(50);
+    config.pong_timeout = Duration::from_millis(200);
+    let mut events = listen(config, None).events;
+
+    assert_eq!(next(&mut events).await, 1);
+    assert_eq!(next(&mut events).await, 2);
+    assert_eq!(
+        connections(&relay, 2).await,
+        vec![None, Some("1".to_string())]
+    );
+}
@@ -0,0 +1,174 @@
+//! Moderation: lexicon terms match as whole words, posts are held back when the moderation
+//! API can't vouch for them, and held back haikus stay out of every public sink.
+
+mod support;
+
+use std::time::Duration;
+
+use atrium_api::app::bsky::feed::post;
+use bsky_firehose_listener::{
+    atom::AtomFeed,
+    feedgen::FeedGenerator,
+    gallery::Gallery,
+    graphql::GraphQl,
+    haiku::{Haiku, HaikuRecord},
+    moderation::{Moderation, ModerationApiConfig},
+    notify::Notification,
+    sqlite::{PostCapture, PostSearch},
+};
+use futures_util::{FutureExt, StreamExt};
+use serde_json::json;
+use support::event;
+
+/// A haiku found in the post `rkey`, held back by moderation when `held_back` is set.
+fn haiku(rkey: &str, held_back: bool) -> HaikuRecord {
+    let record: post::Record = serde_json::from_value(json!({
+        "text": "one two three",
+        "createdAt": "2024-11-02T09:14:03.000Z",
+    }))
+    .unwrap();
+    let found = Haiku {
+        form: "haiku".to_string(),
+        lines: vec!["one".to_string(), "two".to_string(), "three".to_string()],
+        syllables: vec![5, 7, 5],
+    };
+    let evt = event("app.bsky.feed.post", rkey);
+    let mut haiku = HaikuRecord::new(&evt, &record, found, None, None);
+    haiku.held_back = held_back;
+    haiku
+}
+
+/// A haiku output file with a held back haiku and a clean one after it.
+fn haiku_output(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("{name}-{}.jsonl", std::process::id()));
+    let lines = [haiku("held", true), haiku("clean", false)]
+        .iter()
+        .map(|haiku| serde_json::to_string(haiku).unwrap() + "\n")
+        .collect::<String>();
+    std::fs::write(&path, lines).unwrap();
+    path
+}
+
+#[test]
+fn matches_whole_words() {
+    let moderation = Moderation::new(vec![
+        "Jerk".to_string(),
+        "bad word".to_string(),
+        " ".to_string(),
+    ])
+    .unwrap();
+    assert_eq!(
+        moderation.matches("What a JERK, such a bad word. jerk!"),
+        ["jerk", "bad word"],
+        "each term once, ignoring case"
+    );
+    assert!(moderation.matches("jerky is a snack").is_empty());
+    assert!(moderation.matches("a badword").is_empty());
+}
+
+#[tokio::test]
+async fn holds_back_when_unsure() {
+    let lexicon = Moderation::new(vec!["jerk".to_string()]).unwrap();
+    assert!(lexicon.check("have a nice day").await.is_clean());
+    assert_eq!(lexicon.check("you jerk").await.terms, ["jerk"]);
+
+    // Nothing listens on port 1
+    let api = Moderation::new(Vec::new()).unwrap().with_api(
+        reqwest::Client::new(),
+        ModerationApiConfig {
+            url: "http://127.0.0.1:1/v1/moderations".to_string(),
+            model: None,
+            api_key: None,
+        },
+    );
+    let verdict = api.check("have a nice day").await;
+    assert!(!verdict.is_clean());
+    assert!(verdict.error.is_some());
+}
+
+#[tokio::test]
+async fn holds_back_notifications() {
+    let moderation = Moderation::new(vec!["jerk".to_string()]).unwrap();
+    let evt = event("app.bsky.feed.post", "3k");
+    let flagged = Notification::plugin("spam", &evt, "Found".to_string(), "you jerk".to_string());
+    assert!(!moderation.allows_notification(&flagged).await);
+    let clean = Notification::plugin("spam", &evt, "Found".to_string(), "hello".to_string());
+    assert!(moderation.allows_notification(&clean).await);
+}
+
+#[tokio::test]
+async fn keeps_held_back_haikus_off_the_gallery() {
+    let path = haiku_output("gallery-held-back");
+    let gallery = Gallery::new(path.clone(), 10);
+    let (page, _) = gallery.read_page(1).unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].url, haiku("clean", false).url);
+
+    let mut live = gallery.subscribe();
+    gallery.publish(&haiku("held", true));
+    gallery.publish(&haiku("clean", false));
+    let json = live.recv().await.unwrap();
+    assert!(json.contains("/post/clean"), "got {json}");
+    assert!(live.try_recv().is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn keeps_held_back_haikus_out_of_graphql() {
+    let path =
+        std::env::temp_dir().join(format!("graphql-held-back-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let capture = PostCapture::spawn(&path).unwrap();
+    let posts = PostSearch::open(&path).unwrap();
+
+    capture.push_haiku(&haiku("held", true));
+    capture.push_haiku(&haiku("clean", false));
+    let mut stored = Vec::new();
+    for _ in 0..50 {
+        stored = posts.haikus(None, None, None, 10).unwrap();
+        if !stored.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].uri, haiku("clean", false).uri);
+
+    let graphql = GraphQl::new(posts);
+    let mut live = graphql
+        .schema()
+        .execute_stream("subscription { haikus { uri } }");
+    // Polling once runs the resolver, subscribing before anything is published
+    assert!(live.next().now_or_never().is_none());
+    graphql.publish(&haiku("held", true));
+    graphql.publish(&haiku("clean", false));
+    let response = live.next().await.unwrap();
+    assert_eq!(
+        response.data.into_json().unwrap(),
+        json!({ "haikus": { "uri": haiku("clean", false).uri } })
+    );
+}
+
+#[test]
+fn keeps_held_back_haikus_out_of_the_atom_feed() {
+    let feed = AtomFeed::new(10, Vec::new());
+    feed.push_haiku(&haiku("held", true));
+    feed.push_haiku(&haiku("clean", false));
+    let xml = feed.render();
+    assert!(xml.contains(&haiku("clean", false).uri));
+    assert!(!xml.contains(&haiku("held", true).uri));
+}
+
+#[test]
+fn keeps_held_back_haikus_out_of_the_feed_generator() {
+    let feedgen = FeedGenerator::new("feed.example.com", "did:plc:publisher", "haikus", 10);
+    feedgen.push_haiku(&haiku("held", true));
+    feedgen.push_haiku(&haiku("clean", false));
+    assert_eq!(feedgen.uris(), [haiku("clean", false).uri]);
+
+    let path = haiku_output("feedgen-held-back");
+    let reloaded = FeedGenerator::new("feed.example.com", "did:plc:publisher", "haikus", 10);
+    assert_eq!(reloaded.load(&path).unwrap(), 1);
+    assert_eq!(reloaded.uris(), [haiku("clean", false).uri]);
+    std::fs::remove_file(&path).unwrap();
+}
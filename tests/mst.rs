@@ -0,0 +1,122 @@
+//! MST proofs: commits whose ops match the tree pass, and ones that don't are rejected.
+
+use std::collections::BTreeMap;
+
+use atrium_api::{com::atproto::sync::subscribe_repos::Commit, types::CidLink};
+use bsky_firehose_listener::{
+    frame::{self, Frame},
+    mst::{self, ProofError},
+};
+use ipld_core::{
+    cid::{multihash::Multihash, Cid},
+    ipld::Ipld,
+};
+use sha2::{Digest, Sha256};
+
+const DAG_CBOR: u64 = 0x71;
+const SHA2_256: u64 = 0x12;
+
+fn encode(ipld: &Ipld) -> (Cid, Vec<u8>) {
+    let data = serde_ipld_dagcbor::to_vec(ipld).unwrap();
+    let hash = Multihash::wrap(SHA2_256, &Sha256::digest(&data)).unwrap();
+    (Cid::new_v1(DAG_CBOR, hash), data)
+}
+
+fn varint(mut n: usize, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// The fixture commit, with a one-entry MST holding its post and a commit object on top, and
+/// the CID of the MST root.
+async fn proven_commit() -> (Box<Commit>, Cid) {
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(mut commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    let operation = &commit.ops[0];
+    let record = operation.cid.as_ref().unwrap().0;
+    let record_block = frame::blocks(&commit)
+        .await
+        .unwrap()
+        .get(&record)
+        .unwrap()
+        .to_vec();
+
+    let entry = Ipld::Map(BTreeMap::from([
+        ("p".to_string(), Ipld::Integer(0)),
+        (
+            "k".to_string(),
+            Ipld::Bytes(operation.path.as_bytes().to_vec()),
+        ),
+        ("v".to_string(), Ipld::Link(record)),
+        ("t".to_string(), Ipld::Null),
+    ]));
+    let (root, root_block) = encode(&Ipld::Map(BTreeMap::from([
+        ("l".to_string(), Ipld::Null),
+        ("e".to_string(), Ipld::List(vec![entry])),
+    ])));
+    let (head, head_block) = encode(&Ipld::Map(BTreeMap::from([
+        ("did".to_string(), Ipld::String(commit.repo.as_str().into())),
+        ("rev".to_string(), Ipld::String(commit.rev.clone())),
+        ("data".to_string(), Ipld::Link(root)),
+        ("version".to_string(), Ipld::Integer(3)),
+    ])));
+
+    let header = serde_ipld_dagcbor::to_vec(&Ipld::Map(BTreeMap::from([
+        ("roots".to_string(), Ipld::List(vec![Ipld::Link(head)])),
+        ("version".to_string(), Ipld::Integer(1)),
+    ])))
+    .unwrap();
+    let mut car = Vec::new();
+    varint(header.len(), &mut car);
+    car.extend(header);
+    for (cid, block) in [
+        (head, head_block),
+        (root, root_block),
+        (record, record_block),
+    ] {
+        let cid = cid.to_bytes();
+        varint(cid.len() + block.len(), &mut car);
+        car.extend(cid);
+        car.extend(block);
+    }
+
+    commit.commit = CidLink(head);
+    commit.blocks = car;
+    (commit, root)
+}
+
+#[tokio::test]
+async fn accepts_ops_in_the_tree() {
+    let (commit, _) = proven_commit().await;
+    let blocks = frame::blocks(&commit).await.unwrap();
+    mst::verify(&commit, &blocks).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_ops_missing_from_the_tree() {
+    let (mut commit, _) = proven_commit().await;
+    let blocks = frame::blocks(&commit).await.unwrap();
+    commit.ops[0].path.push('x');
+
+    assert!(matches!(
+        mst::verify(&commit, &blocks),
+        Err(ProofError::Mismatch { found: None, .. })
+    ));
+}
+
+#[tokio::test]
+async fn rejects_commits_missing_blocks() {
+    let (commit, root) = proven_commit().await;
+    let mut blocks = frame::blocks(&commit).await.unwrap();
+    blocks.remove(&root);
+
+    assert!(matches!(
+        mst::verify(&commit, &blocks),
+        Err(ProofError::MissingBlock(cid)) if cid == root.to_string()
+    ));
+}
@@ -0,0 +1,65 @@
+//! Near-duplicates: the similarity threshold, clusters and forgetting the oldest posts.
+
+use std::num::NonZeroUsize;
+
+use bsky_firehose_listener::neardup::NearDuplicates;
+
+const POST: &str = "the quick brown fox jumps over the lazy dog while the sun sets slowly \
+                    behind the hills and the birds sing their evening songs";
+const EDITED: &str = "the quick brown fox jumps over the lazy dog while the sun sets slowly \
+                      behind the hills and the birds sing their morning tunes";
+const OTHER: &str = "just setting up my bsky and looking for people who post about gardening \
+                     tomatoes and compost bins";
+
+fn near_duplicates(threshold: f64, capacity: usize) -> NearDuplicates {
+    NearDuplicates::new(threshold, NonZeroUsize::new(capacity).unwrap())
+}
+
+#[test]
+fn finds_copies_above_the_threshold() {
+    let near = near_duplicates(0.5, 100);
+    assert_eq!(near.insert(POST), None);
+
+    let copy = near.insert(&POST.to_uppercase()).unwrap();
+    assert_eq!(copy.similarity, 1.0, "case and punctuation are ignored");
+
+    let edited = near.insert(EDITED).unwrap();
+    assert!(edited.similarity >= 0.5 && edited.similarity < 1.0);
+    assert_eq!(near.insert(OTHER), None);
+    assert_eq!(near.insert("?!"), None, "no words");
+}
+
+#[test]
+fn ignores_edits_below_the_threshold() {
+    let near = near_duplicates(1.0, 100);
+    near.insert(POST);
+    assert_eq!(near.insert(EDITED), None);
+    assert!(near.insert(POST).is_some());
+}
+
+#[test]
+fn duplicates_join_the_first_posts_cluster() {
+    let near = near_duplicates(0.5, 100);
+    near.insert(POST);
+    near.insert(OTHER);
+    let edited = near.insert(EDITED).unwrap();
+    // Closest to the edit, which was itself clustered with the first post
+    let copy = near.insert(EDITED).unwrap();
+    assert_eq!(copy.similarity, 1.0);
+    assert_eq!(copy.cluster, edited.cluster);
+    assert_eq!(near.insert(OTHER).unwrap().cluster, 1);
+    assert_eq!(edited.cluster, 0);
+}
+
+#[test]
+fn forgets_the_oldest_posts() {
+    let near = near_duplicates(0.5, 1);
+    near.insert(POST);
+    near.insert(OTHER);
+    assert_eq!(near.insert(POST), None, "pushed out by the other post");
+
+    let near = near_duplicates(0.5, 2);
+    near.insert(POST);
+    near.insert(OTHER);
+    assert!(near.insert(POST).is_some());
+}
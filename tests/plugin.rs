@@ -0,0 +1,56 @@
+//! WebAssembly plugins: the action a plugin points at is checked against its memory and the
+//! size limit before the host reads it.
+
+mod support;
+
+use bsky_firehose_listener::plugin::{Plugin, PluginError, PluginLimits};
+use support::event;
+
+const LIMITS: PluginLimits = PluginLimits {
+    fuel: 1_000_000,
+    memory_bytes: 1 << 20,
+    max_action_bytes: 1024,
+};
+
+/// Loads a plugin with one page (64 KiB) of memory whose `handle_event` always returns
+/// `result`.
+fn plugin(name: &str, result: i64) -> Plugin {
+    let path = std::env::temp_dir().join(format!("{name}-{}.wat", std::process::id()));
+    std::fs::write(
+        &path,
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "handle_event") (param i32 i32) (result i64) (i64.const {result})))"#
+        ),
+    )
+    .unwrap();
+    let plugin = Plugin::load(&path, LIMITS).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    plugin
+}
+
+#[test]
+fn rejects_actions_over_the_limit() {
+    let mut plugin = plugin("huge-action", 0xffff_ffff);
+    assert!(matches!(
+        plugin.handle(&event("app.bsky.feed.post", "3l3qo2vutsw2b")),
+        Err(PluginError::ActionTooLarge {
+            len: 0xffff_ffff,
+            max: 1024
+        })
+    ));
+}
+
+#[test]
+fn rejects_actions_outside_memory() {
+    let mut plugin = plugin("stray-action", (0xffff << 32) | 16);
+    assert!(matches!(
+        plugin.handle(&event("app.bsky.feed.post", "3l3qo2vutsw2b")),
+        Err(PluginError::ActionOutOfBounds {
+            ptr: 0xffff,
+            len: 16
+        })
+    ));
+}
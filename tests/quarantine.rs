@@ -0,0 +1,32 @@
+//! Quarantine: frames that fail to decode are kept as received, filed by what failed.
+
+use bsky_firehose_listener::{frame, quarantine::Quarantine};
+
+#[test]
+fn keeps_failed_frames_by_kind_up_to_limit() {
+    let dir = std::env::temp_dir().join(format!("quarantine-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let quarantine = Quarantine::open(&dir, 2).unwrap();
+
+    let garbage = b"\xff\xff not a frame";
+    let e = frame::decode(garbage).unwrap_err();
+    assert_eq!(e.kind(), "header");
+    let path = quarantine.keep(garbage, None, &e).unwrap();
+    assert!(path.starts_with(dir.join("header")));
+    assert!(path.to_str().unwrap().ends_with("-unknown.frame"));
+    assert_eq!(std::fs::read(&path).unwrap(), garbage);
+
+    let commit = std::fs::read("fixtures/commit.bin").unwrap();
+    let truncated = &commit[..commit.len() / 2];
+    let e = frame::decode(truncated).unwrap_err();
+    assert_eq!(e.kind(), "commit");
+    let path = quarantine.keep(truncated, Some(42), &e).unwrap();
+    assert!(path.starts_with(dir.join("commit")));
+    assert!(path.to_str().unwrap().ends_with("-42.frame"));
+
+    // Past the limit, frames are no longer written
+    assert!(quarantine.keep(garbage, None, &e).is_none());
+    assert_eq!(std::fs::read_dir(dir.join("commit")).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
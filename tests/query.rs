@@ -0,0 +1,70 @@
+//! Query: SQL runs over the Parquet output, with a view per collection.
+
+use std::time::Duration;
+
+use bsky_firehose_listener::{
+    client::Event,
+    frame::{self, Frame},
+    parquet::{ParquetWriter, Rotation},
+    query,
+};
+
+#[tokio::test]
+async fn queries_written_collections() {
+    let dir = std::env::temp_dir().join(format!("query-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(
+        query::run(&dir, "SELECT 1 + 1 AS two")
+            .unwrap()
+            .contains('2'),
+        "queries run before anything is written"
+    );
+
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    let blocks = frame::blocks(&commit).await.unwrap();
+    let operation = &commit.ops[0];
+    let cid = operation.cid.clone().unwrap();
+    let (collection, rkey) = operation.path.split_once('/').unwrap();
+    let evt = Event {
+        seq: commit.seq,
+        repo: commit.repo.clone(),
+        rev: commit.rev.clone(),
+        since: None,
+        action: "create".to_string(),
+        collection: collection.to_string(),
+        rkey: rkey.to_string(),
+        block: blocks.get(&cid.0).cloned(),
+        cid: Some(cid),
+        account_status: None,
+    };
+
+    // Dropping the writer flushes and closes its files
+    let writer = ParquetWriter::spawn(
+        dir.clone(),
+        Rotation {
+            max_rows: 1,
+            max_age: Duration::from_secs(60),
+        },
+    );
+    writer.push(&evt);
+    drop(writer);
+
+    let sql = format!("SELECT rkey FROM \"{collection}\" UNION ALL SELECT rkey FROM events");
+    let mut table = String::new();
+    for _ in 0..50 {
+        match query::run(&dir, &sql) {
+            Ok(result) if result.contains(rkey) => {
+                table = result;
+                break;
+            }
+            _ => tokio::time::sleep(Duration::from_millis(20)).await,
+        }
+    }
+    assert_eq!(table.matches(rkey).count(), 2, "{table}");
+
+    assert!(query::run(&dir.join("missing"), "SELECT 1").is_err());
+}
@@ -0,0 +1,79 @@
+//! Work queues: the order items come out in, and what gets dropped once a queue is full.
+
+use bsky_firehose_listener::{
+    queue::{OverflowPolicy, PriorityQueue, QueueLimits},
+    shedding::ShedPolicy,
+};
+
+fn queue(limits: QueueLimits) -> PriorityQueue<&'static str> {
+    PriorityQueue::new(usize::MAX, ShedPolicy::Off, limits)
+}
+
+async fn drain(queue: &PriorityQueue<&'static str>) -> Vec<&'static str> {
+    let mut items = Vec::new();
+    while !queue.is_empty() {
+        items.push(queue.pop().await);
+    }
+    items
+}
+
+#[tokio::test]
+async fn keeps_each_keys_order_whatever_the_priority() {
+    let queue = queue(QueueLimits::default());
+    queue.push("did:plc:alice", 0, "alice likes", 1);
+    queue.push("did:plc:bob", 0, "bob likes", 1);
+    queue.push("did:plc:alice", 1, "alice posts", 1);
+    queue.push("did:plc:alice", 0, "alice deletes the like", 1);
+    queue.push("did:plc:bob", 1, "bob posts", 1);
+    queue.push("did:plc:carol", 1, "carol posts", 1);
+
+    let order = drain(&queue).await;
+    let position = |item| order.iter().position(|&popped| popped == item).unwrap();
+    assert!(position("alice likes") < position("alice posts"));
+    assert!(position("alice posts") < position("alice deletes the like"));
+    assert!(position("bob likes") < position("bob posts"));
+    assert_eq!(order[0], "carol posts", "other repos' posts go first");
+}
+
+#[tokio::test]
+async fn drops_oldest_past_max_len() {
+    let queue = queue(QueueLimits {
+        max_len: Some(2),
+        max_bytes: None,
+        overflow: OverflowPolicy::Oldest,
+    });
+
+    assert!(queue.push("did:plc:alice", 1, "old post", 1).is_empty());
+    assert!(queue.push("did:plc:alice", 0, "like", 1).is_empty());
+    assert_eq!(queue.push("did:plc:alice", 0, "follow", 1), ["old post"]);
+    assert_eq!(drain(&queue).await, ["like", "follow"]);
+}
+
+#[tokio::test]
+async fn drops_low_priority_past_max_bytes() {
+    let queue = queue(QueueLimits {
+        max_len: None,
+        max_bytes: Some(100),
+        overflow: OverflowPolicy::LowPriority,
+    });
+
+    assert!(queue.push("did:plc:alice", 1, "post", 60).is_empty());
+    assert!(queue.push("did:plc:alice", 0, "like", 30).is_empty());
+    // Dropping the like alone makes room
+    assert_eq!(queue.push("did:plc:alice", 1, "reply", 40), ["like"]);
+    assert_eq!(queue.bytes(), 100);
+    assert_eq!(drain(&queue).await, ["post", "reply"]);
+}
+
+#[tokio::test]
+async fn keeps_a_lone_item_over_max_bytes() {
+    let queue = queue(QueueLimits {
+        max_len: None,
+        max_bytes: Some(10),
+        overflow: OverflowPolicy::Oldest,
+    });
+
+    assert!(queue.push("did:plc:alice", 0, "huge", 50).is_empty());
+    assert_eq!(queue.push("did:plc:alice", 0, "small", 1), ["huge"]);
+    assert_eq!(drain(&queue).await, ["small"]);
+}
@@ -0,0 +1,39 @@
+//! Sink rate limiting: bursts, refills and what happens to events over the limit.
+
+use std::time::{Duration, Instant};
+
+use bsky_firehose_listener::ratelimit::{LimitPolicy, RateLimit, RateLimiter};
+
+#[tokio::test]
+async fn drops_past_the_burst() {
+    let limiter = RateLimiter::new("2/h".parse().unwrap(), LimitPolicy::Drop);
+
+    assert!(limiter.admit().await);
+    assert!(limiter.admit().await);
+    assert!(!limiter.admit().await);
+}
+
+#[tokio::test]
+async fn queues_until_refilled() {
+    let limiter = RateLimiter::new("20/s".parse().unwrap(), LimitPolicy::Queue);
+    for _ in 0..20 {
+        assert!(limiter.admit().await);
+    }
+
+    let started = Instant::now();
+    assert!(limiter.admit().await);
+    assert!(started.elapsed() >= Duration::from_millis(40));
+}
+
+#[test]
+fn parses_limits() {
+    assert_eq!(
+        "30/m".parse::<RateLimit>(),
+        Ok(RateLimit {
+            count: 30,
+            period: Duration::from_secs(60),
+        })
+    );
+    assert!("0/s".parse::<RateLimit>().is_err());
+    assert!("10/d".parse::<RateLimit>().is_err());
+}
@@ -0,0 +1,85 @@
+//! Per-repo rev ordering: which commits get flagged as out of order.
+
+use std::num::NonZeroUsize;
+
+use bsky_firehose_listener::revisions::{self, RevTracker, ViolationKind};
+
+const REPO: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+
+fn tracker() -> RevTracker {
+    RevTracker::new(NonZeroUsize::new(16).unwrap())
+}
+
+#[test]
+fn accepts_commits_chained_by_since() {
+    let revs = tracker();
+    assert_eq!(revs.check(REPO, 1, "3l3qo2vutsw2b", None), None);
+    assert_eq!(
+        revs.check(REPO, 2, "3l3qo2vuu6a2b", Some("3l3qo2vutsw2b")),
+        None
+    );
+    // Relays don't always know `since`
+    assert_eq!(revs.check(REPO, 3, "3l3qo2vuv2k2b", None), None);
+}
+
+#[test]
+fn flags_rollbacks_without_moving_back() {
+    let revs = tracker();
+    revs.check(REPO, 1, "3l3qo2vuu6a2b", None);
+
+    let violation = revs.check(REPO, 2, "3l3qo2vutsw2b", None).unwrap();
+    assert_eq!(violation.kind, ViolationKind::Rollback);
+    assert_eq!(violation.last_rev, "3l3qo2vuu6a2b");
+
+    // The next commit is still checked against the latest rev
+    assert_eq!(
+        revs.check(REPO, 3, "3l3qo2vuv2k2b", Some("3l3qo2vuu6a2b")),
+        None
+    );
+}
+
+#[test]
+fn flags_gaps_and_moves_on() {
+    let revs = tracker();
+    revs.check(REPO, 1, "3l3qo2vutsw2b", None);
+
+    let violation = revs
+        .check(REPO, 5, "3l3qo2vuv2k2b", Some("3l3qo2vuu6a2b"))
+        .unwrap();
+    assert_eq!(violation.kind, ViolationKind::Gap);
+    assert_eq!(violation.since.as_deref(), Some("3l3qo2vuu6a2b"));
+
+    assert_eq!(
+        revs.check(REPO, 6, "3l3qo2vuvxs2b", Some("3l3qo2vuv2k2b")),
+        None
+    );
+}
+
+#[test]
+fn sync_resets_the_latest_rev() {
+    let revs = tracker();
+    revs.check(REPO, 1, "3l3qo2vuv2k2b", None);
+
+    // The repo was rebuilt at an earlier rev; commits continue from there without a violation
+    revs.reset(REPO, 5, "3l3qo2vutsw2b");
+    assert_eq!(
+        revs.check(REPO, 6, "3l3qo2vuu6a2b", Some("3l3qo2vutsw2b")),
+        None
+    );
+
+    // Commits from before the sync, dispatched late, are ignored
+    revs.reset(REPO, 10, "3l3qo2vuvxs2b");
+    assert_eq!(revs.check(REPO, 9, "3l3qo2vuv2k2b", None), None);
+    let violation = revs.check(REPO, 11, "3l3qo2vuv2k2b", None).unwrap();
+    assert_eq!(violation.kind, ViolationKind::Rollback);
+}
+
+#[test]
+fn decodes_commit_time_from_revs() {
+    assert_eq!(
+        revisions::rev_time("3l3qo2vuowo2b").map(|time| time.timestamp_micros()),
+        Some(1_725_911_162_246_036)
+    );
+    assert_eq!(revisions::rev_time("not-a-tid"), None);
+    assert_eq!(revisions::rev_time("3l3qo2vuowo21"), None);
+}
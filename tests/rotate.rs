@@ -0,0 +1,171 @@
+//! Rotating files: rolling over by period and size, naming closed files and removing old ones.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bsky_firehose_listener::{
+    compress::Compression,
+    rotate::{RotateEvery, RotatingFile, RotationPolicy},
+};
+use chrono::{DateTime, Utc};
+
+const NEVER: RotationPolicy = RotationPolicy {
+    every: RotateEvery::Never,
+    max_bytes: None,
+    compression: Compression::None,
+    retention: None,
+};
+
+/// An empty directory of its own for each test.
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rotate-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Contents of the closed rotations in `dir`, sorted, as their names depend on the clock.
+fn closed(dir: &Path) -> Vec<String> {
+    let mut contents = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.ends_with("posts.csv"))
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect::<Vec<_>>();
+    contents.sort();
+    contents
+}
+
+#[test]
+fn rolls_over_before_growing_past_the_size_limit() {
+    let dir = dir("size");
+    let path = dir.join("posts.csv");
+    let policy = RotationPolicy {
+        max_bytes: Some(18),
+        ..NEVER
+    };
+    let mut file = RotatingFile::open(&path, policy, b"text\n".to_vec()).unwrap();
+    file.write_all(b"first\n").unwrap();
+    file.write_all(b"second\n").unwrap();
+    assert!(closed(&dir).is_empty(), "18 bytes fit");
+
+    file.write_all(b"third\n").unwrap();
+    assert_eq!(closed(&dir), ["text\nfirst\nsecond\n"]);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "text\nthird\n");
+
+    // A row larger than the limit still goes into a file of its own
+    file.write_all(b"a very long row indeed\n").unwrap();
+    file.write_all(b"fourth\n").unwrap();
+    assert_eq!(
+        closed(&dir),
+        ["text\na very long row indeed\n", "text\nfirst\nsecond\n", "text\nthird\n"]
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "text\nfourth\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn numbers_files_closed_within_the_same_second() {
+    let dir = dir("collisions");
+    let path = dir.join("posts.csv");
+    let policy = RotationPolicy {
+        max_bytes: Some(1),
+        ..NEVER
+    };
+    let mut file = RotatingFile::open(&path, policy, Vec::new()).unwrap();
+    for row in ["1\n", "2\n", "3\n", "4\n"] {
+        file.write_all(row.as_bytes()).unwrap();
+    }
+
+    // None overwrote another, whether or not the clock ticked over in between
+    assert_eq!(closed(&dir), ["1\n", "2\n", "3\n"]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rolls_over_a_file_left_from_an_earlier_period() {
+    let dir = dir("period");
+    let path = dir.join("posts.csv");
+    std::fs::write(&path, "text\nyesterday\n").unwrap();
+    let yesterday = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(yesterday)
+        .unwrap();
+
+    let policy = RotationPolicy {
+        every: RotateEvery::Day,
+        ..NEVER
+    };
+    let mut file = RotatingFile::open(&path, policy, b"text\n".to_vec()).unwrap();
+    file.write_all(b"today\n").unwrap();
+
+    let started_at = DateTime::<Utc>::from(yesterday).format("%Y%m%dT%H%M%S");
+    assert_eq!(
+        std::fs::read_to_string(dir.join(format!("posts.{started_at}.csv"))).unwrap(),
+        "text\nyesterday\n"
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "text\ntoday\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn keeps_a_file_from_the_current_period() {
+    let dir = dir("same-period");
+    let path = dir.join("posts.csv");
+    std::fs::write(&path, "text\nearlier\n").unwrap();
+
+    let policy = RotationPolicy {
+        every: RotateEvery::Day,
+        ..NEVER
+    };
+    let mut file = RotatingFile::open(&path, policy, b"text\n".to_vec()).unwrap();
+    file.write_all(b"later\n").unwrap();
+
+    assert!(closed(&dir).is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "text\nearlier\nlater\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn removes_rotations_past_retention() {
+    let dir = dir("retention");
+    let path = dir.join("posts.csv");
+    let expired = dir.join("posts.20000101T000000.csv");
+    let unrelated = dir.join("posts-notes.csv");
+    for old in [&expired, &unrelated] {
+        File::create(old)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+            .unwrap();
+    }
+
+    let policy = RotationPolicy {
+        max_bytes: Some(1),
+        retention: Some(Duration::from_secs(60 * 60)),
+        ..NEVER
+    };
+    let mut file = RotatingFile::open(&path, policy, Vec::new()).unwrap();
+    file.write_all(b"1\n").unwrap();
+    file.write_all(b"2\n").unwrap();
+
+    // Old rotations are removed in the background
+    for _ in 0..50 {
+        if !expired.exists() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert!(!expired.exists());
+    assert!(unrelated.exists(), "not a rotation");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "2\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
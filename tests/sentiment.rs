@@ -0,0 +1,71 @@
+//! Sentiment: word valences and what modifies them.
+
+use bsky_firehose_listener::sentiment::SentimentAnalyzer;
+
+#[test]
+fn scores_positive_negative_and_neutral_text() {
+    let sentiment = SentimentAnalyzer::default();
+    assert!(sentiment.score("this is amazing") > 0.0);
+    assert!(sentiment.score("this is awful") < 0.0);
+    assert_eq!(sentiment.score("the cat sat on the mat"), 0.0);
+    assert_eq!(sentiment.score(""), 0.0);
+
+    let gushing = sentiment.score(&"awesome ".repeat(50));
+    assert!(gushing > 0.9 && gushing < 1.0, "stays within bounds");
+}
+
+#[test]
+fn boosters_and_dampeners_shift_the_score() {
+    let sentiment = SentimentAnalyzer::default();
+    let plain = sentiment.score("this is amazing");
+    assert!(sentiment.score("this is really amazing") > plain);
+    assert!(sentiment.score("this is slightly amazing") < plain);
+    assert!(sentiment.score("this is really awful") < sentiment.score("this is awful"));
+}
+
+#[test]
+fn negation_flips_the_score() {
+    let sentiment = SentimentAnalyzer::default();
+    assert!(sentiment.score("this is not amazing") < 0.0);
+    assert!(sentiment.score("this isn't amazing") < 0.0);
+    assert!(sentiment.score("never bad") > 0.0);
+    assert!(
+        sentiment.score("not that it is so very amazing") > 0.0,
+        "too far back"
+    );
+}
+
+#[test]
+fn capitals_and_exclamations_add_emphasis() {
+    let sentiment = SentimentAnalyzer::default();
+    let plain = sentiment.score("this is amazing");
+    assert!(sentiment.score("this is AMAZING") > plain);
+    assert_eq!(
+        sentiment.score("THIS IS AMAZING"),
+        plain,
+        "shouting everything emphasizes nothing"
+    );
+    assert!(sentiment.score("this is amazing!") > plain);
+    assert_eq!(
+        sentiment.score("this is amazing!!!!!!"),
+        sentiment.score("this is amazing!!!!"),
+        "at most four count"
+    );
+    assert_eq!(sentiment.score("hello!!!"), 0.0);
+}
+
+#[test]
+fn loads_vader_lexicons() {
+    let path = std::env::temp_dir().join(format!("vader-{}.txt", std::process::id()));
+    std::fs::write(&path, "Yay\t2.0\t0.5\t[2, 2]\nmeh\t-0.5\nbroken line\n").unwrap();
+    let sentiment = SentimentAnalyzer::load_lexicon(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(sentiment.score("yay") > 0.0);
+    assert!(sentiment.score("meh") < 0.0);
+    assert_eq!(
+        sentiment.score("amazing"),
+        0.0,
+        "replaces the built-in lexicon"
+    );
+}
@@ -0,0 +1,28 @@
+//! Pipeline sinks: MQTT topics and NATS subjects are filled in from the event, and MQTT QoS
+//! levels are checked.
+
+mod support;
+
+use bsky_firehose_listener::{mqtt, pipeline};
+use rumqttc::QoS;
+use support::event;
+
+#[test]
+fn fills_in_topic_templates() {
+    let evt = event("app.bsky.feed.like", "3l3qo2vutsw2b");
+    assert_eq!(
+        pipeline::topic("bsky/{collection}/{did}", "likes", &evt),
+        "bsky/app.bsky.feed.like/did:plc:ewvi7nxzyoun6zhxrhs64oiz"
+    );
+    assert_eq!(
+        pipeline::topic("home/{pipeline}/{action}/{rkey}/{unknown}", "likes", &evt),
+        "home/likes/create/3l3qo2vutsw2b/{unknown}"
+    );
+    assert_eq!(
+        pipeline::topic("bsky.{collection}", "likes", &evt),
+        "bsky.app.bsky.feed.like"
+    );
+
+    assert_eq!(mqtt::qos(1), Some(QoS::AtLeastOnce));
+    assert_eq!(mqtt::qos(3), None);
+}
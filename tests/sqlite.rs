@@ -0,0 +1,86 @@
+//! Post search: captured posts are found by their text until deleted.
+
+use std::time::Duration;
+
+use atrium_api::app::bsky::feed::post;
+use bsky_firehose_listener::{
+    client::Event,
+    frame::{self, Frame},
+    sqlite::{PostCapture, PostSearch, Window},
+};
+
+/// Searches until the capture has caught up to `expected` hits.
+async fn search_for(posts: &PostSearch, query: &str, expected: usize) -> usize {
+    for _ in 0..50 {
+        let hits = posts
+            .search(query, Some(Window(Duration::from_secs(60))), 10)
+            .unwrap();
+        if hits.len() == expected {
+            return expected;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    posts.search(query, None, 10).unwrap().len()
+}
+
+#[tokio::test]
+async fn finds_posts_until_deleted() {
+    let path = std::env::temp_dir().join(format!("posts-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(commit)) = frame::decode(&data) else {
+        panic!("commit.bin is not a #commit frame");
+    };
+    let blocks = frame::blocks(&commit).await.unwrap();
+    let operation = &commit.ops[0];
+    let cid = operation.cid.clone().unwrap();
+    let (collection, rkey) = operation.path.split_once('/').unwrap();
+    let mut evt = Event {
+        seq: commit.seq,
+        repo: commit.repo.clone(),
+        rev: commit.rev.clone(),
+        since: None,
+        action: "create".to_string(),
+        collection: collection.to_string(),
+        rkey: rkey.to_string(),
+        block: blocks.get(&cid.0).cloned(),
+        cid: Some(cid),
+        account_status: None,
+    };
+    let record = evt.record::<post::Record>().unwrap().unwrap();
+    let word = record
+        .text
+        .split(|c: char| !c.is_alphanumeric())
+        .find(|word| word.len() > 2)
+        .expect("the fixture post has a word to search for");
+
+    let capture = PostCapture::spawn(&path).unwrap();
+    let posts = PostSearch::open(&path).unwrap();
+    capture.push(&evt);
+    assert_eq!(search_for(&posts, word, 1).await, 1);
+    let hit = &posts.search(word, None, 10).unwrap()[0];
+    assert_eq!(hit.text, record.text);
+    assert_eq!(hit.did, commit.repo.as_str());
+
+    evt.action = "delete".to_string();
+    evt.block = None;
+    evt.cid = None;
+    capture.push(&evt);
+    assert_eq!(search_for(&posts, word, 0).await, 0);
+}
+
+#[test]
+fn parses_search_windows() {
+    assert_eq!(
+        "30m".parse::<Window>().unwrap(),
+        Window(Duration::from_secs(30 * 60))
+    );
+    assert_eq!(
+        "1d".parse::<Window>().unwrap(),
+        Window(Duration::from_secs(24 * 60 * 60))
+    );
+    assert!("1w".parse::<Window>().is_err());
+    assert!("d".parse::<Window>().is_err());
+    assert!("".parse::<Window>().is_err());
+}
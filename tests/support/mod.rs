@@ -0,0 +1,159 @@
+//! Local stand-in for a relay: a websocket server replaying canned frames, one script per
+//! connection, and recording the cursor each connection asked for. Also the fixture events
+//! shared by tests that don't need a relay.
+
+#![allow(dead_code)]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use atrium_api::types::string::Did;
+use bsky_firehose_listener::{
+    client::Event,
+    config::Config,
+    frame::{self, Frame},
+};
+use futures_util::SinkExt;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{Request, Response},
+    http::HeaderMap,
+    Message,
+};
+
+/// What the relay does next on a connection.
+#[derive(Debug, Clone)]
+pub enum Step {
+    Send(Vec<u8>),
+    Wait(Duration),
+    /// Closes the connection; also what happens once a script runs out
+    Close,
+}
+
+pub struct MockRelay {
+    pub url: String,
+    cursors: Arc<Mutex<Vec<Option<String>>>>,
+    headers: Arc<Mutex<Vec<HeaderMap>>>,
+}
+
+impl MockRelay {
+    /// Serves `scripts` to successive connections. Connections beyond the last script are
+    /// kept open without sending anything.
+    pub async fn start(scripts: Vec<Vec<Step>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "ws://{}/xrpc/com.atproto.sync.subscribeRepos",
+            listener.local_addr().unwrap()
+        );
+        let cursors = Arc::new(Mutex::new(Vec::new()));
+        let headers = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = cursors.clone();
+        let recorded_headers = headers.clone();
+        tokio::spawn(async move {
+            let mut scripts = scripts.into_iter();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+                let recorded_headers = recorded_headers.clone();
+                let script = scripts.next();
+                tokio::spawn(async move {
+                    // The error type is fixed by tungstenite
+                    #[allow(clippy::result_large_err)]
+                    let callback = |request: &Request, response: Response| {
+                        let cursor = request.uri().query().and_then(|query| {
+                            query
+                                .split('&')
+                                .find_map(|pair| pair.strip_prefix("cursor="))
+                                .map(String::from)
+                        });
+                        recorded.lock().unwrap().push(cursor);
+                        recorded_headers
+                            .lock()
+                            .unwrap()
+                            .push(request.headers().clone());
+                        Ok(response)
+                    };
+                    let mut ws = tokio_tungstenite::accept_hdr_async(stream, callback)
+                        .await
+                        .unwrap();
+
+                    let Some(script) = script else {
+                        std::future::pending::<()>().await;
+                        return;
+                    };
+                    for step in script {
+                        match step {
+                            Step::Send(data) => {
+                                if ws.send(Message::Binary(data)).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Step::Wait(duration) => tokio::time::sleep(duration).await,
+                            Step::Close => break,
+                        }
+                    }
+                    let _ = ws.close(None).await;
+                });
+            }
+        });
+
+        Self {
+            url,
+            cursors,
+            headers,
+        }
+    }
+
+    /// The `cursor` query parameter of each connection so far, in order.
+    pub fn cursors(&self) -> Vec<Option<String>> {
+        self.cursors.lock().unwrap().clone()
+    }
+
+    /// The request headers of each connection so far, in order.
+    pub fn headers(&self) -> Vec<HeaderMap> {
+        self.headers.lock().unwrap().clone()
+    }
+
+    /// Default configuration pointed at this relay alone.
+    pub fn config(&self) -> Config {
+        let mut config = Config::from_env();
+        config.relays = vec![self.url.clone()];
+        config.stats_interval = None;
+        config.workers = 1;
+        config
+    }
+}
+
+/// The fixture commit frame (one post), renumbered to `seq`.
+pub fn commit_frame(seq: i64) -> Vec<u8> {
+    let data = std::fs::read("fixtures/commit.bin").unwrap();
+    let Ok(Frame::Commit(mut commit)) = frame::decode(&data) else {
+        panic!("fixtures/commit.bin is not a commit frame");
+    };
+    commit.data.seq = seq;
+    frame::encode_commit(&commit)
+}
+
+/// A `FutureCursor` error frame.
+pub fn future_cursor_frame() -> Vec<u8> {
+    std::fs::read("fixtures/error.bin").unwrap()
+}
+
+/// A `create` of `collection/rkey` without a record, as handlers see it.
+pub fn event(collection: &str, rkey: &str) -> Event {
+    Event {
+        seq: 7,
+        repo: Did::new("did:plc:ewvi7nxzyoun6zhxrhs64oiz".to_string()).unwrap(),
+        rev: "3l3qo2vuowo2b".to_string(),
+        since: None,
+        action: "create".to_string(),
+        collection: collection.to_string(),
+        rkey: rkey.to_string(),
+        cid: None,
+        block: None,
+        account_status: None,
+    }
+}
@@ -0,0 +1,22 @@
+//! Telegram: the bot token, part of every request URL, never shows in errors.
+
+use bsky_firehose_listener::telegram::TelegramError;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn keeps_the_token_out_of_errors() {
+    // Nothing listens on the port once the listener is dropped
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let token = "123456:secret-token";
+    let e = reqwest::Client::new()
+        .post(format!("http://{addr}/bot{token}/sendMessage"))
+        .send()
+        .await
+        .unwrap_err();
+    let error = TelegramError::from(e);
+    assert!(!error.to_string().contains(token), "{error}");
+    assert!(!format!("{error:?}").contains(token), "{error:?}");
+}
@@ -0,0 +1,58 @@
+//! Trending hashtags: counted once per post and case-insensitively, and ranked.
+
+use bsky_firehose_listener::{
+    facets::Facets,
+    trending::{TagCount, Trending, WINDOWS},
+};
+
+fn tagged(tags: &[&str]) -> Facets {
+    Facets {
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+fn count(tag: &str, posts: u64) -> TagCount {
+    TagCount {
+        tag: tag.to_string(),
+        posts,
+    }
+}
+
+#[test]
+fn counts_each_tag_once_per_post() {
+    let trending = Trending::default();
+    trending.record(&tagged(&["Haiku", "#haiku", "HAIKU"]));
+    trending.record(&tagged(&["haiku", "poetry"]));
+    trending.record(&tagged(&["#", ""]));
+    trending.record(&tagged(&[]));
+
+    for (_, window) in WINDOWS {
+        assert_eq!(
+            trending.top(window, 10),
+            [count("haiku", 2), count("poetry", 1)]
+        );
+    }
+}
+
+#[test]
+fn ranks_by_posts_then_name() {
+    let trending = Trending::default();
+    for tags in [
+        &["zebra", "apple"][..],
+        &["zebra", "mango"],
+        &["zebra"],
+        &["mango"],
+        &["apple"],
+        &["kiwi"],
+    ] {
+        trending.record(&tagged(tags));
+    }
+
+    let (_, window) = WINDOWS[0];
+    assert_eq!(
+        trending.top(window, 3),
+        [count("zebra", 3), count("apple", 2), count("mango", 2)]
+    );
+    assert!(trending.top(window, 0).is_empty());
+}
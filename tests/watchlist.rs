@@ -0,0 +1,87 @@
+//! Watchlist: allow and block modes, and reloading the file.
+
+use std::path::PathBuf;
+
+use bsky_firehose_listener::watchlist::{Watchlist, WatchlistMode};
+
+const ALICE: &str = "did:plc:alice";
+const BOB: &str = "did:plc:bob";
+
+fn path(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("watchlist-{name}-{}.txt", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// A client whose every request is refused, so handle lookups fail without leaving the host.
+fn offline() -> reqwest::Client {
+    reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn allow_mode_only_lets_listed_repos_through() {
+    let path = path("allow", &format!("# Poets\n\n{ALICE}\n"));
+    let watchlist = Watchlist::load(path.clone(), WatchlistMode::Allow, &offline())
+        .await
+        .unwrap();
+
+    assert!(watchlist.allows(ALICE));
+    assert!(!watchlist.allows(BOB));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn block_mode_lets_everyone_else_through() {
+    let path = path("block", &format!("  {ALICE}  \n"));
+    let watchlist = Watchlist::load(path.clone(), WatchlistMode::Block, &offline())
+        .await
+        .unwrap();
+
+    assert!(!watchlist.allows(ALICE));
+    assert!(watchlist.allows(BOB));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn reload_picks_up_changes() {
+    let http = offline();
+    let path = path("reload", &format!("{ALICE}\n"));
+    let watchlist = Watchlist::load(path.clone(), WatchlistMode::Allow, &http)
+        .await
+        .unwrap();
+
+    std::fs::write(&path, format!("{BOB}\n")).unwrap();
+    assert_eq!(watchlist.reload(&http).await.unwrap(), 1);
+    assert!(!watchlist.allows(ALICE));
+    assert!(watchlist.allows(BOB));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn unresolved_handles_are_skipped() {
+    let http = offline();
+    let path = path("handles", &format!("@alice.example.com\n{BOB}\n"));
+    let watchlist = Watchlist::load(path.clone(), WatchlistMode::Allow, &http)
+        .await
+        .unwrap();
+
+    assert!(watchlist.allows(BOB));
+    assert_eq!(watchlist.reload(&http).await.unwrap(), 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn failed_reload_keeps_the_old_list() {
+    let http = offline();
+    let path = path("missing", &format!("{ALICE}\n"));
+    let watchlist = Watchlist::load(path.clone(), WatchlistMode::Allow, &http)
+        .await
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(watchlist.reload(&http).await.is_err());
+    assert!(watchlist.allows(ALICE));
+}